@@ -0,0 +1,66 @@
+//! Exponential backoff for retrying failed operations.
+
+use std::{
+	collections::hash_map::RandomState,
+	hash::{BuildHasher, Hasher},
+	time::Duration,
+};
+
+/// Exponential backoff state, doubling the delay on every retry up to a
+/// maximum.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+	/// Initial delay.
+	initial: Duration,
+	/// Maximum delay.
+	maximum: Duration,
+	/// Delay for the next retry.
+	current: Duration,
+}
+
+impl Backoff {
+	/// Create a new backoff with the initial and maximum delay.
+	pub fn new(initial: Duration, maximum: Duration) -> Self {
+		Self { initial, maximum, current: initial }
+	}
+
+	/// Maximum delay.
+	pub fn maximum(&self) -> Duration {
+		self.maximum
+	}
+
+	/// Get the delay for the next retry and increase it for the following one.
+	pub fn next_delay(&mut self) -> Duration {
+		let delay = self.current;
+		self.current = self.current.saturating_mul(2).min(self.maximum);
+		delay
+	}
+
+	/// Get the delay for the next retry with random jitter, between half and
+	/// the full delay, and increase it for the following one. Spreads out
+	/// retries that would otherwise happen in lockstep.
+	pub fn next_jittered_delay(&mut self) -> Duration {
+		jitter(self.next_delay())
+	}
+
+	/// Reset the delay to the initial delay, e.g. after a success.
+	pub fn reset(&mut self) {
+		self.current = self.initial;
+	}
+}
+
+/// Randomly shorten the delay to between half and the full delay.
+fn jitter(delay: Duration) -> Duration {
+	let half = delay / 2;
+	half + half * random_permille() / 1000
+}
+
+/// Get a random number between 0 and 1000. Uses the randomly seeded hasher of
+/// the standard library, which is good enough for jitter.
+fn random_permille() -> u32 {
+	let random = RandomState::new().build_hasher().finish();
+	u32::try_from(random % 1001).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests;
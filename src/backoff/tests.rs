@@ -0,0 +1,26 @@
+//! Tests for the backoff.
+
+use super::*;
+
+#[test]
+fn backoff_doubles_up_to_maximum() {
+	let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5));
+	assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+	assert_eq!(backoff.next_delay(), Duration::from_secs(2));
+	assert_eq!(backoff.next_delay(), Duration::from_secs(4));
+	assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+	assert_eq!(backoff.next_delay(), Duration::from_secs(5));
+
+	backoff.reset();
+	assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+}
+
+#[test]
+fn jittered_delay_stays_in_bounds() {
+	let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(40));
+	for expected in [10, 20, 40, 40] {
+		let delay = backoff.next_jittered_delay();
+		assert!(delay >= Duration::from_secs(expected) / 2, "{delay:?} is too short");
+		assert!(delay <= Duration::from_secs(expected), "{delay:?} is too long");
+	}
+}
@@ -0,0 +1,61 @@
+//! Creation of subscriptions declared in the configuration on startup.
+
+use color_eyre::Result;
+
+use crate::{
+	database::{AuditEntry, Databases, GithubSubscription, RssSubscription},
+	secrets::SecretKey,
+	settings::BootstrapSettings,
+};
+
+/// Create the configured subscriptions that do not exist yet, encrypting their
+/// secrets with the key. Existing subscriptions are left untouched, so that
+/// seeding is idempotent and does not reset their progress.
+pub async fn seed_subscriptions(
+	config: &BootstrapSettings,
+	key: &SecretKey,
+	db: &Databases,
+) -> Result<()> {
+	for rss in &config.rss {
+		if RssSubscription::find(&rss.room, &rss.url, &db.state).await?.is_none() {
+			tracing::info!("Bootstrapping RSS subscription {} in {}", rss.url, rss.room);
+			RssSubscription::new(rss.room.clone(), rss.url.clone()).insert(&db.state).await?;
+			AuditEntry::record(
+				&rss.room,
+				None,
+				format!("bootstrapped RSS subscription {}", rss.url),
+				&db.state,
+			)
+			.await?;
+		}
+	}
+
+	for github in &config.github {
+		if GithubSubscription::find(&github.room, &github.user, &db.state).await?.is_none() {
+			tracing::info!(
+				"Bootstrapping Github subscription of {} in {}",
+				github.user,
+				github.room
+			);
+			GithubSubscription::new(
+				github.room.clone(),
+				github.user.clone(),
+				github.token.clone(),
+				key,
+			)?
+			.insert(&db.state)
+			.await?;
+			AuditEntry::record(
+				&github.room,
+				None,
+				format!("bootstrapped Github subscription of {}", github.user),
+				&db.state,
+			)
+			.await?;
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,46 @@
+//! Tests for bootstrapping subscriptions.
+
+use bonsaidb::core::schema::SerializedCollection;
+use color_eyre::Result;
+use matrix_sdk::ruma::OwnedRoomId;
+
+use super::*;
+use crate::{
+	settings::{GithubBootstrap, RssBootstrap},
+	test_utils::test_databases,
+};
+
+#[tokio::test]
+async fn bootstrap_is_idempotent() -> Result<()> {
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let config = BootstrapSettings {
+		rss: vec![RssBootstrap {
+			room: room.clone(),
+			url: "https://example.com/feed.xml".parse()?,
+		}],
+		github: vec![GithubBootstrap {
+			room,
+			user: "octocat".to_owned(),
+			token: "token".to_owned(),
+		}],
+	};
+	let key = SecretKey::derive("password");
+
+	seed_subscriptions(&config, &key, &db).await?;
+	let mut rss = RssSubscription::all_async(&db.state).await?;
+	assert_eq!(rss.len(), 1);
+	let latest_update = rss[0].contents.latest_update - time::Duration::days(1);
+	rss[0].contents.latest_update = latest_update;
+	rss[0].update_async(&db.state).await?;
+
+	// Restart.
+	seed_subscriptions(&config, &key, &db).await?;
+	let rss = RssSubscription::all_async(&db.state).await?;
+	assert_eq!(rss.len(), 1);
+	assert_eq!(rss[0].contents.latest_update, latest_update);
+	let github = GithubSubscription::all_async(&db.state).await?;
+	assert_eq!(github.len(), 1);
+	assert_eq!(github[0].contents.tokens(&key), vec!["token".to_owned()]);
+	Ok(())
+}
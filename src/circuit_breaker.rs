@@ -0,0 +1,78 @@
+//! Circuit breaker to pause non-essential requests to a failing service.
+
+use std::{
+	sync::{Mutex, PoisonError},
+	time::Instant,
+};
+
+use crate::backoff::Backoff;
+
+/// Circuit breaker, opening after repeated failures. While open, requests
+/// should be skipped. After the backoff delay, a single probe request is
+/// allowed: on success, the breaker closes again, on failure it stays open
+/// with a longer delay.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+	/// Number of consecutive failures after which the breaker opens.
+	threshold: u32,
+	/// Mutable breaker state.
+	state: Mutex<BreakerState>,
+}
+
+/// Mutable state of the [`CircuitBreaker`].
+#[derive(Debug)]
+struct BreakerState {
+	/// Number of consecutive failures.
+	failures: u32,
+	/// Point in time until which the breaker is open, if it is open.
+	open_until: Option<Instant>,
+	/// Backoff for the open duration.
+	backoff: Backoff,
+}
+
+impl CircuitBreaker {
+	/// Create a new closed circuit breaker, opening after `threshold`
+	/// consecutive failures for the backoff's delay.
+	pub fn new(threshold: u32, backoff: Backoff) -> Self {
+		Self {
+			threshold,
+			state: Mutex::new(BreakerState { failures: 0, open_until: None, backoff }),
+		}
+	}
+
+	/// Whether requests are allowed, i.e. the breaker is closed or the open
+	/// delay passed so that a probe request can be made.
+	pub fn allow(&self) -> bool {
+		let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		state.open_until.map_or(true, |open_until| open_until <= Instant::now())
+	}
+
+	/// Record a successful request, closing the breaker.
+	pub fn record_success(&self) {
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		if state.open_until.is_some() {
+			tracing::info!("Circuit breaker closed again.");
+		}
+		state.failures = 0;
+		state.open_until = None;
+		state.backoff.reset();
+	}
+
+	/// Record a failed request, opening the breaker after too many consecutive
+	/// failures.
+	pub fn record_failure(&self) {
+		let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+		state.failures = state.failures.saturating_add(1);
+		if state.failures >= self.threshold {
+			let delay = state.backoff.next_delay();
+			tracing::warn!(
+				"Circuit breaker open after {} failures, pausing for {delay:?}..",
+				state.failures
+			);
+			state.open_until = Some(Instant::now() + delay);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,36 @@
+//! Tests for the circuit breaker.
+
+use std::time::Duration;
+
+use super::*;
+
+#[test]
+fn repeated_failures_open_and_success_closes() {
+	let backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(600));
+	let breaker = CircuitBreaker::new(3, backoff);
+	assert!(breaker.allow());
+
+	breaker.record_failure();
+	breaker.record_failure();
+	assert!(breaker.allow());
+	breaker.record_failure();
+	assert!(!breaker.allow());
+
+	breaker.record_success();
+	assert!(breaker.allow());
+	breaker.record_failure();
+	assert!(breaker.allow());
+}
+
+#[test]
+fn probe_after_delay() {
+	let breaker = CircuitBreaker::new(1, Backoff::new(Duration::ZERO, Duration::ZERO));
+	breaker.record_failure();
+	assert!(breaker.allow(), "probe must be allowed after the delay");
+
+	let breaker = CircuitBreaker::new(1, Backoff::new(Duration::ZERO, Duration::from_secs(60)));
+	breaker.record_failure();
+	assert!(breaker.allow());
+	breaker.record_failure();
+	assert!(!breaker.allow(), "failed probe must open the breaker longer");
+}
@@ -2,27 +2,41 @@
 
 use std::{fmt::Display, time::Duration};
 
-use color_eyre::Result;
-use reqwest::{
-	header::{self, HeaderMap},
-	Client, StatusCode, Url,
-};
+use color_eyre::{eyre::bail, Report, Result};
+use reqwest::{header, Method, RequestBuilder, Response, StatusCode, Url};
 use serde::{Deserialize, Serialize};
 use time::{
 	format_description::well_known::{Rfc2822, Rfc3339},
 	OffsetDateTime,
 };
 
+use super::GuardedClient;
+use crate::backoff::Backoff;
+
 /// Base URL of the Github API.
 const API_URL: &str = "https://api.github.com/";
-/// User agent to use for Github requests.
-const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+/// Base URL of Github's OAuth endpoints.
+const OAUTH_URL: &str = "https://github.com/";
+/// OAuth scopes requested via the device flow.
+const DEVICE_FLOW_SCOPES: &str = "notifications repo";
+/// Seconds added to the polling interval when Github asks to slow down
+/// without a new interval.
+const SLOW_DOWN_SECONDS: u64 = 5;
+/// Number of repositories requested per page.
+const REPOS_PER_PAGE: usize = 100;
+/// Maximum number of repository pages fetched for one listing.
+const MAX_REPO_PAGES: usize = 10;
+/// Seconds to wait after hitting a secondary rate limit, if Github does not
+/// say how long.
+const SECONDARY_RATE_LIMIT_SECONDS: i64 = 60;
+/// Maximum delay between retries of a failed request.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
 
 /// API client for Github notifications.
 #[derive(Debug)]
 pub struct Github {
 	/// Request client.
-	client: Client,
+	client: GuardedClient,
 	/// Base API url.
 	base_url: Url,
 	/// Username
@@ -31,41 +45,133 @@ pub struct Github {
 	token: String,
 	/// Next allowed request time.
 	allowed_request_time: OffsetDateTime,
+	/// Number of remaining requests in the rate limit window, as of the last
+	/// response.
+	rate_limit_remaining: Option<u64>,
+	/// Whether the token was rejected as expired or revoked.
+	expired: bool,
+	/// Retries of transiently failing requests.
+	retry: RetryPolicy,
 }
 
-impl Github {
-	/// Create new Github client to the default API URL.
-	pub fn new(username: String, token: String) -> Result<Self> {
-		let mut default_headers = HeaderMap::new();
-		default_headers.insert(header::USER_AGENT, USER_AGENT.parse()?);
-		let client = Client::builder().default_headers(default_headers).build()?;
+/// Retries of requests failing transiently, i.e. due to network errors or
+/// unavailable servers. The default is not to retry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetryPolicy {
+	/// Maximum number of retries of a request.
+	pub retries: u32,
+	/// Delay before the first retry, doubling with every further retry.
+	pub base_delay: Duration,
+}
 
+impl Github {
+	/// Create new Github client to the default API URL, sending requests via
+	/// the guarded client and retrying transiently failing requests.
+	pub fn new(
+		username: String,
+		token: String,
+		client: &GuardedClient,
+		retry: RetryPolicy,
+	) -> Result<Self> {
 		Ok(Self {
-			client,
+			client: client.clone(),
 			base_url: API_URL.parse()?,
 			user: username,
 			token,
 			allowed_request_time: OffsetDateTime::UNIX_EPOCH,
+			rate_limit_remaining: None,
+			expired: false,
+			retry,
 		})
 	}
 
-	/// Set the token to the new value.
+	/// Set the token to the new value. Resets the expiry if the token changed.
 	pub fn set_token(&mut self, token: String) -> &mut Self {
-		self.token = token;
+		if self.token != token {
+			self.token = token;
+			self.expired = false;
+		}
 		self
 	}
 
-	/// Test a token for validity.
-	pub async fn test_token(&self) -> Result<()> {
-		let _resp = self
-			.client
-			.head(self.base_url.join("notifications")?)
-			.basic_auth(&self.user, Some(&self.token))
-			.header(header::IF_MODIFIED_SINCE, OffsetDateTime::now_utc().format(&Rfc2822)?)
-			.send()
+	/// Use a different base API URL, e.g. of a Github Enterprise server or a
+	/// mock server.
+	pub fn with_base_url(mut self, base_url: Url) -> Self {
+		self.base_url = base_url;
+		self
+	}
+
+	/// Use the custom base API URL if given, otherwise the public Github API.
+	pub fn with_api_url(self, api_url: Option<&Url>) -> Self {
+		match api_url {
+			Some(api_url) => self.with_base_url(api_url.clone()),
+			None => self,
+		}
+	}
+
+	/// Get the base API URL.
+	pub fn base_url(&self) -> &Url {
+		&self.base_url
+	}
+
+	/// Send a request with the method to the URL via the guarded client,
+	/// retrying network errors and unavailable servers with exponential
+	/// backoff. Other errors, e.g. rejected tokens, are not retried.
+	/// The request is prepared by the function, which is told whether the
+	/// request goes to the API's origin, so that the token is only sent there.
+	async fn send(
+		&self,
+		method: Method,
+		url: Url,
+		prepare: impl Fn(RequestBuilder, bool) -> RequestBuilder,
+	) -> Result<Response> {
+		let is_api = url.origin() == self.base_url.origin();
+		let prepare =
+			|request: RequestBuilder, same_origin: bool| prepare(request, is_api && same_origin);
+		let mut backoff = Backoff::new(self.retry.base_delay, MAX_RETRY_DELAY);
+		for _ in 0..self.retry.retries {
+			match self.client.request(method.clone(), url.clone(), &prepare).await {
+				Ok(response) if !is_transient_status(response.status()) => return Ok(response),
+				Ok(response) => {
+					tracing::debug!("Retrying Github request after status {}", response.status());
+				}
+				Err(err) if is_transient_error(&err) => {
+					tracing::debug!("Retrying Github request after error: {err}");
+				}
+				Err(err) => return Err(err),
+			}
+			tokio::time::sleep(backoff.next_delay()).await;
+		}
+		self.client.request(method, url, prepare).await
+	}
+
+	/// Authenticate the request as the user with the token, if it goes to the
+	/// API's origin.
+	fn authenticate(&self, request: RequestBuilder, is_api: bool) -> RequestBuilder {
+		if is_api {
+			request.basic_auth(&self.user, Some(&self.token))
+		} else {
+			request
+		}
+	}
+
+	/// Get whether the token was rejected as expired or revoked.
+	pub fn is_expired(&self) -> bool {
+		self.expired
+	}
+
+	/// Test a token for validity. Returns information about the token on
+	/// success.
+	pub async fn test_token(&self) -> Result<TokenInfo> {
+		let url = self.base_url.join("notifications")?;
+		let since = OffsetDateTime::now_utc().format(&Rfc2822)?;
+		let response = self
+			.send(Method::HEAD, url, |request, is_api| {
+				self.authenticate(request, is_api).header(header::IF_MODIFIED_SINCE, &since)
+			})
 			.await?
 			.error_for_status()?;
-		Ok(())
+		Ok(TokenInfo::from_headers(response.headers()))
 	}
 
 	/// Get whether the next request is already allowed.
@@ -73,49 +179,496 @@ impl Github {
 		self.allowed_request_time < OffsetDateTime::now_utc()
 	}
 
-	/// List notifications since a specific point in time.
-	pub async fn notifications(&mut self, since: OffsetDateTime) -> Result<Vec<Notification>> {
+	/// Get the number of remaining requests in the rate limit window, as of the
+	/// last response.
+	pub fn rate_limit_remaining(&self) -> Option<u64> {
+		self.rate_limit_remaining
+	}
+
+	/// Delay the next request until the given time, unless it is delayed
+	/// longer already.
+	fn delay_requests_until(&mut self, time: OffsetDateTime) {
+		self.allowed_request_time = self.allowed_request_time.max(time);
+	}
+
+	/// Record the rate limit state of the response. Requests are delayed until
+	/// the rate limit resets when it is exhausted, or as long as Github asks to
+	/// when hitting a secondary rate limit.
+	fn record_rate_limit(&mut self, response: &Response) {
+		let headers = response.headers();
+		let seconds = |name: &str| {
+			headers.get(name).and_then(|value| value.to_str().ok()?.trim().parse::<i64>().ok())
+		};
+		self.rate_limit_remaining = TokenInfo::from_headers(headers).rate_limit_remaining;
+		let now = OffsetDateTime::now_utc();
+
+		if self.rate_limit_remaining == Some(0) {
+			let reset = seconds("X-RateLimit-Reset")
+				.and_then(|reset| OffsetDateTime::from_unix_timestamp(reset).ok());
+			if let Some(reset) = reset {
+				tracing::warn!("Github rate limit of {} exhausted until {reset}", self.user);
+				self.delay_requests_until(reset);
+			}
+		}
+		let retry_after = match response.status() {
+			StatusCode::FORBIDDEN => seconds("Retry-After"),
+			StatusCode::TOO_MANY_REQUESTS => {
+				Some(seconds("Retry-After").unwrap_or(SECONDARY_RATE_LIMIT_SECONDS))
+			}
+			_ => None,
+		};
+		if let Some(retry_after) = retry_after {
+			tracing::warn!("Github asked to retry requests of {} after {retry_after}s", self.user);
+			self.delay_requests_until(now + time::Duration::seconds(retry_after));
+		}
+	}
+
+	/// URL of the notifications endpoint, or of the repository's (`owner/name`)
+	/// notifications endpoint if given.
+	fn notifications_url(&self, repo: Option<&str>) -> Result<Url> {
+		let url = match repo {
+			Some(repo) => self.base_url.join(&format!("repos/{repo}/notifications"))?,
+			None => self.base_url.join("notifications")?,
+		};
+		Ok(url)
+	}
+
+	/// List notifications since a specific point in time, oldest first. Follows
+	/// the pagination links up to the maximum number of pages.
+	pub async fn notifications(
+		&mut self,
+		since: OffsetDateTime,
+		max_pages: usize,
+	) -> Result<Vec<Notification>> {
+		let url = self.notifications_url(None)?;
+		self.fetch_notifications(url, since, max_pages).await
+	}
+
+	/// List notifications of the repository (`owner/name`) since a specific
+	/// point in time, oldest first. Follows the pagination links up to the
+	/// maximum number of pages.
+	pub async fn repo_notifications(
+		&mut self,
+		repo: &str,
+		since: OffsetDateTime,
+		max_pages: usize,
+	) -> Result<Vec<Notification>> {
+		let url = self.notifications_url(Some(repo))?;
+		self.fetch_notifications(url, since, max_pages).await
+	}
+
+	/// List notifications of the endpoint since a specific point in time.
+	async fn fetch_notifications(
+		&mut self,
+		url: Url,
+		since: OffsetDateTime,
+		max_pages: usize,
+	) -> Result<Vec<Notification>> {
 		let since_rfc2822 = since.format(&Rfc2822)?;
 		let since_rfc3339 = since.format(&Rfc3339)?;
 		let query = [("all", "false"), ("per_page", "50"), ("since", &since_rfc3339)];
 		let response = self
-			.client
-			.get(self.base_url.join("notifications")?)
-			.basic_auth(&self.user, Some(&self.token))
-			.header(header::ACCEPT, "application/vnd.github+json")
-			.header(header::IF_MODIFIED_SINCE, since_rfc2822)
-			.query(&query)
-			.send()
-			.await?
-			.error_for_status()?;
+			.send(Method::GET, url, |request, is_api| {
+				self.authenticate(request, is_api)
+					.header(header::ACCEPT, "application/vnd.github+json")
+					.header(header::IF_MODIFIED_SINCE, &since_rfc2822)
+					.query(&query)
+			})
+			.await?;
+		if response.status() == StatusCode::UNAUTHORIZED {
+			self.expired = true;
+		}
+		self.record_rate_limit(&response);
+		let response = response.error_for_status()?;
 
 		if let Some(next_request) = response.headers().get("X-Poll-Interval") {
 			let wait_duration = Duration::from_secs(next_request.to_str()?.parse()?);
-			self.allowed_request_time = OffsetDateTime::now_utc() + wait_duration;
+			self.delay_requests_until(OffsetDateTime::now_utc() + wait_duration);
 		}
 
 		if response.status() == StatusCode::NOT_MODIFIED {
 			return Ok(vec![]);
 		}
 
-		let entries: Vec<Notification> = response.json().await?;
+		let mut next = self.next_page(response.headers());
+		let mut entries: Vec<Notification> = response.json().await?;
+		let mut pages = 1;
+		while let Some(url) = next {
+			if pages >= max_pages {
+				tracing::warn!(
+					"Skipping older Github notifications of {} after {pages} pages",
+					self.user
+				);
+				break;
+			}
+			let response = self
+				.send(Method::GET, url, |request, is_api| {
+					self.authenticate(request, is_api)
+						.header(header::ACCEPT, "application/vnd.github+json")
+				})
+				.await?;
+			self.record_rate_limit(&response);
+			let response = response.error_for_status()?;
+			next = self.next_page(response.headers());
+			let page: Vec<Notification> = response.json().await?;
+			entries.extend(page);
+			pages += 1;
+		}
+		entries.sort_by_key(|notification| notification.updated_at);
 		Ok(entries)
 	}
 
+	/// Get the URL of the next page of a paginated response, unless it leaves
+	/// the API's origin, which the token must not be sent to.
+	fn next_page(&self, headers: &header::HeaderMap) -> Option<Url> {
+		let url = next_page(headers)?;
+		if url.origin() != self.base_url.origin() {
+			tracing::warn!("Not following Github pagination link to another origin: {url}");
+			return None;
+		}
+		Some(url)
+	}
+
+	/// Mark all notifications, or only those of the repository (`owner/name`)
+	/// if given, up to the given time as read.
+	pub async fn mark_read(&self, repo: Option<&str>, last_read_at: OffsetDateTime) -> Result<()> {
+		let body =
+			serde_json::json!({ "last_read_at": last_read_at.format(&Rfc3339)?, "read": true });
+		let url = self.notifications_url(repo)?;
+		self.send(Method::PUT, url, |request, is_api| {
+			self.authenticate(request, is_api)
+				.header(header::ACCEPT, "application/vnd.github+json")
+				.json(&body)
+		})
+		.await?
+		.error_for_status()?;
+		Ok(())
+	}
+
+	/// Mark the notification thread with the given ID as read.
+	pub async fn mark_thread_read(&self, thread_id: &str) -> Result<()> {
+		let url = self.base_url.join(&format!("notifications/threads/{thread_id}"))?;
+		self.send(Method::PATCH, url, |request, is_api| {
+			self.authenticate(request, is_api).header(header::ACCEPT, "application/vnd.github+json")
+		})
+		.await?
+		.error_for_status()?;
+		Ok(())
+	}
+
+	/// Get the most recent notification, read or unread, of the repository
+	/// (`owner/name`) if given. Does not mark anything as read.
+	pub async fn latest_notification(&self, repo: Option<&str>) -> Result<Option<Notification>> {
+		let query = [("all", "true"), ("per_page", "1")];
+		let url = self.notifications_url(repo)?;
+		let response = self
+			.send(Method::GET, url, |request, is_api| {
+				self.authenticate(request, is_api)
+					.header(header::ACCEPT, "application/vnd.github+json")
+					.query(&query)
+			})
+			.await?
+			.error_for_status()?;
+
+		let entries: Vec<Notification> = response.json().await?;
+		Ok(entries.into_iter().next())
+	}
+
+	/// List the repositories the token can access, fetching page after page.
+	/// Stops early when the rate limit is used up or the maximum number of
+	/// pages is reached, marking the list as incomplete.
+	pub async fn get_repos(&self) -> Result<RepositoryList> {
+		let mut repositories = Vec::new();
+		for page in 1..=MAX_REPO_PAGES {
+			let query = [("per_page", REPOS_PER_PAGE.to_string()), ("page", page.to_string())];
+			let url = self.base_url.join("user/repos")?;
+			let response = self
+				.send(Method::GET, url, |request, is_api| {
+					self.authenticate(request, is_api)
+						.header(header::ACCEPT, "application/vnd.github+json")
+						.query(&query)
+				})
+				.await?;
+			let info = TokenInfo::from_headers(response.headers());
+			if matches!(response.status(), StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS)
+				&& info.rate_limit_remaining == Some(0)
+			{
+				if repositories.is_empty() {
+					bail!("Github API rate limit exceeded");
+				}
+				return Ok(RepositoryList { repositories, complete: false });
+			}
+
+			let page: Vec<MinimalRepository> = response.error_for_status()?.json().await?;
+			let last_page = page.len() < REPOS_PER_PAGE;
+			repositories.extend(page);
+			if last_page {
+				return Ok(RepositoryList { repositories, complete: true });
+			}
+			if info.rate_limit_remaining == Some(0) {
+				return Ok(RepositoryList { repositories, complete: false });
+			}
+		}
+		Ok(RepositoryList { repositories, complete: false })
+	}
+
+	/// Get the login name of the token's user.
+	pub async fn current_user(&self) -> Result<String> {
+		let url = self.base_url.join("user")?;
+		let response = self
+			.send(Method::GET, url, |request, is_api| {
+				let request = request.header(header::ACCEPT, "application/vnd.github+json");
+				if is_api {
+					request.bearer_auth(&self.token)
+				} else {
+					request
+				}
+			})
+			.await?
+			.error_for_status()?;
+		let user: AuthenticatedUser = response.json().await?;
+		Ok(user.login)
+	}
+
 	/// Get the thread comment at the given URL.
 	pub async fn get_thread_comment_from(&self, url: Url) -> Result<ThreadComment> {
 		let response = self
-			.client
-			.get(url)
-			.basic_auth(&self.user, Some(&self.token))
-			.header(header::ACCEPT, "application/vnd.github+json")
-			.send()
+			.send(Method::GET, url, |request, is_api| {
+				self.authenticate(request, is_api)
+					.header(header::ACCEPT, "application/vnd.github+json")
+			})
 			.await?
 			.error_for_status()?;
 
 		let comment: ThreadComment = response.json().await?;
 		Ok(comment)
 	}
+
+	/// Get the issue or pull request at the given API URL.
+	pub async fn get_issue_or_pr(&self, url: Url) -> Result<IssueOrPr> {
+		let response = self
+			.send(Method::GET, url, |request, is_api| {
+				self.authenticate(request, is_api)
+					.header(header::ACCEPT, "application/vnd.github+json")
+			})
+			.await?
+			.error_for_status()?;
+
+		let issue: IssueOrPr = response.json().await?;
+		Ok(issue)
+	}
+}
+
+/// Client for Github's OAuth device flow, which lets a user authorize the bot
+/// on Github instead of handing out an access token.
+#[derive(Debug)]
+pub struct DeviceFlow {
+	/// Request client.
+	client: GuardedClient,
+	/// Base OAuth URL.
+	base_url: Url,
+	/// Client ID of the registered OAuth app.
+	client_id: String,
+}
+
+impl DeviceFlow {
+	/// Create a new device flow client for the OAuth app, sending requests via
+	/// the guarded client.
+	pub fn new(client_id: String, client: &GuardedClient) -> Result<Self> {
+		Ok(Self { client: client.clone(), base_url: OAUTH_URL.parse()?, client_id })
+	}
+
+	/// Use a different base OAuth URL, e.g. a mock server.
+	#[cfg(test)]
+	pub fn with_base_url(mut self, base_url: Url) -> Self {
+		self.base_url = base_url;
+		self
+	}
+
+	/// Request a new device code, which the user enters on Github.
+	pub async fn request_code(&self) -> Result<DeviceCode> {
+		let form = [("client_id", self.client_id.as_str()), ("scope", DEVICE_FLOW_SCOPES)];
+		let url = self.base_url.join("login/device/code")?;
+		let response = self
+			.client
+			.request(Method::POST, url, |request, _| {
+				request.header(header::ACCEPT, "application/json").form(&form)
+			})
+			.await?
+			.error_for_status()?;
+		let code: DeviceCode = response.json().await?;
+		Ok(code)
+	}
+
+	/// Poll once whether the user authorized the device code.
+	pub async fn poll(&self, device_code: &str) -> Result<DevicePoll> {
+		let form = [
+			("client_id", self.client_id.as_str()),
+			("device_code", device_code),
+			("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+		];
+		let url = self.base_url.join("login/oauth/access_token")?;
+		let response: AccessTokenResponse = self
+			.client
+			.request(Method::POST, url, |request, _| {
+				request.header(header::ACCEPT, "application/json").form(&form)
+			})
+			.await?
+			.error_for_status()?
+			.json()
+			.await?;
+
+		match (response.access_token, response.error.as_deref()) {
+			(Some(token), _) => Ok(DevicePoll::Authorized(token)),
+			(None, Some("authorization_pending")) => Ok(DevicePoll::Pending),
+			(None, Some("slow_down")) => Ok(DevicePoll::SlowDown(response.interval)),
+			(None, Some("access_denied")) => Ok(DevicePoll::Denied),
+			(None, Some("expired_token")) => Ok(DevicePoll::Expired),
+			(None, error) => bail!("Unexpected device flow response: {error:?}"),
+		}
+	}
+
+	/// Poll in the requested interval until the user authorized or denied the
+	/// device code or it expired.
+	pub async fn wait_for_token(&self, code: &DeviceCode) -> Result<DevicePoll> {
+		let deadline = tokio::time::Instant::now() + Duration::from_secs(code.expires_in);
+		let mut interval = code.interval;
+		loop {
+			tokio::time::sleep(Duration::from_secs(interval)).await;
+			if tokio::time::Instant::now() >= deadline {
+				return Ok(DevicePoll::Expired);
+			}
+			match self.poll(&code.device_code).await? {
+				DevicePoll::Pending => {}
+				DevicePoll::SlowDown(new_interval) => {
+					interval = new_interval.unwrap_or(interval + SLOW_DOWN_SECONDS);
+				}
+				done => return Ok(done),
+			}
+		}
+	}
+}
+
+/// Device code of the OAuth device flow.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+	/// Code identifying the device when polling.
+	pub device_code: String,
+	/// Code the user enters on Github.
+	pub user_code: String,
+	/// URL the user enters the code at.
+	pub verification_uri: String,
+	/// Number of seconds until the codes expire.
+	pub expires_in: u64,
+	/// Minimum number of seconds between polls.
+	pub interval: u64,
+}
+
+/// State of the authorization of a device code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevicePoll {
+	/// The user did not authorize the code yet.
+	Pending,
+	/// Polling too fast, continue with the new interval if given.
+	SlowDown(Option<u64>),
+	/// The user authorized the code, containing the access token.
+	Authorized(String),
+	/// The user denied the authorization.
+	Denied,
+	/// The code expired before the user authorized it.
+	Expired,
+}
+
+/// Response of the access token endpoint, either a token or an error.
+#[derive(Debug, Deserialize)]
+struct AccessTokenResponse {
+	/// Access token on success.
+	access_token: Option<String>,
+	/// Error code otherwise.
+	error: Option<String>,
+	/// New polling interval in seconds when slowing down.
+	interval: Option<u64>,
+}
+
+/// API response type for the authenticated user.
+#[derive(Debug, Deserialize)]
+struct AuthenticatedUser {
+	/// Login name.
+	login: String,
+}
+
+/// Information about an access token, taken from API response headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenInfo {
+	/// OAuth scopes of the token. Not available for fine-grained tokens.
+	pub scopes: Option<Vec<String>>,
+	/// Number of remaining requests in the current rate limit window.
+	pub rate_limit_remaining: Option<u64>,
+}
+
+impl TokenInfo {
+	/// Read the token information from the response headers.
+	pub fn from_headers(headers: &header::HeaderMap) -> Self {
+		let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+		Self {
+			scopes: header("X-OAuth-Scopes").map(parse_scopes),
+			rate_limit_remaining: header("X-RateLimit-Remaining")
+				.and_then(|remaining| remaining.trim().parse().ok()),
+		}
+	}
+}
+
+/// Repositories accessible with a token.
+#[derive(Debug)]
+pub struct RepositoryList {
+	/// The repositories.
+	pub repositories: Vec<MinimalRepository>,
+	/// Whether all repositories were fetched.
+	pub complete: bool,
+}
+
+/// Parse the comma separated list of OAuth scopes.
+pub fn parse_scopes(scopes: &str) -> Vec<String> {
+	scopes
+		.split(',')
+		.map(str::trim)
+		.filter(|scope| !scope.is_empty())
+		.map(ToOwned::to_owned)
+		.collect()
+}
+
+/// Whether the response status means the server is unavailable for now, so
+/// that the request might succeed when retried.
+fn is_transient_status(status: StatusCode) -> bool {
+	matches!(
+		status,
+		StatusCode::INTERNAL_SERVER_ERROR
+			| StatusCode::BAD_GATEWAY
+			| StatusCode::SERVICE_UNAVAILABLE
+			| StatusCode::GATEWAY_TIMEOUT
+	)
+}
+
+/// Whether the request error is likely temporary, i.e. a network error.
+fn is_transient_error(err: &Report) -> bool {
+	err.downcast_ref::<reqwest::Error>()
+		.map_or(false, |err| err.is_connect() || err.is_timeout() || err.is_request())
+		|| err.downcast_ref::<std::io::Error>().is_some()
+}
+
+/// Get the URL of the next page from the `Link` header of a paginated
+/// response, if there is one.
+fn next_page(headers: &header::HeaderMap) -> Option<Url> {
+	let links = headers.get(header::LINK)?.to_str().ok()?;
+	links.split(',').find_map(|link| {
+		let mut parts = link.split(';');
+		let url = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+		if parts.any(|param| param.trim() == "rel=\"next\"") {
+			url.parse().ok()
+		} else {
+			None
+		}
+	})
 }
 
 /// API Response type for Github notifications.
@@ -144,7 +697,7 @@ pub struct Notification {
 }
 
 /// Reason for notification.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum NotificationReason {
 	/// You were assigned to the issue.
@@ -232,6 +785,26 @@ pub struct Subject {
 	pub url: Option<Url>,
 }
 
+/// An issue or pull request object. TODO: this is incomplete!
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssueOrPr {
+	/// State.
+	pub state: IssueState,
+	/// Whether the pull request was merged. Not set for issues.
+	#[serde(default)]
+	pub merged: bool,
+}
+
+/// State of an issue or pull request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueState {
+	/// Open.
+	Open,
+	/// Closed, possibly merged for pull requests.
+	Closed,
+}
+
 /// A thread comment object. TODO: this is incomplete!
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ThreadComment {
@@ -254,6 +827,18 @@ pub struct ThreadComment {
 	/// Update datetime.
 	#[serde(with = "time::serde::iso8601::option")]
 	pub updated_at: Option<OffsetDateTime>,
+	/// Code the comment refers to, if it is a pull request review comment.
+	#[serde(flatten)]
+	pub review: Option<ReviewComment>,
+}
+
+/// Code location of a pull request review comment.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReviewComment {
+	/// Path of the commented file.
+	pub path: String,
+	/// Diff hunk ending at the commented line.
+	pub diff_hunk: String,
 }
 
 /// A Github user. TODO: this is incomplete!
@@ -1,3 +1,213 @@
 //! API clients for querying things for the bot.
 
 pub mod github;
+pub mod rss;
+
+use std::{
+	collections::HashMap,
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+	sync::{Arc, Mutex, PoisonError},
+	time::Duration,
+};
+
+use color_eyre::{
+	eyre::{bail, eyre},
+	Result,
+};
+use reqwest::{
+	header, redirect::Policy, Client, ClientBuilder, Method, RequestBuilder, Response, StatusCode,
+	Url,
+};
+use tokio::net::lookup_host;
+
+use crate::settings::FetchSettings;
+
+/// Maximum number of redirects followed by the [`GuardedClient`].
+const MAX_REDIRECTS: usize = 10;
+
+/// Builder of the HTTP clients for outbound requests, sending the given user
+/// agent. Compressed responses are requested and decoded transparently, so
+/// response bodies are always the decoded content.
+fn client_builder(user_agent: &str) -> ClientBuilder {
+	Client::builder().user_agent(user_agent).gzip(true).deflate(true).brotli(true)
+}
+
+/// HTTP client for user-provided URLs, e.g. feeds and Github Enterprise
+/// servers. Every URL, including every redirect target, is checked via
+/// [`check_url`] and only the checked addresses are connected to, so that
+/// neither redirects nor changing DNS answers reach internal services.
+#[derive(Debug, Clone)]
+pub struct GuardedClient {
+	/// User agent to send.
+	user_agent: Arc<str>,
+	/// Limits of the fetched URLs.
+	config: Arc<FetchSettings>,
+	/// Client for URLs without checked addresses to connect to, e.g. IP
+	/// addresses or any host if private hosts are allowed.
+	client: Client,
+	/// Clients connecting only to the checked addresses of a domain. They are
+	/// reused as long as the domain resolves to the same addresses.
+	pinned: Arc<Mutex<HashMap<String, (Vec<SocketAddr>, Client)>>>,
+}
+
+impl GuardedClient {
+	/// Create a new client sending the user agent and only fetching URLs
+	/// allowed by the configuration, within its timeouts.
+	pub fn new(user_agent: &str, config: &FetchSettings) -> reqwest::Result<Self> {
+		Ok(Self {
+			user_agent: Arc::from(user_agent),
+			config: Arc::new(config.clone()),
+			client: guarded_builder(user_agent, config).build()?,
+			pinned: Arc::default(),
+		})
+	}
+
+	/// Send a GET request to the URL, following redirects. The request to
+	/// every hop is prepared by the function, which is told whether the hop
+	/// is on the origin of the URL, so that credentials are only sent there.
+	pub async fn get(
+		&self,
+		url: Url,
+		prepare: impl Fn(RequestBuilder, bool) -> RequestBuilder,
+	) -> Result<Response> {
+		self.request(Method::GET, url, prepare).await
+	}
+
+	/// Send a request with the method to the URL, following redirects with the
+	/// same method. The request to every hop is prepared by the function like
+	/// for [`GuardedClient::get`].
+	pub async fn request(
+		&self,
+		method: Method,
+		url: Url,
+		prepare: impl Fn(RequestBuilder, bool) -> RequestBuilder,
+	) -> Result<Response> {
+		let origin = url.origin();
+		let mut url = url;
+		for _ in 0..=MAX_REDIRECTS {
+			let addresses = check_url(&url, &self.config).await?;
+			let client = self.client_for(&url, addresses)?;
+			let request =
+				prepare(client.request(method.clone(), url.clone()), url.origin() == origin);
+			let response = request.send().await?;
+			let status = response.status();
+			if !status.is_redirection() || status == StatusCode::NOT_MODIFIED {
+				return Ok(response);
+			}
+			let location = response
+				.headers()
+				.get(header::LOCATION)
+				.and_then(|location| location.to_str().ok())
+				.ok_or_else(|| eyre!("Redirect from {url} has no valid location"))?;
+			url = url.join(location)?;
+		}
+		bail!("Too many redirects, more than {MAX_REDIRECTS}")
+	}
+
+	/// Get the client connecting only to the checked addresses of the URL's
+	/// domain, building it if the domain resolved to other addresses before.
+	fn client_for(&self, url: &Url, mut addresses: Vec<SocketAddr>) -> reqwest::Result<Client> {
+		let domain = match url.domain() {
+			Some(domain) if !addresses.is_empty() => domain,
+			_ => return Ok(self.client.clone()),
+		};
+		addresses.sort_unstable();
+		let mut pinned = self.pinned.lock().unwrap_or_else(PoisonError::into_inner);
+		if let Some((pinned_addresses, client)) = pinned.get(domain) {
+			if *pinned_addresses == addresses {
+				return Ok(client.clone());
+			}
+		}
+		let client = guarded_builder(&self.user_agent, &self.config)
+			.resolve_to_addrs(domain, &addresses)
+			.build()?;
+		pinned.insert(domain.to_owned(), (addresses, client.clone()));
+		Ok(client)
+	}
+}
+
+/// Builder of the clients of the [`GuardedClient`], which do not follow
+/// redirects themselves and abort requests after the configured timeouts.
+fn guarded_builder(user_agent: &str, config: &FetchSettings) -> ClientBuilder {
+	client_builder(user_agent)
+		.redirect(Policy::none())
+		.timeout(Duration::from_secs(config.timeout))
+		.connect_timeout(Duration::from_secs(config.connect_timeout))
+}
+
+/// Check whether fetching the URL is allowed, protecting against requests to
+/// internal services. Only http(s) URLs of allowed hosts are accepted, which
+/// must not resolve to private, loopback or link-local addresses unless
+/// configured otherwise. Returns the checked addresses the host resolved to,
+/// which are none if private hosts are allowed and nothing was resolved.
+pub async fn check_url(url: &Url, config: &FetchSettings) -> Result<Vec<SocketAddr>> {
+	if !matches!(url.scheme(), "http" | "https") {
+		bail!("URL scheme `{}` is not allowed", url.scheme());
+	}
+	let host = match url.host_str() {
+		Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+		None => bail!("URL has no host"),
+	};
+	if config.denied_hosts.iter().any(|denied| host_matches(host, denied)) {
+		bail!("Host `{host}` is denied");
+	}
+	if !config.allowed_hosts.is_empty()
+		&& !config.allowed_hosts.iter().any(|allowed| host_matches(host, allowed))
+	{
+		bail!("Host `{host}` is not in the allowed hosts");
+	}
+
+	if config.allow_private_hosts {
+		return Ok(Vec::new());
+	}
+	let port = url.port_or_known_default().unwrap_or(80);
+	let addresses = lookup_host((host, port)).await?.collect::<Vec<_>>();
+	if let Some(address) = addresses.iter().find(|address| is_internal(address.ip())) {
+		bail!("Host `{host}` resolves to the internal address {}", address.ip());
+	}
+	Ok(addresses)
+}
+
+/// Whether the host is the configured host or one of its subdomains.
+fn host_matches(host: &str, configured: &str) -> bool {
+	let host = host.to_ascii_lowercase();
+	let configured = configured.to_ascii_lowercase();
+	host == configured || host.strip_suffix(&configured).map_or(false, |sub| sub.ends_with('.'))
+}
+
+/// Whether the address is not publicly routable, e.g. private, loopback or
+/// link-local.
+fn is_internal(address: IpAddr) -> bool {
+	match address {
+		IpAddr::V4(address) => is_internal_v4(address),
+		IpAddr::V6(address) => {
+			address.to_ipv4_mapped().map_or_else(|| is_internal_v6(address), is_internal_v4)
+		}
+	}
+}
+
+/// Whether the IPv4 address is not publicly routable.
+fn is_internal_v4(address: Ipv4Addr) -> bool {
+	let [first, second, ..] = address.octets();
+	address.is_private()
+		|| address.is_loopback()
+		|| address.is_link_local()
+		|| address.is_unspecified()
+		|| address.is_broadcast()
+		// Shared address space for carrier-grade NAT, 100.64.0.0/10.
+		|| (first == 100 && (second & 0xc0) == 64)
+}
+
+/// Whether the IPv6 address is not publicly routable.
+fn is_internal_v6(address: Ipv6Addr) -> bool {
+	let first = address.segments()[0];
+	address.is_loopback()
+		|| address.is_unspecified()
+		// Unique local addresses, fc00::/7.
+		|| (first & 0xfe00) == 0xfc00
+		// Link-local addresses, fe80::/10.
+		|| (first & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests;
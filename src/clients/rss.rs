@@ -0,0 +1,420 @@
+//! Client functionality for fetching RSS feeds.
+
+use std::{
+	borrow::Cow,
+	ops::Range,
+	panic::{self, AssertUnwindSafe},
+	sync::Arc,
+	time::Duration,
+};
+
+use color_eyre::{
+	eyre::{bail, eyre},
+	Report, Result,
+};
+use encoding_rs::{Encoding, UTF_8};
+use feed_rs::model::{Feed, FeedType};
+use reqwest::{
+	header::{self, HeaderMap},
+	RequestBuilder, StatusCode, Url,
+};
+use tokio::sync::Semaphore;
+
+use super::{github::RetryPolicy, GuardedClient};
+use crate::{
+	backoff::Backoff,
+	database::{FeedAuth, FeedFormat, RssSubscription},
+	settings::FetchSettings,
+};
+
+/// Maximum size of a feed in bytes that is accepted for parsing.
+const MAX_FEED_SIZE: usize = 10 * 1024 * 1024;
+/// Maximum number of feeds checked concurrently when validating feeds.
+const VALIDATE_CONCURRENCY: usize = 4;
+/// Timeout for checking a single feed when validating feeds.
+const VALIDATE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum delay between retries of a failing feed fetch.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// How to parse a feed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+	/// Format to parse the feed as, detected automatically if unset.
+	pub format: Option<FeedFormat>,
+	/// Whether to retry parsing after cleaning up common mistakes.
+	pub lenient: bool,
+}
+
+impl From<&RssSubscription> for ParseOptions {
+	fn from(subscription: &RssSubscription) -> Self {
+		Self { format: subscription.format, lenient: subscription.lenient }
+	}
+}
+
+/// Validators of a feed response, to only fetch the feed again if it was
+/// modified since.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheValidators {
+	/// `ETag` header of the response.
+	pub etag: Option<String>,
+	/// `Last-Modified` header of the response.
+	pub last_modified: Option<String>,
+}
+
+impl CacheValidators {
+	/// Get the validators from the response headers.
+	fn from_headers(headers: &HeaderMap) -> Self {
+		let get =
+			|name| headers.get(name).and_then(|value| value.to_str().ok()).map(ToOwned::to_owned);
+		Self { etag: get(header::ETAG), last_modified: get(header::LAST_MODIFIED) }
+	}
+}
+
+impl From<&RssSubscription> for CacheValidators {
+	/// Validators of the subscription's last fetch. Subscriptions deferring
+	/// entries to later polls have none, as they have to check the unchanged
+	/// feed again.
+	fn from(subscription: &RssSubscription) -> Self {
+		if subscription.defers_entries() {
+			return Self::default();
+		}
+		Self { etag: subscription.etag.clone(), last_modified: subscription.last_modified.clone() }
+	}
+}
+
+/// Fetch and parse the feed at the given URL, authenticating with the
+/// credentials if given. The credentials are not sent to other origins the
+/// feed redirects to. Feeds in other encodings than UTF-8 are transcoded, also
+/// considering the charset of the response.
+pub async fn fetch_feed(
+	client: &GuardedClient,
+	url: Url,
+	options: ParseOptions,
+	auth: Option<&FeedAuth>,
+) -> Result<Feed> {
+	match fetch_feed_if_modified(client, url, options, auth, &CacheValidators::default()).await? {
+		Some((feed, _)) => Ok(feed),
+		None => bail!("Feed was reported as not modified without being asked"),
+	}
+}
+
+/// Fetch and parse the feed at the given URL like [`fetch_feed`], unless it
+/// was not modified since the response the validators are from. Returns the
+/// feed along with the validators of the new response, or `None` if the feed
+/// was not modified.
+pub async fn fetch_feed_if_modified(
+	client: &GuardedClient,
+	url: Url,
+	options: ParseOptions,
+	auth: Option<&FeedAuth>,
+	validators: &CacheValidators,
+) -> Result<Option<(Feed, CacheValidators)>> {
+	let response = client
+		.get(url, |mut request, same_origin| {
+			if same_origin {
+				request = authorize(request, auth);
+			}
+			if let Some(etag) = &validators.etag {
+				request = request.header(header::IF_NONE_MATCH, etag);
+			}
+			if let Some(last_modified) = &validators.last_modified {
+				request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+			}
+			request
+		})
+		.await?;
+	if response.status() == StatusCode::NOT_MODIFIED {
+		return Ok(None);
+	}
+	let mut response = response.error_for_status()?;
+	let validators = CacheValidators::from_headers(response.headers());
+	if response.content_length().map_or(false, exceeds_max_size) {
+		bail!("Feed exceeds the maximum size of {MAX_FEED_SIZE} bytes");
+	}
+	let charset = response
+		.headers()
+		.get(header::CONTENT_TYPE)
+		.and_then(|content_type| content_type.to_str().ok())
+		.and_then(header_charset)
+		.map(ToOwned::to_owned);
+
+	let mut bytes = Vec::new();
+	while let Some(chunk) = response.chunk().await? {
+		if bytes.len() + chunk.len() > MAX_FEED_SIZE {
+			bail!("Feed exceeds the maximum size of {MAX_FEED_SIZE} bytes");
+		}
+		bytes.extend_from_slice(&chunk);
+	}
+
+	let feed = parse_feed_with(&transcode(&bytes, charset.as_deref()), options)?;
+	Ok(Some((feed, validators)))
+}
+
+/// Fetch the feed like [`fetch_feed_if_modified`], retrying network errors and
+/// server errors with exponential backoff. Other errors, e.g. feeds failing to
+/// parse, are not retried.
+pub async fn fetch_feed_with_retries(
+	client: &GuardedClient,
+	url: Url,
+	options: ParseOptions,
+	auth: Option<&FeedAuth>,
+	validators: &CacheValidators,
+	retry: RetryPolicy,
+) -> Result<Option<(Feed, CacheValidators)>> {
+	let mut backoff = Backoff::new(retry.base_delay, MAX_RETRY_DELAY);
+	for _ in 0..retry.retries {
+		match fetch_feed_if_modified(client, url.clone(), options, auth, validators).await {
+			Err(err) if is_transient(&err) => {
+				tracing::debug!("Retrying fetch of feed {url} after error: {err}");
+			}
+			result => return result,
+		}
+		tokio::time::sleep(backoff.next_delay()).await;
+	}
+	fetch_feed_if_modified(client, url, options, auth, validators).await
+}
+
+/// Add the credentials to the request, if given.
+fn authorize(request: RequestBuilder, auth: Option<&FeedAuth>) -> RequestBuilder {
+	match auth {
+		Some(FeedAuth::Basic { username, password }) => {
+			request.basic_auth(username, password.as_ref())
+		}
+		Some(FeedAuth::Bearer(token)) => request.bearer_auth(token),
+		None => request,
+	}
+}
+
+/// Whether fetching a feed failed due to a network error or a server error, so
+/// that it might succeed when retried.
+fn is_transient(err: &Report) -> bool {
+	err.downcast_ref::<reqwest::Error>().map_or(false, |err| {
+		err.is_connect()
+			|| err.is_timeout()
+			|| err.status().map_or(false, |status| status.is_server_error())
+	})
+}
+
+/// Parse a feed from raw bytes, detecting the format automatically.
+/// Oversized input is rejected and panics of the parser are turned into
+/// errors.
+pub fn parse_feed(bytes: &[u8]) -> Result<Feed> {
+	parse_feed_with(bytes, ParseOptions::default())
+}
+
+/// Parse a feed from raw bytes with the given options. A configured format
+/// skips anything before the feed's root and has to match the parsed feed.
+/// Feeds declaring another encoding than UTF-8 are transcoded first.
+pub fn parse_feed_with(bytes: &[u8], options: ParseOptions) -> Result<Feed> {
+	if bytes.len() > MAX_FEED_SIZE {
+		bail!("Feed exceeds the maximum size of {MAX_FEED_SIZE} bytes");
+	}
+	let transcoded = transcode(bytes, None);
+	let bytes = &*transcoded;
+
+	let feed = match parse_raw(skip_to_root(bytes, options.format)) {
+		Ok(feed) => feed,
+		Err(err) if options.lenient => {
+			let cleaned = clean_up(skip_to_root(bytes, options.format));
+			match parse_raw(&cleaned) {
+				Ok(feed) => {
+					tracing::info!("Parsed feed leniently after cleaning it up: {err}");
+					feed
+				}
+				Err(_) => return Err(err),
+			}
+		}
+		Err(err) => return Err(err),
+	};
+
+	if let Some(format) = options.format {
+		if !format_matches(format, &feed.feed_type) {
+			bail!("Feed is {:?}, not the configured format {format:?}", feed.feed_type);
+		}
+	}
+	Ok(feed)
+}
+
+/// Get the charset parameter of a `Content-Type` header value.
+fn header_charset(content_type: &str) -> Option<&str> {
+	content_type
+		.split(';')
+		.skip(1)
+		.filter_map(|parameter| parameter.split_once('='))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+		.map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+/// Transcode an XML feed to UTF-8 if it is in another encoding, given by its
+/// byte order mark, its XML declaration or the charset of the response, in
+/// that order. The encoding in the XML declaration is changed to UTF-8
+/// accordingly. Other feeds are returned unchanged.
+pub fn transcode<'a>(bytes: &'a [u8], charset: Option<&str>) -> Cow<'a, [u8]> {
+	let ascii_compatible = |encoding: &&Encoding| encoding.is_ascii_compatible();
+	let encoding = match Encoding::for_bom(bytes) {
+		Some((encoding, _)) => encoding,
+		None if !skip_whitespace(bytes).starts_with(b"<") => return Cow::Borrowed(bytes),
+		None => declared_encoding(bytes)
+			.and_then(|range| Encoding::for_label(&bytes[range]))
+			.or_else(|| charset.and_then(|charset| Encoding::for_label(charset.as_bytes())))
+			.filter(ascii_compatible)
+			.unwrap_or(UTF_8),
+	};
+	if encoding == UTF_8 {
+		return Cow::Borrowed(bytes);
+	}
+
+	let (text, encoding, had_errors) = encoding.decode(bytes);
+	tracing::debug!("Transcoding feed from {} to UTF-8 (errors: {had_errors})", encoding.name());
+	let mut text = text.into_owned();
+	if let Some(range) = declared_encoding(text.as_bytes()) {
+		text.replace_range(range, "UTF-8");
+	}
+	Cow::Owned(text.into_bytes())
+}
+
+/// Get the position of the encoding's name in the XML declaration, if the
+/// bytes start with a declaration that has an encoding.
+fn declared_encoding(bytes: &[u8]) -> Option<Range<usize>> {
+	let offset = bytes.len() - skip_whitespace(bytes).len();
+	let declaration = bytes[offset..].strip_prefix(b"<?xml")?;
+	let end = declaration.windows(2).position(|window| window == b"?>")?;
+	let declaration = &declaration[..end];
+	let attribute = declaration.windows(8).position(|window| window == b"encoding")?;
+
+	let value = skip_whitespace(&declaration[attribute + 8..]).strip_prefix(b"=")?;
+	let value = skip_whitespace(value);
+	let quote = *value.first().filter(|quote| matches!(quote, b'"' | b'\''))?;
+	let length = value[1..].iter().position(|byte| *byte == quote)?;
+	let start = offset + 5 + (declaration.len() - value.len()) + 1;
+	Some(start..start + length)
+}
+
+/// Skip leading ASCII whitespace.
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+	let start = bytes.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(bytes.len());
+	&bytes[start..]
+}
+
+/// Parse the bytes with the feed parser, turning its panics into errors.
+fn parse_raw(bytes: &[u8]) -> Result<Feed> {
+	match panic::catch_unwind(AssertUnwindSafe(|| feed_rs::parser::parse(bytes))) {
+		Ok(feed) => Ok(feed?),
+		Err(_) => bail!("Feed parser panicked"),
+	}
+}
+
+/// Skip anything before the root of a feed in the given format, e.g. server
+/// warnings printed before the actual feed.
+fn skip_to_root(bytes: &[u8], format: Option<FeedFormat>) -> &[u8] {
+	let root = match format {
+		Some(FeedFormat::Rss2 | FeedFormat::Atom) => b'<',
+		Some(FeedFormat::Json) => b'{',
+		None => return bytes,
+	};
+	bytes.iter().position(|byte| *byte == root).map_or(bytes, |start| &bytes[start..])
+}
+
+/// Whether the parsed feed type is the given format.
+fn format_matches(format: FeedFormat, feed_type: &FeedType) -> bool {
+	matches!(
+		(format, feed_type),
+		(FeedFormat::Rss2, FeedType::RSS2)
+			| (FeedFormat::Atom, FeedType::Atom)
+			| (FeedFormat::Json, FeedType::JSON)
+	)
+}
+
+/// Clean up common mistakes of broken feeds: Leading whitespace before the
+/// XML declaration and ampersands that do not start a character or entity
+/// reference.
+fn clean_up(bytes: &[u8]) -> Vec<u8> {
+	let start = bytes.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(bytes.len());
+	let bytes = &bytes[start..];
+
+	let mut cleaned = Vec::with_capacity(bytes.len());
+	for (i, byte) in bytes.iter().enumerate() {
+		cleaned.push(*byte);
+		if *byte == b'&' && !starts_reference(&bytes[i + 1..]) {
+			cleaned.extend_from_slice(b"amp;");
+		}
+	}
+	cleaned
+}
+
+/// Whether the bytes following an ampersand form a character or XML entity
+/// reference.
+fn starts_reference(bytes: &[u8]) -> bool {
+	let end = match bytes.iter().take(10).position(|byte| *byte == b';') {
+		Some(end) => end,
+		None => return false,
+	};
+	match &bytes[..end] {
+		b"amp" | b"lt" | b"gt" | b"quot" | b"apos" => true,
+		[b'#', b'x' | b'X', hex @ ..] => !hex.is_empty() && hex.iter().all(u8::is_ascii_hexdigit),
+		[b'#', digits @ ..] => !digits.is_empty() && digits.iter().all(u8::is_ascii_digit),
+		_ => false,
+	}
+}
+
+/// Test a URL whether it is allowed and gives a parsable RSS feed, fetching it
+/// with the credentials if given.
+pub async fn test_feed_url(
+	url: Url,
+	user_agent: &str,
+	options: ParseOptions,
+	auth: Option<&FeedAuth>,
+	fetch: &FetchSettings,
+) -> Result<()> {
+	let client = GuardedClient::new(user_agent, fetch)?;
+	if let Err(err) = fetch_feed(&client, url.clone(), options, auth).await {
+		tracing::debug!("Feed {url} is not valid: {err}");
+		return Err(err);
+	}
+	Ok(())
+}
+
+/// Check all the feeds, with bounded concurrency and a timeout per feed.
+/// Returns the results in the order of the given URLs.
+pub async fn validate_feeds(
+	feeds: Vec<(Url, ParseOptions, Option<FeedAuth>)>,
+	user_agent: &str,
+	fetch: &FetchSettings,
+) -> Vec<(Url, Result<()>)> {
+	let semaphore = Arc::new(Semaphore::new(VALIDATE_CONCURRENCY));
+	let user_agent: Arc<str> = Arc::from(user_agent);
+	let fetch = Arc::new(fetch.clone());
+	let handles = feeds
+		.into_iter()
+		.map(|(url, options, auth)| {
+			let semaphore = semaphore.clone();
+			let user_agent = user_agent.clone();
+			let fetch = fetch.clone();
+			let handle = tokio::spawn({
+				let url = url.clone();
+				async move {
+					let _permit = semaphore.acquire_owned().await?;
+					tokio::time::timeout(
+						VALIDATE_TIMEOUT,
+						test_feed_url(url, &user_agent, options, auth.as_ref(), &fetch),
+					)
+					.await
+					.map_err(|_| eyre!("Timed out after {}s", VALIDATE_TIMEOUT.as_secs()))?
+				}
+			});
+			(url, handle)
+		})
+		.collect::<Vec<_>>();
+
+	let mut results = Vec::with_capacity(handles.len());
+	for (url, handle) in handles {
+		let result = handle.await.map_err(Into::into).and_then(|result| result);
+		results.push((url, result));
+	}
+	results
+}
+
+/// Whether the given content length exceeds the maximum feed size.
+fn exceeds_max_size(len: u64) -> bool {
+	usize::try_from(len).map_or(true, |len| len > MAX_FEED_SIZE)
+}
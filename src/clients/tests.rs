@@ -0,0 +1,639 @@
+//! Tests for the API clients.
+
+use std::{
+	borrow::Cow,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+};
+
+use color_eyre::Result;
+use time::OffsetDateTime;
+
+use super::*;
+use crate::{
+	clients::{
+		github::{DeviceCode, DeviceFlow, DevicePoll, Github, RetryPolicy},
+		rss::ParseOptions,
+	},
+	database::{FeedAuth, FeedFormat},
+	settings::FetchSettings,
+	test_utils::{local_http_client, MockResponse, MockServer},
+};
+
+#[tokio::test]
+async fn http_client_sends_user_agent() -> Result<()> {
+	let server = MockServer::start(|_| MockResponse::new(200, "ok")).await?;
+
+	let fetch = FetchSettings { allow_private_hosts: true, ..FetchSettings::default() };
+	let client = GuardedClient::new("test-agent/1.0 (+https://example.com)", &fetch)?;
+	client.get(server.url().clone(), |request, _| request).await?.error_for_status()?;
+
+	let requests = server.requests();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(requests[0].header("User-Agent"), Some("test-agent/1.0 (+https://example.com)"));
+	Ok(())
+}
+
+/// A minimal valid RSS feed.
+const VALID_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>Test feed</title>
+	<link>https://example.com/</link>
+	<description>Test feed</description>
+	<item>
+		<title>Entry</title>
+		<link>https://example.com/entry</link>
+		<pubDate>Mon, 03 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+</channel>
+</rss>"#;
+
+#[test]
+fn parse_broken_feed_is_error() {
+	assert!(rss::parse_feed(VALID_FEED.as_bytes()).is_ok());
+	assert!(rss::parse_feed(b"<rss><channel><title>Broken</").is_err());
+	assert!(rss::parse_feed(b"\0\0garbage").is_err());
+	assert!(rss::parse_feed(&vec![b' '; 11 * 1024 * 1024]).is_err());
+}
+
+/// An RSS feed with unescaped ampersands and a warning printed before it.
+const MALFORMED_FEED: &str = r#"Warning: {cache} not writable
+<rss version="2.0">
+<channel>
+	<title>Tom & Jerry</title>
+	<link>https://example.com/</link>
+	<description>Cats &amp; mice</description>
+	<item>
+		<title>Entry &#8211; one</title>
+		<link>https://example.com/entry?a=1&b=2</link>
+	</item>
+</channel>
+</rss>"#;
+
+#[test]
+fn malformed_feed_parses_leniently() -> Result<()> {
+	let strict = ParseOptions { format: Some(FeedFormat::Rss2), lenient: false };
+	assert!(rss::parse_feed_with(MALFORMED_FEED.as_bytes(), strict).is_err());
+
+	let lenient = ParseOptions { format: Some(FeedFormat::Rss2), lenient: true };
+	let feed = rss::parse_feed_with(MALFORMED_FEED.as_bytes(), lenient)?;
+	assert_eq!(feed.title.map(|title| title.content).as_deref(), Some("Tom & Jerry"));
+	assert_eq!(feed.entries.len(), 1);
+	assert_eq!(feed.entries[0].links[0].href, "https://example.com/entry?a=1&b=2");
+
+	let atom = ParseOptions { format: Some(FeedFormat::Atom), lenient: true };
+	assert!(rss::parse_feed_with(MALFORMED_FEED.as_bytes(), atom).is_err());
+	Ok(())
+}
+
+#[tokio::test]
+async fn fetch_broken_feed_is_error() -> Result<()> {
+	let server = MockServer::start(|request| {
+		if request.path == "/broken" {
+			MockResponse::new(200, "<rss><channel><item><title>Broken")
+		} else {
+			MockResponse::new(200, VALID_FEED)
+		}
+	})
+	.await?;
+	let client = local_http_client()?;
+
+	assert!(rss::fetch_feed(&client, server.url().join("broken")?, ParseOptions::default(), None)
+		.await
+		.is_err());
+	let feed = rss::fetch_feed(&client, server.url().join("valid")?, ParseOptions::default(), None)
+		.await?;
+	assert_eq!(feed.entries.len(), 1);
+	Ok(())
+}
+
+/// [`VALID_FEED`] compressed with gzip.
+const GZIP_FEED: &[u8] = &[
+	0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x7d, 0x90, 0xbb, 0x0e, 0xc2, 0x30,
+	0x0c, 0x45, 0x67, 0xf8, 0x8a, 0xa8, 0x33, 0xe0, 0x50, 0x16, 0x84, 0x4c, 0x58, 0x78, 0x4c, 0x88,
+	0xa5, 0x7c, 0x40, 0x49, 0x0d, 0x44, 0xa4, 0x69, 0xd5, 0x18, 0x04, 0x7f, 0x8f, 0xa1, 0xbc, 0x26,
+	0x24, 0x4b, 0x96, 0xae, 0x8f, 0x7c, 0x64, 0xe3, 0xec, 0x5a, 0x7a, 0x75, 0xa1, 0x26, 0xba, 0x2a,
+	0x4c, 0x93, 0xe1, 0x40, 0x27, 0x8a, 0x82, 0xad, 0x0a, 0x17, 0x0e, 0xd3, 0x64, 0x9b, 0x2d, 0xfb,
+	0xe3, 0x64, 0x66, 0xba, 0xd8, 0xc4, 0xf8, 0xa5, 0x52, 0xa1, 0x24, 0xb3, 0xc7, 0x3c, 0x04, 0xf2,
+	0xa6, 0xdb, 0x41, 0x76, 0xec, 0xc9, 0x64, 0x14, 0x59, 0xed, 0x89, 0x0a, 0x84, 0x36, 0x90, 0x89,
+	0x77, 0xe1, 0x64, 0x8e, 0xcc, 0x75, 0x9c, 0x00, 0xd0, 0x35, 0x2f, 0x6b, 0x4f, 0x03, 0x5b, 0x95,
+	0x80, 0xf0, 0x1c, 0x09, 0x52, 0x50, 0xb4, 0x8d, 0xab, 0x59, 0x76, 0xff, 0xae, 0xf8, 0x8d, 0x85,
+	0x72, 0x4c, 0xa5, 0xf4, 0xb7, 0x6b, 0x11, 0xb8, 0xb9, 0x7d, 0x3d, 0x7f, 0x44, 0xd4, 0x92, 0x2f,
+	0x5b, 0x07, 0xeb, 0xf3, 0x6e, 0x9e, 0x33, 0x99, 0x75, 0x15, 0x7a, 0x4a, 0x8f, 0xd4, 0xc6, 0xb2,
+	0x4a, 0x75, 0x9a, 0xaa, 0xa1, 0x9e, 0xe8, 0x47, 0xa9, 0xd5, 0x3a, 0x43, 0x78, 0x63, 0xa2, 0x86,
+	0xd6, 0x8d, 0xf0, 0xb9, 0x18, 0x41, 0x1e, 0x62, 0xee, 0xda, 0xea, 0xba, 0x27, 0x3d, 0x01, 0x00,
+	0x00,
+];
+
+#[tokio::test]
+async fn fetch_gzip_encoded_feed() -> Result<()> {
+	let server = MockServer::start(|request| {
+		if request.header("Accept-Encoding").map_or(false, |encodings| encodings.contains("gzip")) {
+			MockResponse::new(200, GZIP_FEED).header("Content-Encoding", "gzip")
+		} else {
+			MockResponse::new(406, "compression required")
+		}
+	})
+	.await?;
+
+	let client = local_http_client()?;
+	let feed =
+		rss::fetch_feed(&client, server.url().clone(), ParseOptions::default(), None).await?;
+	assert_eq!(feed.entries.len(), 1);
+	assert_eq!(feed.title.map(|title| title.content).as_deref(), Some("Test feed"));
+	Ok(())
+}
+
+/// Encode an RSS feed with one entry of the given title as windows-1252,
+/// starting with the XML declaration.
+fn windows_1252_feed(declaration: &str, title: &str) -> Vec<u8> {
+	let feed = format!(
+		"{declaration}<rss version=\"2.0\"><channel><title>Test feed</title><item>\
+		 <title>{title}</title><link>https://example.com/1</link></item></channel></rss>"
+	);
+	encoding_rs::WINDOWS_1252.encode(&feed).0.into_owned()
+}
+
+/// Title of the first entry of the feed.
+fn first_title(feed: &feed_rs::model::Feed) -> Option<&str> {
+	feed.entries.first().and_then(|entry| entry.title.as_ref()).map(|title| title.content.as_str())
+}
+
+#[test]
+fn declared_encoding_is_transcoded() -> Result<()> {
+	let bytes =
+		windows_1252_feed(r#"<?xml version="1.0" encoding="windows-1252"?>"#, "Café – naïve");
+	let transcoded = rss::transcode(&bytes, Some("utf-8"));
+	assert!(String::from_utf8_lossy(&transcoded)
+		.starts_with(r#"<?xml version="1.0" encoding="UTF-8"?>"#));
+
+	let feed = rss::parse_feed(&bytes)?;
+	assert_eq!(first_title(&feed), Some("Café – naïve"));
+	Ok(())
+}
+
+#[test]
+fn utf8_and_json_feeds_are_unchanged() {
+	let utf8 = r#"<?xml version="1.0" encoding="UTF-8"?><rss/>"#.as_bytes();
+	assert!(matches!(rss::transcode(utf8, Some("iso-8859-1")), Cow::Borrowed(_)));
+	let json = r#"{"version":"https://jsonfeed.org/version/1.1"}"#.as_bytes();
+	assert!(matches!(rss::transcode(json, Some("iso-8859-1")), Cow::Borrowed(_)));
+}
+
+#[tokio::test]
+async fn fetched_feed_is_transcoded_by_charset() -> Result<()> {
+	let server = MockServer::start(|_| {
+		MockResponse::new(200, windows_1252_feed("", "Ärger über Öl"))
+			.header("Content-Type", "application/rss+xml; charset=windows-1252")
+	})
+	.await?;
+
+	let client = local_http_client()?;
+	let feed =
+		rss::fetch_feed(&client, server.url().clone(), ParseOptions::default(), None).await?;
+	assert_eq!(first_title(&feed), Some("Ärger über Öl"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn internal_urls_are_rejected() -> Result<()> {
+	let fetch = FetchSettings::default();
+	for url in [
+		"file:///etc/passwd",
+		"ftp://example.com/feed",
+		"http://169.254.169.254/latest/meta-data/",
+		"http://127.0.0.1:8080/feed",
+		"http://localhost/feed",
+		"http://10.0.0.1/feed",
+		"http://192.168.1.1/feed",
+		"http://100.64.0.1/feed",
+		"http://[::1]/feed",
+		"http://[fd00::1]/feed",
+		"http://[::ffff:127.0.0.1]/feed",
+	] {
+		assert!(check_url(&Url::parse(url)?, &fetch).await.is_err(), "{url} was allowed");
+	}
+
+	assert!(check_url(&Url::parse("https://1.1.1.1/feed")?, &fetch).await.is_ok());
+	Ok(())
+}
+
+#[tokio::test]
+async fn configured_hosts_are_enforced() -> Result<()> {
+	let fetch = FetchSettings {
+		allow_private_hosts: true,
+		allowed_hosts: vec![String::from("example.com")],
+		denied_hosts: vec![String::from("private.example.com")],
+		..FetchSettings::default()
+	};
+	assert!(check_url(&Url::parse("https://example.com/feed")?, &fetch).await.is_ok());
+	assert!(check_url(&Url::parse("https://blog.example.com/feed")?, &fetch).await.is_ok());
+	assert!(check_url(&Url::parse("https://notexample.com/feed")?, &fetch).await.is_err());
+	assert!(check_url(&Url::parse("https://feeds.private.example.com/")?, &fetch).await.is_err());
+
+	let fetch = FetchSettings { allow_private_hosts: true, ..FetchSettings::default() };
+	assert!(check_url(&Url::parse("http://127.0.0.1/feed")?, &fetch).await.is_ok());
+	Ok(())
+}
+
+/// Device code polled immediately and expiring after a minute.
+fn device_code() -> DeviceCode {
+	DeviceCode {
+		device_code: String::from("device"),
+		user_code: String::from("ABCD-1234"),
+		verification_uri: String::from("https://github.com/login/device"),
+		expires_in: 60,
+		interval: 0,
+	}
+}
+
+#[tokio::test]
+async fn device_flow_polls_until_authorized() -> Result<()> {
+	let polls = Arc::new(AtomicUsize::new(0));
+	let counter = polls.clone();
+	let server = MockServer::start(move |request| match request.path.as_str() {
+		"/login/device/code" => MockResponse::new(
+			200,
+			r#"{"device_code":"device","user_code":"ABCD-1234","verification_uri":
+			"https://github.com/login/device","expires_in":900,"interval":5}"#,
+		),
+		"/login/oauth/access_token" => match counter.fetch_add(1, Ordering::SeqCst) {
+			0 => MockResponse::new(200, r#"{"error":"authorization_pending"}"#),
+			1 => MockResponse::new(200, r#"{"error":"slow_down","interval":0}"#),
+			_ => MockResponse::new(200, r#"{"access_token":"secret","token_type":"bearer"}"#),
+		},
+		_ => MockResponse::new(404, "not found"),
+	})
+	.await?;
+	let flow = DeviceFlow::new(String::from("client"), &local_http_client()?)?
+		.with_base_url(server.url().clone());
+
+	let code = flow.request_code().await?;
+	assert_eq!(code.user_code, "ABCD-1234");
+	assert_eq!((code.expires_in, code.interval), (900, 5));
+
+	let result = flow.wait_for_token(&device_code()).await?;
+	assert_eq!(result, DevicePoll::Authorized(String::from("secret")));
+	assert_eq!(polls.load(Ordering::SeqCst), 3);
+	let body = String::from_utf8_lossy(&server.requests()[1].body).into_owned();
+	assert!(body.contains("client_id=client") && body.contains("device_code=device"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn device_flow_stops_when_denied_or_expired() -> Result<()> {
+	let server = MockServer::start(|request| {
+		if String::from_utf8_lossy(&request.body).contains("device_code=denied") {
+			MockResponse::new(200, r#"{"error":"access_denied"}"#)
+		} else {
+			MockResponse::new(200, r#"{"error":"expired_token"}"#)
+		}
+	})
+	.await?;
+	let flow = DeviceFlow::new(String::from("client"), &local_http_client()?)?
+		.with_base_url(server.url().clone());
+
+	let denied = DeviceCode { device_code: String::from("denied"), ..device_code() };
+	assert_eq!(flow.wait_for_token(&denied).await?, DevicePoll::Denied);
+	assert_eq!(flow.wait_for_token(&device_code()).await?, DevicePoll::Expired);
+
+	let expired = DeviceCode { expires_in: 0, ..device_code() };
+	let requests = server.requests().len();
+	assert_eq!(flow.wait_for_token(&expired).await?, DevicePoll::Expired);
+	assert_eq!(server.requests().len(), requests);
+	Ok(())
+}
+
+/// Github notification API response for testing.
+fn notification_json(id: &str, updated_at: &str) -> serde_json::Value {
+	serde_json::json!({
+		"id": id,
+		"last_read_at": null,
+		"reason": "mention",
+		"repository": {
+			"description": null,
+			"fork": false,
+			"full_name": "owner/repo",
+			"html_url": "https://github.com/owner/repo",
+			"id": 1,
+			"node_id": "R_1",
+			"name": "repo",
+			"private": false,
+			"url": "https://api.github.com/repos/owner/repo",
+		},
+		"subject": {
+			"latest_comment_url": null,
+			"title": "Fix the bug",
+			"type": "Issue",
+			"url": null,
+		},
+		"subscription_url": "https://api.github.com/notifications/threads/1/subscription",
+		"unread": true,
+		"updated_at": updated_at,
+		"url": "https://api.github.com/notifications/threads/1",
+	})
+}
+
+#[tokio::test]
+async fn notifications_follow_pagination_links() -> Result<()> {
+	let first = serde_json::to_vec(&[notification_json("2", "2022-10-05T11:00:00Z")])?;
+	let second = serde_json::to_vec(&[notification_json("1", "2022-10-05T10:00:00Z")])?;
+	let server = MockServer::start(move |request| {
+		if request.path.contains("page=2") {
+			MockResponse::new(200, second.clone())
+		} else {
+			let host = request.header("Host").unwrap_or_default();
+			MockResponse::new(200, first.clone())
+				.header(
+					"Link",
+					&format!(
+						"<http://{host}/notifications?page=2>; rel=\"next\", \
+						 <http://{host}/notifications?page=2>; rel=\"last\""
+					),
+				)
+				.header("X-Poll-Interval", "60")
+		}
+	})
+	.await?;
+	let mut github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+
+	let notifications = github.notifications(OffsetDateTime::UNIX_EPOCH, 10).await?;
+	let ids = notifications.iter().map(|notification| notification.id.as_str()).collect::<Vec<_>>();
+	assert_eq!(ids, ["1", "2"]);
+	assert!(!github.next_request_allowed());
+	assert_eq!(server.requests().len(), 2);
+
+	let notifications = github.notifications(OffsetDateTime::UNIX_EPOCH, 1).await?;
+	assert_eq!(notifications.len(), 1);
+	assert_eq!(notifications[0].id, "2");
+	assert_eq!(server.requests().len(), 3);
+	Ok(())
+}
+
+#[tokio::test]
+async fn pagination_links_to_other_origins_are_not_followed() -> Result<()> {
+	let page = serde_json::to_vec(&[notification_json("2", "2022-10-05T11:00:00Z")])?;
+	let server = MockServer::start(move |request| {
+		// The same server, but on another origin than the API URL.
+		let port = request.header("Host").and_then(|host| host.rsplit(':').next()).unwrap_or("80");
+		MockResponse::new(200, page.clone()).header(
+			"Link",
+			&format!("<http://localhost:{port}/notifications?page=2>; rel=\"next\""),
+		)
+	})
+	.await?;
+	let mut github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+
+	let notifications = github.notifications(OffsetDateTime::UNIX_EPOCH, 10).await?;
+	assert_eq!(notifications.len(), 1);
+	assert_eq!(server.requests().len(), 1);
+	Ok(())
+}
+
+#[tokio::test]
+async fn exhausted_rate_limit_delays_requests() -> Result<()> {
+	let reset = OffsetDateTime::now_utc().unix_timestamp() + 3600;
+	let server = MockServer::start(move |request| {
+		// Basic authentication of the user "second".
+		if request.header("Authorization").map_or(false, |auth| auth.contains("c2Vjb25k")) {
+			MockResponse::new(429, "").header("Retry-After", "120")
+		} else {
+			MockResponse::new(403, "")
+				.header("X-RateLimit-Remaining", "0")
+				.header("X-RateLimit-Reset", &reset.to_string())
+		}
+	})
+	.await?;
+
+	let mut github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+	assert!(github.next_request_allowed());
+	assert!(github.notifications(OffsetDateTime::UNIX_EPOCH, 1).await.is_err());
+	assert!(!github.next_request_allowed());
+	assert_eq!(github.rate_limit_remaining(), Some(0));
+
+	let mut github = Github::new(
+		"second".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+	assert!(github.notifications(OffsetDateTime::UNIX_EPOCH, 1).await.is_err());
+	assert!(!github.next_request_allowed());
+	assert_eq!(github.rate_limit_remaining(), None);
+	Ok(())
+}
+
+#[tokio::test]
+async fn repo_notifications_use_repo_endpoint() -> Result<()> {
+	let page = serde_json::to_vec(&[notification_json("1", "2022-10-05T10:00:00Z")])?;
+	let server = MockServer::start(move |_| MockResponse::new(200, page.clone())).await?;
+	let mut github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+
+	let notifications =
+		github.repo_notifications("owner/repo", OffsetDateTime::UNIX_EPOCH, 1).await?;
+	assert_eq!(notifications.len(), 1);
+	let requests = server.requests();
+	assert_eq!(requests.len(), 1);
+	assert!(requests[0].path.starts_with("/repos/owner/repo/notifications?"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn repo_scoped_requests_use_repo_endpoint() -> Result<()> {
+	let page = serde_json::to_vec(&[notification_json("1", "2022-10-05T10:00:00Z")])?;
+	let server = MockServer::start(move |request| match request.method.as_str() {
+		"GET" => MockResponse::new(200, page.clone()),
+		_ => MockResponse::new(205, ""),
+	})
+	.await?;
+	let github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+
+	let notification = github.latest_notification(Some("owner/repo")).await?;
+	assert_eq!(notification.map(|notification| notification.id), Some(String::from("1")));
+	github.mark_read(Some("owner/repo"), OffsetDateTime::UNIX_EPOCH).await?;
+	github.mark_read(None, OffsetDateTime::UNIX_EPOCH).await?;
+
+	let requests = server.requests();
+	assert!(requests[0].path.starts_with("/repos/owner/repo/notifications?"));
+	assert_eq!(
+		(requests[1].method.as_str(), requests[1].path.as_str()),
+		("PUT", "/repos/owner/repo/notifications")
+	);
+	assert_eq!(requests[2].path, "/notifications");
+	Ok(())
+}
+
+#[tokio::test]
+async fn unavailable_server_is_retried() -> Result<()> {
+	let attempts = Arc::new(AtomicUsize::new(0));
+	let counter = attempts.clone();
+	let server = MockServer::start(move |request| match request.path.as_str() {
+		"/notifications/threads/1" => match counter.fetch_add(1, Ordering::SeqCst) {
+			0 => MockResponse::new(500, ""),
+			1 => MockResponse::new(502, ""),
+			_ => MockResponse::new(205, ""),
+		},
+		"/notifications/threads/2" => MockResponse::new(503, ""),
+		_ => MockResponse::new(404, ""),
+	})
+	.await?;
+	let retry = RetryPolicy { retries: 2, base_delay: std::time::Duration::from_millis(1) };
+	let github = Github::new("user".to_owned(), "token".to_owned(), &local_http_client()?, retry)?
+		.with_base_url(server.url().clone());
+
+	github.mark_thread_read("1").await?;
+	assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+	assert!(github.mark_thread_read("2").await.is_err());
+	assert_eq!(server.requests().len(), 6);
+
+	assert!(github.mark_thread_read("3").await.is_err());
+	assert_eq!(server.requests().len(), 7);
+	Ok(())
+}
+
+#[tokio::test]
+async fn failing_feed_fetches_are_retried() -> Result<()> {
+	let attempts = Arc::new(AtomicUsize::new(0));
+	let counter = attempts.clone();
+	let server = MockServer::start(move |request| match request.path.as_str() {
+		"/flaky" => match counter.fetch_add(1, Ordering::SeqCst) {
+			0 => MockResponse::new(500, ""),
+			1 => MockResponse::new(503, ""),
+			_ => MockResponse::new(200, VALID_FEED),
+		},
+		"/down" => MockResponse::new(500, ""),
+		"/broken" => MockResponse::new(200, "<rss><channel><item><title>Broken"),
+		_ => MockResponse::new(404, ""),
+	})
+	.await?;
+	let client = local_http_client()?;
+	let retry = RetryPolicy { retries: 2, base_delay: std::time::Duration::from_millis(1) };
+	let fetch = |path: &str| {
+		let url = server.url().join(path);
+		let client = client.clone();
+		async move {
+			let validators = rss::CacheValidators::default();
+			let options = ParseOptions::default();
+			rss::fetch_feed_with_retries(&client, url?, options, None, &validators, retry).await
+		}
+	};
+
+	assert!(fetch("flaky").await?.is_some());
+	assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+	assert!(fetch("down").await.is_err());
+	assert_eq!(server.requests().len(), 6);
+
+	assert!(fetch("broken").await.is_err());
+	assert!(fetch("missing").await.is_err());
+	assert_eq!(server.requests().len(), 8);
+	Ok(())
+}
+
+#[tokio::test]
+async fn protected_feed_is_fetched_with_credentials() -> Result<()> {
+	let server = MockServer::start(|request| match request.header("Authorization") {
+		Some("Basic dXNlcjpodW50ZXIy" | "Bearer token") => MockResponse::new(200, VALID_FEED),
+		_ => MockResponse::new(401, ""),
+	})
+	.await?;
+	let client = local_http_client()?;
+	let url = server.url().clone();
+	let options = ParseOptions::default();
+
+	assert!(rss::fetch_feed(&client, url.clone(), options, None).await.is_err());
+	let basic =
+		FeedAuth::Basic { username: String::from("user"), password: Some(String::from("hunter2")) };
+	rss::fetch_feed(&client, url.clone(), options, Some(&basic)).await?;
+	let bearer = FeedAuth::Bearer(String::from("token"));
+	rss::fetch_feed(&client, url, options, Some(&bearer)).await?;
+	Ok(())
+}
+
+#[tokio::test]
+async fn hanging_fetches_time_out() -> Result<()> {
+	// Accept connections, but never respond.
+	let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+	let url = Url::parse(&format!("http://{}/feed", listener.local_addr()?))?;
+	let server = tokio::spawn(async move {
+		let mut connections = Vec::new();
+		while let Ok((connection, _)) = listener.accept().await {
+			connections.push(connection);
+		}
+	});
+	let fetch = FetchSettings { allow_private_hosts: true, timeout: 1, ..FetchSettings::default() };
+	let client = GuardedClient::new("test-agent", &fetch)?;
+
+	let result = client.get(url, |request, _| request).await;
+	server.abort();
+	let err = result.err().ok_or_else(|| eyre!("Fetch did not time out"))?;
+	assert!(err.downcast_ref::<reqwest::Error>().map_or(false, reqwest::Error::is_timeout));
+	Ok(())
+}
+
+#[tokio::test]
+async fn redirects_to_internal_hosts_are_refused() -> Result<()> {
+	let server = MockServer::start(|request| match request.path.as_str() {
+		"/feed" => MockResponse::new(200, VALID_FEED),
+		"/moved" => MockResponse::new(301, "").header("Location", "/feed"),
+		"/loopback" => MockResponse::new(302, "").header("Location", "http://localhost/feed"),
+		"/metadata" => {
+			MockResponse::new(302, "").header("Location", "http://169.254.169.254/latest/meta-data")
+		}
+		_ => MockResponse::new(404, ""),
+	})
+	.await?;
+	// Only the mock server's address is allowed, as if it was a public host.
+	let fetch = FetchSettings {
+		allow_private_hosts: true,
+		allowed_hosts: vec![String::from("127.0.0.1")],
+		..FetchSettings::default()
+	};
+	let client = GuardedClient::new("test-agent", &fetch)?;
+	let options = ParseOptions::default();
+
+	let feed = rss::fetch_feed(&client, server.url().join("moved")?, options, None).await?;
+	assert_eq!(feed.entries.len(), 1);
+	for path in ["loopback", "metadata"] {
+		let result = rss::fetch_feed(&client, server.url().join(path)?, options, None).await;
+		assert!(result.is_err(), "redirect of /{path} was followed");
+	}
+	let paths = server.requests().into_iter().map(|request| request.path).collect::<Vec<_>>();
+	assert_eq!(paths, ["/moved", "/feed", "/loopback", "/metadata"]);
+	Ok(())
+}
@@ -0,0 +1,312 @@
+//! Administration commands for bot operators.
+
+use clap::{Args, Subcommand};
+use color_eyre::Result;
+use matrix_sdk::{
+	async_trait,
+	ruma::{
+		api::client::{device::Device, uiaa},
+		events::room::message::RoomMessageEventContent,
+		DeviceId, OwnedDeviceId, OwnedRoomId, RoomId,
+	},
+	Client,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use super::{BotCommand, Context};
+use crate::{
+	database::AuditEntry,
+	maintenance,
+	settings::{LoginSettings, Settings},
+};
+
+/// Admin command.
+#[derive(Debug, Args)]
+pub struct Admin {
+	/// Admin command to execute.
+	#[clap(subcommand)]
+	command: SubCommand,
+}
+
+/// Which admin sub-command to execute.
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+	/// Show the effective configuration with secrets redacted.
+	Config,
+	/// Compact the databases in the background.
+	Compact,
+	/// Back up the databases into the configured backup directory in the
+	/// background.
+	Backup,
+	/// Force a full re-sync of the room state in the background, then leave
+	/// empty rooms and process pending invites again.
+	Resync,
+	/// Show the latest audit log entries.
+	Audit {
+		/// Number of entries to show.
+		#[arg(default_value_t = 20)]
+		count: usize,
+		/// Room to show the audit log of, defaults to this room.
+		#[arg(long)]
+		room: Option<OwnedRoomId>,
+	},
+	/// List the devices of the bot's account.
+	Devices {
+		/// What to do with the devices.
+		#[command(subcommand)]
+		action: Option<DeviceAction>,
+	},
+}
+
+/// Action on the devices of the bot's account.
+#[derive(Debug, Subcommand)]
+enum DeviceAction {
+	/// Revoke a stale device, logging out its session.
+	Revoke {
+		/// ID of the device to revoke.
+		device: String,
+	},
+}
+
+#[async_trait]
+impl BotCommand for Admin {
+	async fn execute<'a>(&mut self, context: Context<'a>) -> Result<()> {
+		if !context.is_admin() || !context.room.is_direct() {
+			tracing::trace!("Person not allowed to use admin commands or not in a direct chat!");
+			return Ok(());
+		}
+
+		match &self.command {
+			SubCommand::Config => config(&context).await,
+			SubCommand::Audit { count, room } => audit(&context, *count, room.as_deref()).await,
+			SubCommand::Devices { action } => devices(&context, action.as_ref()).await,
+			SubCommand::Compact => compact(&context).await,
+			SubCommand::Backup => backup(&context).await,
+			SubCommand::Resync => resync(&context).await,
+		}
+	}
+}
+
+/// Show the effective configuration.
+async fn config(context: &Context<'_>) -> Result<()> {
+	let msg = RoomMessageEventContent::text_markdown(format_config(context.config))
+		.make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Show the latest audit log entries of the room, defaulting to this room.
+async fn audit(context: &Context<'_>, count: usize, room: Option<&RoomId>) -> Result<()> {
+	let room_id = room.unwrap_or_else(|| context.room.room_id());
+	let entries = AuditEntry::latest_for_room(room_id, count, &context.db.state).await?;
+	let msg =
+		RoomMessageEventContent::text_markdown(format_audit(&entries)).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// List the devices of the bot's account or execute the action on them.
+async fn devices(context: &Context<'_>, action: Option<&DeviceAction>) -> Result<()> {
+	let devices = context.client.devices().await?.devices;
+	let current = context.client.device_id();
+	let text = match action {
+		None => format_devices(&devices, current),
+		Some(DeviceAction::Revoke { device }) => {
+			let device: OwnedDeviceId = device.as_str().into();
+			match check_revoke(&device, &devices, current) {
+				Ok(()) => {
+					delete_device(context.client, device.clone(), &context.config.login).await?;
+					context.audit(format!("revoked device {device}")).await?;
+					format!("Revoked device `{device}`.")
+				}
+				Err(err) => err.to_owned(),
+			}
+		}
+	};
+	let msg = RoomMessageEventContent::text_markdown(text).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Compact the databases in the background and reply with the result.
+async fn compact(context: &Context<'_>) -> Result<()> {
+	let config = context.config.clone();
+	let databases = context.db.clone();
+	let room = context.room.clone();
+	let event = context.event.clone();
+	tokio::spawn(async move {
+		let text = match maintenance::compact(&config, &databases).await {
+			Ok(report) => format!("Compacted databases, reclaimed {} bytes.", report.reclaimed()),
+			Err(err) => format!("Compacting databases failed: {err}"),
+		};
+		let msg = RoomMessageEventContent::text_plain(text).make_reply_to(&event);
+		if let Err(err) = room.send(msg, None).await {
+			tracing::error!("Failed sending compaction result: {err}");
+		}
+	});
+
+	let msg = RoomMessageEventContent::text_plain("Started compacting databases.")
+		.make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Back up the databases in the background and reply with the result.
+async fn backup(context: &Context<'_>) -> Result<()> {
+	let backup_dir = match &context.config.store.backup_dir {
+		Some(backup_dir) => backup_dir.clone(),
+		None => {
+			let msg = RoomMessageEventContent::text_plain("No backup directory configured.")
+				.make_reply_to(context.event);
+			context.room.send(msg, None).await?;
+			return Ok(());
+		}
+	};
+	context.audit("started database backup").await?;
+
+	let databases = context.db.clone();
+	let room = context.room.clone();
+	let event = context.event.clone();
+	tokio::spawn(async move {
+		let text = match maintenance::backup(&databases, &backup_dir).await {
+			Ok(path) => format!("Backed up databases to `{}`.", path.display()),
+			Err(err) => format!("Backing up databases failed: {err}"),
+		};
+		let msg = RoomMessageEventContent::text_markdown(text).make_reply_to(&event);
+		if let Err(err) = room.send(msg, None).await {
+			tracing::error!("Failed sending backup result: {err}");
+		}
+	});
+
+	let msg = RoomMessageEventContent::text_plain("Started backing up databases.")
+		.make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Re-sync the room state in the background and reply with the result.
+async fn resync(context: &Context<'_>) -> Result<()> {
+	context.audit("started full re-sync").await?;
+
+	let config = context.config.clone();
+	let databases = context.db.clone();
+	let client = context.client.clone();
+	let room = context.room.clone();
+	let event = context.event.clone();
+	tokio::spawn(async move {
+		let text = match crate::resync(&config, &databases, &client).await {
+			Ok(()) => String::from("Finished full re-sync."),
+			Err(err) => format!("Full re-sync failed: {err}"),
+		};
+		let msg = RoomMessageEventContent::text_plain(text).make_reply_to(&event);
+		if let Err(err) = room.send(msg, None).await {
+			tracing::error!("Failed sending re-sync result: {err}");
+		}
+	});
+
+	let msg =
+		RoomMessageEventContent::text_plain("Started full re-sync.").make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Format the configuration as markdown code block. Secrets are redacted by
+/// the [`Debug`] implementation of the settings.
+fn format_config(config: &Settings) -> String {
+	format!("```\n{config:#?}\n```")
+}
+
+/// Format audit log entries as markdown list.
+fn format_audit(entries: &[AuditEntry]) -> String {
+	if entries.is_empty() {
+		return String::from("There are no audit log entries for this room.");
+	}
+
+	entries
+		.iter()
+		.map(|entry| {
+			let at = entry.at.format(&Rfc3339).unwrap_or_else(|_| entry.at.to_string());
+			match &entry.who {
+				Some(who) => format!("- `{at}` {who}: {}", entry.action),
+				None => format!("- `{at}` {}", entry.action),
+			}
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Format the devices of the bot's account as markdown list, marking the
+/// device of the running session.
+fn format_devices(devices: &[Device], current: Option<&DeviceId>) -> String {
+	if devices.is_empty() {
+		return String::from("The account has no devices.");
+	}
+
+	devices
+		.iter()
+		.map(|device| {
+			let mut line = format!("- `{}`", device.device_id);
+			if let Some(name) = &device.display_name {
+				line.push_str(&format!(" {name}"));
+			}
+			let seen = device
+				.last_seen_ts
+				.and_then(|ts| ts.to_system_time())
+				.map(OffsetDateTime::from)
+				.and_then(|at| at.format(&Rfc3339).ok());
+			match (seen, &device.last_seen_ip) {
+				(Some(at), Some(ip)) => line.push_str(&format!(", last seen `{at}` from {ip}")),
+				(Some(at), None) => line.push_str(&format!(", last seen `{at}`")),
+				(None, Some(ip)) => line.push_str(&format!(", last seen from {ip}")),
+				(None, None) => {}
+			}
+			if current == Some(&*device.device_id) {
+				line.push_str(" (this session)");
+			}
+			line
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
+
+/// Check whether the device can be revoked. It has to be one of the account's
+/// devices and must not be the device of the running session.
+fn check_revoke(
+	device: &DeviceId,
+	devices: &[Device],
+	current: Option<&DeviceId>,
+) -> Result<(), &'static str> {
+	if current == Some(device) {
+		return Err("Refusing to revoke the device of the running session.");
+	}
+	if !devices.iter().any(|known| *known.device_id == *device) {
+		return Err("The account has no device with this ID.");
+	}
+	Ok(())
+}
+
+/// Delete a device of the bot's account. Authenticates with the configured
+/// password if the homeserver requires it.
+async fn delete_device(
+	client: &Client,
+	device: OwnedDeviceId,
+	login: &LoginSettings,
+) -> Result<()> {
+	let devices = [device];
+	if let Err(err) = client.delete_devices(&devices, None).await {
+		let info = match err.uiaa_response() {
+			Some(info) => info,
+			None => return Err(err.into()),
+		};
+		let mut password = uiaa::Password::new(
+			uiaa::UserIdentifier::UserIdOrLocalpart(&login.user),
+			&login.password,
+		);
+		password.session = info.session.as_deref();
+		client.delete_devices(&devices, Some(uiaa::AuthData::Password(password))).await?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests;
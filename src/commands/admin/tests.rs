@@ -0,0 +1,85 @@
+//! Tests for the admin commands.
+
+use color_eyre::Result;
+
+use super::*;
+
+#[test]
+fn config_redacts_secrets() -> Result<()> {
+	let config = Settings::read_from("config.sample.yaml")?;
+	let formatted = format_config(&config);
+
+	assert!(formatted.contains(&config.login.user));
+	assert!(!formatted.contains(&format!("{:?}", config.login.password)));
+	assert!(!formatted.contains(&format!("{:?}", config.store.passphrase)));
+	assert!(formatted.contains("<redacted>"));
+	Ok(())
+}
+
+#[test]
+fn audit_entries_formatted() -> Result<()> {
+	assert_eq!(format_audit(&[]), "There are no audit log entries for this room.");
+
+	let at = time::OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+	let entries = [
+		AuditEntry {
+			room: "!room:example.com".try_into()?,
+			who: Some("@user:example.com".try_into()?),
+			action: "!rss".to_owned(),
+			at,
+		},
+		AuditEntry {
+			room: "!room:example.com".try_into()?,
+			who: None,
+			action: "followed the room upgrade from !old:example.com".to_owned(),
+			at,
+		},
+	];
+	assert_eq!(
+		format_audit(&entries),
+		"- `2022-10-05T20:00:00Z` @user:example.com: !rss\n- `2022-10-05T20:00:00Z` followed the \
+		 room upgrade from !old:example.com"
+	);
+	Ok(())
+}
+
+/// Devices of the bot's account for testing.
+fn devices() -> Result<Vec<Device>> {
+	let devices = serde_json::from_value(serde_json::json!([
+		{
+			"device_id": "CURRENT",
+			"display_name": "Matrix-Bot",
+			"last_seen_ip": "192.0.2.1",
+			"last_seen_ts": 1_665_000_000_000_u64,
+		},
+		{ "device_id": "STALE" },
+	]))?;
+	Ok(devices)
+}
+
+#[test]
+fn devices_formatted() -> Result<()> {
+	assert_eq!(format_devices(&[], None), "The account has no devices.");
+
+	let current: OwnedDeviceId = "CURRENT".into();
+	assert_eq!(
+		format_devices(&devices()?, Some(&*current)),
+		"- `CURRENT` Matrix-Bot, last seen `2022-10-05T20:00:00Z` from 192.0.2.1 (this \
+		 session)\n- `STALE`"
+	);
+	Ok(())
+}
+
+#[test]
+fn revoking_is_restricted() -> Result<()> {
+	let devices = devices()?;
+	let current: OwnedDeviceId = "CURRENT".into();
+	let stale: OwnedDeviceId = "STALE".into();
+	let unknown: OwnedDeviceId = "UNKNOWN".into();
+
+	assert_eq!(check_revoke(&stale, &devices, Some(&*current)), Ok(()));
+	assert!(check_revoke(&current, &devices, Some(&*current)).is_err());
+	assert!(check_revoke(&unknown, &devices, Some(&*current)).is_err());
+	assert_eq!(check_revoke(&current, &devices, None), Ok(()));
+	Ok(())
+}
@@ -1,11 +1,28 @@
 //! Github notifications configuration to receive Github notificiations.
 
+use bonsaidb::core::document::CollectionDocument;
 use clap::{Args, Subcommand};
 use color_eyre::Result;
-use matrix_sdk::{async_trait, ruma::events::room::message::RoomMessageEventContent};
+use matrix_sdk::{
+	async_trait,
+	room::Joined,
+	ruma::{events::room::message::RoomMessageEventContent, OwnedUserId},
+};
+use time::OffsetDateTime;
+use url::Url;
 
-use super::{BotCommand, Context};
-use crate::{clients, database::GithubSubscription};
+use super::{exceeds_subscription_limit, BotCommand, Context};
+use crate::{
+	clients::{
+		self,
+		github::{DeviceCode, DeviceFlow, DevicePoll, Notification, RepositoryList, TokenInfo},
+		GuardedClient,
+	},
+	database::{AuditEntry, Databases, GithubSubscription, PostedNotification},
+	intervals::github::render_notification,
+	settings::Settings,
+	timestamps::TimestampFormatter,
+};
 
 /// Github command.
 #[derive(Debug, Args)]
@@ -15,6 +32,9 @@ pub struct Github {
 	command: SubCommand,
 }
 
+/// Maximum number of repositories listed in a reply.
+const MAX_LISTED_REPOS: usize = 50;
+
 /// Which Github sub-command to execute.
 #[derive(Debug, Subcommand)]
 enum SubCommand {
@@ -23,110 +43,558 @@ enum SubCommand {
 	/// Clear active Github notification subscriptions.
 	Clear,
 	/// Enable new Github notification subscription.
-	Enable {
+	Enable(EnableArgs),
+	/// Enable a new Github notification subscription by authorizing the bot
+	/// on Github, without sharing an access token in the room.
+	Login,
+	/// Disable Github notification subscription.
+	Disable {
 		/// Github login username.
 		username: String,
-		/// Github API token. Get one from <https://github.com/settings/tokens>.
-		/// It needs `notifications` and `repos` API scope.
-		token: String,
 	},
-	/// Disable Github notification subscription.
-	Disable {
+	/// Test the stored tokens of a Github notification subscription.
+	Test {
 		/// Github login username.
 		username: String,
 	},
+	/// Preview how the most recent Github notification of a subscription is
+	/// rendered. Does not mark it as read.
+	Preview {
+		/// Github login username.
+		username: String,
+	},
+	/// List the repositories the stored token of a subscription can access.
+	Repos {
+		/// Github login username.
+		username: String,
+	},
+	/// Mark all notifications of a subscription as read, so that they are not
+	/// posted anymore.
+	#[command(name = "markread")]
+	MarkRead {
+		/// Github login username.
+		username: String,
+	},
+}
+
+/// Arguments of the Github enable sub-command.
+#[derive(Debug, Args)]
+struct EnableArgs {
+	/// Github login username.
+	username: String,
+	/// Github API token. Get one from <https://github.com/settings/tokens>.
+	/// It needs `notifications` and `repos` API scope.
+	token: String,
+	/// Additional API tokens of the same user to rotate through, spreading
+	/// the rate limits. Can be given multiple times.
+	#[arg(long = "extra-token")]
+	extra_tokens: Vec<String>,
+	/// Mark notifications as read on Github once they are posted.
+	#[arg(long)]
+	mark_read: bool,
+	/// Only post notifications of this repository, given as `owner/name`.
+	#[arg(long, value_parser = parse_repo)]
+	repo: Option<String>,
+	/// Base API URL of a Github Enterprise server, e.g.
+	/// `https://github.example.com/api/v3/`. Defaults to the public Github
+	/// API.
+	#[arg(long, value_parser = parse_api_url)]
+	api_url: Option<Url>,
 }
 
 #[async_trait]
 impl BotCommand for Github {
 	async fn execute<'a>(&mut self, context: Context<'a>) -> Result<()> {
-		let is_mod = context.config.access.admins.contains(&context.event.sender)
-			|| context.config.access.mods.contains(&context.event.sender);
-		if !is_mod {
+		if !context.is_mod() {
 			tracing::trace!("Person not allowed to edit Github notification settings!");
 			return Ok(());
 		}
 
 		match &self.command {
-			SubCommand::List => {
-				let subscriptions =
-					GithubSubscription::for_room(context.room.room_id(), &context.db.state).await?;
-				let formatted_subscriptions = subscriptions
-					.into_values()
-					.map(|doc| format!("- {}", doc.contents.user))
-					.collect::<Vec<_>>();
-				if formatted_subscriptions.is_empty() {
-					let msg = RoomMessageEventContent::text_plain(
-						"Currently, there are no Github subscriptions.",
-					)
-					.make_reply_to(context.event);
-					context.room.send(msg, None).await?;
-				} else {
-					let msg =
-						RoomMessageEventContent::text_markdown(formatted_subscriptions.join("\n"))
-							.make_reply_to(context.event);
-					context.room.send(msg, None).await?;
-				}
-			}
+			SubCommand::List => list(&context).await,
+			SubCommand::Clear => clear(&context).await,
+			SubCommand::Enable(args) => enable(&context, args).await,
+			SubCommand::Login => login(&context).await,
+			SubCommand::Disable { username } => disable(&context, username).await,
+			SubCommand::Test { username } => test(&context, username).await,
+			SubCommand::Preview { username } => preview(&context, username).await,
+			SubCommand::Repos { username } => repos(&context, username).await,
+			SubCommand::MarkRead { username } => mark_all_read(&context, username).await,
+		}
+	}
+}
 
-			SubCommand::Clear => {
-				for subscription in
-					GithubSubscription::for_room(context.room.room_id(), &context.db.state)
-						.await?
-						.into_values()
-				{
-					subscription.delete_async(&context.db.state).await?;
-				}
-
-				let success_msg = RoomMessageEventContent::text_plain(
-					"Successfully cleared Github subscriptions.",
-				)
+/// List the Github subscriptions of the room.
+async fn list(context: &Context<'_>) -> Result<()> {
+	let subscriptions =
+		GithubSubscription::for_room(context.room.room_id(), &context.db.state).await?;
+	let formatted_subscriptions = subscriptions
+		.into_values()
+		.map(|doc| format_subscription(&doc.contents))
+		.collect::<Vec<_>>();
+	if formatted_subscriptions.is_empty() {
+		let msg =
+			RoomMessageEventContent::text_plain("Currently, there are no Github subscriptions.")
 				.make_reply_to(context.event);
-				context.room.send(success_msg, None).await?;
-			}
+		context.room.send(msg, None).await?;
+	} else {
+		let msg = RoomMessageEventContent::text_markdown(formatted_subscriptions.join("\n"))
+			.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+	}
+	Ok(())
+}
 
-			SubCommand::Enable { username, token } => {
-				let client = clients::github::Github::new(username.clone(), token.clone())?;
-				if client.test_token().await.is_ok() {
-					let subscription = GithubSubscription::new(
-						context.room.room_id().to_owned(),
-						username.clone(),
-						token.clone(),
-					);
-					subscription.insert(&context.db.state).await?;
-
-					let success_msg = RoomMessageEventContent::text_plain(
-						"Successfully enabled Github subscription.",
-					)
-					.make_reply_to(context.event);
-					context.room.send(success_msg, None).await?;
-				} else {
-					let failure_msg = RoomMessageEventContent::text_plain("Token is invalid.")
-						.make_reply_to(context.event);
-					context.room.send(failure_msg, None).await?;
-				}
-			}
+/// Remove all Github subscriptions of the room.
+async fn clear(context: &Context<'_>) -> Result<()> {
+	for subscription in
+		GithubSubscription::for_room(context.room.room_id(), &context.db.state).await?.into_values()
+	{
+		subscription.delete_async(&context.db.state).await?;
+	}
+
+	context.audit("cleared Github subscriptions").await?;
+
+	let success_msg =
+		RoomMessageEventContent::text_plain("Successfully cleared Github subscriptions.")
+			.make_reply_to(context.event);
+	context.room.send(success_msg, None).await?;
+	Ok(())
+}
+
+/// Subscribe the room to the notifications of the user, if it is allowed and
+/// all tokens are valid.
+async fn enable(context: &Context<'_>, args: &EnableArgs) -> Result<()> {
+	let username = &args.username;
+	let subscriptions =
+		GithubSubscription::for_room(context.room.room_id(), &context.db.state).await?;
+	let is_new = !subscriptions.values().any(|doc| doc.contents.user == *username);
+	if !context.is_admin()
+		&& exceeds_subscription_limit(
+			subscriptions.len(),
+			is_new,
+			context.config.rooms.max_github_per_room,
+		) {
+		let msg = RoomMessageEventContent::text_plain(
+			"This room reached the maximum number of Github subscriptions.",
+		)
+		.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+		return Ok(());
+	}
+
+	if let Some(api_url) = &args.api_url {
+		if let Err(err) = clients::check_url(api_url, &context.config.fetch).await {
+			let msg = RoomMessageEventContent::text_plain(format!("API URL is not allowed: {err}"))
+				.make_reply_to(context.event);
+			context.room.send(msg, None).await?;
+			return Ok(());
+		}
+	}
+
+	let tokens =
+		std::iter::once(&args.token).chain(&args.extra_tokens).cloned().collect::<Vec<_>>();
+	let http_client = GuardedClient::new(&context.config.user_agent, &context.config.fetch)?;
+	let mut valid = true;
+	for token in &tokens {
+		let client = clients::github::Github::new(
+			username.clone(),
+			token.clone(),
+			&http_client,
+			context.config.github.retry_policy(),
+		)?
+		.with_api_url(args.api_url.as_ref());
+		valid &= client.test_token().await.is_ok();
+	}
+	if !valid {
+		let failure_msg =
+			RoomMessageEventContent::text_plain("A token is invalid.").make_reply_to(context.event);
+		context.room.send(failure_msg, None).await?;
+		return Ok(());
+	}
+
+	let key = context.config.store.secret_key();
+	let mut subscription = GithubSubscription::new(
+		context.room.room_id().to_owned(),
+		username.clone(),
+		args.token.clone(),
+		&key,
+	)?;
+	subscription.set_tokens(&tokens, &key)?;
+	subscription.mark_read = args.mark_read;
+	subscription.repo = args.repo.clone();
+	subscription.api_url = args.api_url.clone();
+	subscription.insert(&context.db.state).await?;
+	context.audit(format!("enabled Github subscription of {username}")).await?;
+
+	let success_msg =
+		RoomMessageEventContent::text_plain("Successfully enabled Github subscription.")
+			.make_reply_to(context.event);
+	context.room.send(success_msg, None).await?;
+	Ok(())
+}
+
+/// Start the Github device login and finish it in the background, replying
+/// with the instructions for the user.
+async fn login(context: &Context<'_>) -> Result<()> {
+	let client_id = match &context.config.github.oauth_client_id {
+		Some(client_id) => client_id.clone(),
+		None => {
+			let msg = RoomMessageEventContent::text_plain(
+				"Github login is not configured, use `!github enable` instead.",
+			)
+			.make_reply_to(context.event);
+			context.room.send(msg, None).await?;
+			return Ok(());
+		}
+	};
+	let subscriptions =
+		GithubSubscription::for_room(context.room.room_id(), &context.db.state).await?;
+	if !context.is_admin()
+		&& exceeds_subscription_limit(
+			subscriptions.len(),
+			true,
+			context.config.rooms.max_github_per_room,
+		) {
+		let msg = RoomMessageEventContent::text_plain(
+			"This room reached the maximum number of Github subscriptions.",
+		)
+		.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+		return Ok(());
+	}
+
+	let http_client = GuardedClient::new(&context.config.user_agent, &context.config.fetch)?;
+	let flow = DeviceFlow::new(client_id, &http_client)?;
+	let code = flow.request_code().await?;
+	context.audit("started Github login").await?;
+
+	let config = context.config.clone();
+	let databases = context.db.clone();
+	let room = context.room.clone();
+	let event = context.event.clone();
+	let instructions = login_instructions(&code);
+	tokio::spawn(async move {
+		let sender = &event.sender;
+		let result = finish_login(&flow, &code, &config, &databases, &room, sender).await;
+		let text = result.unwrap_or_else(|err| {
+			tracing::error!("Github login failed: {err:?}");
+			String::from("Github login failed. The error was logged for the bot operators.")
+		});
+		let msg = RoomMessageEventContent::text_plain(text).make_reply_to(&event);
+		if let Err(err) = room.send(msg, None).await {
+			tracing::error!("Failed sending Github login result: {err}");
+		}
+	});
 
-			SubCommand::Disable { username } => {
-				if let Some(subscription) =
-					GithubSubscription::find(context.room.room_id(), username, &context.db.state)
-						.await?
-				{
-					subscription.delete_async(&context.db.state).await?;
-
-					let success_msg = RoomMessageEventContent::text_plain(
-						"Successfully disabled Github subscription.",
-					)
-					.make_reply_to(context.event);
-					context.room.send(success_msg, None).await?;
-				} else {
-					let failure_msg =
-						RoomMessageEventContent::text_plain("Github subscription not found.")
-							.make_reply_to(context.event);
-					context.room.send(failure_msg, None).await?;
-				}
+	let msg = RoomMessageEventContent::text_markdown(instructions).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Unsubscribe the room from the notifications of the user.
+async fn disable(context: &Context<'_>, username: &str) -> Result<()> {
+	if let Some(subscription) =
+		GithubSubscription::find(context.room.room_id(), username, &context.db.state).await?
+	{
+		subscription.delete_async(&context.db.state).await?;
+		PostedNotification::forget(context.room.room_id(), username, &context.db.state).await?;
+		context.audit(format!("disabled Github subscription of {username}")).await?;
+
+		let success_msg =
+			RoomMessageEventContent::text_plain("Successfully disabled Github subscription.")
+				.make_reply_to(context.event);
+		context.room.send(success_msg, None).await?;
+	} else {
+		let failure_msg = RoomMessageEventContent::text_plain("Github subscription not found.")
+			.make_reply_to(context.event);
+		context.room.send(failure_msg, None).await?;
+	}
+	Ok(())
+}
+
+/// Test all stored tokens of the user's subscription and report the last
+/// successful poll.
+async fn test(context: &Context<'_>, username: &str) -> Result<()> {
+	let text = if let Some(subscription) =
+		GithubSubscription::find(context.room.room_id(), username, &context.db.state).await?
+	{
+		let timestamps = context.timestamps().await?;
+		let mut reports = Vec::new();
+		let tokens = subscription.contents.tokens(&context.config.store.secret_key());
+		for (index, token) in tokens.into_iter().enumerate() {
+			let client = subscription_client(context, &subscription.contents, token)?;
+			reports.push(format_token_report(index, &client.test_token().await));
+		}
+		reports.push(format_last_poll(subscription.contents.last_polled, &timestamps));
+		reports.join("\n")
+	} else {
+		String::from("Github subscription not found.")
+	};
+
+	let msg = RoomMessageEventContent::text_markdown(text).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Preview the most recent notification of the user's subscription.
+async fn preview(context: &Context<'_>, username: &str) -> Result<()> {
+	let msg = if let Some(subscription) =
+		GithubSubscription::find(context.room.room_id(), username, &context.db.state).await?
+	{
+		let token = subscription.contents.main_token(&context.config.store.secret_key())?;
+		let client = subscription_client(context, &subscription.contents, token)?;
+		let repo = subscription.contents.repo.as_deref();
+		let notification = client.latest_notification(repo).await?;
+		let timestamps = context.timestamps().await?;
+		preview_message(
+			&client,
+			notification.as_ref(),
+			&context.config.prefixes.github,
+			context.config.github.max_comment_length,
+			&timestamps,
+		)
+		.await?
+	} else {
+		RoomMessageEventContent::text_plain("Github subscription not found.")
+	};
+	context.room.send(msg.make_reply_to(context.event), None).await?;
+	Ok(())
+}
+
+/// List the repositories the main token of the user's subscription can access.
+async fn repos(context: &Context<'_>, username: &str) -> Result<()> {
+	let text = if let Some(subscription) =
+		GithubSubscription::find(context.room.room_id(), username, &context.db.state).await?
+	{
+		let token = subscription.contents.main_token(&context.config.store.secret_key())?;
+		let client = subscription_client(context, &subscription.contents, token)?;
+		format_repos(&client.get_repos().await?)
+	} else {
+		String::from("Github subscription not found.")
+	};
+
+	let msg = RoomMessageEventContent::text_markdown(text).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Mark all notifications of the user's subscription as read.
+async fn mark_all_read(context: &Context<'_>, username: &str) -> Result<()> {
+	let text = if let Some(mut subscription) =
+		GithubSubscription::find(context.room.room_id(), username, &context.db.state).await?
+	{
+		let token = subscription.contents.main_token(&context.config.store.secret_key())?;
+		let client = subscription_client(context, &subscription.contents, token)?;
+		mark_read(&client, &mut subscription, OffsetDateTime::now_utc(), context.db).await?;
+		context.audit(format!("marked Github notifications of {username} as read")).await?;
+		String::from("Successfully marked the Github notifications as read.")
+	} else {
+		String::from("Github subscription not found.")
+	};
+
+	let msg = RoomMessageEventContent::text_plain(text).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Create the Github client of the subscription with the given token.
+fn subscription_client(
+	context: &Context<'_>,
+	subscription: &GithubSubscription,
+	token: String,
+) -> Result<clients::github::Github> {
+	let client = clients::github::Github::new(
+		subscription.user.clone(),
+		token,
+		&GuardedClient::new(&context.config.user_agent, &context.config.fetch)?,
+		context.config.github.retry_policy(),
+	)?
+	.with_api_url(subscription.api_url.as_ref());
+	Ok(client)
+}
+
+/// Parse a Github repository given as `owner/name`.
+fn parse_repo(repo: &str) -> Result<String, String> {
+	let is_name = |name: &str| {
+		!name.is_empty()
+			&& name != "."
+			&& name != ".."
+			&& name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+	};
+	match repo.split_once('/') {
+		Some((owner, name)) if is_name(owner) && is_name(name) => Ok(repo.to_owned()),
+		_ => Err(format!("`{repo}` is not a repository in the form `owner/name`!")),
+	}
+}
+
+/// Parse the base API URL of a Github Enterprise server. A missing trailing
+/// slash is added, so that the API paths are joined onto the URL.
+fn parse_api_url(s: &str) -> Result<Url, String> {
+	let mut url = Url::parse(s).map_err(|err| format!("`{s}` is not a valid URL: {err}"))?;
+	if !matches!(url.scheme(), "http" | "https") {
+		return Err(format!("`{s}` is not an HTTP(S) URL!"));
+	}
+	if !url.path().ends_with('/') {
+		let path = format!("{}/", url.path());
+		url.set_path(&path);
+	}
+	Ok(url)
+}
+
+/// Format the subscription as markdown list item, along with its repository
+/// filter and Github Enterprise server.
+fn format_subscription(subscription: &GithubSubscription) -> String {
+	let repo = subscription.repo.as_ref().map_or_else(String::new, |repo| format!(" in `{repo}`"));
+	let server = subscription
+		.api_url
+		.as_ref()
+		.and_then(Url::host_str)
+		.map_or_else(String::new, |host| format!(" on {host}"));
+	let muted = if subscription.muted { " (muted)" } else { "" };
+	let disabled = if subscription.disabled { " (disabled, token invalid)" } else { "" };
+	format!("- {}{repo}{server}{muted}{disabled}", subscription.user)
+}
+
+/// Instructions for authorizing the device code on Github.
+fn login_instructions(code: &DeviceCode) -> String {
+	format!(
+		"Open {} and enter the code `{}` within {} minutes to subscribe to your Github \
+		 notifications.",
+		code.verification_uri,
+		code.user_code,
+		code.expires_in / 60
+	)
+}
+
+/// Wait for the authorization of the device code and subscribe the room to the
+/// notifications of the authorizing user. Returns the reply text.
+async fn finish_login(
+	flow: &DeviceFlow,
+	code: &DeviceCode,
+	config: &Settings,
+	db: &Databases,
+	room: &Joined,
+	sender: &OwnedUserId,
+) -> Result<String> {
+	let token = match flow.wait_for_token(code).await? {
+		DevicePoll::Authorized(token) => token,
+		DevicePoll::Denied => return Ok(String::from("The Github login was denied.")),
+		_ => return Ok(String::from("The Github login code expired, please try again.")),
+	};
+	let user = clients::github::Github::new(
+		String::new(),
+		token.clone(),
+		&GuardedClient::new(&config.user_agent, &config.fetch)?,
+		config.github.retry_policy(),
+	)?
+	.current_user()
+	.await?;
+	let key = config.store.secret_key();
+	GithubSubscription::login(room.room_id(), &user, &token, &key, &db.state).await?;
+	AuditEntry::record(
+		room.room_id(),
+		Some(sender),
+		format!("enabled Github subscription of {user} via login"),
+		&db.state,
+	)
+	.await?;
+	Ok(format!("Successfully enabled Github subscription of {user}."))
+}
+
+/// Mark the notifications of the subscription as read up to now and advance
+/// its latest update, so that they are not posted anymore.
+async fn mark_read(
+	client: &clients::github::Github,
+	subscription: &mut CollectionDocument<GithubSubscription>,
+	now: OffsetDateTime,
+	db: &Databases,
+) -> Result<()> {
+	client.mark_read(subscription.contents.repo.as_deref(), now).await?;
+	subscription.contents.latest_update = now;
+	subscription.update_async(&db.state).await?;
+	Ok(())
+}
+
+/// Render the preview message of the notification, or a hint if there is no
+/// notification.
+async fn preview_message(
+	client: &clients::github::Github,
+	notification: Option<&Notification>,
+	prefix: &str,
+	max_comment_length: usize,
+	timestamps: &TimestampFormatter,
+) -> Result<RoomMessageEventContent> {
+	let msg = match notification {
+		Some(notification) => {
+			let (html, body) =
+				render_notification(client, notification, prefix, max_comment_length, timestamps)
+					.await?;
+			RoomMessageEventContent::text_html(body, html)
+		}
+		None => RoomMessageEventContent::text_plain("There are no notifications to preview."),
+	};
+	Ok(msg)
+}
+
+/// Format the repositories as markdown list, trimmed to a maximum length.
+fn format_repos(list: &RepositoryList) -> String {
+	if list.repositories.is_empty() {
+		return String::from("The token cannot access any repositories.");
+	}
+
+	let mut lines = list
+		.repositories
+		.iter()
+		.take(MAX_LISTED_REPOS)
+		.map(|repo| {
+			let private = if repo.private { " (private)" } else { "" };
+			format!("- {}{private}", repo.full_name)
+		})
+		.collect::<Vec<_>>();
+	let hidden = list.repositories.len().saturating_sub(MAX_LISTED_REPOS);
+	if hidden > 0 {
+		lines.push(format!("- ... and {hidden} more"));
+	}
+	if !list.complete {
+		lines.push(String::from("\nThe list is incomplete due to rate or page limits."));
+	}
+	lines.join("\n")
+}
+
+/// Format the time of the last successful poll as markdown list item.
+fn format_last_poll(
+	last_polled: Option<OffsetDateTime>,
+	timestamps: &TimestampFormatter,
+) -> String {
+	match last_polled {
+		Some(last_polled) => format!("- Last successful poll: {}", timestamps.format(last_polled)),
+		None => String::from("- Last successful poll: never"),
+	}
+}
+
+/// Format the test result of the token with the index as markdown list item.
+fn format_token_report(index: usize, result: &Result<TokenInfo>) -> String {
+	let info = match result {
+		Ok(info) => info,
+		Err(err) => return format!("- Token #{index}: invalid ({err})"),
+	};
+
+	let remaining = info
+		.rate_limit_remaining
+		.map_or_else(|| String::from("unknown"), |remaining| remaining.to_string());
+	let mut report = format!("- Token #{index}: valid, {remaining} requests remaining");
+	match &info.scopes {
+		Some(scopes) => {
+			report.push_str(&format!(", scopes: {}", scopes.join(", ")));
+			if !scopes.iter().any(|scope| scope == "notifications") {
+				report.push_str("\n  - Warning: the `notifications` scope is missing!");
 			}
 		}
-		Ok(())
+		None => report.push_str(", fine-grained token without scopes"),
 	}
+	report
 }
+
+#[cfg(test)]
+mod tests;
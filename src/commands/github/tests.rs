@@ -0,0 +1,272 @@
+//! Tests for the Github commands.
+
+use color_eyre::eyre::eyre;
+use matrix_sdk::ruma::OwnedRoomId;
+use serde_json::json;
+
+use super::*;
+use crate::{
+	clients::github::{parse_scopes, RetryPolicy},
+	secrets::SecretKey,
+	settings::TimestampSettings,
+	test_utils::{local_http_client, test_databases, MockResponse, MockServer},
+};
+
+/// Github API JSON of a repository with the number.
+fn repository(number: usize) -> serde_json::Value {
+	json!({
+		"description": null,
+		"fork": false,
+		"full_name": format!("owner/repo-{number}"),
+		"html_url": format!("https://github.com/owner/repo-{number}"),
+		"id": number,
+		"node_id": format!("R_{number}"),
+		"name": format!("repo-{number}"),
+		"private": number % 2 == 0,
+		"url": format!("https://api.github.com/repos/owner/repo-{number}"),
+	})
+}
+
+#[test]
+fn scopes_are_parsed() {
+	assert_eq!(parse_scopes("notifications, repo"), vec!["notifications", "repo"]);
+	assert_eq!(parse_scopes(" repo ,, "), vec!["repo"]);
+	assert!(parse_scopes("").is_empty());
+}
+
+#[test]
+fn token_reports() {
+	let info = TokenInfo {
+		scopes: Some(parse_scopes("notifications, repo")),
+		rate_limit_remaining: Some(4999),
+	};
+	assert_eq!(
+		format_token_report(0, &Ok(info)),
+		"- Token #0: valid, 4999 requests remaining, scopes: notifications, repo"
+	);
+
+	let info = TokenInfo { scopes: Some(parse_scopes("repo")), rate_limit_remaining: None };
+	let report = format_token_report(1, &Ok(info));
+	assert!(report.starts_with("- Token #1: valid, unknown requests remaining, scopes: repo"));
+	assert!(report.contains("`notifications` scope is missing"));
+
+	let info = TokenInfo { scopes: None, rate_limit_remaining: Some(10) };
+	let report = format_token_report(2, &Ok(info));
+	assert!(report.ends_with("fine-grained token without scopes"));
+
+	let report = format_token_report(3, &Err(eyre!("401 Unauthorized")));
+	assert_eq!(report, "- Token #3: invalid (401 Unauthorized)");
+}
+
+#[test]
+fn last_poll_is_reported() -> Result<()> {
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+	let timestamps = TimestampFormatter::new(&TimestampSettings::default(), now);
+	assert_eq!(format_last_poll(None, &timestamps), "- Last successful poll: never");
+	assert_eq!(
+		format_last_poll(Some(now - time::Duration::minutes(5)), &timestamps),
+		"- Last successful poll: 5m ago (2022-10-05 19:55 UTC+00)"
+	);
+	Ok(())
+}
+
+#[tokio::test]
+async fn preview_renders_notification() -> Result<()> {
+	let notification: Notification = serde_json::from_value(json!({
+		"id": "1",
+		"last_read_at": null,
+		"reason": "mention",
+		"repository": {
+			"description": null,
+			"fork": false,
+			"full_name": "owner/repo",
+			"html_url": "https://github.com/owner/repo",
+			"id": 1,
+			"node_id": "R_1",
+			"name": "repo",
+			"private": false,
+			"url": "https://api.github.com/repos/owner/repo",
+		},
+		"subject": {
+			"latest_comment_url": null,
+			"title": "Fix the bug",
+			"type": "Issue",
+			"url": null,
+		},
+		"subscription_url": "https://api.github.com/notifications/threads/1/subscription",
+		"unread": false,
+		"updated_at": "2022-10-05T10:00:00Z",
+		"url": "https://api.github.com/notifications/threads/1",
+	}))?;
+	let client = clients::github::Github::new(
+		"octocat".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?;
+	let timestamps =
+		TimestampFormatter::new(&TimestampSettings::default(), OffsetDateTime::now_utc());
+
+	let preview = preview_message(&client, Some(&notification), "[GH]", 500, &timestamps).await?;
+	assert!(preview.body().starts_with("[GH] owner/repo\n"));
+	assert!(preview.body().contains("Issue: Fix the bug (Mentioned)"));
+
+	let preview = preview_message(&client, None, "[GH]", 500, &timestamps).await?;
+	assert_eq!(preview.body(), "There are no notifications to preview.");
+	Ok(())
+}
+
+#[tokio::test]
+async fn repos_are_paginated() -> Result<()> {
+	let server = MockServer::start(|request| {
+		let (range, remaining) = if request.path.ends_with("&page=1") {
+			(0..100, "10")
+		} else if request.path.ends_with("&page=2") {
+			(100..160, "9")
+		} else {
+			(0..0, "8")
+		};
+		let page = range.map(repository).collect::<Vec<_>>();
+		MockResponse::new(200, json!(page).to_string())
+			.header("Content-Type", "application/json")
+			.header("X-RateLimit-Remaining", remaining)
+	})
+	.await?;
+	let client = clients::github::Github::new(
+		"octocat".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+
+	let list = client.get_repos().await?;
+	assert!(list.complete);
+	assert_eq!(list.repositories.len(), 160);
+	assert_eq!(server.requests().len(), 2);
+	assert!(server.requests()[0].path.starts_with("/user/repos?"));
+
+	let formatted = format_repos(&list);
+	assert!(formatted.starts_with("- owner/repo-0 (private)\n- owner/repo-1\n"));
+	assert!(formatted.ends_with("- ... and 110 more"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn repos_stop_at_rate_limit() -> Result<()> {
+	let server = MockServer::start(|request| {
+		if request.path.ends_with("&page=1") {
+			let page = (0..100).map(repository).collect::<Vec<_>>();
+			MockResponse::new(200, json!(page).to_string()).header("X-RateLimit-Remaining", "1")
+		} else {
+			MockResponse::new(403, r#"{"message":"API rate limit exceeded"}"#)
+				.header("X-RateLimit-Remaining", "0")
+		}
+	})
+	.await?;
+	let client = clients::github::Github::new(
+		"octocat".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+
+	let list = client.get_repos().await?;
+	assert!(!list.complete);
+	assert_eq!(list.repositories.len(), 100);
+	assert!(format_repos(&list).ends_with("The list is incomplete due to rate or page limits."));
+	Ok(())
+}
+
+#[tokio::test]
+async fn mark_read_advances_latest_update() -> Result<()> {
+	let server = MockServer::start(|_| MockResponse::new(205, "")).await?;
+	let client = clients::github::Github::new(
+		"octocat".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let key = SecretKey::derive("password");
+	let mut subscription =
+		GithubSubscription::new(room.clone(), "octocat".to_owned(), "token".to_owned(), &key)?;
+	subscription.latest_update = OffsetDateTime::UNIX_EPOCH;
+	subscription.insert(&db.state).await?;
+	let mut subscription = GithubSubscription::find(&room, "octocat", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?;
+
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+	mark_read(&client, &mut subscription, now, &db).await?;
+
+	let requests = server.requests();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(requests[0].method, "PUT");
+	assert_eq!(requests[0].path, "/notifications");
+	let body: serde_json::Value = serde_json::from_slice(&requests[0].body)?;
+	assert_eq!(body, json!({ "last_read_at": "2022-10-05T20:00:00Z", "read": true }));
+
+	let stored = GithubSubscription::find(&room, "octocat", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?;
+	assert_eq!(stored.contents.latest_update, now);
+	Ok(())
+}
+
+#[test]
+fn repo_is_parsed() {
+	assert_eq!(parse_repo("owner/repo.rs"), Ok(String::from("owner/repo.rs")));
+	assert_eq!(parse_repo("my-org/my_repo"), Ok(String::from("my-org/my_repo")));
+	assert!(parse_repo("owner").is_err());
+	assert!(parse_repo("owner/").is_err());
+	assert!(parse_repo("owner/repo/issues").is_err());
+	assert!(parse_repo("owner/..").is_err());
+	assert!(parse_repo("owner/repo?page=2").is_err());
+}
+
+#[test]
+fn subscription_shows_repo_filter() -> Result<()> {
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let key = SecretKey::derive("password");
+	let mut subscription =
+		GithubSubscription::new(room, "octocat".to_owned(), "token".to_owned(), &key)?;
+	assert_eq!(format_subscription(&subscription), "- octocat");
+
+	subscription.repo = Some(String::from("owner/repo"));
+	subscription.muted = true;
+	assert_eq!(format_subscription(&subscription), "- octocat in `owner/repo` (muted)");
+
+	subscription.disabled = true;
+	assert_eq!(
+		format_subscription(&subscription),
+		"- octocat in `owner/repo` (muted) (disabled, token invalid)"
+	);
+
+	subscription.api_url = Some(Url::parse("https://github.example.com/api/v3/")?);
+	subscription.muted = false;
+	subscription.disabled = false;
+	assert_eq!(
+		format_subscription(&subscription),
+		"- octocat in `owner/repo` on github.example.com"
+	);
+	Ok(())
+}
+
+#[test]
+fn api_url_gets_trailing_slash() -> Result<()> {
+	let url = parse_api_url("https://github.example.com/api/v3").map_err(|err| eyre!(err))?;
+	assert_eq!(url.as_str(), "https://github.example.com/api/v3/");
+	assert_eq!(
+		url.join("notifications")?.as_str(),
+		"https://github.example.com/api/v3/notifications"
+	);
+	let url = parse_api_url("https://github.example.com/api/v3/").map_err(|err| eyre!(err))?;
+	assert_eq!(url.as_str(), "https://github.example.com/api/v3/");
+	assert!(parse_api_url("ftp://github.example.com/").is_err());
+	assert!(parse_api_url("not a url").is_err());
+	Ok(())
+}
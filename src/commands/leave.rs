@@ -12,11 +12,31 @@ pub struct Leave;
 #[async_trait]
 impl BotCommand for Leave {
 	async fn execute<'a>(&mut self, context: Context<'a>) -> color_eyre::Result<()> {
-		if context.config.access.admins.contains(&context.event.sender)
-			|| context.config.access.mods.contains(&context.event.sender)
-		{
+		if context.is_mod() {
+			let min_power_level = context.config.commands.leave_min_power_level;
+			if min_power_level.is_some() {
+				let power_level = context
+					.room
+					.get_member(&context.event.sender)
+					.await?
+					.map_or(0, |member| member.power_level());
+				if !has_power_level(power_level, min_power_level) {
+					tracing::trace!("Person does not have the power level to make the bot leave!");
+					return Ok(());
+				}
+			}
+
+			context.audit("made the bot leave the room").await?;
 			context.room.leave().await?;
 		}
 		Ok(())
 	}
 }
+
+/// Whether the power level reaches the minimum power level, if there is one.
+fn has_power_level(power_level: i64, min_power_level: Option<i64>) -> bool {
+	min_power_level.map_or(true, |min| power_level >= min)
+}
+
+#[cfg(test)]
+mod tests;
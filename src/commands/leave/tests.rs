@@ -0,0 +1,12 @@
+//! Tests for the leave command.
+
+use super::*;
+
+#[test]
+fn power_level_gate() {
+	assert!(has_power_level(0, None));
+	assert!(has_power_level(50, Some(50)));
+	assert!(has_power_level(100, Some(50)));
+	assert!(!has_power_level(0, Some(50)));
+	assert!(!has_power_level(49, Some(50)));
+}
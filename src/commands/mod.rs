@@ -1,18 +1,34 @@
 //! Bot command module.
 
+mod admin;
 mod github;
 mod leave;
+mod preferences;
 mod remind;
 mod rss;
 
-use clap::Parser;
-use color_eyre::Result;
+use clap::{CommandFactory, Parser};
+use color_eyre::{Report, Result};
 use matrix_sdk::{
-	async_trait, room::Joined, ruma::events::room::message::OriginalRoomMessageEvent, Client,
+	async_trait,
+	room::Joined,
+	ruma::{
+		events::room::message::{OriginalRoomMessageEvent, RoomMessageEventContent},
+		RoomId, UserId,
+	},
+	Client,
 };
+use serde_json::{json, Value};
+use time::OffsetDateTime;
 
-use self::{github::Github, leave::Leave, remind::Remind, rss::Rss};
-use crate::{database::Databases, settings::Settings};
+use self::{
+	admin::Admin, github::Github, leave::Leave, preferences::Preferences, remind::Remind, rss::Rss,
+};
+use crate::{
+	database::{AuditEntry, Databases, RoomPreferences},
+	settings::{RoomSettings, Settings, TimestampSettings},
+	timestamps::TimestampFormatter,
+};
 
 /// The trait every command implements. This is used for executing the command.
 #[async_trait]
@@ -34,6 +50,10 @@ pub enum Command {
 	Rss(Rss),
 	/// Github notifications subscription configuration.
 	Github(Github),
+	/// Bot administration, only available to admins in direct chats.
+	Admin(Admin),
+	/// Settings of this room, e.g. its timezone.
+	Settings(Preferences),
 }
 
 impl Command {
@@ -44,10 +64,41 @@ impl Command {
 			Command::Remind(cmd) => cmd,
 			Command::Rss(cmd) => cmd,
 			Command::Github(cmd) => cmd,
+			Command::Admin(cmd) => cmd,
+			Command::Settings(cmd) => cmd,
 		}
 	}
 
-	/// Execute the command.
+	/// Name of the command as invoked.
+	fn name(&self) -> &'static str {
+		match self {
+			Command::Leave(_) => "leave",
+			Command::Remind(_) => "remind",
+			Command::Rss(_) => "rss",
+			Command::Github(_) => "github",
+			Command::Admin(_) => "admin",
+			Command::Settings(_) => "settings",
+		}
+	}
+
+	/// Whether the command is enabled in the room. All commands are enabled,
+	/// unless configured otherwise for the room.
+	fn is_enabled_in(&self, config: &RoomSettings, room: &RoomId) -> bool {
+		let name = self.name();
+		config.commands.get(room).map_or(true, |commands| {
+			commands.enabled.as_ref().map_or(true, |enabled| enabled.iter().any(|cmd| cmd == name))
+				&& !commands.disabled.iter().any(|cmd| cmd == name)
+		})
+	}
+
+	/// Record the invocation of the command in the audit log.
+	async fn audit(&self, db: &Databases, room: &RoomId, sender: &UserId) -> Result<()> {
+		AuditEntry::record(room, Some(sender), format!("!{}", self.name()), &db.state).await?;
+		Ok(())
+	}
+
+	/// Execute the command. Errors of the command are logged and answered with
+	/// a user-friendly reply.
 	#[tracing::instrument(level = "debug", skip_all)]
 	pub async fn execute(
 		&mut self,
@@ -57,10 +108,36 @@ impl Command {
 		room: &Joined,
 		event: &OriginalRoomMessageEvent,
 	) -> Result<()> {
-		self.as_bot_command().execute(Context { config, db, client, room, event }).await
+		if !self.is_enabled_in(&config.rooms, room.room_id()) {
+			tracing::trace!("Command `{}` is disabled in this room!", self.name());
+			let msg =
+				RoomMessageEventContent::text_plain("Command disabled here.").make_reply_to(event);
+			room.send(msg, None).await?;
+			return Ok(());
+		}
+
+		self.audit(db, room.room_id(), &event.sender).await?;
+		let result =
+			self.as_bot_command().execute(Context { config, db, client, room, event }).await;
+		if let Err(error) = result {
+			let msg = RoomMessageEventContent::text_plain(error_reply(self.name(), &error))
+				.make_reply_to(event);
+			room.send(msg, None).await?;
+		}
+		Ok(())
 	}
 }
 
+/// Log the full error of a failed command and get a user-friendly reply, which
+/// does not expose the internal error chain.
+fn error_reply(command: &str, error: &Report) -> String {
+	tracing::error!("Error executing the command `!{command}`: {error:?}");
+	format!(
+		"Sorry, something went wrong while executing `!{command}`. The error was logged for the \
+		 bot operators."
+	)
+}
+
 /// Command context
 #[allow(dead_code)] // Available context will be used later.
 struct Context<'a> {
@@ -76,6 +153,51 @@ struct Context<'a> {
 	pub event: &'a OriginalRoomMessageEvent,
 }
 
+impl Context<'_> {
+	/// Whether the command's sender is an admin.
+	fn is_admin(&self) -> bool {
+		self.config.access.is_admin(&self.event.sender)
+	}
+
+	/// Whether the command's sender is a moderator or admin.
+	fn is_mod(&self) -> bool {
+		self.config.access.is_mod(&self.event.sender)
+	}
+
+	/// Timestamp settings of the room, i.e. the global settings with the
+	/// room's overrides.
+	async fn timestamp_settings(&self) -> Result<TimestampSettings> {
+		let settings = RoomPreferences::timestamps_for(
+			self.room.room_id(),
+			&self.config.timestamps,
+			&self.db.state,
+		)
+		.await?;
+		Ok(settings)
+	}
+
+	/// Timestamp formatter for messages in the room, relative to now. Uses the
+	/// room's timezone if it has one.
+	async fn timestamps(&self) -> Result<TimestampFormatter> {
+		let settings = self.timestamp_settings().await?;
+		Ok(TimestampFormatter::new(&settings, OffsetDateTime::now_utc()))
+	}
+
+	/// Record an action caused by the command's sender in the audit log.
+	async fn audit(&self, action: impl Into<String> + Send) -> Result<()> {
+		AuditEntry::record(self.room.room_id(), Some(&self.event.sender), action, &self.db.state)
+			.await?;
+		Ok(())
+	}
+}
+
+/// Whether adding a subscription to a room with `existing` subscriptions
+/// exceeds the limit. Re-enabling an already existing subscription replaces it
+/// and does not count against the limit.
+fn exceeds_subscription_limit(existing: usize, is_new: bool, limit: Option<usize>) -> bool {
+	is_new && limit.map_or(false, |limit| existing >= limit)
+}
+
 /// Parse arguments in a message by splitting it on spaces. This keeps into
 /// account quotes for giving arguments that include spaces.
 #[allow(clippy::collapsible_else_if)] // more readable
@@ -120,5 +242,44 @@ pub fn parse_arguments(message: &str) -> Vec<String> {
 	arguments
 }
 
+/// Rewrite natural phrasings of commands into their strict form, e.g.
+/// `remind me in 30m to do X`. Other arguments are returned unchanged.
+pub fn normalize_arguments(arguments: Vec<String>) -> Vec<String> {
+	remind::natural_arguments(&arguments).unwrap_or(arguments)
+}
+
+/// Get the description of all commands as pretty-printed JSON, if the
+/// arguments ask for it via `help --json`.
+pub fn json_help(arguments: &[String]) -> Option<String> {
+	match arguments {
+		[help, flag] if help == "help" && flag == "--json" => {
+			serde_json::to_string_pretty(&command_json(&Command::command())).ok()
+		}
+		_ => None,
+	}
+}
+
+/// Describe the clap command with its arguments and sub-commands as JSON.
+fn command_json(command: &clap::Command) -> Value {
+	let arguments = command
+		.get_arguments()
+		.map(|arg| {
+			json!({
+				"name": arg.get_id().as_str(),
+				"long": arg.get_long(),
+				"positional": arg.is_positional(),
+				"required": arg.is_required_set(),
+				"description": arg.get_help().map(ToString::to_string),
+			})
+		})
+		.collect::<Vec<_>>();
+	json!({
+		"name": command.get_name(),
+		"description": command.get_about().map(ToString::to_string),
+		"arguments": arguments,
+		"subcommands": command.get_subcommands().map(command_json).collect::<Vec<_>>(),
+	})
+}
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,165 @@
+//! Settings of a room, overriding the global configuration for the room.
+
+use clap::{Args, Subcommand};
+use color_eyre::Result;
+use matrix_sdk::{async_trait, ruma::events::room::message::RoomMessageEventContent};
+
+use super::{BotCommand, Context};
+use crate::{
+	database::{RenderFormat, RoomPreferences},
+	timestamps,
+};
+
+/// Maximum offset of a timezone from UTC in hours.
+const MAX_OFFSET_HOURS: u16 = 14;
+
+/// Room settings command.
+#[derive(Debug, Args)]
+pub struct Preferences {
+	/// Settings command to execute.
+	#[command(subcommand)]
+	command: SubCommand,
+}
+
+/// Which settings sub-command to execute.
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+	/// Show the settings of this room.
+	Show,
+	/// Set the timezone of this room as offset from UTC, e.g. `+02:00` or
+	/// `UTC-5`. `default` uses the configured timezone again.
+	Timezone {
+		/// Offset from UTC or `default`.
+		#[arg(allow_hyphen_values = true)]
+		timezone: String,
+	},
+	/// Set the language of this room, e.g. `de` or `en-US`, which selects the
+	/// format of dates and times. `default` uses the configured format again.
+	Locale {
+		/// Language tag or `default`.
+		locale: String,
+	},
+}
+
+#[async_trait]
+impl BotCommand for Preferences {
+	async fn execute<'a>(&mut self, context: Context<'a>) -> Result<()> {
+		if !context.is_mod() {
+			tracing::trace!("Person not allowed to change room settings!");
+			return Ok(());
+		}
+
+		let room = context.room.room_id();
+		let mut preferences = RoomPreferences::for_room(room, &context.db.state)
+			.await?
+			.map_or_else(|| RoomPreferences::new(room.to_owned()), |doc| doc.contents);
+		let text = match &self.command {
+			SubCommand::Show => format_preferences(
+				&preferences,
+				context.config.timestamps.utc_offset_minutes,
+				context.config.rss.format,
+			),
+			SubCommand::Timezone { timezone } => match parse_timezone(timezone) {
+				Ok(offset) => {
+					preferences.utc_offset_minutes = offset;
+					preferences.save(&context.db.state).await?;
+					context.audit(format!("set the room timezone to {timezone}")).await?;
+					String::from("Successfully set the timezone.")
+				}
+				Err(err) => err,
+			},
+			SubCommand::Locale { locale } => match parse_locale(locale) {
+				Ok(parsed) => {
+					preferences.locale = parsed;
+					preferences.save(&context.db.state).await?;
+					context.audit(format!("set the room language to {locale}")).await?;
+					String::from("Successfully set the language.")
+				}
+				Err(err) => err,
+			},
+		};
+
+		let msg = RoomMessageEventContent::text_plain(text).make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+		Ok(())
+	}
+}
+
+/// Parse a timezone given as offset from UTC, e.g. `+02:00`, `UTC-5`, `-0530`
+/// or `UTC`. Returns the offset in minutes, or `None` for `default`.
+fn parse_timezone(s: &str) -> Result<Option<i32>, String> {
+	if s.eq_ignore_ascii_case("default") {
+		return Ok(None);
+	}
+	let invalid = || format!("`{s}` is not a valid offset from UTC, e.g. `+02:00`!");
+	if !s.is_ascii() {
+		return Err(invalid());
+	}
+
+	let offset = if s.len() >= 3 && s[..3].eq_ignore_ascii_case("utc") { &s[3..] } else { s };
+	if offset.is_empty() {
+		return Ok(Some(0));
+	}
+	let (sign, offset) = match (offset.strip_prefix('+'), offset.strip_prefix('-')) {
+		(Some(offset), _) => (1, offset),
+		(_, Some(offset)) => (-1, offset),
+		_ => (1, offset),
+	};
+	let (hours, minutes) = match offset.split_once(':') {
+		Some(parts) => parts,
+		None if offset.len() == 4 => offset.split_at(2),
+		None => (offset, "0"),
+	};
+	let hours: u16 = hours.parse().map_err(|_| invalid())?;
+	let minutes: u16 = minutes.parse().map_err(|_| invalid())?;
+	if hours > MAX_OFFSET_HOURS || minutes >= 60 {
+		return Err(invalid());
+	}
+	Ok(Some(sign * (i32::from(hours) * 60 + i32::from(minutes))))
+}
+
+/// Parse a language tag, e.g. `de` or `en-US`. Only languages with a known
+/// date format are accepted. Returns `None` for `default`.
+fn parse_locale(s: &str) -> Result<Option<String>, String> {
+	if s.eq_ignore_ascii_case("default") {
+		return Ok(None);
+	}
+	let valid = (2..=35).contains(&s.len())
+		&& s.starts_with(|c: char| c.is_ascii_alphabetic())
+		&& s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+	if !valid {
+		Err(format!("`{s}` is not a valid language tag, e.g. `de` or `en-US`!"))
+	} else if timestamps::locale_format(s).is_none() {
+		Err(format!("`{s}` is not a supported language, e.g. `de` or `en-US`!"))
+	} else {
+		Ok(Some(s.to_owned()))
+	}
+}
+
+/// Format an offset from UTC in minutes, e.g. `UTC+02:00`.
+fn format_offset(minutes: i32) -> String {
+	let sign = if minutes < 0 { '-' } else { '+' };
+	let minutes = minutes.unsigned_abs();
+	format!("UTC{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// Format the room's settings, noting which ones fall back to the defaults.
+fn format_preferences(
+	preferences: &RoomPreferences,
+	default_offset: i32,
+	default_format: RenderFormat,
+) -> String {
+	let timezone = match preferences.utc_offset_minutes {
+		Some(offset) => format_offset(offset),
+		None => format!("{} (default)", format_offset(default_offset)),
+	};
+	let locale = preferences.locale.as_deref().unwrap_or("default");
+	let rss_format = match preferences.rss_format {
+		Some(format) => format.to_string(),
+		None => format!("{default_format} (default)"),
+	};
+	format!("Timezone: {timezone}\nLanguage: {locale}\nRSS format: {rss_format}")
+}
+
+#[cfg(test)]
+mod tests;
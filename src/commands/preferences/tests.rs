@@ -0,0 +1,102 @@
+//! Tests for the room settings command.
+
+use color_eyre::Result;
+use matrix_sdk::ruma::OwnedRoomId;
+use time::OffsetDateTime;
+
+use super::*;
+use crate::{
+	settings::TimestampSettings, test_utils::test_databases, timestamps::TimestampFormatter,
+};
+
+#[test]
+fn timezones_are_parsed() {
+	assert_eq!(parse_timezone("+02:00"), Ok(Some(120)));
+	assert_eq!(parse_timezone("UTC-5"), Ok(Some(-300)));
+	assert_eq!(parse_timezone("utc+0530"), Ok(Some(330)));
+	assert_eq!(parse_timezone("-09:30"), Ok(Some(-570)));
+	assert_eq!(parse_timezone("UTC"), Ok(Some(0)));
+	assert_eq!(parse_timezone("default"), Ok(None));
+	assert!(parse_timezone("+15").is_err());
+	assert!(parse_timezone("+02:60").is_err());
+	assert!(parse_timezone("Europe/Berlin").is_err());
+	assert!(parse_timezone("ü").is_err());
+}
+
+#[test]
+fn locales_are_validated() {
+	assert_eq!(parse_locale("de"), Ok(Some(String::from("de"))));
+	assert_eq!(parse_locale("en-US"), Ok(Some(String::from("en-US"))));
+	assert_eq!(parse_locale("default"), Ok(None));
+	assert_eq!(parse_locale("de-AT"), Ok(Some(String::from("de-AT"))));
+	assert!(parse_locale("x").is_err());
+	assert!(parse_locale("de_DE").is_err());
+	assert!(parse_locale("tlh").is_err());
+}
+
+#[test]
+fn preferences_are_formatted() -> Result<()> {
+	let mut preferences = RoomPreferences::new("!room:example.com".try_into()?);
+	assert_eq!(
+		format_preferences(&preferences, 60, RenderFormat::Normal),
+		"Timezone: UTC+01:00 (default)\nLanguage: default\nRSS format: normal (default)"
+	);
+
+	preferences.utc_offset_minutes = Some(-330);
+	preferences.locale = Some(String::from("de"));
+	preferences.rss_format = Some(RenderFormat::Compact);
+	assert_eq!(
+		format_preferences(&preferences, 60, RenderFormat::Normal),
+		"Timezone: UTC-05:30\nLanguage: de\nRSS format: compact"
+	);
+	Ok(())
+}
+
+#[tokio::test]
+async fn room_timezone_overrides_global() -> Result<()> {
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let other: OwnedRoomId = "!other:example.com".try_into()?;
+	let global = TimestampSettings {
+		utc_offset_minutes: 0,
+		format: String::from("[hour]:[minute] UTC[offset_hour sign:mandatory]"),
+	};
+	let mut preferences = RoomPreferences::new(room.clone());
+	preferences.utc_offset_minutes = Some(120);
+	preferences.save(&db.state).await?;
+
+	let at = OffsetDateTime::from_unix_timestamp(1_664_964_000)?;
+	let settings = RoomPreferences::timestamps_for(&room, &global, &db.state).await?;
+	assert_eq!(TimestampFormatter::new(&settings, at).absolute(at), "12:00 UTC+02");
+	let settings = RoomPreferences::timestamps_for(&other, &global, &db.state).await?;
+	assert_eq!(TimestampFormatter::new(&settings, at).absolute(at), "10:00 UTC+00");
+
+	RoomPreferences::new(room.clone()).save(&db.state).await?;
+	assert!(RoomPreferences::for_room(&room, &db.state).await?.is_none());
+	Ok(())
+}
+
+#[tokio::test]
+async fn room_locale_selects_the_date_format() -> Result<()> {
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let global = TimestampSettings {
+		utc_offset_minutes: 0,
+		format: String::from("[year]-[month]-[day] [hour]:[minute]"),
+	};
+	let at = OffsetDateTime::from_unix_timestamp(1_664_964_000)?;
+
+	let mut preferences = RoomPreferences::new(room.clone());
+	for (locale, expected) in [
+		("de", "05.10.2022 10:00"),
+		("en-US", "10/05/2022 10:00 AM"),
+		("en-GB", "05/10/2022 10:00"),
+		("default", "2022-10-05 10:00"),
+	] {
+		preferences.locale = parse_locale(locale).map_err(color_eyre::eyre::Report::msg)?;
+		preferences.clone().save(&db.state).await?;
+		let settings = RoomPreferences::timestamps_for(&room, &global, &db.state).await?;
+		assert_eq!(TimestampFormatter::new(&settings, at).absolute(at), expected, "{locale}");
+	}
+	Ok(())
+}
@@ -2,46 +2,213 @@
 
 use std::time::Duration;
 
+use bonsaidb::{
+	core::schema::{CollectionDocument, SerializedCollection},
+	local::AsyncDatabase,
+};
 use bonsaimq::JobRegister;
-use clap::Args;
+use clap::{Args, Subcommand};
 use matrix_sdk::{
 	async_trait,
-	ruma::{events::room::message::RoomMessageEventContent, OwnedUserId},
+	ruma::{
+		events::{
+			reaction::{ReactionEventContent, Relation},
+			room::message::{
+				OriginalRoomMessageEvent, Relation as MessageRelation, RoomMessageEventContent,
+			},
+			AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent,
+		},
+		EventId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId, RoomId, RoomOrAliasId, UserId,
+	},
+	Client,
 };
-use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, Time, UtcOffset};
 
 use super::{BotCommand, Context};
-use crate::jobs::{remind::RemindInput, JobRegistry};
+use crate::{
+	database::{Databases, Reminder},
+	jobs::{
+		remind::{job_id, RemindInput},
+		JobRegistry,
+	},
+	matrix,
+	settings::ReminderConfirmation,
+	timestamps::TimestampFormatter,
+};
+
+/// Number of reminders shown per page when listing reminders.
+const PAGE_SIZE: usize = 20;
 
 /// Remind command.
 #[derive(Debug, Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Remind {
+	/// Reminder sub-command to execute instead of scheduling a reminder.
+	#[command(subcommand)]
+	command: Option<SubCommand>,
 	/// Who to remind (MXID) or reminds yourself if not given.
 	#[arg(short, long)]
 	who: Option<OwnedUserId>,
+	/// Room (ID or alias) to send the reminder to instead of this room (admins
+	/// only).
+	#[arg(long)]
+	room: Option<OwnedRoomOrAliasId>,
 	/// When to remind. Can be either a duration to wait until reminding (e.g
-	/// "5:30" for remind in 5 hours and 30 minutes) or a specific date-time
+	/// "5:30" for remind in 5 hours and 30 minutes), a time of day with am/pm
+	/// (e.g. "9am" or "2:30pm") in the room's timezone or a specific date-time
 	/// when it should happen in RFC3339 format.
-	#[arg(value_parser = parse_when)]
-	when: OffsetDateTime,
-	/// Reminder message.
-	message: String,
+	#[arg(value_parser = parse_when, required = true)]
+	when: Option<When>,
+	/// Reminder message. Can be left out when replying to a message, which is
+	/// then quoted in the reminder.
+	message: Option<String>,
+}
+
+/// Which reminder sub-command to execute.
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+	/// List pending reminders of this room. Everyone but mods only sees their
+	/// own reminders.
+	List {
+		/// List pending reminders across all rooms (admins only).
+		#[arg(long)]
+		all: bool,
+		/// Page of the list to show.
+		#[arg(long, default_value_t = 1)]
+		page: usize,
+		/// Only list reminders of this person (MXID).
+		#[arg(long)]
+		who: Option<OwnedUserId>,
+		/// Only list reminders firing before this time, in the same format as
+		/// when scheduling.
+		#[arg(long, value_parser = parse_when)]
+		before: Option<When>,
+		/// Only list reminders firing after this time, in the same format as
+		/// when scheduling.
+		#[arg(long, value_parser = parse_when)]
+		after: Option<When>,
+	},
+	/// Change the time or message of a pending reminder.
+	Edit {
+		/// ID of the reminder as shown in the list.
+		id: u64,
+		/// New time to remind, in the same format as when scheduling.
+		#[arg(long, value_parser = parse_when)]
+		when: Option<When>,
+		/// New reminder message.
+		#[arg(long)]
+		message: Option<String>,
+	},
+}
+
+/// When to execute a reminder, as given in the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum When {
+	/// Specific date-time.
+	At(OffsetDateTime),
+	/// Time of day at its next occurrence, in the room's timezone.
+	Clock(Time),
+}
+
+impl When {
+	/// Resolve to the date-time to execute the reminder at. Times of day are
+	/// scheduled for today if still in the future, tomorrow otherwise.
+	fn resolve(self, now: OffsetDateTime, offset: UtcOffset) -> OffsetDateTime {
+		match self {
+			When::At(when) => when,
+			When::Clock(time) => {
+				let now = now.to_offset(offset);
+				let today = now.replace_time(time);
+				if today > now {
+					today
+				} else {
+					today + time::Duration::DAY
+				}
+			}
+		}
+	}
+}
+
+/// Filter of the listed reminders.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ReminderFilter {
+	/// Only reminders of this person.
+	who: Option<OwnedUserId>,
+	/// Only reminders firing before this time.
+	before: Option<OffsetDateTime>,
+	/// Only reminders firing after this time.
+	after: Option<OffsetDateTime>,
 }
 
-/// Parse "when" string into a specific date-time to execute the reminder.
-fn parse_when(s: &str) -> Result<OffsetDateTime, String> {
+impl ReminderFilter {
+	/// Whether the reminder passes the filter.
+	fn matches(&self, reminder: &Reminder) -> bool {
+		self.who.as_ref().map_or(true, |who| reminder.who == *who)
+			&& self.before.map_or(true, |before| reminder.fire_at < before)
+			&& self.after.map_or(true, |after| reminder.fire_at > after)
+	}
+
+	/// Restrict the filter to the reminders the sender may see: mods see
+	/// everyone's reminders, others only their own. Returns `None` if others'
+	/// reminders are requested without permission.
+	fn scoped(mut self, sender: &UserId, is_mod: bool) -> Option<Self> {
+		if is_mod {
+			return Some(self);
+		}
+		match &self.who {
+			Some(who) if who != sender => None,
+			_ => {
+				self.who = Some(sender.to_owned());
+				Some(self)
+			}
+		}
+	}
+}
+
+/// Parse "when" string into a specific date-time or time of day to execute the
+/// reminder.
+fn parse_when(s: &str) -> Result<When, String> {
 	if let Ok(when) = OffsetDateTime::parse(s, &Rfc3339) {
-		Ok(when)
+		Ok(When::At(when))
+	} else if let Some(time) = parse_clock_time(s) {
+		Ok(When::Clock(time))
 	} else {
 		let now = OffsetDateTime::now_utc();
 		let when_duration = parse_when_duration(s)?;
-		Ok(now + when_duration)
+		Ok(When::At(now + when_duration))
 	}
 }
 
-/// Parse the when string as a [`Duration`] in the format of `%h:%m` or just
-/// `%m`.
+/// Parse a time of day with am/pm, e.g. `9am`, `12pm` or `2:30pm`.
+fn parse_clock_time(s: &str) -> Option<Time> {
+	let lower = s.to_ascii_lowercase();
+	let (clock, afternoon) = match (lower.strip_suffix("am"), lower.strip_suffix("pm")) {
+		(Some(clock), _) => (clock, false),
+		(_, Some(clock)) => (clock, true),
+		_ => return None,
+	};
+	let (hour, minute) = match clock.split_once(':') {
+		Some((hour, minute)) if minute.len() == 2 => (hour, minute.parse::<u8>().ok()?),
+		Some(_) => return None,
+		None => (clock, 0),
+	};
+	let hour = hour.parse::<u8>().ok().filter(|hour| (1..=12).contains(hour))?;
+	let hour = match (hour, afternoon) {
+		(12, false) => 0,
+		(12, true) => 12,
+		(hour, false) => hour,
+		(hour, true) => hour + 12,
+	};
+	Time::from_hms(hour, minute, 0).ok()
+}
+
+/// Parse the when string as a [`Duration`] in the format of `%h:%m`, just
+/// `%m` or with units like `1h30m`.
 fn parse_when_duration(s: &str) -> Result<Duration, String> {
+	if let Some(duration) = parse_unit_duration(s) {
+		return Ok(duration);
+	}
+
 	match s.split_once(':') {
 		Some((hours, minutes)) => {
 			let hours: u32 = hours.parse().map_err(|_| format!("`{hours}` is not a number!"))?;
@@ -60,13 +227,66 @@ fn parse_when_duration(s: &str) -> Result<Duration, String> {
 	}
 }
 
+/// Parse a duration with hour and minute units, e.g. `2h`, `30m` or `1h30m`.
+fn parse_unit_duration(s: &str) -> Option<Duration> {
+	let (hours, minutes) = match s.split_once('h') {
+		Some((hours, minutes)) => (hours.parse::<u32>().ok()?, minutes),
+		None => (0, s),
+	};
+	let minutes = match minutes {
+		"" if s.ends_with('h') => 0,
+		minutes => minutes.strip_suffix('m')?.parse::<u32>().ok()?,
+	};
+	Some(Duration::from_secs(u64::from(hours) * 60 * 60 + u64::from(minutes) * 60))
+}
+
+/// Rewrite the natural self-reminder form `remind me [in|at] <when> [to]
+/// <message...>` into the strict form `remind <when> <message>`. Returns `None`
+/// if the arguments are not in the natural form.
+pub fn natural_arguments(arguments: &[String]) -> Option<Vec<String>> {
+	let rest = match arguments {
+		[command, me, rest @ ..] if command == "remind" && me == "me" => rest,
+		_ => return None,
+	};
+	let rest = match rest {
+		[filler, rest @ ..] if filler == "in" || filler == "at" => rest,
+		rest => rest,
+	};
+	let (when, message) = rest.split_first()?;
+	let message = match message {
+		[filler, message @ ..] if filler == "to" => message,
+		message => message,
+	};
+
+	let mut strict = vec![String::from("remind"), when.clone()];
+	if !message.is_empty() {
+		strict.push(message.join(" "));
+	}
+	Some(strict)
+}
+
 #[async_trait]
 impl BotCommand for Remind {
 	async fn execute<'a>(&mut self, context: Context<'a>) -> color_eyre::Result<()> {
+		match self.command.take() {
+			Some(SubCommand::List { all, page, who, before, after }) => {
+				let now = OffsetDateTime::now_utc();
+				let offset = context.timestamp_settings().await?.utc_offset();
+				let filter = ReminderFilter {
+					who,
+					before: before.map(|before| before.resolve(now, offset)),
+					after: after.map(|after| after.resolve(now, offset)),
+				};
+				return list(&context, all, page, filter).await;
+			}
+			Some(SubCommand::Edit { id, when, message }) => {
+				return edit(&context, id, when, message).await;
+			}
+			None => {}
+		}
+
 		let who = if let Some(user_id) = self.who.take() {
-			let is_mod = context.config.access.admins.contains(&context.event.sender)
-				|| context.config.access.mods.contains(&context.event.sender);
-			if !is_mod {
+			if !context.is_mod() {
 				tracing::trace!("Person not allowed to remind others!");
 				return Ok(());
 			}
@@ -75,22 +295,357 @@ impl BotCommand for Remind {
 			context.event.sender.clone()
 		};
 
-		let delay = Duration::try_from(self.when - OffsetDateTime::now_utc()).unwrap_or_default();
-		let room_id = context.room.room_id().to_owned();
-		let remind_input = RemindInput { who, room_id, message: self.message.clone() };
+		let room = match target_room(
+			context.client,
+			context.room.room_id(),
+			self.room.as_deref(),
+			context.is_admin(),
+		)
+		.await
+		{
+			Ok(room) => room,
+			Err(reason) => {
+				let msg = RoomMessageEventContent::text_plain(reason).make_reply_to(context.event);
+				context.room.send(msg, None).await?;
+				return Ok(());
+			}
+		};
 
-		JobRegistry::Remind
-			.builder()
-			.delay(delay)
-			.payload_json(remind_input)?
-			.spawn(&context.db.jobs)
+		let now = OffsetDateTime::now_utc();
+		let offset = context.timestamp_settings().await?.utc_offset();
+		let when = self.when.map_or(now, |when| when.resolve(now, offset));
+		let message = match self.message.take() {
+			Some(message) => message,
+			None => {
+				if let Some(message) = replied_message(&context).await? {
+					message
+				} else {
+					let msg = RoomMessageEventContent::text_plain(
+						"Please give a reminder message or reply to a message.",
+					)
+					.make_reply_to(context.event);
+					context.room.send(msg, None).await?;
+					return Ok(());
+				}
+			}
+		};
+		let reminder = Reminder { room, who, message, fire_at: when, generation: 0 }
+			.push_into_async(&context.db.state)
 			.await?;
+		spawn_job(&reminder, &context.db.jobs).await?;
+		if &*reminder.contents.room != context.room.room_id() {
+			context
+				.audit(format!(
+					"scheduled reminder #{} into {}",
+					reminder.header.id, reminder.contents.room
+				))
+				.await?;
+		}
 
 		tracing::trace!("Scheduled reminder message.");
-		let scheduled_msg = RoomMessageEventContent::text_plain("Successfully scheduled reminder.")
-			.make_reply_to(context.event);
-		context.room.send(scheduled_msg, None).await?;
+		match context.config.reminders.confirmation {
+			ReminderConfirmation::Message => {
+				let timestamps = context.timestamps().await?;
+				let scheduled_msg = RoomMessageEventContent::text_plain(confirmation_text(
+					&timestamps,
+					reminder.contents.fire_at,
+				))
+				.make_reply_to(context.event);
+				context.room.send(scheduled_msg, None).await?;
+			}
+			ReminderConfirmation::Reaction => {
+				context.room.send(confirmation_reaction(context.event), None).await?;
+			}
+			ReminderConfirmation::None => {}
+		}
 
 		Ok(())
 	}
 }
+
+/// Confirmation of a scheduled reminder, showing the resolved absolute time in
+/// the room's timezone and the time until then, so that misparsed times stand
+/// out.
+fn confirmation_text(timestamps: &TimestampFormatter, fire_at: OffsetDateTime) -> String {
+	format!("Reminder scheduled for {}.", timestamps.scheduled(fire_at))
+}
+
+/// Get the room to send the reminder to. Only admins can target another room
+/// than the current one, which has to be resolvable and joined by the bot.
+/// Returns the reason to reply with otherwise.
+async fn target_room(
+	client: &Client,
+	current: &RoomId,
+	requested: Option<&RoomOrAliasId>,
+	is_admin: bool,
+) -> Result<OwnedRoomId, &'static str> {
+	let requested = match requested {
+		Some(requested) => requested,
+		None => return Ok(current.to_owned()),
+	};
+	if !is_admin {
+		tracing::trace!("Person not allowed to schedule reminders into other rooms!");
+		return Err("Only admins can schedule reminders into other rooms.");
+	}
+
+	let room_id = resolve_room(client, requested).await.ok_or("Could not resolve the room.")?;
+	if client.get_joined_room(&room_id).is_none() {
+		return Err("I am not joined to the room.");
+	}
+	Ok(room_id)
+}
+
+/// Resolve a room ID or alias to the room ID.
+async fn resolve_room(client: &Client, room: &RoomOrAliasId) -> Option<OwnedRoomId> {
+	match <&RoomId>::try_from(room) {
+		Ok(room_id) => Some(room_id.to_owned()),
+		Err(alias) => match client.resolve_room_alias(alias).await {
+			Ok(response) => Some(response.room_id),
+			Err(err) => {
+				tracing::debug!("Could not resolve room alias {alias}: {err}");
+				None
+			}
+		},
+	}
+}
+
+/// Spawn the job firing the reminder in its current generation.
+async fn spawn_job(
+	reminder: &CollectionDocument<Reminder>,
+	jobs: &AsyncDatabase,
+) -> color_eyre::Result<()> {
+	let delay = Duration::try_from(reminder.contents.fire_at - OffsetDateTime::now_utc())
+		.unwrap_or_default();
+	let remind_input = RemindInput {
+		who: reminder.contents.who.clone(),
+		room_id: reminder.contents.room.clone(),
+		message: reminder.contents.message.clone(),
+		reminder: Some(reminder.header.id),
+		generation: reminder.contents.generation,
+		fire_at: Some(reminder.contents.fire_at),
+	};
+
+	JobRegistry::Remind
+		.builder()
+		.id(job_id(reminder.header.id, reminder.contents.generation))
+		.delay(delay)
+		.payload_json(remind_input)?
+		.spawn(jobs)
+		.await?;
+	Ok(())
+}
+
+/// Edit the time or message of a pending reminder of this room. Allowed for
+/// the reminded person and mods.
+async fn edit(
+	context: &Context<'_>,
+	id: u64,
+	when: Option<When>,
+	message: Option<String>,
+) -> color_eyre::Result<()> {
+	let reminder = Reminder::get_async(id, &context.db.state)
+		.await?
+		.filter(|reminder| &*reminder.contents.room == context.room.room_id());
+	let mut reminder = match reminder {
+		Some(reminder) => reminder,
+		None => {
+			let msg = RoomMessageEventContent::text_plain("Reminder not found.")
+				.make_reply_to(context.event);
+			context.room.send(msg, None).await?;
+			return Ok(());
+		}
+	};
+
+	if !context.is_mod() && reminder.contents.who != context.event.sender {
+		tracing::trace!("Person not allowed to edit the reminder!");
+		return Ok(());
+	}
+
+	let offset = context.timestamp_settings().await?.utc_offset();
+	let when = when.map(|when| when.resolve(OffsetDateTime::now_utc(), offset));
+	reschedule(&mut reminder, when, message, context.db).await?;
+	context.audit(format!("edited reminder #{id}")).await?;
+
+	let timestamps = context.timestamps().await?;
+	let fire_at = timestamps.format(reminder.contents.fire_at);
+	let (html, body) = edit_reply(id, &fire_at, &reminder.contents.message);
+	let msg = RoomMessageEventContent::text_html(body, html).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Reply to an edited reminder as HTML and plain body, rendering the message
+/// as Markdown.
+fn edit_reply(id: u64, fire_at: &str, message: &str) -> (String, String) {
+	let body = format!("Reminder #{id} is scheduled for `{fire_at}`: {message}");
+	let html = format!(
+		"Reminder #{id} is scheduled for <code>{}</code>: {}",
+		matrix::escape_html(fire_at),
+		matrix::markdown_to_html(message)
+	);
+	(html, body)
+}
+
+/// Update the time and message of the reminder and schedule a new job for it.
+/// The previously scheduled job is outdated by the new generation and does not
+/// fire anymore.
+async fn reschedule(
+	reminder: &mut CollectionDocument<Reminder>,
+	when: Option<OffsetDateTime>,
+	message: Option<String>,
+	db: &Databases,
+) -> color_eyre::Result<()> {
+	if let Some(when) = when {
+		reminder.contents.fire_at = when;
+	}
+	if let Some(message) = message {
+		reminder.contents.message = message;
+	}
+	reminder.contents.generation += 1;
+	reminder.update_async(&db.state).await?;
+	spawn_job(reminder, &db.jobs).await
+}
+
+/// Get the reminder message quoting the message the command replied to, if it
+/// is a reply.
+async fn replied_message(context: &Context<'_>) -> color_eyre::Result<Option<String>> {
+	let event_id = match &context.event.content.relates_to {
+		Some(MessageRelation::Reply { in_reply_to }) => in_reply_to.event_id.clone(),
+		_ => return Ok(None),
+	};
+
+	let event = context.room.event(&event_id).await?.event.deserialize()?;
+	let body = match &event {
+		AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+			MessageLikeEvent::Original(event),
+		)) => event.content.body(),
+		_ => "",
+	};
+	Ok(Some(reply_reminder_message(body, context.room.room_id(), &event_id)))
+}
+
+/// Build the reminder message for a reply to a message with the given body.
+fn reply_reminder_message(body: &str, room_id: &RoomId, event_id: &EventId) -> String {
+	let quote = matrix::strip_reply_fallback(body).trim();
+	let link = format!("https://matrix.to/#/{room_id}/{event_id}");
+	if quote.is_empty() {
+		link
+	} else {
+		format!("\"{quote}\" ({link})")
+	}
+}
+
+/// Reaction confirming the command message.
+fn confirmation_reaction(event: &OriginalRoomMessageEvent) -> ReactionEventContent {
+	ReactionEventContent::new(Relation::new(event.event_id.clone(), String::from("✅")))
+}
+
+/// List pending reminders of the room, or of all rooms for admins, that pass
+/// the filter. Everyone but mods only sees their own reminders.
+async fn list(
+	context: &Context<'_>,
+	all: bool,
+	page: usize,
+	filter: ReminderFilter,
+) -> color_eyre::Result<()> {
+	if all && !context.is_admin() {
+		tracing::trace!("Person not allowed to list reminders of all rooms!");
+		return Ok(());
+	}
+	let filter = match filter.scoped(&context.event.sender, context.is_mod()) {
+		Some(filter) => filter,
+		None => {
+			let msg = RoomMessageEventContent::text_plain("You can only list your own reminders.")
+				.make_reply_to(context.event);
+			context.room.send(msg, None).await?;
+			return Ok(());
+		}
+	};
+
+	let reminders = if all {
+		Reminder::all_by_time(&context.db.state).await?
+	} else {
+		let mut reminders = Reminder::for_room(context.room.room_id(), &context.db.state)
+			.await?
+			.into_values()
+			.collect::<Vec<_>>();
+		reminders.sort_by_key(|doc| doc.contents.fire_at);
+		reminders
+	};
+	let reminders = reminders
+		.into_iter()
+		.filter(|doc| filter.matches(&doc.contents))
+		.map(|doc| (doc.header.id, doc.contents))
+		.collect::<Vec<_>>();
+
+	let timestamps = context.timestamps().await?;
+	let (html, body) = if all {
+		format_reminders(
+			&reminders,
+			page,
+			&timestamps,
+			Some(&|room_id: &RoomId| room_display_name(context, room_id)),
+		)
+	} else {
+		format_reminders(&reminders, page, &timestamps, None)
+	};
+	let msg = RoomMessageEventContent::text_html(body, html).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Get a displayable name for a room, including its ID.
+fn room_display_name(context: &Context<'_>, room_id: &RoomId) -> String {
+	context
+		.client
+		.get_room(room_id)
+		.and_then(|room| room.name())
+		.map_or_else(|| room_id.to_string(), |name| format!("{name} ({room_id})"))
+}
+
+/// Format a page of the reminders with their IDs as list, as HTML and plain
+/// body. The messages are rendered as Markdown. Includes the room if a room
+/// naming function is given.
+fn format_reminders(
+	reminders: &[(u64, Reminder)],
+	page: usize,
+	timestamps: &TimestampFormatter,
+	room_name: Option<&dyn Fn(&RoomId) -> String>,
+) -> (String, String) {
+	if reminders.is_empty() {
+		let text = String::from("Currently, there are no pending reminders.");
+		return (text.clone(), text);
+	}
+
+	let pages = reminders.len().div_ceil(PAGE_SIZE);
+	let page = page.clamp(1, pages);
+	let mut items = Vec::new();
+	let mut lines = Vec::new();
+	for (id, reminder) in reminders.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+		let fire_at = timestamps.absolute(reminder.fire_at);
+		let mut item = format!(
+			"#{id} <code>{}</code> {}: {}",
+			matrix::escape_html(&fire_at),
+			matrix::escape_html(reminder.who.as_str()),
+			matrix::markdown_to_html(&reminder.message)
+		);
+		let mut line = format!("- #{id} `{fire_at}` {}: {}", reminder.who, reminder.message);
+		if let Some(room_name) = room_name {
+			let room = room_name(&reminder.room);
+			item.push_str(&format!(" (in {})", matrix::escape_html(&room)));
+			line.push_str(&format!(" (in {room})"));
+		}
+		items.push(format!("<li>{item}</li>"));
+		lines.push(line);
+	}
+
+	let mut html = format!("<ul>{}</ul>", items.concat());
+	if pages > 1 {
+		html.push_str(&format!("<p>Page {page}/{pages}</p>"));
+		lines.push(format!("\nPage {page}/{pages}"));
+	}
+	(html, lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,332 @@
+//! Tests for the remind command.
+
+use clap::Parser;
+use color_eyre::eyre::{bail, eyre};
+use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, OwnedRoomOrAliasId, OwnedUserId};
+use time::Duration;
+
+use super::*;
+use crate::{
+	commands::{normalize_arguments, parse_arguments, Command},
+	settings::TimestampSettings,
+	test_utils::{test_databases, text_message_event, MockResponse, MockServer},
+};
+
+/// Create reminders for testing.
+fn reminders(count: i64) -> color_eyre::Result<Vec<(u64, Reminder)>> {
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let who: OwnedUserId = "@user:example.com".try_into()?;
+	let now = OffsetDateTime::now_utc();
+	Ok((0..count)
+		.zip(1_u64..)
+		.map(|(i, id)| {
+			let reminder = Reminder {
+				room: room.clone(),
+				who: who.clone(),
+				message: format!("message {i}"),
+				fire_at: now + Duration::minutes(i),
+				generation: 0,
+			};
+			(id, reminder)
+		})
+		.collect())
+}
+
+/// Timestamp formatter with the default settings.
+fn timestamps() -> TimestampFormatter {
+	TimestampFormatter::new(&TimestampSettings::default(), OffsetDateTime::now_utc())
+}
+
+#[test]
+fn format_reminders_paginates() -> color_eyre::Result<()> {
+	let reminders = reminders(25)?;
+
+	let (_, first) = format_reminders(&reminders, 1, &timestamps(), None);
+	assert!(first.contains("message 0"));
+	assert!(first.contains("message 19"));
+	assert!(!first.contains("message 20"));
+	assert!(first.ends_with("Page 1/2"));
+
+	let (_, second) = format_reminders(&reminders, 2, &timestamps(), None);
+	assert!(second.contains("message 24"));
+	assert!(!second.contains("message 19"));
+	assert!(second.ends_with("Page 2/2"));
+	Ok(())
+}
+
+#[test]
+fn confirmation_shows_absolute_and_relative_time() -> color_eyre::Result<()> {
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?; // 2022-10-05 20:00 UTC
+	let settings = TimestampSettings {
+		utc_offset_minutes: 120,
+		format: "[year]-[month]-[day] [hour]:[minute] UTC[offset_hour sign:mandatory]".to_owned(),
+	};
+	let timestamps = TimestampFormatter::new(&settings, now);
+
+	assert_eq!(
+		confirmation_text(&timestamps, now + Duration::minutes(192)),
+		"Reminder scheduled for 2022-10-06 01:12 UTC+02 (in 3h12m)."
+	);
+	assert_eq!(
+		confirmation_text(&timestamps, now),
+		"Reminder scheduled for 2022-10-05 22:00 UTC+02 (now)."
+	);
+	Ok(())
+}
+
+#[test]
+fn format_reminders_with_rooms() -> color_eyre::Result<()> {
+	let reminders = reminders(1)?;
+	let (_, formatted) = format_reminders(
+		&reminders,
+		1,
+		&timestamps(),
+		Some(&|room: &RoomId| format!("Room {room}")),
+	);
+	assert!(formatted.contains("(in Room !room:example.com)"));
+	assert_eq!(
+		format_reminders(&[], 1, &timestamps(), None).1,
+		"Currently, there are no pending reminders."
+	);
+	Ok(())
+}
+
+#[test]
+fn reminder_filter_by_person_and_time() -> color_eyre::Result<()> {
+	let mut reminders = reminders(5)?;
+	let other: OwnedUserId = "@other:example.com".try_into()?;
+	reminders[1].1.who = other.clone();
+	let start = reminders[0].1.fire_at;
+	let ids = |filter: &ReminderFilter| {
+		reminders
+			.iter()
+			.filter(|(_, reminder)| filter.matches(reminder))
+			.map(|(id, _)| *id)
+			.collect::<Vec<_>>()
+	};
+
+	assert_eq!(ids(&ReminderFilter::default()), vec![1, 2, 3, 4, 5]);
+	assert_eq!(ids(&ReminderFilter { who: Some(other), ..ReminderFilter::default() }), vec![2]);
+	let range = ReminderFilter {
+		after: Some(start),
+		before: Some(start + Duration::minutes(3)),
+		..ReminderFilter::default()
+	};
+	assert_eq!(ids(&range), vec![2, 3]);
+	Ok(())
+}
+
+#[test]
+fn reminder_list_is_scoped_to_own_reminders() -> color_eyre::Result<()> {
+	let user: OwnedUserId = "@user:example.com".try_into()?;
+	let other: OwnedUserId = "@other:example.com".try_into()?;
+	let everyone = ReminderFilter::default();
+	let of_other = ReminderFilter { who: Some(other.clone()), ..ReminderFilter::default() };
+	let of_user = ReminderFilter { who: Some(user.clone()), ..ReminderFilter::default() };
+
+	assert_eq!(everyone.clone().scoped(&user, true), Some(everyone.clone()));
+	assert_eq!(of_other.clone().scoped(&user, true), Some(of_other.clone()));
+	assert_eq!(everyone.scoped(&user, false), Some(of_user.clone()));
+	assert_eq!(of_user.clone().scoped(&user, false), Some(of_user));
+	assert_eq!(of_other.scoped(&user, false), None);
+	Ok(())
+}
+
+#[test]
+fn confirmation_reaction_relates_to_command() -> color_eyre::Result<()> {
+	let event = text_message_event("@user:example.com", "!remind 5 test")?;
+	let reaction = confirmation_reaction(&event);
+	assert_eq!(reaction.relates_to.event_id, event.event_id);
+	assert_eq!(reaction.relates_to.key, "✅");
+	Ok(())
+}
+
+#[test]
+fn reply_reminder_quotes_message() -> color_eyre::Result<()> {
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let event_id: OwnedEventId = "$original:example.com".try_into()?;
+
+	let message =
+		reply_reminder_message("> <@other:example.com> Earlier\n\nDo the thing", &room, &event_id);
+	assert_eq!(
+		message,
+		"\"Do the thing\" (https://matrix.to/#/!room:example.com/$original:example.com)"
+	);
+
+	let message = reply_reminder_message("", &room, &event_id);
+	assert_eq!(message, "https://matrix.to/#/!room:example.com/$original:example.com");
+	Ok(())
+}
+
+#[test]
+fn format_reminders_shows_ids() -> color_eyre::Result<()> {
+	let reminders = reminders(2)?;
+	let (_, formatted) = format_reminders(&reminders, 1, &timestamps(), None);
+	assert!(formatted.starts_with("- #1 `"));
+	assert!(formatted.contains("- #2 `"));
+	Ok(())
+}
+
+#[test]
+fn format_reminders_escapes_messages() -> color_eyre::Result<()> {
+	let mut reminders = reminders(1)?;
+	reminders[0].1.message = String::from("**Tea** is <ready> & hot");
+	let (html, body) = format_reminders(&reminders, 1, &timestamps(), None);
+	assert!(html.starts_with("<ul><li>#1 <code>"));
+	assert!(html.ends_with(": <strong>Tea</strong> is &lt;ready&gt; &amp; hot</li></ul>"));
+	assert!(body.ends_with(": **Tea** is <ready> & hot"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn edit_reschedules_job() -> color_eyre::Result<()> {
+	let db = test_databases().await?;
+	let mut reminder = reminders(1)?.remove(0).1.push_into_async(&db.state).await?;
+	let id = reminder.header.id;
+	spawn_job(&reminder, &db.jobs).await?;
+	assert!(bonsaimq::job_exists(job_id(id, 0), &db.jobs).await?);
+
+	let when = OffsetDateTime::now_utc() + Duration::hours(3);
+	reschedule(&mut reminder, Some(when), None, &db).await?;
+
+	assert!(bonsaimq::job_exists(job_id(id, 1), &db.jobs).await?);
+	let stored =
+		Reminder::get_async(id, &db.state).await?.ok_or_else(|| eyre!("Reminder vanished"))?;
+	assert_eq!(stored.contents.fire_at, when);
+	assert_eq!(stored.contents.generation, 1);
+	assert_eq!(stored.contents.message, "message 0");
+	Ok(())
+}
+
+#[test]
+fn unit_durations() {
+	assert_eq!(parse_when_duration("30m"), Ok(std::time::Duration::from_secs(30 * 60)));
+	assert_eq!(parse_unit_duration("2h"), Some(std::time::Duration::from_secs(2 * 60 * 60)));
+	assert_eq!(parse_unit_duration("1h30m"), Some(std::time::Duration::from_secs(90 * 60)));
+	assert_eq!(parse_unit_duration("30"), None);
+	assert_eq!(parse_unit_duration("h"), None);
+	assert_eq!(parse_unit_duration("5x"), None);
+}
+
+#[test]
+fn clock_times_are_parsed() {
+	let time = |hour, minute| Time::from_hms(hour, minute, 0).ok();
+	assert_eq!(parse_clock_time("9am"), time(9, 0));
+	assert_eq!(parse_clock_time("9:30PM"), time(21, 30));
+	assert_eq!(parse_clock_time("12am"), time(0, 0));
+	assert_eq!(parse_clock_time("12pm"), time(12, 0));
+	assert_eq!(parse_clock_time("13pm"), None);
+	assert_eq!(parse_clock_time("9:5am"), None);
+	assert_eq!(parse_clock_time("9:60am"), None);
+	assert_eq!(parse_clock_time("14:30"), None);
+	assert_eq!(parse_when("9am").ok(), time(9, 0).map(When::Clock));
+}
+
+#[test]
+fn clock_times_roll_to_next_occurrence() -> color_eyre::Result<()> {
+	let offset = UtcOffset::from_hms(2, 0, 0)?;
+	// 2022-10-05 12:00 in UTC+02.
+	let now = OffsetDateTime::from_unix_timestamp(1_664_964_000)?;
+	let at = |clock| -> color_eyre::Result<OffsetDateTime> {
+		let time = parse_clock_time(clock).ok_or_else(|| eyre!("Invalid clock time"))?;
+		Ok(When::Clock(time).resolve(now, offset))
+	};
+
+	// Morning: already passed today, so tomorrow.
+	assert_eq!(at("9am")?, OffsetDateTime::from_unix_timestamp(1_665_039_600)?);
+	// Afternoon: still today.
+	assert_eq!(at("2:30pm")?, OffsetDateTime::from_unix_timestamp(1_664_973_000)?);
+	// Exactly now counts as passed.
+	assert_eq!(at("12pm")?, now + Duration::days(1));
+
+	let evening = now + Duration::hours(8); // 20:00 in UTC+02
+	assert_eq!(
+		When::Clock(Time::from_hms(21, 0, 0)?).resolve(evening, offset),
+		evening + Duration::hours(1)
+	);
+	assert_eq!(
+		When::Clock(Time::from_hms(8, 0, 0)?).resolve(evening, offset),
+		evening + Duration::hours(12)
+	);
+	Ok(())
+}
+
+#[test]
+fn natural_self_reminder() -> color_eyre::Result<()> {
+	let arguments = normalize_arguments(parse_arguments("remind me in 30m to water plants"));
+	assert_eq!(arguments, vec!["remind", "30m", "water plants"]);
+
+	let before = OffsetDateTime::now_utc();
+	let command = Command::try_parse_from(std::iter::once("!".to_owned()).chain(arguments))?;
+	let remind = match command {
+		Command::Remind(remind) => remind,
+		_ => bail!("Expected the remind command"),
+	};
+	assert!(remind.who.is_none());
+	assert_eq!(remind.message.as_deref(), Some("water plants"));
+	let when = match remind.when {
+		Some(When::At(when)) => when,
+		_ => bail!("Expected a time"),
+	};
+	assert!(when >= before + Duration::minutes(30));
+	assert!(when <= OffsetDateTime::now_utc() + Duration::minutes(30));
+	Ok(())
+}
+
+#[test]
+fn natural_clock_time_reminder() {
+	let arguments = normalize_arguments(parse_arguments("remind me at 9am to join the standup"));
+	assert_eq!(arguments, vec!["remind", "9am", "join the standup"]);
+}
+
+#[test]
+fn strict_reminder_form_is_unchanged() {
+	let arguments = vec!["remind".to_owned(), "5".to_owned(), "message".to_owned()];
+	assert_eq!(normalize_arguments(arguments.clone()), arguments);
+	assert_eq!(natural_arguments(&["remind".to_owned(), "me".to_owned()]), None);
+}
+
+#[tokio::test]
+async fn room_target_is_resolved_for_admins() -> color_eyre::Result<()> {
+	let server = MockServer::start(|request| {
+		if request.path.ends_with("/versions") {
+			MockResponse::new(200, r#"{"versions":["v1.1"]}"#)
+		} else if request.path.contains("/directory/room/") {
+			MockResponse::new(200, r#"{"room_id":"!ops:example.com","servers":["example.com"]}"#)
+		} else {
+			MockResponse::new(404, r#"{"errcode":"M_NOT_FOUND","error":"Not found"}"#)
+		}
+	})
+	.await?;
+	let client = Client::builder().homeserver_url(server.url().as_str()).build().await?;
+	let current: OwnedRoomId = "!room:example.com".try_into()?;
+	let alias: OwnedRoomOrAliasId = "#ops:example.com".try_into()?;
+	let id: OwnedRoomOrAliasId = "!other:example.com".try_into()?;
+	let ops: OwnedRoomId = "!ops:example.com".try_into()?;
+	let other: OwnedRoomId = "!other:example.com".try_into()?;
+
+	assert_eq!(resolve_room(&client, &alias).await, Some(ops));
+	assert_eq!(resolve_room(&client, &id).await, Some(other));
+
+	assert_eq!(target_room(&client, &current, None, false).await, Ok(current.clone()));
+	assert_eq!(
+		target_room(&client, &current, Some(&alias), false).await,
+		Err("Only admins can schedule reminders into other rooms.")
+	);
+	assert_eq!(
+		target_room(&client, &current, Some(&alias), true).await,
+		Err("I am not joined to the room.")
+	);
+	Ok(())
+}
+
+#[test]
+fn edit_reply_escapes_message() {
+	let (html, body) = edit_reply(3, "2022-10-05 20:00", "**Tea** is <ready>");
+	assert_eq!(body, "Reminder #3 is scheduled for `2022-10-05 20:00`: **Tea** is <ready>");
+	assert_eq!(
+		html,
+		"Reminder #3 is scheduled for <code>2022-10-05 20:00</code>: <strong>Tea</strong> is \
+		 &lt;ready&gt;"
+	);
+}
@@ -1,12 +1,24 @@
 //! RSS feed configuration to receive and notify of e.g. news via RSS.
 
+use bonsaidb::core::schema::SerializedCollection;
 use clap::{Args, Subcommand};
 use color_eyre::Result;
 use matrix_sdk::{async_trait, ruma::events::room::message::RoomMessageEventContent};
+use time::Duration;
 use url::Url;
 
-use super::{BotCommand, Context};
-use crate::database::RssSubscription;
+use super::{exceeds_subscription_limit, BotCommand, Context};
+use crate::{
+	clients::{
+		check_url,
+		rss::{test_feed_url, validate_feeds, ParseOptions},
+	},
+	database::{
+		ActiveHours, FeedAuth, FeedFormat, InitialEntries, LinkRewrite, OutsideActiveHours,
+		RenderFormat, RoomPreferences, RssDigest, RssSubscription,
+	},
+	timestamps::precise_duration,
+};
 
 /// RSS command.
 #[derive(Debug, Args)]
@@ -23,110 +35,373 @@ enum SubCommand {
 	List,
 	/// Clear active RSS feeds.
 	Clear,
+	/// Check all active RSS feeds of this room without changing anything.
+	Validate,
 	/// Enable new RSS feed.
-	Enable {
-		/// Full feed URL.
-		url: Url,
-	},
+	Enable(EnableArgs),
 	/// Disable RSS feed.
 	Disable {
 		/// Full feed URL.
 		url: Url,
 	},
+	/// Post new entries of all feeds in this room as one periodic digest
+	/// instead of one message per entry.
+	Digest {
+		/// Number of hours between digests.
+		#[arg(long, default_value_t = 24)]
+		hours: u64,
+		/// Disable the digest and post entries individually again.
+		#[arg(long)]
+		disable: bool,
+	},
+	/// Show or set the default format of entries in this room for feeds
+	/// without their own format: `compact`, `normal`, `full` or `default`.
+	Format {
+		/// New default format, shows the current one if omitted.
+		format: Option<String>,
+	},
+}
+
+/// Arguments of the RSS enable sub-command.
+#[derive(Debug, Args)]
+struct EnableArgs {
+	/// Full feed URL.
+	url: Url,
+	/// Which already existing entries to post on the first poll: `none`,
+	/// `latest` or `backfill:N` for the newest N entries.
+	#[arg(long, default_value = "none")]
+	initial: InitialEntries,
+	/// Only post entries in this category (case-insensitive). Can be given
+	/// multiple times to allow multiple categories.
+	#[arg(long = "category")]
+	categories: Vec<String>,
+	/// Only post entries whose title or summary contains this keyword
+	/// (case-insensitive). Can be given multiple times to allow multiple
+	/// keywords.
+	#[arg(long = "include")]
+	include_keywords: Vec<String>,
+	/// Never post entries whose title or summary contains this keyword
+	/// (case-insensitive). Can be given multiple times.
+	#[arg(long = "exclude")]
+	exclude_keywords: Vec<String>,
+	/// Only post entries once they are at least this many seconds old, so
+	/// that rapid edits of fresh entries settle first.
+	#[arg(long, default_value_t = 0)]
+	min_age: u64,
+	/// Rewrite entry links, given as `PATTERN=>REPLACEMENT` with a regular
+	/// expression pattern, e.g. `^https://twitter.com/=>https://nitter.net/`.
+	/// Can be given multiple times, the rules are applied in order.
+	#[arg(long = "rewrite")]
+	rewrites: Vec<LinkRewrite>,
+	/// Only post entries during this time of day, e.g. `09:00-17:00` in the
+	/// configured timezone. Wraps around midnight, e.g. `22:00-06:00`.
+	#[arg(long)]
+	active_hours: Option<ActiveHours>,
+	/// What to do with new entries outside of the active hours: `hold` to
+	/// post them later or `drop` to skip them.
+	#[arg(long, default_value = "hold")]
+	outside_hours: OutsideActiveHours,
+	/// Parse the feed as this format instead of detecting it: `rss2`,
+	/// `atom` or `json`.
+	#[arg(long)]
+	format: Option<FeedFormat>,
+	/// Retry parsing after cleaning up common mistakes, e.g. unescaped
+	/// ampersands, if the feed fails to parse.
+	#[arg(long)]
+	lenient: bool,
+	/// Post the full content of entries instead of their summary, if the
+	/// feed provides it. Same as `--render full`.
+	#[arg(long, conflicts_with = "render")]
+	full: bool,
+	/// Format to render entries in, overriding the room's default:
+	/// `compact`, `normal` or `full`.
+	#[arg(long)]
+	render: Option<RenderFormat>,
+	/// Check the feed every this many seconds instead of at the global RSS
+	/// interval, which is the shortest possible interval.
+	#[arg(long)]
+	interval: Option<u64>,
+	/// Name of the feed shown in its messages, e.g. "Hacker News". Defaults
+	/// to the feed's title.
+	#[arg(long)]
+	label: Option<String>,
+	/// Username for feeds protected by HTTP basic authentication.
+	#[arg(long, conflicts_with = "token")]
+	user: Option<String>,
+	/// Password for feeds protected by HTTP basic authentication.
+	#[arg(long, requires = "user")]
+	password: Option<String>,
+	/// Bearer token for feeds protected by token authentication.
+	#[arg(long)]
+	token: Option<String>,
 }
 
 #[async_trait]
 impl BotCommand for Rss {
 	async fn execute<'a>(&mut self, context: Context<'a>) -> Result<()> {
-		let is_mod = context.config.access.admins.contains(&context.event.sender)
-			|| context.config.access.mods.contains(&context.event.sender);
-		if !is_mod {
+		if !context.is_mod() {
 			tracing::trace!("Person not allowed to edit RSS settings!");
 			return Ok(());
 		}
 
 		match &self.command {
-			SubCommand::List => {
-				let subscriptions =
-					RssSubscription::for_room(context.room.room_id(), &context.db.state).await?;
-				let formatted_subscriptions = subscriptions
-					.into_values()
-					.map(|doc| format!("- {}", doc.contents.url))
-					.collect::<Vec<_>>();
-				if formatted_subscriptions.is_empty() {
-					let msg = RoomMessageEventContent::text_plain(
-						"Currently, there are no RSS subscriptions.",
-					)
-					.make_reply_to(context.event);
-					context.room.send(msg, None).await?;
-				} else {
-					let msg =
-						RoomMessageEventContent::text_markdown(formatted_subscriptions.join("\n"))
-							.make_reply_to(context.event);
-					context.room.send(msg, None).await?;
-				}
-			}
-
-			SubCommand::Clear => {
-				for subscription in
-					RssSubscription::for_room(context.room.room_id(), &context.db.state)
-						.await?
-						.into_values()
-				{
-					subscription.delete_async(&context.db.state).await?;
-				}
-
-				let success_msg =
-					RoomMessageEventContent::text_plain("Successfully cleared RSS subscriptions.")
-						.make_reply_to(context.event);
-				context.room.send(success_msg, None).await?;
-			}
-
-			SubCommand::Enable { url } => {
-				if test_feed_url(url.clone()).await.is_ok() {
-					let subscription =
-						RssSubscription::new(context.room.room_id().to_owned(), url.clone());
-					subscription.insert(&context.db.state).await?;
-
-					let success_msg = RoomMessageEventContent::text_plain(
-						"Successfully enabled RSS subscription.",
-					)
-					.make_reply_to(context.event);
-					context.room.send(success_msg, None).await?;
-				} else {
-					let failure_msg =
-						RoomMessageEventContent::text_plain("URL is not a valid RSS stream.")
-							.make_reply_to(context.event);
-					context.room.send(failure_msg, None).await?;
-				}
-			}
-
-			SubCommand::Disable { url } => {
-				if let Some(subscription) =
-					RssSubscription::find(context.room.room_id(), url, &context.db.state).await?
-				{
-					subscription.delete_async(&context.db.state).await?;
-
-					let success_msg = RoomMessageEventContent::text_plain(
-						"Successfully disabled RSS subscription.",
-					)
-					.make_reply_to(context.event);
-					context.room.send(success_msg, None).await?;
-				} else {
-					let failure_msg =
-						RoomMessageEventContent::text_plain("RSS subscription not found.")
-							.make_reply_to(context.event);
-					context.room.send(failure_msg, None).await?;
-				}
-			}
+			SubCommand::List => list(&context).await,
+			SubCommand::Clear => clear(&context).await,
+			SubCommand::Validate => validate(&context).await,
+			SubCommand::Enable(args) => enable(&context, args).await,
+			SubCommand::Disable { url } => disable(&context, url).await,
+			SubCommand::Digest { hours, disable } => digest(&context, *hours, *disable).await,
+			SubCommand::Format { format } => room_format(&context, format.as_deref()).await,
 		}
-		Ok(())
 	}
 }
 
-/// Test a URL whether it gives a parsable RSS feed.
-async fn test_feed_url(url: Url) -> Result<()> {
-	let bytes = reqwest::get(url).await?.bytes().await?;
-	let _feed = feed_rs::parser::parse(bytes.as_ref())?;
+/// List the RSS subscriptions of the room.
+async fn list(context: &Context<'_>) -> Result<()> {
+	let subscriptions =
+		RssSubscription::for_room(context.room.room_id(), &context.db.state).await?;
+	let formatted_subscriptions = subscriptions
+		.into_values()
+		.map(|doc| format_subscription(&doc.contents, context.config.intervals.rss))
+		.collect::<Vec<_>>();
+	if formatted_subscriptions.is_empty() {
+		let msg = RoomMessageEventContent::text_plain("Currently, there are no RSS subscriptions.")
+			.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+	} else {
+		let msg = RoomMessageEventContent::text_markdown(formatted_subscriptions.join("\n"))
+			.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+	}
+	Ok(())
+}
+
+/// Remove all RSS subscriptions of the room.
+async fn clear(context: &Context<'_>) -> Result<()> {
+	for subscription in
+		RssSubscription::for_room(context.room.room_id(), &context.db.state).await?.into_values()
+	{
+		subscription.delete_async(&context.db.state).await?;
+	}
+
+	context.audit("cleared RSS subscriptions").await?;
+
+	let success_msg =
+		RoomMessageEventContent::text_plain("Successfully cleared RSS subscriptions.")
+			.make_reply_to(context.event);
+	context.room.send(success_msg, None).await?;
+	Ok(())
+}
+
+/// Check all RSS subscriptions of the room without changing anything.
+async fn validate(context: &Context<'_>) -> Result<()> {
+	let key = context.config.store.secret_key();
+	let feeds = RssSubscription::for_room(context.room.room_id(), &context.db.state)
+		.await?
+		.into_values()
+		.map(|doc| {
+			let options = ParseOptions::from(&doc.contents);
+			(doc.contents.url.clone(), options, doc.contents.auth(&key))
+		})
+		.collect::<Vec<_>>();
+	let results = validate_feeds(feeds, &context.config.user_agent, &context.config.fetch).await;
+
+	let msg = RoomMessageEventContent::text_markdown(format_validation(&results))
+		.make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Subscribe the room to the feed, if it is allowed and a valid feed.
+async fn enable(context: &Context<'_>, args: &EnableArgs) -> Result<()> {
+	let url = &args.url;
+	let subscriptions =
+		RssSubscription::for_room(context.room.room_id(), &context.db.state).await?;
+	let is_new = !subscriptions.values().any(|doc| doc.contents.url == *url);
+	if !context.is_admin()
+		&& exceeds_subscription_limit(
+			subscriptions.len(),
+			is_new,
+			context.config.rooms.max_rss_per_room,
+		) {
+		let msg = RoomMessageEventContent::text_plain(
+			"This room reached the maximum number of RSS subscriptions.",
+		)
+		.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+		return Ok(());
+	}
+
+	if let Err(err) = check_url(url, &context.config.fetch).await {
+		let msg = RoomMessageEventContent::text_plain(format!("URL is not allowed: {err}"))
+			.make_reply_to(context.event);
+		context.room.send(msg, None).await?;
+		return Ok(());
+	}
+
+	let options = ParseOptions { format: args.format, lenient: args.lenient };
+	let auth = feed_auth(args.user.as_deref(), args.password.as_deref(), args.token.as_deref());
+	let valid = test_feed_url(
+		url.clone(),
+		&context.config.user_agent,
+		options,
+		auth.as_ref(),
+		&context.config.fetch,
+	)
+	.await
+	.is_ok();
+	if !valid {
+		let failure_msg = RoomMessageEventContent::text_plain("URL is not a valid RSS stream.")
+			.make_reply_to(context.event);
+		context.room.send(failure_msg, None).await?;
+		return Ok(());
+	}
+
+	let mut subscription = RssSubscription::new(context.room.room_id().to_owned(), url.clone());
+	subscription.initial_entries = Some(args.initial);
+	subscription.categories = args.categories.clone();
+	subscription.include_keywords = args.include_keywords.clone();
+	subscription.exclude_keywords = args.exclude_keywords.clone();
+	subscription.min_entry_age = args.min_age;
+	subscription.link_rewrites = args.rewrites.clone();
+	subscription.active_hours = args.active_hours;
+	subscription.outside_active_hours = args.outside_hours;
+	subscription.format = args.format;
+	subscription.lenient = args.lenient;
+	subscription.render = if args.full { Some(RenderFormat::Full) } else { args.render };
+	subscription.interval = args.interval;
+	subscription.label = args.label.clone();
+	subscription.set_auth(auth.as_ref(), &context.config.store.secret_key())?;
+	subscription.insert(&context.db.state).await?;
+	context.audit(format!("enabled RSS subscription {url}")).await?;
+
+	let success_msg = RoomMessageEventContent::text_plain("Successfully enabled RSS subscription.")
+		.make_reply_to(context.event);
+	context.room.send(success_msg, None).await?;
+	Ok(())
+}
+
+/// Unsubscribe the room from the feed.
+async fn disable(context: &Context<'_>, url: &Url) -> Result<()> {
+	if let Some(subscription) =
+		RssSubscription::find(context.room.room_id(), url, &context.db.state).await?
+	{
+		subscription.delete_async(&context.db.state).await?;
+		context.audit(format!("disabled RSS subscription {url}")).await?;
+
+		let success_msg =
+			RoomMessageEventContent::text_plain("Successfully disabled RSS subscription.")
+				.make_reply_to(context.event);
+		context.room.send(success_msg, None).await?;
+	} else {
+		let failure_msg = RoomMessageEventContent::text_plain("RSS subscription not found.")
+			.make_reply_to(context.event);
+		context.room.send(failure_msg, None).await?;
+	}
+	Ok(())
+}
+
+/// Enable the room's digest every given number of hours, or disable it.
+async fn digest(context: &Context<'_>, hours: u64, disable: bool) -> Result<()> {
+	let existing = RssDigest::for_room(context.room.room_id(), &context.db.state).await?;
+	let text = if disable {
+		if let Some(digest) = existing {
+			digest.delete_async(&context.db.state).await?;
+			context.audit("disabled RSS digest").await?;
+		}
+		String::from("RSS entries are posted individually.")
+	} else {
+		let every = hours.saturating_mul(60 * 60);
+		if let Some(mut digest) = existing {
+			digest.contents.every = every;
+			digest.update_async(&context.db.state).await?;
+		} else {
+			RssDigest::new(context.room.room_id().to_owned(), every)
+				.push_into_async(&context.db.state)
+				.await?;
+		}
+		context.audit(format!("enabled RSS digest every {hours} hours")).await?;
+		format!("RSS entries are posted as digest every {hours} hours.")
+	};
+
+	let msg = RoomMessageEventContent::text_plain(text).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
+	Ok(())
+}
+
+/// Show the room's default entry format, or set it if given.
+async fn room_format(context: &Context<'_>, format: Option<&str>) -> Result<()> {
+	let room = context.room.room_id();
+	let mut preferences = RoomPreferences::for_room(room, &context.db.state)
+		.await?
+		.map_or_else(|| RoomPreferences::new(room.to_owned()), |doc| doc.contents);
+	let text = match format.map(parse_room_format) {
+		None => match preferences.rss_format {
+			Some(format) => format!("RSS entries are rendered as {format} by default."),
+			None => format!(
+				"RSS entries are rendered as {} by default (configured default).",
+				context.config.rss.format
+			),
+		},
+		Some(Ok(parsed)) => {
+			preferences.rss_format = parsed;
+			preferences.save(&context.db.state).await?;
+			let format = parsed.unwrap_or(context.config.rss.format);
+			context.audit(format!("set the default RSS format to {format}")).await?;
+			format!("Successfully set the default RSS format to {format}.")
+		}
+		Some(Err(err)) => err,
+	};
+
+	let msg = RoomMessageEventContent::text_plain(text).make_reply_to(context.event);
+	context.room.send(msg, None).await?;
 	Ok(())
 }
+
+/// Parse the default format of a room. Returns `None` for `default`.
+fn parse_room_format(s: &str) -> Result<Option<RenderFormat>, String> {
+	if s.eq_ignore_ascii_case("default") {
+		return Ok(None);
+	}
+	s.parse().map(Some)
+}
+
+/// Credentials of a protected feed given via `--user` and `--password` or
+/// `--token`, if any.
+fn feed_auth(user: Option<&str>, password: Option<&str>, token: Option<&str>) -> Option<FeedAuth> {
+	match (user, token) {
+		(Some(username), _) => Some(FeedAuth::Basic {
+			username: username.to_owned(),
+			password: password.map(ToOwned::to_owned),
+		}),
+		(None, Some(token)) => Some(FeedAuth::Bearer(token.to_owned())),
+		(None, None) => None,
+	}
+}
+
+/// Format the subscription as markdown list item, along with its effective
+/// check interval.
+fn format_subscription(subscription: &RssSubscription, global_interval: u64) -> String {
+	let interval = subscription.effective_interval(global_interval);
+	let every = precise_duration(Duration::seconds(i64::try_from(interval).unwrap_or(i64::MAX)));
+	let muted = if subscription.muted { " (muted)" } else { "" };
+	format!("- {} (every {every}){muted}", subscription.url)
+}
+
+/// Format the feed validation results as markdown summary.
+fn format_validation(results: &[(Url, Result<()>)]) -> String {
+	if results.is_empty() {
+		return String::from("Currently, there are no RSS subscriptions.");
+	}
+
+	let ok = results.iter().filter(|(_, result)| result.is_ok()).count();
+	let mut lines = vec![format!("{ok}/{} feeds are OK:", results.len())];
+	lines.extend(results.iter().map(|(url, result)| match result {
+		Ok(()) => format!("- OK: {url}"),
+		Err(err) => format!("- Error: {url} ({err})"),
+	}));
+	lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,99 @@
+//! Tests for the RSS commands.
+
+use color_eyre::Result;
+
+use super::*;
+use crate::{
+	settings::FetchSettings,
+	test_utils::{MockResponse, MockServer},
+};
+
+/// A minimal valid RSS feed.
+const VALID_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>Test feed</title>
+	<link>https://example.com/</link>
+	<description>Test feed</description>
+	<item>
+		<title>Entry</title>
+		<link>https://example.com/entry</link>
+	</item>
+</channel>
+</rss>"#;
+
+#[tokio::test]
+async fn validate_reports_each_feed() -> Result<()> {
+	let server = MockServer::start(|request| match request.path.as_str() {
+		"/valid" | "/also-valid" => MockResponse::new(200, VALID_FEED),
+		"/broken" => MockResponse::new(200, "<rss><channel><item><title>Broken"),
+		_ => MockResponse::new(404, "not found"),
+	})
+	.await?;
+	let urls = ["valid", "missing", "broken", "also-valid"]
+		.into_iter()
+		.map(|path| server.url().join(path))
+		.collect::<Result<Vec<_>, _>>()?;
+
+	let feeds = urls.iter().map(|url| (url.clone(), ParseOptions::default(), None)).collect();
+	let fetch = FetchSettings { allow_private_hosts: true, ..FetchSettings::default() };
+	let results = validate_feeds(feeds, "test-agent", &fetch).await;
+	let checked = results.iter().map(|(url, _)| url.clone()).collect::<Vec<_>>();
+	assert_eq!(checked, urls);
+	let ok = results.iter().map(|(_, result)| result.is_ok()).collect::<Vec<_>>();
+	assert_eq!(ok, [true, false, false, true]);
+
+	let summary = format_validation(&results);
+	assert!(summary.starts_with("2/4 feeds are OK:"));
+	assert!(summary.contains(&format!("- OK: {}", urls[0])));
+	assert!(summary.contains(&format!("- Error: {}", urls[1])));
+	assert!(summary.contains(&format!("- Error: {}", urls[2])));
+	assert!(summary.contains(&format!("- OK: {}", urls[3])));
+	Ok(())
+}
+
+#[test]
+fn validate_without_feeds() {
+	assert_eq!(format_validation(&[]), "Currently, there are no RSS subscriptions.");
+}
+
+#[test]
+fn room_format_is_parsed() {
+	assert_eq!(parse_room_format("compact"), Ok(Some(RenderFormat::Compact)));
+	assert_eq!(parse_room_format("full"), Ok(Some(RenderFormat::Full)));
+	assert_eq!(parse_room_format("Default"), Ok(None));
+	assert!(parse_room_format("verbose").is_err());
+}
+
+#[test]
+fn list_shows_effective_interval() -> Result<()> {
+	let room = "!room:example.com".try_into()?;
+	let mut subscription = RssSubscription::new(room, Url::parse("https://example.com/feed")?);
+	assert_eq!(format_subscription(&subscription, 600), "- https://example.com/feed (every 10m)");
+	subscription.interval = Some(7200);
+	subscription.muted = true;
+	assert_eq!(
+		format_subscription(&subscription, 600),
+		"- https://example.com/feed (every 2h0m) (muted)"
+	);
+	subscription.interval = Some(60);
+	assert_eq!(
+		format_subscription(&subscription, 600),
+		"- https://example.com/feed (every 10m) (muted)"
+	);
+	Ok(())
+}
+
+#[test]
+fn feed_credentials_from_arguments() {
+	assert_eq!(feed_auth(None, None, None), None);
+	assert_eq!(feed_auth(None, None, Some("token")), Some(FeedAuth::Bearer(String::from("token"))));
+	let auth = feed_auth(Some("user"), Some("hunter2"), None);
+	assert_eq!(
+		auth,
+		Some(FeedAuth::Basic {
+			username: String::from("user"),
+			password: Some(String::from("hunter2"))
+		})
+	);
+}
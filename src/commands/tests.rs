@@ -1,8 +1,17 @@
 //! Tests for commands
 
+use std::{
+	io::Write,
+	sync::{Arc, Mutex, PoisonError},
+};
+
 use clap::CommandFactory;
+use color_eyre::eyre::{eyre, WrapErr};
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+use tracing_subscriber::fmt::MakeWriter;
 
 use super::*;
+use crate::settings::RoomCommands;
 
 #[test]
 fn argument_parsing() {
@@ -23,3 +32,127 @@ fn argument_parsing() {
 fn clap_verify() {
 	Command::command().debug_assert();
 }
+
+#[test]
+fn help_as_json() -> color_eyre::Result<()> {
+	assert_eq!(json_help(&["help".to_owned()]), None);
+	let help = json_help(&["help".to_owned(), "--json".to_owned()])
+		.ok_or_else(|| eyre!("Expected JSON help"))?;
+	let help: Value = serde_json::from_str(&help)?;
+
+	let subcommands = help["subcommands"]
+		.as_array()
+		.ok_or_else(|| eyre!("Expected sub-commands"))?
+		.iter()
+		.filter_map(|command| command["name"].as_str())
+		.collect::<Vec<_>>();
+	for name in ["leave", "remind", "rss", "github", "admin"] {
+		assert!(subcommands.contains(&name), "missing command {name}");
+	}
+
+	let rss = help["subcommands"]
+		.as_array()
+		.and_then(|commands| commands.iter().find(|command| command["name"] == "rss"))
+		.ok_or_else(|| eyre!("Expected rss command"))?;
+	let rss_subcommands =
+		rss["subcommands"].as_array().ok_or_else(|| eyre!("Expected rss sub-commands"))?;
+	assert!(rss_subcommands.iter().any(|command| command["name"] == "enable"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn command_invocation_is_audited() -> color_eyre::Result<()> {
+	let db = crate::test_utils::test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let sender: OwnedUserId = "@user:example.com".try_into()?;
+
+	let command = Command::try_parse_from(["!", "rss", "list"])?;
+	command.audit(&db, &room, &sender).await?;
+
+	let entries = AuditEntry::latest_for_room(&room, 10, &db.state).await?;
+	assert_eq!(entries.len(), 1);
+	assert_eq!(entries[0].who.as_ref(), Some(&sender));
+	assert_eq!(entries[0].action, "!rss");
+	Ok(())
+}
+
+#[test]
+fn command_disabled_per_room() -> color_eyre::Result<()> {
+	let restricted: OwnedRoomId = "!restricted:example.com".try_into()?;
+	let allow_listed: OwnedRoomId = "!allow-listed:example.com".try_into()?;
+	let other: OwnedRoomId = "!other:example.com".try_into()?;
+	let mut config = RoomSettings::default();
+	config.commands.insert(
+		restricted.clone(),
+		RoomCommands { enabled: None, disabled: vec!["leave".to_owned()] },
+	);
+	config.commands.insert(
+		allow_listed.clone(),
+		RoomCommands { enabled: Some(vec!["remind".to_owned()]), disabled: Vec::new() },
+	);
+
+	let leave = Command::try_parse_from(["!", "leave"])?;
+	let remind = Command::try_parse_from(["!", "remind", "5", "test"])?;
+	assert!(!leave.is_enabled_in(&config, &restricted));
+	assert!(remind.is_enabled_in(&config, &restricted));
+	assert!(!leave.is_enabled_in(&config, &allow_listed));
+	assert!(remind.is_enabled_in(&config, &allow_listed));
+	assert!(leave.is_enabled_in(&config, &other));
+	Ok(())
+}
+
+#[test]
+fn subscription_limit() {
+	assert!(!exceeds_subscription_limit(100, true, None));
+	assert!(!exceeds_subscription_limit(1, true, Some(2)));
+	assert!(exceeds_subscription_limit(2, true, Some(2)));
+	assert!(exceeds_subscription_limit(0, true, Some(0)));
+	// Re-enabling an existing subscription does not count.
+	assert!(!exceeds_subscription_limit(2, false, Some(2)));
+}
+
+/// Log output captured in memory.
+#[derive(Debug, Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl LogBuffer {
+	/// The captured log output.
+	fn contents(&self) -> String {
+		String::from_utf8_lossy(&self.0.lock().unwrap_or_else(PoisonError::into_inner)).into_owned()
+	}
+}
+
+impl Write for LogBuffer {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.0.lock().unwrap_or_else(PoisonError::into_inner).extend_from_slice(buf);
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+	type Writer = Self;
+
+	fn make_writer(&'a self) -> Self::Writer {
+		self.clone()
+	}
+}
+
+#[test]
+fn internal_errors_are_not_exposed() {
+	let logs = LogBuffer::default();
+	let subscriber = tracing_subscriber::fmt().with_writer(logs.clone()).with_ansi(false).finish();
+	let error = eyre!("connection to database lost").wrap_err("failed saving subscription");
+
+	let reply = tracing::subscriber::with_default(subscriber, || error_reply("rss", &error));
+
+	assert!(reply.contains("`!rss`"));
+	assert!(!reply.contains("database"));
+	assert!(!reply.contains("subscription"));
+	let logs = logs.contents();
+	assert!(logs.contains("failed saving subscription"));
+	assert!(logs.contains("connection to database lost"));
+}
@@ -1,11 +1,16 @@
 //! The bot's database.
 
-use std::collections::BTreeMap;
+use std::{
+	collections::BTreeMap,
+	fmt::{Debug, Display, Formatter, Result as FmtResult},
+	str::FromStr,
+};
 
 use bonsaidb::{
 	core::{
 		connection::AsyncConnection,
 		document::{CollectionDocument, DocumentId, Emit},
+		keyvalue::AsyncKeyValue,
 		schema::{
 			Collection, CollectionViewSchema, Schema, SerializedCollection, View, ViewMapResult,
 		},
@@ -16,13 +21,27 @@ use bonsaidb::{
 	},
 };
 use bonsaimq::MessageQueueSchema;
-use color_eyre::Result;
-use matrix_sdk::ruma::{OwnedRoomId, RoomId};
+use color_eyre::{eyre::eyre, Result};
+use matrix_sdk::ruma::{EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use time::OffsetDateTime;
+use time::{OffsetDateTime, UtcOffset};
 use url::Url;
 
-use crate::settings::Settings;
+use crate::{
+	secrets::{Secret, SecretKey},
+	settings::{Settings, TimestampSettings},
+	timestamps,
+};
+
+/// Key-value store key for the latest Matrix sync token.
+const SYNC_TOKEN_KEY: &str = "sync_token";
+/// Maximum number of sent messages remembered to map reactions back to their
+/// source.
+const MAX_SENT_MESSAGES: u64 = 1000;
+/// Maximum number of seconds between checks of a failing feed, unless its own
+/// interval is longer.
+const MAX_FAILURE_BACKOFF: u64 = 24 * 60 * 60;
 
 /// Open all databases as specified from the config.
 pub async fn open_databases(config: &Settings) -> Result<Databases> {
@@ -44,9 +63,73 @@ pub struct Databases {
 	pub jobs: AsyncDatabase,
 }
 
+/// Get the latest Matrix sync token persisted in the database.
+pub async fn get_sync_token(db: &AsyncDatabase) -> Result<Option<String>, bonsaidb::core::Error> {
+	db.get_key(SYNC_TOKEN_KEY).into().await
+}
+
+/// Persist the latest Matrix sync token in the database.
+pub async fn set_sync_token(db: &AsyncDatabase, token: &str) -> Result<(), bonsaidb::core::Error> {
+	db.set_key(SYNC_TOKEN_KEY, &token).await?;
+	Ok(())
+}
+
+/// Migrate all subscriptions and reminders of a room to another room, e.g.
+/// after a room upgrade. The posted Github notifications move along with their
+/// subscriptions, so that they are not posted again in the new room.
+pub async fn migrate_room(
+	old_room: &RoomId,
+	new_room: &RoomId,
+	db: &AsyncDatabase,
+) -> Result<(), bonsaidb::core::Error> {
+	for subscription in RssSubscription::for_room(old_room, db).await?.into_values() {
+		let mut contents = subscription.contents.clone();
+		contents.room = new_room.to_owned();
+		contents.insert(db).await?;
+		subscription.delete_async(db).await?;
+	}
+
+	for subscription in GithubSubscription::for_room(old_room, db).await?.into_values() {
+		let mut contents = subscription.contents.clone();
+		contents.room = new_room.to_owned();
+		let user = contents.user.clone();
+		contents.insert(db).await?;
+		subscription.delete_async(db).await?;
+
+		for mut posted in PostedNotification::for_subscription(old_room, &user, db).await? {
+			posted.contents.room = new_room.to_owned();
+			posted.update_async(db).await?;
+		}
+	}
+
+	if let Some(mut digest) = RssDigest::for_room(old_room, db).await? {
+		if RssDigest::for_room(new_room, db).await?.is_none() {
+			digest.contents.room = new_room.to_owned();
+			digest.update_async(db).await?;
+		} else {
+			digest.delete_async(db).await?;
+		}
+	}
+
+	for mut reminder in Reminder::for_room(old_room, db).await?.into_values() {
+		reminder.contents.room = new_room.to_owned();
+		reminder.update_async(db).await?;
+	}
+
+	if let Some(mut preferences) = RoomPreferences::for_room(old_room, db).await? {
+		if RoomPreferences::for_room(new_room, db).await?.is_none() {
+			preferences.contents.room = new_room.to_owned();
+			preferences.update_async(db).await?;
+		} else {
+			preferences.delete_async(db).await?;
+		}
+	}
+	Ok(())
+}
+
 /// The bot's database schema for saving state.
 #[derive(Debug, Schema)]
-#[schema(name = "matrix_bot", collections = [RssSubscription, GithubSubscription])]
+#[schema(name = "matrix_bot", collections = [RssSubscription, RssDigest, GithubSubscription, PostedNotification, Reminder, AuditEntry, SentMessage, RoomPreferences])]
 pub struct BotSchema;
 
 /// Document entry for one RSS subscription.
@@ -59,12 +142,175 @@ pub struct RssSubscription {
 	pub url: Url,
 	/// Latest update posted into the room.
 	pub latest_update: OffsetDateTime,
+	/// Which already existing entries to post on the first poll. Cleared after
+	/// the first poll.
+	#[serde(default)]
+	pub initial_entries: Option<InitialEntries>,
+	/// Only post entries in one of these categories (case-insensitive). Posts
+	/// all entries if empty.
+	#[serde(default)]
+	pub categories: Vec<String>,
+	/// Only post entries whose title or summary contains one of these keywords
+	/// (case-insensitive). Posts all entries if empty.
+	#[serde(default)]
+	pub include_keywords: Vec<String>,
+	/// Never post entries whose title or summary contains one of these
+	/// keywords (case-insensitive).
+	#[serde(default)]
+	pub exclude_keywords: Vec<String>,
+	/// Whether posting entries is muted.
+	#[serde(default)]
+	pub muted: bool,
+	/// Minimum age (in seconds) of entries before they are posted, so that
+	/// rapid edits of fresh entries settle first. Zero to post immediately.
+	#[serde(default)]
+	pub min_entry_age: u64,
+	/// Rules rewriting the links of entries, applied in order.
+	#[serde(default)]
+	pub link_rewrites: Vec<LinkRewrite>,
+	/// Time of day to post entries in. Entries are posted at any time if
+	/// unset.
+	#[serde(default)]
+	pub active_hours: Option<ActiveHours>,
+	/// What to do with new entries outside of the active hours.
+	#[serde(default)]
+	pub outside_active_hours: OutsideActiveHours,
+	/// Format the feed is parsed as, detected automatically if unset.
+	#[serde(default)]
+	pub format: Option<FeedFormat>,
+	/// Whether to retry parsing the feed after cleaning up common mistakes if
+	/// it fails to parse.
+	#[serde(default)]
+	pub lenient: bool,
+	/// Hash of the title and link of the last posted entry, to suppress
+	/// feeds repeating the same entry.
+	#[serde(default)]
+	pub last_posted_hash: Option<u64>,
+	/// Whether to post the full content of entries instead of their summary.
+	/// Superseded by `render`, still honored for older subscriptions.
+	#[serde(default)]
+	pub use_content: bool,
+	/// Format entries are rendered in, overriding the room's default.
+	#[serde(default)]
+	pub render: Option<RenderFormat>,
+	/// Last time the feed was polled successfully. Unset if it was not polled
+	/// yet.
+	#[serde(default)]
+	pub last_polled: Option<OffsetDateTime>,
+	/// Number of seconds between checks of the feed, overriding the global RSS
+	/// interval. The global interval is the shortest possible interval.
+	#[serde(default)]
+	pub interval: Option<u64>,
+	/// `ETag` header of the last feed response, to only fetch the feed again if
+	/// it was modified.
+	#[serde(default)]
+	pub etag: Option<String>,
+	/// `Last-Modified` header of the last feed response, to only fetch the
+	/// feed again if it was modified.
+	#[serde(default)]
+	pub last_modified: Option<String>,
+	/// IDs of the feed's entries that were posted or skipped, oldest first, so
+	/// that entries republished with a new time are not posted again.
+	#[serde(default)]
+	pub seen_entry_ids: Vec<String>,
+	/// Name of the feed shown in its messages instead of the feed's title.
+	#[serde(default)]
+	pub label: Option<String>,
+	/// Number of fetches of the feed that failed in a row.
+	#[serde(default)]
+	pub consecutive_failures: u32,
+	/// Credentials to fetch the feed with if it is protected, encrypted with
+	/// the key derived from the store passphrase.
+	#[serde(default)]
+	pub encrypted_auth: Option<Secret>,
 }
 
 impl RssSubscription {
 	/// Create a new RSS subscription for the current time.
 	pub fn new(room: OwnedRoomId, url: Url) -> Self {
-		Self { room, url, latest_update: OffsetDateTime::now_utc() }
+		Self {
+			room,
+			url,
+			latest_update: OffsetDateTime::now_utc(),
+			initial_entries: Some(InitialEntries::None),
+			categories: Vec::new(),
+			include_keywords: Vec::new(),
+			exclude_keywords: Vec::new(),
+			muted: false,
+			min_entry_age: 0,
+			link_rewrites: Vec::new(),
+			active_hours: None,
+			outside_active_hours: OutsideActiveHours::default(),
+			format: None,
+			lenient: false,
+			last_posted_hash: None,
+			use_content: false,
+			render: None,
+			last_polled: None,
+			interval: None,
+			etag: None,
+			last_modified: None,
+			seen_entry_ids: Vec::new(),
+			label: None,
+			consecutive_failures: 0,
+			encrypted_auth: None,
+		}
+	}
+
+	/// Whether entries of the feed may be deferred to a later poll, i.e. by a
+	/// minimum entry age or by holding them outside of the active hours.
+	pub fn defers_entries(&self) -> bool {
+		self.min_entry_age > 0
+			|| (self.active_hours.is_some()
+				&& self.outside_active_hours == OutsideActiveHours::Hold)
+	}
+
+	/// Number of seconds between checks of the feed, given the global RSS
+	/// interval.
+	pub fn effective_interval(&self, global: u64) -> u64 {
+		self.interval.map_or(global, |interval| interval.max(global))
+	}
+
+	/// Number of seconds until the feed is checked again. Once it failed
+	/// `failure_threshold` times in a row, the interval doubles with every
+	/// further failure, up to a day.
+	pub fn poll_interval(&self, global: u64, failure_threshold: u32) -> u64 {
+		let interval = self.effective_interval(global);
+		match self.consecutive_failures.checked_sub(failure_threshold) {
+			Some(exceeded) => interval
+				.saturating_mul(2_u64.saturating_pow(exceeded.saturating_add(1)))
+				.min(MAX_FAILURE_BACKOFF.max(interval)),
+			None => interval,
+		}
+	}
+
+	/// Decrypted credentials to fetch the feed with, if any. Credentials that
+	/// fail to decrypt, e.g. after the passphrase was changed, are logged and
+	/// skipped.
+	pub fn auth(&self, key: &SecretKey) -> Option<FeedAuth> {
+		let secret = self.encrypted_auth.as_ref()?;
+		let auth = secret.open(key).and_then(|json| Ok(serde_json::from_str(&json)?));
+		match auth {
+			Ok(auth) => Some(auth),
+			Err(err) => {
+				tracing::error!("Failed decrypting the credentials of feed {}: {err}", self.url);
+				None
+			}
+		}
+	}
+
+	/// Store the credentials to fetch the feed with, encrypted with the key.
+	pub fn set_auth(&mut self, auth: Option<&FeedAuth>, key: &SecretKey) -> Result<()> {
+		self.encrypted_auth = match auth {
+			Some(auth) => Some(Secret::seal(&serde_json::to_string(auth)?, key)?),
+			None => None,
+		};
+		Ok(())
+	}
+
+	/// Format override of the subscription, if any.
+	pub fn render_override(&self) -> Option<RenderFormat> {
+		self.render.or_else(|| self.use_content.then_some(RenderFormat::Full))
 	}
 
 	/// Get RSS subscriptions for a specific room.
@@ -90,10 +336,11 @@ impl RssSubscription {
 		Ok(Self::for_room(room, db).await?.into_values().find(|doc| doc.contents.url == *url))
 	}
 
-	/// Insert the given RSS subscription into the database.
+	/// Insert the given RSS subscription into the database, replacing an
+	/// existing subscription for the same room and URL.
 	pub async fn insert(self, db: &AsyncDatabase) -> Result<(), bonsaidb::core::Error> {
 		if let Some(mut current) = Self::find(&self.room, &self.url, db).await? {
-			current.contents.latest_update = self.latest_update;
+			current.contents = self;
 			current.update_async(db).await?;
 		} else {
 			self.push_into_async(db).await?;
@@ -102,6 +349,439 @@ impl RssSubscription {
 	}
 }
 
+/// Which already existing feed entries to post on the first poll of a new
+/// subscription.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InitialEntries {
+	/// Only post entries newer than the subscription.
+	None,
+	/// Post the newest existing entry.
+	Latest,
+	/// Post the newest N existing entries.
+	Backfill(usize),
+}
+
+impl FromStr for InitialEntries {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"none" => Ok(Self::None),
+			"latest" => Ok(Self::Latest),
+			_ => {
+				let count = s
+					.strip_prefix("backfill:")
+					.ok_or_else(|| format!("`{s}` is neither none, latest nor backfill:N!"))?;
+				let count = count.parse().map_err(|_| format!("`{count}` is not a number!"))?;
+				Ok(Self::Backfill(count))
+			}
+		}
+	}
+}
+
+/// Credentials to fetch a protected feed with. They are stored encrypted and
+/// redacted in debug output.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedAuth {
+	/// HTTP basic authentication.
+	Basic {
+		/// Username.
+		username: String,
+		/// Password, if any.
+		password: Option<String>,
+	},
+	/// Bearer token in the `Authorization` header.
+	Bearer(String),
+}
+
+impl Debug for FeedAuth {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match self {
+			Self::Basic { username, .. } => f
+				.debug_struct("Basic")
+				.field("username", username)
+				.field("password", &"<redacted>")
+				.finish(),
+			Self::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+		}
+	}
+}
+
+/// Rule rewriting the links of feed entries, e.g. to privacy frontends. Given
+/// as `PATTERN=>REPLACEMENT`, where the pattern is a regular expression and
+/// the replacement can refer to capture groups, e.g. `$1`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkRewrite {
+	/// Regular expression matching (parts of) links.
+	pub pattern: String,
+	/// Replacement of the matched parts.
+	pub replacement: String,
+}
+
+impl LinkRewrite {
+	/// Apply the rules to the link in order. Rules with invalid patterns are
+	/// skipped.
+	pub fn apply_all(rules: &[Self], link: &str) -> String {
+		rules.iter().fold(link.to_owned(), |link, rule| match Regex::new(&rule.pattern) {
+			Ok(regex) => regex.replace_all(&link, rule.replacement.as_str()).into_owned(),
+			Err(err) => {
+				tracing::warn!("Invalid link rewrite pattern `{}`: {err}", rule.pattern);
+				link
+			}
+		})
+	}
+}
+
+impl FromStr for LinkRewrite {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (pattern, replacement) = s
+			.split_once("=>")
+			.ok_or_else(|| format!("`{s}` is not of the form PATTERN=>REPLACEMENT!"))?;
+		Regex::new(pattern)
+			.map_err(|err| format!("`{pattern}` is not a valid regular expression: {err}"))?;
+		Ok(Self { pattern: pattern.to_owned(), replacement: replacement.to_owned() })
+	}
+}
+
+/// Time of day window, given as `HH:MM-HH:MM` in the configured timezone. The
+/// window wraps around midnight if the end is before the start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveHours {
+	/// Start of the window in minutes after midnight (inclusive).
+	pub start: u16,
+	/// End of the window in minutes after midnight (exclusive).
+	pub end: u16,
+}
+
+impl ActiveHours {
+	/// Whether the point in time is within the window, in the timezone of the
+	/// offset.
+	pub fn contains(&self, at: OffsetDateTime, offset: UtcOffset) -> bool {
+		let at = at.to_offset(offset);
+		let minute = u16::from(at.hour()) * 60 + u16::from(at.minute());
+		if self.start <= self.end {
+			self.start <= minute && minute < self.end
+		} else {
+			minute >= self.start || minute < self.end
+		}
+	}
+}
+
+impl FromStr for ActiveHours {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let parse_time = |time: &str| -> Result<u16, String> {
+			let (hours, minutes) = time
+				.split_once(':')
+				.ok_or_else(|| format!("`{time}` is not of the form HH:MM!"))?;
+			let hours: u16 = hours.parse().map_err(|_| format!("`{hours}` is not a number!"))?;
+			let minutes: u16 =
+				minutes.parse().map_err(|_| format!("`{minutes}` is not a number!"))?;
+			if hours > 24 || minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+				return Err(format!("`{time}` is not a valid time of day!"));
+			}
+			Ok(hours * 60 + minutes)
+		};
+
+		let (start, end) =
+			s.split_once('-').ok_or_else(|| format!("`{s}` is not of the form HH:MM-HH:MM!"))?;
+		Ok(Self { start: parse_time(start)?, end: parse_time(end)? })
+	}
+}
+
+/// What to do with new feed entries outside of the active hours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutsideActiveHours {
+	/// Hold the entries back and post them when the active hours begin.
+	#[default]
+	Hold,
+	/// Drop the entries without posting them.
+	Drop,
+}
+
+impl FromStr for OutsideActiveHours {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"hold" => Ok(Self::Hold),
+			"drop" => Ok(Self::Drop),
+			_ => Err(format!("`{s}` is neither hold nor drop!")),
+		}
+	}
+}
+
+/// Format of a feed, overriding the automatic detection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeedFormat {
+	/// RSS 2.0 feed.
+	Rss2,
+	/// Atom feed.
+	Atom,
+	/// JSON feed.
+	Json,
+}
+
+impl FromStr for FeedFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"rss2" => Ok(Self::Rss2),
+			"atom" => Ok(Self::Atom),
+			"json" => Ok(Self::Json),
+			_ => Err(format!("`{s}` is neither rss2, atom nor json!")),
+		}
+	}
+}
+
+/// How much of a feed entry is rendered in its message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderFormat {
+	/// Only the title and links.
+	Compact,
+	/// Title, time, summary and links.
+	#[default]
+	Normal,
+	/// Title, time, full content and links, falling back to the summary.
+	Full,
+}
+
+impl RenderFormat {
+	/// Resolve the format of a feed: the feed's override, else the room's
+	/// default, else the global default.
+	pub fn resolve(feed: Option<Self>, room: Option<Self>, global: Self) -> Self {
+		feed.or(room).unwrap_or(global)
+	}
+}
+
+impl FromStr for RenderFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"compact" => Ok(Self::Compact),
+			"normal" => Ok(Self::Normal),
+			"full" => Ok(Self::Full),
+			_ => Err(format!("`{s}` is neither compact, normal nor full!")),
+		}
+	}
+}
+
+impl Display for RenderFormat {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		match self {
+			Self::Compact => f.write_str("compact"),
+			Self::Normal => f.write_str("normal"),
+			Self::Full => f.write_str("full"),
+		}
+	}
+}
+
+/// Document entry for the room-wide RSS digest of a room. If it exists, new
+/// entries of all feeds in the room are collected and posted periodically in
+/// one message instead of one message per entry.
+#[derive(Debug, Clone, Serialize, Deserialize, Collection)]
+#[collection(name = "rss_digests", views = [RssDigestByRoom])]
+pub struct RssDigest {
+	/// Matrix room ID of the digest.
+	pub room: OwnedRoomId,
+	/// Number of seconds between digests.
+	pub every: u64,
+	/// Point in time the last digest was sent.
+	pub last_sent: OffsetDateTime,
+	/// Entries collected for the next digest.
+	pub pending: Vec<DigestEntry>,
+	/// Number of older entries skipped while catching up on a feed since the
+	/// last digest, by feed URL.
+	#[serde(default)]
+	pub skipped: BTreeMap<String, usize>,
+}
+
+impl RssDigest {
+	/// Create a new digest for the room, sending every `every` seconds.
+	pub fn new(room: OwnedRoomId, every: u64) -> Self {
+		Self {
+			room,
+			every,
+			last_sent: OffsetDateTime::now_utc(),
+			pending: Vec::new(),
+			skipped: BTreeMap::new(),
+		}
+	}
+
+	/// Get the digest of a specific room.
+	pub async fn for_room(
+		room: &RoomId,
+		db: &AsyncDatabase,
+	) -> Result<Option<CollectionDocument<Self>>, bonsaidb::core::Error> {
+		let digest = db
+			.view::<RssDigestByRoom>()
+			.with_key(room.to_string())
+			.query_with_collection_docs()
+			.await?
+			.documents
+			.into_values()
+			.next();
+		Ok(digest)
+	}
+
+	/// Whether the next digest is due at the given time.
+	pub fn is_due(&self, now: OffsetDateTime) -> bool {
+		let every = time::Duration::seconds(i64::try_from(self.every).unwrap_or(i64::MAX));
+		self.last_sent.checked_add(every).map_or(false, |due| due <= now)
+	}
+}
+
+/// One entry collected for a digest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestEntry {
+	/// Title of the feed, or its URL if it has no title.
+	pub feed: String,
+	/// Title of the entry.
+	pub title: String,
+	/// Link to the entry.
+	pub link: Option<String>,
+}
+
+/// View on RSS digests by room ID.
+#[derive(Debug, Clone, View)]
+#[view(collection = RssDigest, name = "rss_digests_by_room", key = String, value = ())]
+pub struct RssDigestByRoom;
+
+impl CollectionViewSchema for RssDigestByRoom {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<RssDigest>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.room.to_string(), ())
+	}
+
+	fn unique(&self) -> bool {
+		true
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+/// Document entry for the preferences of a room, overriding the global
+/// configuration for messages in the room. Set via the `!settings` command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Collection)]
+#[collection(name = "room_preferences", views = [RoomPreferencesByRoom])]
+pub struct RoomPreferences {
+	/// Matrix room ID of the preferences.
+	pub room: OwnedRoomId,
+	/// Offset of the room's timezone in minutes from UTC. Uses the configured
+	/// timezone if unset.
+	#[serde(default)]
+	pub utc_offset_minutes: Option<i32>,
+	/// Language of the room, e.g. `de` or `en-US`, selecting the format of
+	/// absolute times. Uses the configured format if unset.
+	#[serde(default)]
+	pub locale: Option<String>,
+	/// Default format of RSS entries in the room. Uses the configured format
+	/// if unset.
+	#[serde(default)]
+	pub rss_format: Option<RenderFormat>,
+}
+
+impl RoomPreferences {
+	/// Create new preferences for the room, using the global defaults.
+	pub fn new(room: OwnedRoomId) -> Self {
+		Self { room, utc_offset_minutes: None, locale: None, rss_format: None }
+	}
+
+	/// Get the preferences of a specific room.
+	pub async fn for_room(
+		room: &RoomId,
+		db: &AsyncDatabase,
+	) -> Result<Option<CollectionDocument<Self>>, bonsaidb::core::Error> {
+		let preferences = db
+			.view::<RoomPreferencesByRoom>()
+			.with_key(room.to_string())
+			.query_with_collection_docs()
+			.await?
+			.documents
+			.into_values()
+			.next();
+		Ok(preferences)
+	}
+
+	/// Save the preferences, replacing the existing preferences of the room.
+	/// Preferences without any overrides are removed instead.
+	pub async fn save(self, db: &AsyncDatabase) -> Result<(), bonsaidb::core::Error> {
+		let existing = Self::for_room(&self.room, db).await?;
+		match existing {
+			Some(current) if self.is_default() => current.delete_async(db).await?,
+			Some(mut current) => {
+				current.contents = self;
+				current.update_async(db).await?;
+			}
+			None if self.is_default() => {}
+			None => {
+				self.push_into_async(db).await?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Whether the preferences do not override anything.
+	fn is_default(&self) -> bool {
+		self.utc_offset_minutes.is_none() && self.locale.is_none() && self.rss_format.is_none()
+	}
+
+	/// Apply the room's overrides to the global timestamp settings.
+	pub fn timestamps(&self, global: &TimestampSettings) -> TimestampSettings {
+		let mut settings = global.clone();
+		if let Some(offset) = self.utc_offset_minutes {
+			settings.utc_offset_minutes = offset;
+		}
+		if let Some(format) = self.locale.as_deref().and_then(timestamps::locale_format) {
+			settings.format = format.to_owned();
+		}
+		settings
+	}
+
+	/// Get the timestamp settings for the room, which are the global settings
+	/// with the room's overrides.
+	pub async fn timestamps_for(
+		room: &RoomId,
+		global: &TimestampSettings,
+		db: &AsyncDatabase,
+	) -> Result<TimestampSettings, bonsaidb::core::Error> {
+		Ok(Self::for_room(room, db)
+			.await?
+			.map_or_else(|| global.clone(), |preferences| preferences.contents.timestamps(global)))
+	}
+}
+
+/// View on room preferences by room ID.
+#[derive(Debug, Clone, View)]
+#[view(collection = RoomPreferences, name = "room_preferences_by_room", key = String, value = ())]
+pub struct RoomPreferencesByRoom;
+
+impl CollectionViewSchema for RoomPreferencesByRoom {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<RoomPreferences>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.room.to_string(), ())
+	}
+
+	fn unique(&self) -> bool {
+		true
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
 /// View on RSS subscriptions by room ID.
 #[derive(Debug, Clone, View)]
 #[view(collection = RssSubscription, name = "rss_subscriptions_by_room", key = String, value = ())]
@@ -131,16 +811,141 @@ pub struct GithubSubscription {
 	pub room: OwnedRoomId,
 	/// User name.
 	pub user: String,
-	/// Access token.
-	pub token: String,
+	/// Access tokens encrypted with the database key, the main token first.
+	/// Additional tokens of the user are rotated through, spreading the rate
+	/// limits.
+	#[serde(default)]
+	pub encrypted_tokens: Vec<Secret>,
+	/// Plaintext access token of subscriptions stored before the tokens were
+	/// encrypted. Encrypted on startup.
+	#[serde(default, rename = "token", skip_serializing_if = "Option::is_none")]
+	legacy_token: Option<String>,
+	/// Plaintext additional access tokens of subscriptions stored before the
+	/// tokens were encrypted. Encrypted on startup.
+	#[serde(default, rename = "extra_tokens", skip_serializing_if = "Vec::is_empty")]
+	legacy_extra_tokens: Vec<String>,
 	/// Latest update posted into the room.
 	pub latest_update: OffsetDateTime,
+	/// Whether posting notifications is muted.
+	#[serde(default)]
+	pub muted: bool,
+	/// Whether to mark notifications as read on Github once they are posted.
+	#[serde(default)]
+	pub mark_read: bool,
+	/// Repository as `owner/name` to only post notifications of. All
+	/// notifications of the account are posted if not set.
+	#[serde(default)]
+	pub repo: Option<String>,
+	/// Last time the notifications were polled successfully. Unset if they were
+	/// not polled yet.
+	#[serde(default)]
+	pub last_polled: Option<OffsetDateTime>,
+	/// Whether the subscription was disabled, because all of its tokens were
+	/// rejected. Enabling it again with new tokens clears it.
+	#[serde(default)]
+	pub disabled: bool,
+	/// Base API URL of a Github Enterprise server, ending in a slash. The
+	/// public Github API is used if not set.
+	#[serde(default)]
+	pub api_url: Option<Url>,
 }
 
 impl GithubSubscription {
-	/// Create a new Github subscription for the current time.
-	pub fn new(room: OwnedRoomId, user: String, token: String) -> Self {
-		Self { room, user, token, latest_update: OffsetDateTime::now_utc() }
+	/// Create a new Github subscription for the current time, with the token
+	/// encrypted with the key.
+	pub fn new(room: OwnedRoomId, user: String, token: String, key: &SecretKey) -> Result<Self> {
+		Ok(Self {
+			room,
+			user,
+			encrypted_tokens: vec![Secret::seal(&token, key)?],
+			legacy_token: None,
+			legacy_extra_tokens: Vec::new(),
+			latest_update: OffsetDateTime::now_utc(),
+			muted: false,
+			mark_read: false,
+			repo: None,
+			last_polled: None,
+			disabled: false,
+			api_url: None,
+		})
+	}
+
+	/// All access tokens of the subscription decrypted with the key, the main
+	/// token first. Tokens that cannot be decrypted are logged and skipped.
+	pub fn tokens(&self, key: &SecretKey) -> Vec<String> {
+		let encrypted = self.encrypted_tokens.iter().filter_map(|secret| match secret.open(key) {
+			Ok(token) => Some(token),
+			Err(err) => {
+				tracing::error!("Failed decrypting a Github token of {}: {err}", self.user);
+				None
+			}
+		});
+		self.legacy_token
+			.iter()
+			.chain(&self.legacy_extra_tokens)
+			.cloned()
+			.chain(encrypted)
+			.collect()
+	}
+
+	/// The main access token decrypted with the key.
+	pub fn main_token(&self, key: &SecretKey) -> Result<String> {
+		self.tokens(key)
+			.into_iter()
+			.next()
+			.ok_or_else(|| eyre!("Github subscription of {} has no usable token", self.user))
+	}
+
+	/// Number of access tokens of the subscription.
+	pub fn token_count(&self) -> usize {
+		usize::from(self.legacy_token.is_some())
+			+ self.legacy_extra_tokens.len()
+			+ self.encrypted_tokens.len()
+	}
+
+	/// Store the access tokens encrypted with the key, the main token first.
+	pub fn set_tokens(&mut self, tokens: &[String], key: &SecretKey) -> Result<()> {
+		self.encrypted_tokens =
+			tokens.iter().map(|token| Secret::seal(token, key)).collect::<Result<_>>()?;
+		self.legacy_token = None;
+		self.legacy_extra_tokens.clear();
+		Ok(())
+	}
+
+	/// Replace the main access token, keeping the additional tokens.
+	pub fn set_main_token(&mut self, token: &str, key: &SecretKey) -> Result<()> {
+		self.seal_legacy_tokens(key)?;
+		let sealed = Secret::seal(token, key)?;
+		match self.encrypted_tokens.first_mut() {
+			Some(main) => *main = sealed,
+			None => self.encrypted_tokens.push(sealed),
+		}
+		Ok(())
+	}
+
+	/// Encrypt the plaintext tokens stored before the tokens were encrypted.
+	/// Returns whether there were any.
+	fn seal_legacy_tokens(&mut self, key: &SecretKey) -> Result<bool> {
+		if self.legacy_token.is_none() && self.legacy_extra_tokens.is_empty() {
+			return Ok(false);
+		}
+		let legacy = self.legacy_token.take().into_iter().chain(self.legacy_extra_tokens.drain(..));
+		let mut sealed =
+			legacy.map(|token| Secret::seal(&token, key)).collect::<Result<Vec<_>>>()?;
+		sealed.append(&mut self.encrypted_tokens);
+		self.encrypted_tokens = sealed;
+		Ok(true)
+	}
+
+	/// Encrypt the plaintext tokens of all subscriptions stored before the
+	/// tokens were encrypted.
+	pub async fn seal_all_legacy_tokens(key: &SecretKey, db: &AsyncDatabase) -> Result<()> {
+		for mut subscription in Self::all_async(db).await? {
+			if subscription.contents.seal_legacy_tokens(key)? {
+				subscription.update_async(db).await?;
+			}
+		}
+		Ok(())
 	}
 
 	/// Get Github subscriptions for a specific room.
@@ -169,17 +974,49 @@ impl GithubSubscription {
 			.find(|doc| doc.contents.user.as_str() == user))
 	}
 
-	/// Insert the given Github subscription into the database.
+	/// Insert the given Github subscription into the database. An existing
+	/// subscription of the user is replaced, including its options.
 	pub async fn insert(self, db: &AsyncDatabase) -> Result<(), bonsaidb::core::Error> {
 		if let Some(mut current) = Self::find(&self.room, &self.user, db).await? {
-			current.contents.token = self.token;
+			current.contents.encrypted_tokens = self.encrypted_tokens;
+			current.contents.legacy_token = self.legacy_token;
+			current.contents.legacy_extra_tokens = self.legacy_extra_tokens;
 			current.contents.latest_update = self.latest_update;
+			current.contents.mark_read = self.mark_read;
+			current.contents.repo = self.repo;
+			current.contents.disabled = self.disabled;
+			current.contents.api_url = self.api_url;
 			current.update_async(db).await?;
 		} else {
 			self.push_into_async(db).await?;
 		}
 		Ok(())
 	}
+
+	/// Subscribe the room to the notifications of the user logged in with the
+	/// token. An existing subscription of the user only gets the new main token
+	/// and is enabled again, keeping its options and additional tokens.
+	pub async fn login(
+		room: &RoomId,
+		user: &str,
+		token: &str,
+		key: &SecretKey,
+		db: &AsyncDatabase,
+	) -> Result<()> {
+		match Self::find(room, user, db).await? {
+			Some(mut current) => {
+				current.contents.set_main_token(token, key)?;
+				current.contents.disabled = false;
+				current.update_async(db).await?;
+			}
+			None => {
+				Self::new(room.to_owned(), user.to_owned(), token.to_owned(), key)?
+					.push_into_async(db)
+					.await?;
+			}
+		}
+		Ok(())
+	}
 }
 
 /// View on Github subscriptions by room ID.
@@ -202,3 +1039,440 @@ impl CollectionViewSchema for GithubSubByRoom {
 		0
 	}
 }
+
+/// Document entry for a Github notification posted for a subscription, so
+/// that it is not posted twice when it is fetched again.
+#[derive(Debug, Clone, Serialize, Deserialize, Collection)]
+#[collection(name = "posted_github_notifications", views = [PostedNotificationBySubscription])]
+pub struct PostedNotification {
+	/// Matrix room ID of the subscription.
+	pub room: OwnedRoomId,
+	/// User name of the subscription.
+	pub user: String,
+	/// Github notification (thread) ID.
+	pub id: String,
+	/// Point in time the notification was updated when it was posted.
+	pub updated_at: OffsetDateTime,
+}
+
+impl PostedNotification {
+	/// Remember a posted notification of the subscription.
+	pub async fn record(
+		room: &RoomId,
+		user: &str,
+		id: &str,
+		updated_at: OffsetDateTime,
+		db: &AsyncDatabase,
+	) -> Result<(), bonsaidb::core::Error> {
+		Self { room: room.to_owned(), user: user.to_owned(), id: id.to_owned(), updated_at }
+			.push_into_async(db)
+			.await?;
+		Ok(())
+	}
+
+	/// Get the posted notifications of a subscription.
+	pub async fn for_subscription(
+		room: &RoomId,
+		user: &str,
+		db: &AsyncDatabase,
+	) -> Result<Vec<CollectionDocument<Self>>, bonsaidb::core::Error> {
+		let posted = db
+			.view::<PostedNotificationBySubscription>()
+			.with_key(subscription_key(room, user))
+			.query_with_collection_docs()
+			.await?
+			.documents
+			.into_values()
+			.collect();
+		Ok(posted)
+	}
+
+	/// Whether the notification was posted already in the same or a newer
+	/// version.
+	pub fn contains(
+		posted: &[CollectionDocument<Self>],
+		id: &str,
+		updated_at: OffsetDateTime,
+	) -> bool {
+		posted.iter().any(|doc| doc.contents.id == id && doc.contents.updated_at >= updated_at)
+	}
+
+	/// Forget the posted notifications of a subscription that were updated
+	/// before the given point in time. Returns the number of forgotten
+	/// notifications.
+	pub async fn prune_before(
+		room: &RoomId,
+		user: &str,
+		before: OffsetDateTime,
+		db: &AsyncDatabase,
+	) -> Result<usize, bonsaidb::core::Error> {
+		let mut count = 0;
+		for posted in Self::for_subscription(room, user, db).await? {
+			if posted.contents.updated_at < before {
+				posted.delete_async(db).await?;
+				count += 1;
+			}
+		}
+		Ok(count)
+	}
+
+	/// Forget all posted notifications of a subscription, e.g. when it is
+	/// removed.
+	pub async fn forget(
+		room: &RoomId,
+		user: &str,
+		db: &AsyncDatabase,
+	) -> Result<(), bonsaidb::core::Error> {
+		for posted in Self::for_subscription(room, user, db).await? {
+			posted.delete_async(db).await?;
+		}
+		Ok(())
+	}
+}
+
+/// Key of a subscription in views, made of the room ID and user name.
+fn subscription_key(room: &RoomId, user: &str) -> String {
+	format!("{room} {user}")
+}
+
+/// View on posted Github notifications by subscription, keyed by room ID and
+/// user name.
+#[derive(Debug, Clone, View)]
+#[view(collection = PostedNotification, name = "posted_github_notifications_by_subscription", key = String, value = ())]
+pub struct PostedNotificationBySubscription;
+
+impl CollectionViewSchema for PostedNotificationBySubscription {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<PostedNotification>) -> ViewMapResult<Self::View> {
+		let key = subscription_key(&document.contents.room, &document.contents.user);
+		document.header.emit_key_and_value(key, ())
+	}
+
+	fn unique(&self) -> bool {
+		false
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+/// Document entry for one scheduled reminder.
+#[derive(Debug, Clone, Serialize, Deserialize, Collection)]
+#[collection(name = "reminders", views = [ReminderByRoom, ReminderByTime])]
+pub struct Reminder {
+	/// Matrix room ID the reminder is sent to.
+	pub room: OwnedRoomId,
+	/// Who to remind.
+	pub who: OwnedUserId,
+	/// Reminder message.
+	pub message: String,
+	/// Point in time the reminder fires.
+	pub fire_at: OffsetDateTime,
+	/// Increased on every edit. Jobs scheduled for an older generation are
+	/// outdated and do not fire.
+	#[serde(default)]
+	pub generation: u64,
+}
+
+impl Reminder {
+	/// Get pending reminders for a specific room.
+	pub async fn for_room(
+		room: &RoomId,
+		db: &AsyncDatabase,
+	) -> Result<BTreeMap<DocumentId, CollectionDocument<Self>>, bonsaidb::core::Error> {
+		let reminders = db
+			.view::<ReminderByRoom>()
+			.with_key(room.to_string())
+			.query_with_collection_docs()
+			.await?
+			.documents;
+		Ok(reminders)
+	}
+
+	/// Get all pending reminders across all rooms, ordered by fire time.
+	pub async fn all_by_time(
+		db: &AsyncDatabase,
+	) -> Result<Vec<CollectionDocument<Self>>, bonsaidb::core::Error> {
+		let reminders =
+			db.view::<ReminderByTime>().ascending().query_with_collection_docs().await?;
+		Ok(reminders
+			.mappings
+			.iter()
+			.filter_map(|mapping| reminders.documents.get(&mapping.source.id))
+			.cloned()
+			.collect())
+	}
+}
+
+/// View on reminders by room ID.
+#[derive(Debug, Clone, View)]
+#[view(collection = Reminder, name = "reminders_by_room", key = String, value = ())]
+pub struct ReminderByRoom;
+
+impl CollectionViewSchema for ReminderByRoom {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<Reminder>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.room.to_string(), ())
+	}
+
+	fn unique(&self) -> bool {
+		false
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+/// View on reminders by fire time (UNIX timestamp).
+#[derive(Debug, Clone, View)]
+#[view(collection = Reminder, name = "reminders_by_time", key = i64, value = ())]
+pub struct ReminderByTime;
+
+impl CollectionViewSchema for ReminderByTime {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<Reminder>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.fire_at.unix_timestamp(), ())
+	}
+
+	fn unique(&self) -> bool {
+		false
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+/// Document entry for one audit log entry, e.g. a command invocation or a
+/// significant action of the bot.
+#[derive(Debug, Clone, Serialize, Deserialize, Collection)]
+#[collection(name = "audit_log", views = [AuditByRoom, AuditByTime])]
+pub struct AuditEntry {
+	/// Matrix room ID the action happened in.
+	pub room: OwnedRoomId,
+	/// Who caused the action, if caused by a user.
+	pub who: Option<OwnedUserId>,
+	/// Description of the action.
+	pub action: String,
+	/// Point in time of the action.
+	pub at: OffsetDateTime,
+}
+
+impl AuditEntry {
+	/// Record a new audit entry for the current time.
+	pub async fn record(
+		room: &RoomId,
+		who: Option<&UserId>,
+		action: impl Into<String> + Send,
+		db: &AsyncDatabase,
+	) -> Result<(), bonsaidb::core::Error> {
+		Self {
+			room: room.to_owned(),
+			who: who.map(ToOwned::to_owned),
+			action: action.into(),
+			at: OffsetDateTime::now_utc(),
+		}
+		.push_into_async(db)
+		.await?;
+		Ok(())
+	}
+
+	/// Get the latest `count` audit entries of a room, newest first.
+	pub async fn latest_for_room(
+		room: &RoomId,
+		count: usize,
+		db: &AsyncDatabase,
+	) -> Result<Vec<Self>, bonsaidb::core::Error> {
+		let mut entries = db
+			.view::<AuditByRoom>()
+			.with_key(room.to_string())
+			.query_with_collection_docs()
+			.await?
+			.documents
+			.into_values()
+			.map(|doc| doc.contents)
+			.collect::<Vec<_>>();
+		entries.sort_by(|a, b| b.at.cmp(&a.at));
+		entries.truncate(count);
+		Ok(entries)
+	}
+
+	/// Delete all audit entries from before the given point in time. Returns
+	/// the number of deleted entries.
+	pub async fn prune_before(
+		before: OffsetDateTime,
+		db: &AsyncDatabase,
+	) -> Result<usize, bonsaidb::core::Error> {
+		let entries = db
+			.view::<AuditByTime>()
+			.with_key_range(..before.unix_timestamp())
+			.query_with_collection_docs()
+			.await?
+			.documents;
+		let count = entries.len();
+		for entry in entries.into_values() {
+			entry.delete_async(db).await?;
+		}
+		Ok(count)
+	}
+}
+
+/// View on audit entries by room ID.
+#[derive(Debug, Clone, View)]
+#[view(collection = AuditEntry, name = "audit_log_by_room", key = String, value = ())]
+pub struct AuditByRoom;
+
+impl CollectionViewSchema for AuditByRoom {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<AuditEntry>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.room.to_string(), ())
+	}
+
+	fn unique(&self) -> bool {
+		false
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+/// View on audit entries by time (UNIX timestamp).
+#[derive(Debug, Clone, View)]
+#[view(collection = AuditEntry, name = "audit_log_by_time", key = i64, value = ())]
+pub struct AuditByTime;
+
+impl CollectionViewSchema for AuditByTime {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<AuditEntry>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.at.unix_timestamp(), ())
+	}
+
+	fn unique(&self) -> bool {
+		false
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+/// Subscription a message was sent for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageSource {
+	/// RSS subscription of the feed URL.
+	Rss(Url),
+	/// Github notifications subscription of the user.
+	Github(String),
+}
+
+/// Document entry for a message sent by the bot for a subscription, to map
+/// reactions to the message back to the subscription.
+#[derive(Debug, Clone, Serialize, Deserialize, Collection)]
+#[collection(name = "sent_messages", views = [SentMessageByEvent])]
+pub struct SentMessage {
+	/// Matrix room ID the message was sent to.
+	pub room: OwnedRoomId,
+	/// Event ID of the message.
+	pub event_id: OwnedEventId,
+	/// Subscription the message was sent for.
+	pub source: MessageSource,
+}
+
+impl SentMessage {
+	/// Remember a sent message. Only the latest [`MAX_SENT_MESSAGES`] messages
+	/// are remembered, older ones are forgotten.
+	pub async fn record(
+		room: &RoomId,
+		event_id: OwnedEventId,
+		source: MessageSource,
+		db: &AsyncDatabase,
+	) -> Result<(), bonsaidb::core::Error> {
+		Self { room: room.to_owned(), event_id, source }.push_into_async(db).await?;
+
+		let count = Self::all_async(db).count().await?;
+		if count > MAX_SENT_MESSAGES {
+			let excess = u32::try_from(count - MAX_SENT_MESSAGES).unwrap_or(u32::MAX);
+			for message in Self::all_async(db).limit(excess).await? {
+				message.delete_async(db).await?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Find a remembered sent message by its event ID.
+	pub async fn find(
+		event_id: &EventId,
+		db: &AsyncDatabase,
+	) -> Result<Option<Self>, bonsaidb::core::Error> {
+		let message = db
+			.view::<SentMessageByEvent>()
+			.with_key(event_id.to_string())
+			.query_with_collection_docs()
+			.await?
+			.documents
+			.into_values()
+			.next()
+			.map(|doc| doc.contents);
+		Ok(message)
+	}
+
+	/// Toggle muting the subscription the message was sent for. Returns whether
+	/// it is muted now, or `None` if the subscription does not exist anymore.
+	pub async fn toggle_mute(
+		&self,
+		db: &AsyncDatabase,
+	) -> Result<Option<bool>, bonsaidb::core::Error> {
+		match &self.source {
+			MessageSource::Rss(url) => {
+				let mut subscription = match RssSubscription::find(&self.room, url, db).await? {
+					Some(subscription) => subscription,
+					None => return Ok(None),
+				};
+				subscription.contents.muted = !subscription.contents.muted;
+				subscription.update_async(db).await?;
+				Ok(Some(subscription.contents.muted))
+			}
+			MessageSource::Github(user) => {
+				let mut subscription = match GithubSubscription::find(&self.room, user, db).await? {
+					Some(subscription) => subscription,
+					None => return Ok(None),
+				};
+				subscription.contents.muted = !subscription.contents.muted;
+				subscription.update_async(db).await?;
+				Ok(Some(subscription.contents.muted))
+			}
+		}
+	}
+}
+
+/// View on sent messages by event ID.
+#[derive(Debug, Clone, View)]
+#[view(collection = SentMessage, name = "sent_messages_by_event", key = String, value = ())]
+pub struct SentMessageByEvent;
+
+impl CollectionViewSchema for SentMessageByEvent {
+	type View = Self;
+
+	fn map(&self, document: CollectionDocument<SentMessage>) -> ViewMapResult<Self::View> {
+		document.header.emit_key_and_value(document.contents.event_id.to_string(), ())
+	}
+
+	fn unique(&self) -> bool {
+		true
+	}
+
+	fn version(&self) -> u64 {
+		0
+	}
+}
+
+#[cfg(test)]
+mod tests;
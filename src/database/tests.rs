@@ -0,0 +1,155 @@
+//! Tests for the database.
+
+use bonsaidb::core::schema::SerializedCollection;
+use color_eyre::{eyre::eyre, Result};
+use time::Duration;
+
+use super::*;
+use crate::test_utils::test_databases;
+
+#[tokio::test]
+async fn reminders_across_rooms_by_time() -> Result<()> {
+	let db = test_databases().await?;
+	let room_a: OwnedRoomId = "!a:example.com".try_into()?;
+	let room_b: OwnedRoomId = "!b:example.com".try_into()?;
+	let who: OwnedUserId = "@user:example.com".try_into()?;
+	let now = OffsetDateTime::now_utc();
+
+	for (room, offset, message) in
+		[(&room_a, 30, "third"), (&room_b, 10, "first"), (&room_a, 20, "second")]
+	{
+		Reminder {
+			room: room.clone(),
+			who: who.clone(),
+			message: message.to_owned(),
+			fire_at: now + Duration::minutes(offset),
+			generation: 0,
+		}
+		.push_into_async(&db.state)
+		.await?;
+	}
+
+	let all = Reminder::all_by_time(&db.state).await?;
+	let messages = all.iter().map(|doc| doc.contents.message.as_str()).collect::<Vec<_>>();
+	assert_eq!(messages, vec!["first", "second", "third"]);
+
+	let in_room_a = Reminder::for_room(&room_a, &db.state).await?;
+	assert_eq!(in_room_a.len(), 2);
+	Ok(())
+}
+
+#[tokio::test]
+async fn migrate_room_moves_everything() -> Result<()> {
+	let db = test_databases().await?;
+	let old_room: OwnedRoomId = "!old:example.com".try_into()?;
+	let new_room: OwnedRoomId = "!new:example.com".try_into()?;
+	let other_room: OwnedRoomId = "!other:example.com".try_into()?;
+	let who: OwnedUserId = "@user:example.com".try_into()?;
+
+	let feed: Url = "https://example.com/feed".parse()?;
+	RssSubscription::new(old_room.clone(), feed.clone()).insert(&db.state).await?;
+	RssSubscription::new(new_room.clone(), feed.clone()).insert(&db.state).await?;
+	RssSubscription::new(other_room.clone(), feed.clone()).insert(&db.state).await?;
+	let key = SecretKey::derive("password");
+	GithubSubscription::new(old_room.clone(), "user".to_owned(), "token".to_owned(), &key)?
+		.insert(&db.state)
+		.await?;
+	let updated_at = OffsetDateTime::now_utc();
+	PostedNotification::record(&old_room, "user", "1", updated_at, &db.state).await?;
+	PostedNotification::record(&other_room, "user", "2", updated_at, &db.state).await?;
+	Reminder {
+		room: old_room.clone(),
+		who,
+		message: "message".to_owned(),
+		fire_at: OffsetDateTime::now_utc(),
+		generation: 0,
+	}
+	.push_into_async(&db.state)
+	.await?;
+
+	migrate_room(&old_room, &new_room, &db.state).await?;
+
+	assert!(RssSubscription::for_room(&old_room, &db.state).await?.is_empty());
+	assert!(GithubSubscription::for_room(&old_room, &db.state).await?.is_empty());
+	assert!(Reminder::for_room(&old_room, &db.state).await?.is_empty());
+	assert_eq!(RssSubscription::for_room(&new_room, &db.state).await?.len(), 1);
+	assert_eq!(GithubSubscription::for_room(&new_room, &db.state).await?.len(), 1);
+	assert_eq!(Reminder::for_room(&new_room, &db.state).await?.len(), 1);
+	assert_eq!(RssSubscription::for_room(&other_room, &db.state).await?.len(), 1);
+
+	assert!(PostedNotification::for_subscription(&old_room, "user", &db.state).await?.is_empty());
+	let posted = PostedNotification::for_subscription(&new_room, "user", &db.state).await?;
+	assert!(PostedNotification::contains(&posted, "1", updated_at));
+	assert_eq!(posted.len(), 1);
+	let posted = PostedNotification::for_subscription(&other_room, "user", &db.state).await?;
+	assert!(PostedNotification::contains(&posted, "2", updated_at));
+	Ok(())
+}
+
+#[tokio::test]
+async fn feed_credentials_are_stored_encrypted_and_redacted() -> Result<()> {
+	let db = test_databases().await?;
+	let key = SecretKey::derive("password");
+	let auth =
+		FeedAuth::Basic { username: String::from("user"), password: Some(String::from("hunter2")) };
+	let debug = format!("{auth:?}");
+	assert!(debug.contains("user") && !debug.contains("hunter2"));
+	assert!(!format!("{:?}", FeedAuth::Bearer(String::from("token"))).contains("token"));
+
+	let mut subscription =
+		RssSubscription::new("!room:example.com".try_into()?, "https://example.com/feed".parse()?);
+	subscription.set_auth(Some(&auth), &key)?;
+	let stored = serde_json::to_string(&subscription)?;
+	assert!(!stored.contains("hunter2"));
+	assert!(!format!("{subscription:?}").contains("hunter2"));
+
+	subscription.insert(&db.state).await?;
+	let loaded = RssSubscription::all_async(&db.state).await?;
+	assert_eq!(loaded[0].contents.auth(&key), Some(auth));
+	assert_eq!(loaded[0].contents.auth(&SecretKey::derive("other")), None);
+	Ok(())
+}
+
+#[tokio::test]
+async fn github_tokens_are_stored_encrypted_and_kept_on_login() -> Result<()> {
+	let db = test_databases().await?;
+	let key = SecretKey::derive("password");
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+
+	// Subscription stored before the tokens were encrypted.
+	let subscription =
+		GithubSubscription::new(room.clone(), "user".to_owned(), "ghp_first".to_owned(), &key)?;
+	let mut legacy = serde_json::to_value(subscription)?;
+	legacy["encrypted_tokens"] = serde_json::json!([]);
+	legacy["token"] = serde_json::json!("ghp_first");
+	legacy["extra_tokens"] = serde_json::json!(["ghp_second"]);
+	serde_json::from_value::<GithubSubscription>(legacy)?.push_into_async(&db.state).await?;
+
+	GithubSubscription::seal_all_legacy_tokens(&key, &db.state).await?;
+	let mut stored = GithubSubscription::find(&room, "user", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?;
+	assert_eq!(stored.contents.tokens(&key), ["ghp_first", "ghp_second"].map(String::from));
+	assert!(!serde_json::to_string(&stored.contents)?.contains("ghp_"));
+	assert!(stored.contents.tokens(&SecretKey::derive("other")).is_empty());
+
+	stored.contents.mark_read = true;
+	stored.contents.repo = Some("owner/repo".to_owned());
+	stored.contents.api_url = Some("https://github.example.com/api/v3/".parse()?);
+	stored.contents.disabled = true;
+	stored.update_async(&db.state).await?;
+	GithubSubscription::login(&room, "user", "ghp_login", &key, &db.state).await?;
+
+	let stored = GithubSubscription::find(&room, "user", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?
+		.contents;
+	assert_eq!(stored.tokens(&key), ["ghp_login", "ghp_second"].map(String::from));
+	assert!(stored.mark_read && !stored.disabled);
+	assert_eq!(stored.repo.as_deref(), Some("owner/repo"));
+	assert!(stored.api_url.is_some());
+
+	GithubSubscription::login(&room, "octocat", "ghp_octocat", &key, &db.state).await?;
+	assert_eq!(GithubSubscription::for_room(&room, &db.state).await?.len(), 2);
+	Ok(())
+}
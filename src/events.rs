@@ -10,21 +10,38 @@ use color_eyre::{
 };
 use matrix_sdk::{
 	event_handler::Ctx,
-	room::Room,
-	ruma::events::room::{
-		member::{MembershipState, StrippedRoomMemberEvent, SyncRoomMemberEvent},
-		message::OriginalSyncRoomMessageEvent,
+	room::{Invited, Room},
+	ruma::{
+		api::client::{membership::leave_room, message::send_message_event},
+		events::{
+			reaction::OriginalSyncReactionEvent,
+			room::{
+				member::{MembershipState, StrippedRoomMemberEvent, SyncRoomMemberEvent},
+				message::{
+					MessageFormat, MessageType, OriginalSyncRoomMessageEvent, Relation,
+					RoomMessageEventContent,
+				},
+				tombstone::OriginalSyncRoomTombstoneEvent,
+			},
+		},
+		EventId, RoomId, TransactionId, UserId,
 	},
 	Client,
 };
 
 use crate::{
-	commands::{parse_arguments, Command},
-	database::Databases,
+	commands::{json_help, normalize_arguments, parse_arguments, Command},
+	database::{
+		self, AuditEntry, Databases, GithubSubscription, MessageSource, RssSubscription,
+		SentMessage,
+	},
 	matrix,
-	settings::Settings,
+	settings::{CommandSettings, Settings},
 };
 
+/// Reaction to toggle muting the subscription of a message.
+const MUTE_REACTION: &str = "🔇";
+
 /// Matrix room message event handler.
 #[tracing::instrument(level = "debug", skip_all)]
 pub async fn on_room_message(
@@ -35,7 +52,7 @@ pub async fn on_room_message(
 	db: Ctx<Databases>,
 ) -> Result<()> {
 	let own_id = client.user_id().ok_or_else(|| eyre!("Couldn't get own user ID"))?;
-	if event.sender == own_id {
+	if is_ignored_sender(&event.sender, own_id, &config.commands) {
 		return Ok(());
 	}
 
@@ -44,7 +61,8 @@ pub async fn on_room_message(
 		_ => bail!("Received message from not-joined room"),
 	};
 
-	// Ignore messages from before joining.
+	// Ignore messages from before joining, except within the grace period and
+	// clock skew tolerance.
 	let joined_ts = room
 		.get_member_no_sync(own_id)
 		.await?
@@ -52,16 +70,26 @@ pub async fn on_room_message(
 		.event()
 		.origin_server_ts()
 		.ok_or_else(|| eyre!("Own join event does not have timestamp"))?;
-	if event.origin_server_ts < joined_ts {
+	if !is_after_join(
+		event.origin_server_ts.get().into(),
+		joined_ts.get().into(),
+		config.commands.pre_join_grace,
+		config.clock_skew_tolerance,
+	) {
 		return Ok(());
 	}
 
-	let msg = event.content.body();
-	tracing::trace!("{}: {msg}", event.sender);
+	tracing::trace!("{}: {}", event.sender, event.content.body());
 
 	// Check if there is a command we need to react on
-	if let Some(arguments) = msg.strip_prefix('!') {
-		let mut arguments = parse_arguments(arguments);
+	if let Some(arguments) = command_arguments(&event.content) {
+		let mut arguments = normalize_arguments(parse_arguments(&arguments));
+		if let Some(help) = json_help(&arguments) {
+			let message = RoomMessageEventContent::text_markdown(format!("```json\n{help}\n```"))
+				.make_reply_to(&event.into_full_event(room.room_id().to_owned()));
+			room.send(message, None).await?;
+			return Ok(());
+		}
 		arguments.insert(0, String::from("!"));
 		match Command::try_parse_from(arguments) {
 			Ok(mut command) => {
@@ -87,6 +115,47 @@ pub async fn on_room_message(
 	Ok(())
 }
 
+/// Get the command arguments (after the `!` prefix) of a message, if it is a
+/// command. If the plain body is not a command, the HTML formatted body is
+/// checked with its formatting stripped, as clients might format the prefix.
+/// Reply fallbacks are ignored, so that replies can be commands.
+fn command_arguments(content: &RoomMessageEventContent) -> Option<String> {
+	let is_reply = matches!(content.relates_to, Some(Relation::Reply { .. }));
+	let body = if is_reply { matrix::strip_reply_fallback(content.body()) } else { content.body() };
+	if let Some(arguments) = body.strip_prefix('!') {
+		return Some(arguments.to_owned());
+	}
+
+	let formatted = match &content.msgtype {
+		MessageType::Text(text) => text.formatted.as_ref()?,
+		_ => return None,
+	};
+	if formatted.format != MessageFormat::Html {
+		return None;
+	}
+	let html =
+		formatted.body.split_once("</mx-reply>").map_or(formatted.body.as_str(), |(_, html)| html);
+	matrix::html_to_plain(html).strip_prefix('!').map(ToOwned::to_owned)
+}
+
+/// Whether a message (timestamp in milliseconds) was sent after joining the
+/// room (timestamp in milliseconds), or at most `grace_secs` seconds before.
+/// The timestamps might come from different servers, so their clocks are
+/// allowed to differ by `tolerance_secs` seconds.
+fn is_after_join(message_ts: u64, joined_ts: u64, grace_secs: u64, tolerance_secs: u64) -> bool {
+	let allowed_secs = grace_secs.saturating_add(tolerance_secs);
+	message_ts.saturating_add(allowed_secs.saturating_mul(1000)) >= joined_ts
+}
+
+/// Whether events of the sender are ignored by the account with the own ID.
+/// The account always ignores itself, but sibling accounts of the same
+/// deployment are only ignored if processing their messages is disabled.
+fn is_ignored_sender(sender: &UserId, own_id: &UserId, config: &CommandSettings) -> bool {
+	sender == own_id
+		|| (!config.process_sibling_messages
+			&& config.sibling_accounts.iter().any(|sibling| sibling == sender))
+}
+
 /// Matrix invite event handler.
 #[tracing::instrument(level = "debug", skip_all)]
 pub async fn on_invite_event(
@@ -94,9 +163,10 @@ pub async fn on_invite_event(
 	room: Room,
 	client: Client,
 	config: Ctx<Arc<Settings>>,
+	db: Ctx<Databases>,
 ) -> Result<()> {
 	let own_id = client.user_id().ok_or_else(|| eyre!("Couldn't get own user ID"))?;
-	if event.sender == own_id {
+	if is_ignored_sender(&event.sender, own_id, &config.commands) {
 		return Ok(());
 	}
 
@@ -113,14 +183,86 @@ pub async fn on_invite_event(
 		let room_name = room.name().unwrap_or_else(|| room.room_id().to_string());
 		tracing::debug!("Received invite for room {room_name}");
 
-		if config.access.admins.contains(&event.sender) {
+		if config.access.is_admin(&event.sender) {
 			tracing::info!("Joining room {room_name}");
-			room.accept_invitation().await?;
+			accept_invite(&config, &db, &client, &room, &event.sender).await?;
 		} else {
 			tracing::info!("Rejecting invitation to {room_name} from {}", event.sender);
-			room.reject_invitation().await?;
+			reject_invite(&config, &client, &room).await?;
+		}
+	}
+	Ok(())
+}
+
+/// Accept the invite to the room. Posts a summary of the room's existing
+/// subscriptions if enabled, e.g. when the bot is invited back into a room.
+pub async fn accept_invite(
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	room: &Invited,
+	inviter: &UserId,
+) -> Result<()> {
+	room.accept_invitation().await?;
+	AuditEntry::record(room.room_id(), Some(inviter), "invited the bot", &db.state).await?;
+
+	if config.rooms.rejoin_summary {
+		if let Some(summary) = rejoin_summary(room.room_id(), db).await? {
+			let message = RoomMessageEventContent::notice_plain(summary);
+			let txn_id = TransactionId::new();
+			let request = send_message_event::v3::Request::new(room.room_id(), &txn_id, &message)?;
+			if let Err(err) = client.send(request, None).await {
+				tracing::warn!("Failed sending the rejoin summary: {err}");
+			}
+		}
+	}
+	Ok(())
+}
+
+/// Summary of the existing subscriptions of the room, if there are any.
+async fn rejoin_summary(room: &RoomId, db: &Databases) -> Result<Option<String>> {
+	let feeds = RssSubscription::for_room(room, &db.state).await?.len();
+	let users = GithubSubscription::for_room(room, &db.state).await?.len();
+	if feeds == 0 && users == 0 {
+		return Ok(None);
+	}
+
+	let plural = |count: usize| if count == 1 { "" } else { "s" };
+	Ok(Some(format!(
+		"I'm back! Tracking {feeds} RSS feed{} and {users} Github user{} here.",
+		plural(feeds),
+		plural(users)
+	)))
+}
+
+/// Reject the invite to the room, sending the configured rejection message
+/// into the room before if there is one.
+pub async fn reject_invite(config: &Settings, client: &Client, room: &Invited) -> Result<()> {
+	if let Some(message) = rejection_message(config) {
+		match send_and_leave(client, room.room_id(), &message).await {
+			Ok(()) => return Ok(()),
+			Err(err) => tracing::warn!("Failed sending invite rejection message: {err}"),
 		}
 	}
+	room.reject_invitation().await?;
+	Ok(())
+}
+
+/// The message to send when rejecting an invite, if configured.
+fn rejection_message(config: &Settings) -> Option<RoomMessageEventContent> {
+	config.access.invite_rejection_message.as_deref().map(RoomMessageEventContent::notice_plain)
+}
+
+/// Join the room, send the message and leave again.
+async fn send_and_leave(
+	client: &Client,
+	room_id: &RoomId,
+	message: &RoomMessageEventContent,
+) -> Result<()> {
+	client.join_room_by_id(room_id).await?;
+	let txn_id = TransactionId::new();
+	client.send(send_message_event::v3::Request::new(room_id, &txn_id, message)?, None).await?;
+	client.send(leave_room::v3::Request::new(room_id), None).await?;
 	Ok(())
 }
 
@@ -159,3 +301,96 @@ pub async fn on_room_membership_event(
 	}
 	Ok(())
 }
+
+/// Matrix reaction event handler. Toggles muting the subscription a message
+/// was sent for when a mod reacts to it with [`MUTE_REACTION`].
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn on_reaction(
+	event: OriginalSyncReactionEvent,
+	room: Room,
+	client: Client,
+	config: Ctx<Arc<Settings>>,
+	db: Ctx<Databases>,
+) -> Result<()> {
+	let own_id = client.user_id().ok_or_else(|| eyre!("Couldn't get own user ID"))?;
+	if is_ignored_sender(&event.sender, own_id, &config.commands)
+		|| event.content.relates_to.key != MUTE_REACTION
+		|| !config.access.is_mod(&event.sender)
+	{
+		return Ok(());
+	}
+
+	let room = match room {
+		Room::Joined(room) => room,
+		_ => return Ok(()),
+	};
+	if let Some(text) = mute_by_reaction(&event.content.relates_to.event_id, &db).await? {
+		AuditEntry::record(room.room_id(), Some(&event.sender), text.clone(), &db.state).await?;
+		room.send(RoomMessageEventContent::notice_plain(text), None).await?;
+	}
+	Ok(())
+}
+
+/// Toggle muting the subscription the message with the event ID was sent for.
+/// Returns the confirmation text, or `None` if the message's subscription is
+/// not known.
+async fn mute_by_reaction(event_id: &EventId, db: &Databases) -> Result<Option<String>> {
+	let message = match SentMessage::find(event_id, &db.state).await? {
+		Some(message) => message,
+		None => return Ok(None),
+	};
+	let muted = match message.toggle_mute(&db.state).await? {
+		Some(muted) => muted,
+		None => return Ok(None),
+	};
+
+	let source = match &message.source {
+		MessageSource::Rss(url) => format!("RSS feed {url}"),
+		MessageSource::Github(user) => format!("Github notifications of {user}"),
+	};
+	Ok(Some(if muted {
+		format!("Muted {source}. React with {MUTE_REACTION} again to unmute.")
+	} else {
+		format!("Unmuted {source}.")
+	}))
+}
+
+/// Matrix room tombstone event handler. Follows room upgrades by joining the
+/// replacement room and migrating the subscriptions.
+#[tracing::instrument(level = "debug", skip_all)]
+pub async fn on_room_tombstone(
+	event: OriginalSyncRoomTombstoneEvent,
+	room: Room,
+	client: Client,
+	config: Ctx<Arc<Settings>>,
+	db: Ctx<Databases>,
+) -> Result<()> {
+	let room = match room {
+		Room::Joined(joined) => joined,
+		_ => return Ok(()),
+	};
+	let new_room = &event.content.replacement_room;
+	tracing::info!("Room {} was upgraded to {new_room}", room.room_id());
+
+	if let Err(err) = client.join_room_by_id(new_room).await {
+		tracing::warn!("Could not join replacement room {new_room}: {err}");
+		return Ok(());
+	}
+	database::migrate_room(room.room_id(), new_room, &db.state).await?;
+	AuditEntry::record(
+		new_room,
+		None,
+		format!("followed the room upgrade from {}", room.room_id()),
+		&db.state,
+	)
+	.await?;
+
+	if config.rooms.leave_upgraded {
+		tracing::info!("Leaving upgraded room {}", room.room_id());
+		room.leave().await?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,150 @@
+//! Tests for the event handlers.
+
+use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, OwnedUserId};
+
+use super::*;
+use crate::secrets::SecretKey;
+
+#[test]
+fn pre_join_messages_without_grace() {
+	let joined = 1_665_000_000_000;
+	assert!(is_after_join(joined, joined, 0, 0));
+	assert!(is_after_join(joined + 1, joined, 0, 0));
+	assert!(!is_after_join(joined - 1, joined, 0, 0));
+}
+
+#[test]
+fn pre_join_messages_within_grace() {
+	let joined = 1_665_000_000_000;
+	assert!(is_after_join(joined - 60_000, joined, 60, 0));
+	assert!(is_after_join(joined - 59_999, joined, 60, 0));
+	assert!(!is_after_join(joined - 60_001, joined, 60, 0));
+}
+
+#[test]
+fn pre_join_messages_with_clock_skew() {
+	let joined = 1_665_000_000_000;
+	assert!(is_after_join(joined - 5_000, joined, 0, 5));
+	assert!(!is_after_join(joined - 5_001, joined, 0, 5));
+	assert!(is_after_join(joined - 65_000, joined, 60, 5));
+	assert!(!is_after_join(joined - 65_001, joined, 60, 5));
+}
+
+#[test]
+fn command_from_plain_body() {
+	let content = RoomMessageEventContent::text_plain("!remind 5 'Tea is ready'");
+	assert_eq!(command_arguments(&content).as_deref(), Some("remind 5 'Tea is ready'"));
+
+	let content = RoomMessageEventContent::text_plain("Not a !command");
+	assert_eq!(command_arguments(&content), None);
+}
+
+#[test]
+fn command_from_formatted_body() {
+	let content = RoomMessageEventContent::text_html("**!**leave", "<b>!</b>leave");
+	assert_eq!(command_arguments(&content).as_deref(), Some("leave"));
+
+	let content = RoomMessageEventContent::text_html("*hello* !leave", "<em>hello</em> !leave");
+	assert_eq!(command_arguments(&content), None);
+}
+
+#[test]
+fn rejection_message_when_configured() -> Result<()> {
+	let mut config = Settings::read_from("config.sample.yaml")?;
+	config.access.invite_rejection_message = None;
+	assert!(rejection_message(&config).is_none());
+
+	config.access.invite_rejection_message = Some("Only admins can add this bot.".to_owned());
+	let message = rejection_message(&config).ok_or_else(|| eyre!("Expected a message"))?;
+	assert_eq!(message.body(), "Only admins can add this bot.");
+	Ok(())
+}
+
+#[test]
+fn command_in_reply() -> Result<()> {
+	let content: RoomMessageEventContent = serde_json::from_value(serde_json::json!({
+		"msgtype": "m.text",
+		"body": "> <@other:example.com> Do the thing\n\n!remind 2:00",
+		"format": "org.matrix.custom.html",
+		"formatted_body": "<mx-reply><blockquote>Do the thing</blockquote></mx-reply>!remind 2:00",
+		"m.relates_to": { "m.in_reply_to": { "event_id": "$original:example.com" } },
+	}))?;
+	assert_eq!(command_arguments(&content).as_deref(), Some("remind 2:00"));
+	Ok(())
+}
+
+#[test]
+fn sibling_accounts_are_not_skipped() -> Result<()> {
+	let account_a: OwnedUserId = "@bot-a:example.com".try_into()?;
+	let account_b: OwnedUserId = "@bot-b:example.com".try_into()?;
+	let mut config = CommandSettings {
+		sibling_accounts: vec![account_b.clone()],
+		process_sibling_messages: true,
+		..Default::default()
+	};
+
+	assert!(is_ignored_sender(&account_a, &account_a, &config));
+	assert!(!is_ignored_sender(&account_b, &account_a, &config));
+
+	config.process_sibling_messages = false;
+	assert!(is_ignored_sender(&account_b, &account_a, &config));
+	Ok(())
+}
+
+#[tokio::test]
+async fn rejoin_summary_counts_subscriptions() -> Result<()> {
+	let db = crate::test_utils::test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let other: OwnedRoomId = "!other:example.com".try_into()?;
+	assert_eq!(rejoin_summary(&room, &db).await?, None);
+
+	for url in ["https://example.com/a.xml", "https://example.com/b.xml"] {
+		RssSubscription::new(room.clone(), url.parse()?).insert(&db.state).await?;
+	}
+	RssSubscription::new(other.clone(), "https://example.com/c.xml".parse()?)
+		.insert(&db.state)
+		.await?;
+	GithubSubscription::new(
+		room.clone(),
+		"octocat".to_owned(),
+		"token".to_owned(),
+		&SecretKey::derive("password"),
+	)?
+	.insert(&db.state)
+	.await?;
+
+	assert_eq!(
+		rejoin_summary(&room, &db).await?.as_deref(),
+		Some("I'm back! Tracking 2 RSS feeds and 1 Github user here.")
+	);
+	assert_eq!(
+		rejoin_summary(&other, &db).await?.as_deref(),
+		Some("I'm back! Tracking 1 RSS feed and 0 Github users here.")
+	);
+	Ok(())
+}
+
+#[tokio::test]
+async fn mute_reaction_toggles_subscription() -> Result<()> {
+	let db = crate::test_utils::test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let event_id: OwnedEventId = "$feed-entry:example.com".try_into()?;
+	let url: url::Url = "https://example.com/feed.xml".parse()?;
+	RssSubscription::new(room.clone(), url.clone()).insert(&db.state).await?;
+	SentMessage::record(&room, event_id.clone(), MessageSource::Rss(url.clone()), &db.state)
+		.await?;
+
+	let unknown: OwnedEventId = "$unknown:example.com".try_into()?;
+	assert_eq!(mute_by_reaction(&unknown, &db).await?, None);
+
+	let text = mute_by_reaction(&event_id, &db).await?;
+	assert!(text.as_deref().map_or(false, |text| text.starts_with("Muted RSS feed")));
+	let subscription = RssSubscription::find(&room, &url, &db.state).await?;
+	assert_eq!(subscription.map(|doc| doc.contents.muted), Some(true));
+
+	let text = mute_by_reaction(&event_id, &db).await?;
+	assert!(text.as_deref().map_or(false, |text| text.starts_with("Unmuted RSS feed")));
+	let subscription = RssSubscription::find(&room, &url, &db.state).await?;
+	assert_eq!(subscription.map(|doc| doc.contents.muted), Some(false));
+	Ok(())
+}
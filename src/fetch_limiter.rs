@@ -0,0 +1,66 @@
+//! Limiter for outbound fetches of feeds and API requests.
+
+use std::{
+	sync::{Mutex, PoisonError},
+	time::Duration,
+};
+
+use color_eyre::Result;
+use tokio::{
+	sync::{Semaphore, SemaphorePermit},
+	time::Instant,
+};
+
+use crate::settings::FetchSettings;
+
+/// Limiter bounding the number of concurrent fetches and the rate at which
+/// fetches are started. Shared between all fetches of the intervals.
+#[derive(Debug)]
+pub struct FetchLimiter {
+	/// Permits for concurrent fetches.
+	semaphore: Semaphore,
+	/// Minimum time between the starts of two fetches.
+	min_interval: Duration,
+	/// Earliest start of the next fetch.
+	next_start: Mutex<Instant>,
+}
+
+impl FetchLimiter {
+	/// Create a new limiter allowing `max_concurrent` fetches at the same time
+	/// and starting at most `max_per_second` fetches per second. Zero requests
+	/// per second means no rate limit.
+	pub fn new(max_concurrent: usize, max_per_second: u32) -> Self {
+		let min_interval = if max_per_second == 0 {
+			Duration::ZERO
+		} else {
+			Duration::from_secs(1) / max_per_second
+		};
+		Self {
+			semaphore: Semaphore::new(max_concurrent.max(1)),
+			min_interval,
+			next_start: Mutex::new(Instant::now()),
+		}
+	}
+
+	/// Create a new limiter from the settings.
+	pub fn from_settings(config: &FetchSettings) -> Self {
+		Self::new(config.max_concurrent_fetches, config.max_requests_per_second)
+	}
+
+	/// Wait until a fetch may start. The fetch counts as running as long as
+	/// the returned permit is held.
+	pub async fn acquire(&self) -> Result<SemaphorePermit<'_>> {
+		let permit = self.semaphore.acquire().await?;
+		let start = {
+			let mut next_start = self.next_start.lock().unwrap_or_else(PoisonError::into_inner);
+			let start = (*next_start).max(Instant::now());
+			*next_start = start + self.min_interval;
+			start
+		};
+		tokio::time::sleep_until(start).await;
+		Ok(permit)
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,50 @@
+//! Tests for the fetch limiter.
+
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc,
+};
+
+use color_eyre::Result;
+
+use super::*;
+
+#[tokio::test]
+async fn concurrency_is_limited() -> Result<()> {
+	let limiter = Arc::new(FetchLimiter::new(2, 0));
+	let running = Arc::new(AtomicUsize::new(0));
+	let max_running = Arc::new(AtomicUsize::new(0));
+
+	let handles = (0..10)
+		.map(|_| {
+			let limiter = limiter.clone();
+			let running = running.clone();
+			let max_running = max_running.clone();
+			tokio::spawn(async move {
+				let _permit = limiter.acquire().await?;
+				let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+				max_running.fetch_max(now_running, Ordering::SeqCst);
+				tokio::time::sleep(Duration::from_millis(10)).await;
+				running.fetch_sub(1, Ordering::SeqCst);
+				Result::<()>::Ok(())
+			})
+		})
+		.collect::<Vec<_>>();
+	for handle in handles {
+		handle.await??;
+	}
+
+	assert_eq!(max_running.load(Ordering::SeqCst), 2);
+	Ok(())
+}
+
+#[tokio::test]
+async fn request_rate_is_limited() -> Result<()> {
+	let limiter = FetchLimiter::new(10, 100);
+	let start = Instant::now();
+	for _ in 0..6 {
+		let _permit = limiter.acquire().await?;
+	}
+	assert!(start.elapsed() >= Duration::from_millis(50));
+	Ok(())
+}
@@ -1,134 +1,735 @@
 //! Github notification subscription interval handler.
 
-use std::collections::HashMap;
+use std::{
+	collections::{hash_map::Entry, HashMap},
+	time::Duration,
+};
 
-use bonsaidb::core::schema::SerializedCollection;
-use color_eyre::Result;
+use bonsaidb::core::{document::CollectionDocument, schema::SerializedCollection};
+use color_eyre::{eyre::eyre, Report, Result};
 use matrix_sdk::{
 	room::Joined,
-	ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId},
+	ruma::{OwnedRoomId, RoomId},
 	Client,
 };
 use time::OffsetDateTime;
+use url::Url;
 
 use crate::{
-	clients::github::{Github, Notification},
-	database::{Databases, GithubSubscription},
+	clients::{
+		github::{
+			Github, IssueOrPr, IssueState, Notification, NotificationReason, RetryPolicy,
+			ReviewComment, ThreadComment,
+		},
+		GuardedClient,
+	},
+	database::{
+		Databases, GithubSubscription, MessageSource, PostedNotification, RoomPreferences,
+		SentMessage,
+	},
+	fetch_limiter::FetchLimiter,
+	intervals::timing::SubscriptionTimer,
+	matrix,
+	secrets::SecretKey,
+	send_guard::SendGuard,
+	settings::{GithubSettings, MessageKind, Settings},
+	sink::NotificationSink,
+	timestamps::TimestampFormatter,
 };
 
+/// Maximum number of lines of a diff hunk shown for a review comment.
+const MAX_DIFF_HUNK_LINES: usize = 10;
+
+/// A notification along with its latest comment and the state of its issue or
+/// pull request, fetched once for all rooms.
+type Fetched = (Notification, Option<ThreadComment>, Option<IssueOrPr>);
+
 /// State for the github interval.
 #[derive(Debug, Default)]
 pub struct IntervalState {
-	/// Cache of github clients, so that rate limiting is not reached.
-	clients: HashMap<(OwnedRoomId, String), Github>,
+	/// Cache of github clients per room, user and token index, so that rate
+	/// limiting is not reached.
+	clients: HashMap<(OwnedRoomId, String, usize), Github>,
+	/// Index of the token to try next per room-user pair.
+	next_token: HashMap<(OwnedRoomId, String), usize>,
+	/// States of the issues and pull requests per API URL, fetched during the
+	/// current interval run.
+	subjects: HashMap<Url, IssueOrPr>,
+	/// HTTP client shared by the Github clients, built on first use.
+	http_client: Option<GuardedClient>,
 }
 
 impl IntervalState {
-	/// Get or create the client for the room-user pair.
+	/// Get the HTTP client shared by the Github clients, building it from the
+	/// configuration on first use.
+	fn http_client(&mut self, config: &Settings) -> Result<GuardedClient> {
+		if let Some(http_client) = &self.http_client {
+			return Ok(http_client.clone());
+		}
+		let http_client = GuardedClient::new(&config.user_agent, &config.fetch)?;
+		self.http_client = Some(http_client.clone());
+		Ok(http_client)
+	}
+
+	/// Get or create the client for the subscription's token with the index,
+	/// decrypting the tokens with the key. New clients send their requests via
+	/// the HTTP client. The cached client is replaced if the subscription's API
+	/// URL changed.
 	pub fn get_client(
 		&mut self,
-		room: OwnedRoomId,
-		user: String,
-		token: String,
+		subscription: &GithubSubscription,
+		secret_key: &SecretKey,
+		index: usize,
+		http_client: &GuardedClient,
+		retry: RetryPolicy,
 	) -> Result<&mut Github> {
-		let new_client = Github::new(user.clone(), token.clone())?;
-		let client = self.clients.entry((room, user)).or_insert(new_client);
+		let token = subscription
+			.tokens(secret_key)
+			.get(index)
+			.cloned()
+			.ok_or_else(|| eyre!("Github subscription has no token #{index}"))?;
+		let new_client = Github::new(subscription.user.clone(), token.clone(), http_client, retry)?
+			.with_api_url(subscription.api_url.as_ref());
+		let key = (subscription.room.clone(), subscription.user.clone(), index);
+		let client = match self.clients.entry(key) {
+			Entry::Occupied(entry) if entry.get().base_url() == new_client.base_url() => {
+				entry.into_mut()
+			}
+			Entry::Occupied(mut entry) => {
+				entry.insert(new_client);
+				entry.into_mut()
+			}
+			Entry::Vacant(entry) => entry.insert(new_client),
+		};
 		client.set_token(token);
 		Ok(client)
 	}
+
+	/// Whether all of the given number of tokens of the room-user pair were
+	/// rejected as expired.
+	pub fn all_expired(&self, room: &OwnedRoomId, user: &str, tokens: usize) -> bool {
+		(0..tokens).all(|index| {
+			self.clients
+				.get(&(room.clone(), user.to_owned(), index))
+				.map_or(false, Github::is_expired)
+		})
+	}
+
+	/// Get the client of the subscription's next token in round-robin order,
+	/// skipping expired and rate-limited tokens. Returns the token index along
+	/// with the client, or `None` if no token can be used now.
+	pub fn next_client(
+		&mut self,
+		subscription: &GithubSubscription,
+		secret_key: &SecretKey,
+		http_client: &GuardedClient,
+		retry: RetryPolicy,
+	) -> Result<Option<(usize, &mut Github)>> {
+		let (room, user) = (&subscription.room, subscription.user.as_str());
+		let tokens = subscription.tokens(secret_key);
+		let key = (room.clone(), user.to_owned());
+		let start = self.next_token.get(&key).copied().unwrap_or(0);
+		let mut selected = None;
+		for offset in 0..tokens.len() {
+			let index = (start + offset) % tokens.len();
+			let client = self.get_client(subscription, secret_key, index, http_client, retry)?;
+			if !client.is_expired() && client.next_request_allowed() {
+				selected = Some(index);
+				break;
+			}
+		}
+
+		let index = match selected {
+			Some(index) => index,
+			None => return Ok(None),
+		};
+		self.next_token.insert(key, (index + 1) % tokens.len());
+		let client = self
+			.clients
+			.get_mut(&(room.clone(), user.to_owned(), index))
+			.ok_or_else(|| eyre!("Github client vanished from the cache"))?;
+		Ok(Some((index, client)))
+	}
 }
 
 /// Interval function to be called every time the interval fires.
-pub async fn interval(db: &Databases, client: &Client, state: &mut IntervalState) -> Result<()> {
+pub async fn interval(
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	state: &mut IntervalState,
+	limiter: &FetchLimiter,
+	sends: &SendGuard,
+) -> Result<()> {
 	tracing::debug!("Running Github interval..");
 
-	let subscriptions = GithubSubscription::all_async(&db.state).await?;
-	for mut subscription in subscriptions {
-		if let Some(room) = client.get_joined_room(&subscription.contents.room) {
-			let github_client = state.get_client(
-				subscription.contents.room.clone(),
-				subscription.contents.user.clone(),
-				subscription.contents.token.clone(),
-			)?;
-			if !github_client.next_request_allowed() {
-				continue;
+	state.subjects.clear();
+	let mut subscriptions = GithubSubscription::all_async(&db.state).await?;
+	subscriptions.retain(|subscription| !subscription.contents.disabled);
+	for group in group_by_credentials(subscriptions, &config.store.secret_key()) {
+		poll_group(group, config, db, client, state, limiter, sends).await?;
+	}
+	Ok(())
+}
+
+/// Group the subscriptions by user, tokens, repository and API URL, so that the
+/// notifications of subscriptions into multiple rooms are fetched once. The
+/// tokens are compared decrypted with the key.
+fn group_by_credentials(
+	subscriptions: Vec<CollectionDocument<GithubSubscription>>,
+	key: &SecretKey,
+) -> Vec<Vec<CollectionDocument<GithubSubscription>>> {
+	let mut groups: Vec<Vec<CollectionDocument<GithubSubscription>>> = Vec::new();
+	for subscription in subscriptions {
+		let existing = groups.iter_mut().find(|group| {
+			group.first().map_or(false, |first| {
+				first.contents.user == subscription.contents.user
+					&& first.contents.tokens(key) == subscription.contents.tokens(key)
+					&& first.contents.repo == subscription.contents.repo
+					&& first.contents.api_url == subscription.contents.api_url
+			})
+		});
+		match existing {
+			Some(group) => group.push(subscription),
+			None => groups.push(vec![subscription]),
+		}
+	}
+	groups
+}
+
+/// Notifications fetched once for a group of subscriptions sharing the same
+/// credentials, to be posted into each of their rooms.
+struct FetchedGroup {
+	/// Fetched notifications along with their details.
+	notifications: Vec<Fetched>,
+	/// Update times of the notifications whose details failed to fetch.
+	failures: Vec<OffsetDateTime>,
+	/// Point in time before the notifications were fetched.
+	fetched_at: OffsetDateTime,
+}
+
+/// Fetch the notifications of a group of subscriptions sharing the same
+/// credentials once and send them into all the subscribed rooms.
+async fn poll_group(
+	group: Vec<CollectionDocument<GithubSubscription>>,
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	state: &mut IntervalState,
+	limiter: &FetchLimiter,
+	sends: &SendGuard,
+) -> Result<()> {
+	let targets = poll_targets(group, db, client, sends).await?;
+	let lead = match targets.first() {
+		Some((_, lead)) => lead.contents.clone(),
+		None => return Ok(()),
+	};
+	let since = targets
+		.iter()
+		.map(|(_, subscription)| subscription.contents.latest_update)
+		.min()
+		.unwrap_or(lead.latest_update);
+	let muted = targets.iter().all(|(_, subscription)| subscription.contents.muted);
+
+	let key = config.store.secret_key();
+	let retry = config.github.retry_policy();
+	let http_client = state.http_client(config)?;
+	let index = match state.next_client(&lead, &key, &http_client, retry)? {
+		Some((index, _)) => index,
+		None => return Ok(()),
+	};
+	tracing::trace!("Polling Github notifications of {} with token #{index}", lead.user);
+
+	let _permit = limiter.acquire().await?;
+	let _timer = SubscriptionTimer::start(
+		format!("Github notifications of {}", lead.user),
+		Duration::from_secs(config.intervals.slow_subscription),
+	);
+	let max_pages = config.github.max_notification_pages;
+	let fetched = match fetch_group(state, &lead, index, since, muted, max_pages).await? {
+		Some(fetched) => fetched,
+		None => {
+			if state.all_expired(&lead.room, &lead.user, lead.token_count()) {
+				for (room, mut subscription) in targets {
+					disable_subscription(client, &mut subscription, room.is_direct(), db).await?;
+				}
 			}
+			return Ok(());
+		}
+	};
+
+	let github_client = state
+		.clients
+		.get(&(lead.room.clone(), lead.user.clone(), index))
+		.ok_or_else(|| eyre!("Github client vanished from the cache"))?;
+	post_group(targets, &fetched, github_client, config, db, client, sends).await
+}
+
+/// The rooms of the group's subscriptions that notifications can be sent to,
+/// along with their subscriptions. Subscriptions of rooms the bot left are
+/// deleted.
+async fn poll_targets(
+	group: Vec<CollectionDocument<GithubSubscription>>,
+	db: &Databases,
+	client: &Client,
+	sends: &SendGuard,
+) -> Result<Vec<(Joined, CollectionDocument<GithubSubscription>)>> {
+	let mut targets = Vec::new();
+	for subscription in group {
+		match client.get_joined_room(&subscription.contents.room) {
+			Some(room) if sends.allow(room.room_id()) => targets.push((room, subscription)),
+			Some(_) => {}
+			None => subscription.delete_async(&db.state).await?,
+		}
+	}
+	Ok(targets)
+}
+
+/// Fetch the notifications of the group's lead subscription with the token of
+/// the index, along with their details unless all subscriptions are muted.
+/// Returns `None` if the token is expired or rate-limited.
+async fn fetch_group(
+	state: &mut IntervalState,
+	lead: &GithubSubscription,
+	index: usize,
+	since: OffsetDateTime,
+	muted: bool,
+	max_pages: usize,
+) -> Result<Option<FetchedGroup>> {
+	// Borrow the client via its field, so that the subject cache can be used
+	// alongside it.
+	let github_client = state
+		.clients
+		.get_mut(&(lead.room.clone(), lead.user.clone(), index))
+		.ok_or_else(|| eyre!("Github client vanished from the cache"))?;
+	let fetched_at = OffsetDateTime::now_utc();
+	let result = match &lead.repo {
+		Some(repo) => github_client.repo_notifications(repo, since, max_pages).await,
+		None => github_client.notifications(since, max_pages).await,
+	};
+	let notifications = match result {
+		Ok(notifications) => notifications,
+		Err(err) if github_client.is_expired() => {
+			tracing::warn!("Github token #{index} of {} is expired: {err}", lead.user);
+			return Ok(None);
+		}
+		Err(err) if !github_client.next_request_allowed() => {
+			tracing::warn!("Github token #{index} of {} is rate-limited: {err}", lead.user);
+			return Ok(None);
+		}
+		Err(err) => return Err(err),
+	};
+	if let Some(remaining) = github_client.rate_limit_remaining() {
+		tracing::trace!("Github token #{index} of {} has {remaining} requests left", lead.user);
+	}
 
-			let now = OffsetDateTime::now_utc();
-			let notifications =
-				github_client.notifications(subscription.contents.latest_update).await?;
-			send_notification_messages(&room, &notifications, github_client).await?;
+	let (notifications, failures) = if muted {
+		(Vec::new(), Vec::new())
+	} else {
+		fetch_details(github_client, notifications, &mut state.subjects).await
+	};
+	Ok(Some(FetchedGroup { notifications, failures, fetched_at }))
+}
 
-			subscription.contents.latest_update = now;
-			subscription.update_async(&db.state).await?;
+/// Post the fetched notifications into the rooms of the group that did not
+/// get them yet and advance the subscriptions' watermarks.
+async fn post_group(
+	targets: Vec<(Joined, CollectionDocument<GithubSubscription>)>,
+	fetched: &FetchedGroup,
+	github_client: &Github,
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	sends: &SendGuard,
+) -> Result<()> {
+	let now = fetched.fetched_at;
+	for (room, mut subscription) in targets {
+		let user = subscription.contents.user.clone();
+		let previous = subscription.contents.latest_update;
+		let mut failures = Vec::new();
+		if subscription.contents.muted {
+			tracing::trace!("Not posting notifications of muted user {user}");
 		} else {
-			subscription.delete_async(&db.state).await?;
+			failures.extend_from_slice(&fetched.failures);
+			let new =
+				unposted_notifications(&fetched.notifications, previous, room.room_id(), &user, db)
+					.await?;
+			let (sent, result) = send_notification_messages(
+				client,
+				room.room_id(),
+				room.is_direct(),
+				&new,
+				&user,
+				config,
+				db,
+			)
+			.await;
+			if subscription.contents.mark_read {
+				mark_threads_read(github_client, &sent).await;
+			}
+			let result = result.map(|send_failures| failures.extend(send_failures));
+			if !sends.handle_result(config, client, room.room_id(), result).await? {
+				continue;
+			}
 		}
+
+		subscription.contents.latest_update = next_watermark(previous, now, &failures);
+		subscription.contents.last_polled = Some(now);
+		subscription.update_async(&db.state).await?;
+		let watermark = subscription.contents.latest_update;
+		PostedNotification::prune_before(room.room_id(), &user, watermark, &db.state).await?;
 	}
 	Ok(())
 }
 
-/// Send messages for the notifications into the room.
-async fn send_notification_messages(
-	room: &Joined,
-	notifications: &[Notification],
-	client: &Github,
+/// Disable the subscription, because all of its tokens were rejected, and
+/// tell its room once to enable it again with a new token.
+async fn disable_subscription(
+	sink: &dyn NotificationSink,
+	subscription: &mut CollectionDocument<GithubSubscription>,
+	is_direct: bool,
+	db: &Databases,
 ) -> Result<()> {
-	for notification in notifications {
-		let (html, body) = render_notification(client, notification).await?;
-		let message = if room.is_direct() {
-			RoomMessageEventContent::text_html(body, html)
-		} else {
-			RoomMessageEventContent::notice_html(body, html)
-		};
-		room.send(message, None).await?;
+	subscription.contents.disabled = true;
+	subscription.update_async(&db.state).await?;
+	tracing::info!(
+		"Disabled Github subscription of {} in {}",
+		subscription.contents.user,
+		subscription.contents.room
+	);
+
+	let body = format!(
+		"Your Github token for {} appears to be invalid, please re-run `!github enable`.",
+		subscription.contents.user
+	);
+	let html = matrix::escape_html(&body);
+	let kind = MessageKind::default_for(is_direct);
+	if let Err(err) = sink.send(&subscription.contents.room, body, html, kind).await {
+		tracing::error!("Failed sending the invalid Github token notice: {err}");
 	}
 	Ok(())
 }
 
-/// Render a notification as body and html message.
-async fn render_notification(
+/// Fetch the latest comment and subject state of each notification, so that
+/// they are fetched only once for all rooms. Notifications whose comment fails
+/// to fetch are logged and left out, returning their update times as failures.
+async fn fetch_details(
+	client: &Github,
+	notifications: Vec<Notification>,
+	subjects: &mut HashMap<Url, IssueOrPr>,
+) -> (Vec<Fetched>, Vec<OffsetDateTime>) {
+	let mut fetched = Vec::with_capacity(notifications.len());
+	let mut failures = Vec::new();
+	for notification in notifications {
+		match latest_comment(client, &notification).await {
+			Ok(comment) => {
+				let subject = subject_state(client, &notification, subjects).await;
+				fetched.push((notification, comment, subject));
+			}
+			Err(err) => {
+				tracing::error!(
+					"Failed fetching the comment of Github notification {}: {err:?}",
+					notification.id
+				);
+				failures.push(notification.updated_at);
+			}
+		}
+	}
+	(fetched, failures)
+}
+
+/// The fetched notifications that were updated after the given time.
+fn notifications_since(fetched: &[Fetched], since: OffsetDateTime) -> Vec<&Fetched> {
+	fetched.iter().filter(|(notification, ..)| notification.updated_at > since).collect()
+}
+
+/// The fetched notifications that were updated after the given time and were
+/// not posted into the room for the user yet, e.g. by an interrupted run.
+async fn unposted_notifications<'a>(
+	fetched: &'a [Fetched],
+	since: OffsetDateTime,
+	room: &RoomId,
+	user: &str,
+	db: &Databases,
+) -> Result<Vec<&'a Fetched>> {
+	let posted = PostedNotification::for_subscription(room, user, &db.state).await?;
+	Ok(notifications_since(fetched, since)
+		.into_iter()
+		.filter(|(notification, ..)| {
+			!PostedNotification::contains(&posted, &notification.id, notification.updated_at)
+		})
+		.collect())
+}
+
+/// Send messages for the notifications of the user into the room and remember
+/// them as posted. A failing notification is logged and skipped, returning its
+/// update time as failure.
+/// If none of the notifications could be sent, the first error is returned
+/// instead, as the room itself is likely the problem. Returns the
+/// notifications that were sent along with the result.
+async fn send_notification_messages<'a>(
+	sink: &dyn NotificationSink,
+	room: &RoomId,
+	is_direct: bool,
+	notifications: &[&'a Fetched],
+	user: &str,
+	config: &Settings,
+	db: &Databases,
+) -> (Vec<&'a Notification>, Result<Vec<OffsetDateTime>>) {
+	let mut sent = Vec::new();
+	let result = async {
+		let settings = RoomPreferences::timestamps_for(room, &config.timestamps, &db.state).await?;
+		let mut failures = Vec::new();
+		let mut first_error = None;
+		for &(ref notification, ref comment, ref subject) in notifications.iter().copied() {
+			let timestamps = TimestampFormatter::new(&settings, OffsetDateTime::now_utc());
+			let (html, body) = format_notification(
+				notification,
+				comment.as_ref(),
+				subject.as_ref(),
+				&config.prefixes.github,
+				config.github.max_comment_length,
+				&timestamps,
+			);
+			let kind = message_kind(&config.github, &notification.reason, is_direct);
+			let event_id = match sink.send(room, body, html, kind).await {
+				Ok(event_id) => event_id,
+				Err(err) => {
+					tracing::error!(
+						"Failed sending Github notification {} to {room}: {err:?}",
+						notification.id
+					);
+					failures.push(notification.updated_at);
+					first_error.get_or_insert(err);
+					continue;
+				}
+			};
+			sent.push(notification);
+			PostedNotification::record(
+				room,
+				user,
+				&notification.id,
+				notification.updated_at,
+				&db.state,
+			)
+			.await?;
+			if let Some(event_id) = event_id {
+				let source = MessageSource::Github(user.to_owned());
+				SentMessage::record(room, event_id, source, &db.state).await?;
+			}
+		}
+		match first_error {
+			Some(err) if sent.is_empty() => Err(err),
+			_ => Ok::<_, Report>(failures),
+		}
+	}
+	.await;
+	(sent, result)
+}
+
+/// The point in time up to which the notifications of a subscription were
+/// handled. This is now, unless notifications updated after the previous
+/// watermark failed. Then it stays just before the oldest failure, so that the
+/// failed notifications are retried next time.
+fn next_watermark(
+	previous: OffsetDateTime,
+	now: OffsetDateTime,
+	failures: &[OffsetDateTime],
+) -> OffsetDateTime {
+	failures
+		.iter()
+		.filter(|&&failed| failed > previous)
+		.map(|&failed| failed - time::Duration::nanoseconds(1))
+		.fold(now, Ord::min)
+}
+
+/// Mark the threads of the posted notifications as read on Github. Failures
+/// are only logged, as the notifications were posted already.
+async fn mark_threads_read(client: &Github, notifications: &[&Notification]) {
+	for notification in notifications {
+		if let Err(err) = client.mark_thread_read(&notification.id).await {
+			tracing::warn!("Failed marking Github notification {} as read: {err}", notification.id);
+		}
+	}
+}
+
+/// Message type to send a notification with the reason as. Defaults to text
+/// messages in direct chats and notices otherwise, unless configured for the
+/// reason.
+fn message_kind(
+	config: &GithubSettings,
+	reason: &NotificationReason,
+	is_direct: bool,
+) -> MessageKind {
+	config.message_types.get(reason).copied().unwrap_or_else(|| MessageKind::default_for(is_direct))
+}
+
+/// Render a notification as body and html message, starting with the prefix.
+/// Its comment is collapsed after the maximum number of characters.
+pub async fn render_notification(
 	client: &Github,
 	notification: &Notification,
+	prefix: &str,
+	max_comment_length: usize,
+	timestamps: &TimestampFormatter,
 ) -> Result<(String, String)> {
+	let comment = latest_comment(client, notification).await?;
+	let subject = subject_state(client, notification, &mut HashMap::new()).await;
+	Ok(format_notification(
+		notification,
+		comment.as_ref(),
+		subject.as_ref(),
+		prefix,
+		max_comment_length,
+		timestamps,
+	))
+}
+
+/// Fetch the latest comment of the notification, if it has one.
+async fn latest_comment(
+	client: &Github,
+	notification: &Notification,
+) -> Result<Option<ThreadComment>> {
+	match notification.subject.latest_comment_url.clone() {
+		Some(url) => Ok(Some(client.get_thread_comment_from(url).await?)),
+		None => Ok(None),
+	}
+}
+
+/// Fetch the state of the issue or pull request the notification is about.
+/// States are cached per API URL. Failures are only logged, as the state is
+/// not essential.
+async fn subject_state(
+	client: &Github,
+	notification: &Notification,
+	subjects: &mut HashMap<Url, IssueOrPr>,
+) -> Option<IssueOrPr> {
+	let url = match (&notification.subject.url, notification.subject.r#type.as_str()) {
+		(Some(url), "Issue" | "PullRequest") => url,
+		_ => return None,
+	};
+	if let Some(subject) = subjects.get(url) {
+		return Some(subject.clone());
+	}
+	match client.get_issue_or_pr(url.clone()).await {
+		Ok(subject) => {
+			subjects.insert(url.clone(), subject.clone());
+			Some(subject)
+		}
+		Err(err) => {
+			tracing::warn!("Failed fetching the state of {url}: {err}");
+			None
+		}
+	}
+}
+
+/// Emoji showing the state of an issue or pull request, i.e. open, closed or
+/// merged.
+fn state_emoji(subject: &IssueOrPr) -> &'static str {
+	match subject.state {
+		IssueState::Open => "\u{1f7e2}",
+		IssueState::Closed if subject.merged => "\u{1f7e3}",
+		IssueState::Closed => "\u{1f534}",
+	}
+}
+
+/// Format a notification, its latest comment and the state of its issue or
+/// pull request as html and body message, starting with the prefix. The
+/// comment is collapsed after the maximum number of characters. The body
+/// contains all essential information without relying on the HTML.
+fn format_notification(
+	notification: &Notification,
+	comment: Option<&ThreadComment>,
+	subject: Option<&IssueOrPr>,
+	prefix: &str,
+	max_comment_length: usize,
+	timestamps: &TimestampFormatter,
+) -> (String, String) {
 	let mut html = String::new();
 	let mut body = String::new();
 
 	html.push_str(&format!(
-		"<a href={}>{}</a><br>\n",
+		"<a href=\"{}\">{}</a><br>\n",
 		notification.repository.html_url, notification.repository.full_name
 	));
 	body.push_str(&format!("{}\n", notification.repository.full_name));
 
-	if let Some(url) = notification.subject.latest_comment_url.clone() {
-		let comment = client.get_thread_comment_from(url).await?;
-		let html_url = comment.html_url;
-
-		html.push_str(&format!(
-			"<a href=\"{}\"><b>{}: {} ({})</b></a><br>\n",
-			html_url, notification.subject.r#type, notification.subject.title, notification.reason
-		));
-		body.push_str(&format!(
-			"{}: {} ({})\n",
-			notification.subject.r#type, notification.subject.title, notification.reason
-		));
-
-		html.push_str(&format!("{}: {}<br>\n", comment.user.login, comment.body));
-		body.push_str(&format!("{}: {}\n", comment.user.login, comment.body));
+	let state = subject.map_or_else(String::new, |subject| format!("{} ", state_emoji(subject)));
+	let title = format!(
+		"{state}{}: {} ({})",
+		notification.subject.r#type, notification.subject.title, notification.reason
+	);
+	if let Some(comment) = comment {
+		html.push_str(&format!("<a href=\"{}\"><b>{title}</b></a><br>\n", comment.html_url));
+		body.push_str(&format!("{title}\n{}\n", comment.html_url));
+
+		if let Some(review) = &comment.review {
+			let (code_html, code_body) = render_diff_hunk(review);
+			html.push_str(&code_html);
+			body.push_str(&code_body);
+		}
+		let (quote_html, quote_body) = quote_comment(comment, max_comment_length);
+		html.push_str(&quote_html);
+		body.push_str(&quote_body);
 	} else {
-		html.push_str(&format!(
-			"<b>{}: {} ({})</b><br>\n",
-			notification.subject.r#type, notification.subject.title, notification.reason
-		));
-		body.push_str(&format!(
-			"{}: {} ({})\n",
-			notification.subject.r#type, notification.subject.title, notification.reason
-		));
+		html.push_str(&format!("<b>{title}</b><br>\n"));
+		body.push_str(&format!("{title}\n"));
 	}
 
+	let updated = timestamps.format(notification.updated_at);
+	html.push_str(&format!("<i>Updated {updated}</i><br>\n"));
+	body.push_str(&format!("Updated {updated}\n"));
+
 	let url = "https://github.com/notifications";
 	html.push_str(&format!("<a href=\"{}\">{}</a>", url, "See notifications"));
 	body.push_str(url);
 
-	Ok((html, body))
+	(matrix::prefixed(prefix, &html), matrix::prefixed(prefix, &body))
+}
+
+/// Render the comment with a link to its author as blockquote, as html and
+/// body. The Markdown of the comment is rendered in the html and kept in the
+/// body. Long comments are collapsed to their beginning, linking to the full
+/// comment.
+fn quote_comment(comment: &ThreadComment, max_length: usize) -> (String, String) {
+	let text = comment.body.trim();
+	let collapsed = collapse(text, max_length);
+	let read_more = if collapsed == text {
+		String::new()
+	} else {
+		format!(" <a href=\"{}\">read more</a>", comment.html_url)
+	};
+	let html = format!(
+		"<blockquote><a href=\"{}\">{}</a>: {}{read_more}</blockquote>\n",
+		comment.user.html_url,
+		matrix::escape_html(&comment.user.login),
+		matrix::markdown_to_html_with_breaks(&collapsed)
+	);
+	let text = format!("{}: {collapsed}", comment.user.login);
+	let body = text.lines().map(|line| format!("> {line}\n")).collect();
+	(html, body)
+}
+
+/// Render the file path and diff hunk of a review comment as code block, as
+/// html and body. Only the last lines of the hunk, which end at the commented
+/// line, are shown.
+fn render_diff_hunk(review: &ReviewComment) -> (String, String) {
+	let lines = review.diff_hunk.lines().collect::<Vec<_>>();
+	let hunk = lines[lines.len().saturating_sub(MAX_DIFF_HUNK_LINES)..].join("\n");
+	let html = format!(
+		"<code>{}</code>\n<pre><code class=\"language-diff\">{}\n</code></pre>\n",
+		matrix::escape_html(&review.path),
+		matrix::escape_html(&hunk)
+	);
+	let body = format!("{}\n```diff\n{hunk}\n```\n", review.path);
+	(html, body)
 }
+
+/// Cut the text off after the maximum number of characters, ending it with
+/// "…".
+fn collapse(text: &str, max_length: usize) -> String {
+	matrix::truncate(text, max_length)
+}
+
+#[cfg(test)]
+mod tests;
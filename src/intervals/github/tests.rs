@@ -0,0 +1,505 @@
+//! Tests for the Github interval.
+
+use color_eyre::Result;
+use matrix_sdk::{async_trait, ruma::OwnedEventId};
+use serde_json::json;
+
+use super::*;
+use crate::{
+	settings::TimestampSettings,
+	test_utils::{local_http_client, test_databases, CaptureSink, MockResponse, MockServer},
+};
+
+/// Sink failing every send.
+struct FailingSink;
+
+#[async_trait]
+impl NotificationSink for FailingSink {
+	async fn send(
+		&self,
+		_room: &RoomId,
+		_body: String,
+		_html: String,
+		_kind: MessageKind,
+	) -> Result<Option<OwnedEventId>> {
+		Err(eyre!("Sending failed"))
+	}
+}
+
+/// Create a notification for testing.
+fn notification(latest_comment_url: Option<&str>) -> Result<Notification> {
+	let notification = serde_json::from_value(json!({
+		"id": "1",
+		"last_read_at": null,
+		"reason": "mention",
+		"repository": {
+			"description": null,
+			"fork": false,
+			"full_name": "owner/repo",
+			"html_url": "https://github.com/owner/repo",
+			"id": 1,
+			"node_id": "R_1",
+			"name": "repo",
+			"private": false,
+			"url": "https://api.github.com/repos/owner/repo",
+		},
+		"subject": {
+			"latest_comment_url": latest_comment_url,
+			"title": "Fix the bug",
+			"type": "Issue",
+			"url": "https://api.github.com/repos/owner/repo/issues/1",
+		},
+		"subscription_url": "https://api.github.com/notifications/threads/1/subscription",
+		"unread": true,
+		"updated_at": "2022-10-05T10:00:00Z",
+		"url": "https://api.github.com/notifications/threads/1",
+	}))?;
+	Ok(notification)
+}
+
+/// Create a thread comment for testing.
+fn comment() -> Result<ThreadComment> {
+	let comment = serde_json::from_value(json!({
+		"id": 2,
+		"node_id": "IC_2",
+		"url": "https://api.github.com/repos/owner/repo/issues/comments/2",
+		"html_url": "https://github.com/owner/repo/issues/1#issuecomment-2",
+		"body": "Please have a look.",
+		"user": {
+			"id": 3,
+			"node_id": "U_3",
+			"login": "octocat",
+			"html_url": "https://github.com/octocat",
+			"site_admin": false,
+		},
+		"created_at": "2022-10-05T10:00:00Z",
+		"updated_at": null,
+	}))?;
+	Ok(comment)
+}
+
+/// Timestamp formatter relative to shortly after the test entries.
+fn timestamps() -> TimestampFormatter {
+	let now =
+		OffsetDateTime::from_unix_timestamp(1_664_964_180).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+	TimestampFormatter::new(&TimestampSettings::default(), now)
+}
+
+#[test]
+fn body_contains_essential_info() -> Result<()> {
+	let notification =
+		notification(Some("https://api.github.com/repos/owner/repo/issues/comments/2"))?;
+	let comment = comment()?;
+	let (_html, body) =
+		format_notification(&notification, Some(&comment), None, "", 500, &timestamps());
+
+	assert!(body.contains("owner/repo"));
+	assert!(body.contains("Issue: Fix the bug (Mentioned)"));
+	assert!(body.contains("https://github.com/owner/repo/issues/1#issuecomment-2"));
+	assert!(body.contains("octocat: Please have a look."));
+	assert!(body.contains("Updated 3m ago (2022-10-05 10:00 UTC+00)"));
+	assert!(body.contains("https://github.com/notifications"));
+	assert!(!body.contains('<'));
+	Ok(())
+}
+
+#[test]
+fn comment_is_quoted() -> Result<()> {
+	let notification =
+		notification(Some("https://api.github.com/repos/owner/repo/issues/comments/2"))?;
+	let mut comment = comment()?;
+	comment.body = String::from("First <line>\nSecond line");
+	let (html, body) =
+		format_notification(&notification, Some(&comment), None, "", 500, &timestamps());
+
+	assert!(html.contains(
+		"<blockquote><a href=\"https://github.com/octocat\">octocat</a>: First &lt;line&gt;<br \
+		 />\nSecond line</blockquote>"
+	));
+	assert!(body.contains("\n> octocat: First <line>\n> Second line\n"));
+	Ok(())
+}
+
+#[test]
+fn review_comment_shows_diff_hunk() -> Result<()> {
+	let notification =
+		notification(Some("https://api.github.com/repos/owner/repo/pulls/comments/2"))?;
+	let mut value = serde_json::to_value(comment()?)?;
+	value["path"] = json!("src/main.rs");
+	value["diff_hunk"] =
+		json!("@@ -1,2 +1,2 @@\n fn main() {\n-\tlet ok = a < b;\n+\tlet ok = a <= b;");
+	let comment: ThreadComment = serde_json::from_value(value)?;
+	let (html, body) =
+		format_notification(&notification, Some(&comment), None, "", 500, &timestamps());
+
+	assert!(html.contains("<code>src/main.rs</code>\n<pre><code class=\"language-diff\">"));
+	assert!(html.contains("-\tlet ok = a &lt; b;\n+\tlet ok = a &lt;= b;\n</code></pre>"));
+	assert!(body.contains("src/main.rs\n```diff\n@@ -1,2 +1,2 @@\n fn main() {\n"));
+	assert!(body.contains("+\tlet ok = a <= b;\n```\n> octocat: Please have a look."));
+	Ok(())
+}
+
+#[test]
+fn long_diff_hunk_shows_last_lines() -> Result<()> {
+	let lines = (0..20).map(|i| format!("+line {i}")).collect::<Vec<_>>();
+	let review = ReviewComment { path: String::from("a.rs"), diff_hunk: lines.join("\n") };
+	let (_html, body) = render_diff_hunk(&review);
+
+	assert!(!body.contains("+line 9\n"));
+	assert!(body.contains("```diff\n+line 10\n"));
+	assert!(body.contains("+line 19\n```"));
+	Ok(())
+}
+
+#[test]
+fn issue_comment_has_no_review() -> Result<()> {
+	assert!(comment()?.review.is_none());
+	Ok(())
+}
+
+#[test]
+fn long_comment_is_collapsed() {
+	let long = "a".repeat(510);
+	let collapsed = collapse(&long, 500);
+	assert_eq!(collapsed.chars().count(), 501);
+	assert!(collapsed.ends_with('…'));
+	assert_eq!(collapse("short", 500), "short");
+}
+
+#[test]
+fn comment_markdown_is_rendered() -> Result<()> {
+	let mut comment = comment()?;
+	comment.body = String::from("**Looks good**, see `main.rs`.");
+	let (html, body) = quote_comment(&comment, 500);
+	assert!(html.contains(": <strong>Looks good</strong>, see <code>main.rs</code>.</blockquote>"));
+	assert_eq!(body, "> octocat: **Looks good**, see `main.rs`.\n");
+	assert!(!html.contains("read more"));
+
+	comment.body = format!("**{}**", "a".repeat(20));
+	let (html, body) = quote_comment(&comment, 10);
+	assert!(html.contains(
+		"**aaaaaaaa… <a href=\"https://github.com/owner/repo/issues/1#issuecomment-2\">read \
+		 more</a></blockquote>"
+	));
+	assert_eq!(body, "> octocat: **aaaaaaaa…\n");
+	Ok(())
+}
+
+#[test]
+fn body_without_comment() -> Result<()> {
+	let notification = notification(None)?;
+	let (html, body) = format_notification(&notification, None, None, "", 500, &timestamps());
+
+	assert!(html.contains("<a href=\"https://github.com/owner/repo\">owner/repo</a>"));
+	assert!(body.contains("owner/repo"));
+	assert!(body.contains("Issue: Fix the bug (Mentioned)"));
+	assert!(!body.contains('<'));
+	Ok(())
+}
+
+#[tokio::test]
+async fn one_fetch_is_sent_to_multiple_rooms() -> Result<()> {
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let other_room: OwnedRoomId = "!other:example.com".try_into()?;
+	let updated = OffsetDateTime::from_unix_timestamp(1_664_964_000)?; // 2022-10-05 10:00
+	let key = SecretKey::derive("password");
+	let mut subscription =
+		GithubSubscription::new(room, "user".to_owned(), "token".to_owned(), &key)?;
+	subscription.latest_update = updated - time::Duration::hours(1);
+	subscription.push_into_async(&db.state).await?;
+	let mut mirror =
+		GithubSubscription::new(other_room, "user".to_owned(), "token".to_owned(), &key)?;
+	mirror.latest_update = updated - time::Duration::minutes(30);
+	mirror.push_into_async(&db.state).await?;
+	GithubSubscription::new(
+		"!room:example.com".try_into()?,
+		"user".to_owned(),
+		"other".to_owned(),
+		&key,
+	)?
+	.push_into_async(&db.state)
+	.await?;
+
+	let groups = group_by_credentials(GithubSubscription::all_async(&db.state).await?, &key);
+	assert_eq!(groups.iter().map(Vec::len).collect::<Vec<_>>(), [2, 1]);
+	let since = groups[0].iter().map(|sub| sub.contents.latest_update).min();
+	assert_eq!(since, Some(subscription.latest_update));
+
+	let fetched = vec![(notification(None)?, None, None)];
+	assert_eq!(notifications_since(&fetched, subscription.latest_update).len(), 1);
+	assert_eq!(notifications_since(&fetched, mirror.latest_update).len(), 1);
+	assert!(notifications_since(&fetched, updated).is_empty());
+	Ok(())
+}
+
+#[tokio::test]
+async fn only_posted_notifications_are_marked_read() -> Result<()> {
+	let server = MockServer::start(|_| MockResponse::new(205, "")).await?;
+	let github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?
+	.with_base_url(server.url().clone());
+	let config = Settings::read_from("config.sample.yaml")?;
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let fetched = vec![(notification(None)?, None, None)];
+	let new = notifications_since(&fetched, OffsetDateTime::UNIX_EPOCH);
+
+	let (sent, result) =
+		send_notification_messages(&FailingSink, &room, false, &new, "user", &config, &db).await;
+	assert!(result.is_err());
+	mark_threads_read(&github, &sent).await;
+	assert!(server.requests().is_empty());
+
+	let sink = CaptureSink::default();
+	let (sent, result) =
+		send_notification_messages(&sink, &room, false, &new, "user", &config, &db).await;
+	result?;
+	assert_eq!(sink.sent().len(), 1);
+	mark_threads_read(&github, &sent).await;
+	let requests = server.requests();
+	assert_eq!(requests.len(), 1);
+	assert_eq!(requests[0].method, "PATCH");
+	assert_eq!(requests[0].path, "/notifications/threads/1");
+	Ok(())
+}
+
+#[tokio::test]
+async fn failing_notifications_do_not_stop_the_others() -> Result<()> {
+	let good = serde_json::to_vec(&comment()?)?;
+	let mut malformed = serde_json::to_value(comment()?)?;
+	if let Some(fields) = malformed.as_object_mut() {
+		fields.remove("user");
+	}
+	let malformed = serde_json::to_vec(&malformed)?;
+	let server = MockServer::start(move |request| {
+		if request.path.ends_with("/2") {
+			MockResponse::new(200, malformed.clone())
+		} else {
+			MockResponse::new(200, good.clone())
+		}
+	})
+	.await?;
+	let github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?;
+	let config = Settings::read_from("config.sample.yaml")?;
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+
+	let updated = OffsetDateTime::from_unix_timestamp(1_664_964_000)?; // 2022-10-05 10:00
+	let mut notifications = Vec::new();
+	for (id, minutes) in [("1", 0), ("2", 1), ("3", 2)] {
+		let url = server.url().join(&format!("comments/{id}"))?;
+		let mut notification = notification(Some(url.as_str()))?;
+		notification.id = id.to_owned();
+		notification.updated_at = updated + time::Duration::minutes(minutes);
+		notification.subject.url = None;
+		notifications.push(notification);
+	}
+	let failed_at = notifications[1].updated_at;
+
+	let (fetched, failures) = fetch_details(&github, notifications, &mut HashMap::new()).await;
+	assert_eq!(failures, [failed_at]);
+	let new = notifications_since(&fetched, OffsetDateTime::UNIX_EPOCH);
+	let sink = CaptureSink::default();
+	let (sent, result) =
+		send_notification_messages(&sink, &room, false, &new, "user", &config, &db).await;
+	assert!(result?.is_empty());
+	assert_eq!(
+		sent.iter().map(|notification| notification.id.as_str()).collect::<Vec<_>>(),
+		["1", "3"]
+	);
+	assert_eq!(sink.sent().len(), 2);
+
+	let now = updated + time::Duration::hours(1);
+	assert_eq!(next_watermark(updated, now, &[]), now);
+	assert_eq!(next_watermark(updated, now, &failures), failed_at - time::Duration::nanoseconds(1));
+	assert_eq!(next_watermark(failed_at, now, &failures), now);
+	Ok(())
+}
+
+#[tokio::test]
+async fn replayed_notifications_are_not_sent_twice() -> Result<()> {
+	let config = Settings::read_from("config.sample.yaml")?;
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let since = OffsetDateTime::UNIX_EPOCH;
+	let fetched = vec![(notification(None)?, None, None)];
+	let sink = CaptureSink::default();
+
+	// The watermark is not updated between the runs, e.g. due to a crash.
+	for _ in 0..2 {
+		let new = unposted_notifications(&fetched, since, &room, "user", &db).await?;
+		let (_, result) =
+			send_notification_messages(&sink, &room, false, &new, "user", &config, &db).await;
+		result?;
+	}
+	assert_eq!(sink.sent().len(), 1);
+	assert_eq!(unposted_notifications(&fetched, since, &room, "other", &db).await?.len(), 1);
+
+	let mut updated = notification(None)?;
+	updated.updated_at += time::Duration::minutes(1);
+	let fetched = vec![(updated, None, None)];
+	assert_eq!(unposted_notifications(&fetched, since, &room, "user", &db).await?.len(), 1);
+
+	let watermark = fetched[0].0.updated_at;
+	assert_eq!(PostedNotification::prune_before(&room, "user", watermark, &db.state).await?, 1);
+	assert!(PostedNotification::for_subscription(&room, "user", &db.state).await?.is_empty());
+	Ok(())
+}
+
+#[tokio::test]
+async fn rejected_subscription_is_disabled_once() -> Result<()> {
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let key = SecretKey::derive("password");
+	GithubSubscription::new(room.clone(), "user".to_owned(), "token".to_owned(), &key)?
+		.push_into_async(&db.state)
+		.await?;
+	let mut subscription = GithubSubscription::find(&room, "user", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?;
+
+	let sink = CaptureSink::default();
+	disable_subscription(&sink, &mut subscription, false, &db).await?;
+	let sent = sink.sent();
+	assert_eq!(sent.len(), 1);
+	assert_eq!(sent[0].room, room);
+	assert_eq!(
+		sent[0].body,
+		"Your Github token for user appears to be invalid, please re-run `!github enable`."
+	);
+	let stored = GithubSubscription::find(&room, "user", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?;
+	assert!(stored.contents.disabled);
+
+	GithubSubscription::new(room.clone(), "user".to_owned(), "new token".to_owned(), &key)?
+		.insert(&db.state)
+		.await?;
+	let stored = GithubSubscription::find(&room, "user", &db.state)
+		.await?
+		.ok_or_else(|| eyre!("Subscription not found"))?;
+	assert!(!stored.contents.disabled);
+	assert_eq!(stored.contents.tokens(&key), vec!["new token".to_owned()]);
+	Ok(())
+}
+
+#[tokio::test]
+async fn subject_state_is_shown_and_cached() -> Result<()> {
+	let server = MockServer::start(|request| {
+		if request.path.ends_with("/pulls/2") {
+			MockResponse::new(200, r#"{"state":"closed","merged":true}"#)
+		} else {
+			MockResponse::new(200, r#"{"state":"open"}"#)
+		}
+	})
+	.await?;
+	let github = Github::new(
+		"user".to_owned(),
+		"token".to_owned(),
+		&local_http_client()?,
+		RetryPolicy::default(),
+	)?;
+	let mut subjects = HashMap::new();
+	let mut issue = notification(None)?;
+	issue.subject.url = Some(server.url().join("repos/owner/repo/issues/1")?);
+	let mut pull = notification(None)?;
+	pull.subject.r#type = String::from("PullRequest");
+	pull.subject.url = Some(server.url().join("repos/owner/repo/pulls/2")?);
+
+	let state = subject_state(&github, &issue, &mut subjects).await;
+	let (_html, body) = format_notification(&issue, None, state.as_ref(), "", 500, &timestamps());
+	assert!(body.contains("\u{1f7e2} Issue: Fix the bug (Mentioned)"));
+	let state = subject_state(&github, &pull, &mut subjects).await;
+	let (html, _body) = format_notification(&pull, None, state.as_ref(), "", 500, &timestamps());
+	assert!(html.contains("<b>\u{1f7e3} PullRequest: Fix the bug (Mentioned)</b>"));
+
+	assert!(subject_state(&github, &issue, &mut subjects).await.is_some());
+	assert_eq!(server.requests().len(), 2);
+
+	let closed = IssueOrPr { state: IssueState::Closed, merged: false };
+	assert_eq!(state_emoji(&closed), "\u{1f534}");
+	issue.subject.r#type = String::from("Release");
+	assert!(subject_state(&github, &issue, &mut HashMap::new()).await.is_none());
+	Ok(())
+}
+
+#[test]
+fn requests_rotate_across_tokens() -> Result<()> {
+	let mut state = IntervalState::default();
+	let room = OwnedRoomId::try_from("!room:example.com")?;
+	let key = SecretKey::derive("password");
+	let mut subscription =
+		GithubSubscription::new(room, "user".to_owned(), "first".to_owned(), &key)?;
+	let tokens = ["first", "second", "third"].map(String::from);
+	subscription.set_tokens(&tokens, &key)?;
+
+	let mut indices = Vec::new();
+	for _ in 0..4 {
+		let (index, _) = state
+			.next_client(&subscription, &key, &local_http_client()?, RetryPolicy::default())?
+			.ok_or_else(|| eyre!("Expected an available client"))?;
+		indices.push(index);
+	}
+	assert_eq!(indices, [0, 1, 2, 0]);
+
+	let other_room = OwnedRoomId::try_from("!other:example.com")?;
+	let other = GithubSubscription::new(other_room, "user".to_owned(), "first".to_owned(), &key)?;
+	let (index, _) = state
+		.next_client(&other, &key, &local_http_client()?, RetryPolicy::default())?
+		.ok_or_else(|| eyre!("Expected an available client"))?;
+	assert_eq!(index, 0);
+	Ok(())
+}
+
+#[test]
+fn changed_api_url_replaces_cached_client() -> Result<()> {
+	let mut state = IntervalState::default();
+	let room = OwnedRoomId::try_from("!room:example.com")?;
+	let key = SecretKey::derive("password");
+	let mut subscription =
+		GithubSubscription::new(room, "user".to_owned(), "token".to_owned(), &key)?;
+
+	let client =
+		state.get_client(&subscription, &key, 0, &local_http_client()?, RetryPolicy::default())?;
+	assert_eq!(client.base_url().as_str(), "https://api.github.com/");
+
+	subscription.api_url = Some(Url::parse("https://github.example.com/api/v3/")?);
+	let client =
+		state.get_client(&subscription, &key, 0, &local_http_client()?, RetryPolicy::default())?;
+	assert_eq!(client.base_url().as_str(), "https://github.example.com/api/v3/");
+	assert_eq!(state.clients.len(), 1);
+	Ok(())
+}
+
+#[test]
+fn configured_prefix_appears() -> Result<()> {
+	let notification = notification(None)?;
+	let (html, body) =
+		format_notification(&notification, None, None, "\u{1f419}", 500, &timestamps());
+
+	assert!(html.starts_with("\u{1f419} <a href="));
+	assert!(body.starts_with("\u{1f419} owner/repo"));
+	Ok(())
+}
+
+#[test]
+fn message_kind_per_reason() {
+	let mut config = GithubSettings::default();
+	config.message_types.insert(NotificationReason::SecurityAlert, MessageKind::Text);
+	config.message_types.insert(NotificationReason::Subscribed, MessageKind::Notice);
+
+	assert_eq!(message_kind(&config, &NotificationReason::SecurityAlert, false), MessageKind::Text);
+	assert_eq!(message_kind(&config, &NotificationReason::Subscribed, true), MessageKind::Notice);
+	assert_eq!(message_kind(&config, &NotificationReason::Mention, false), MessageKind::Notice);
+	assert_eq!(message_kind(&config, &NotificationReason::Mention, true), MessageKind::Text);
+}
@@ -1,47 +1,148 @@
 //! Intervalled execution of periodic tasks.
 
-mod github;
+pub mod github;
 mod rss;
+mod schedule;
+mod timing;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
-use color_eyre::Result;
+use color_eyre::{Report, Result};
 use matrix_sdk::Client;
-use tokio::time::{interval, MissedTickBehavior};
 
-use crate::{database::Databases, settings::Settings};
+use self::schedule::{Scheduler, Task};
+use crate::{
+	backoff::Backoff, circuit_breaker::CircuitBreaker, database::Databases,
+	fetch_limiter::FetchLimiter, send_guard::SendGuard, settings::Settings,
+};
 
-/// Run the intervals, logging and restarting on error.
+/// Number of consecutive Matrix request failures after which the intervals
+/// are paused.
+const BREAKER_THRESHOLD: u32 = 3;
+/// Initial delay before restarting the intervals after an error.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+/// Maximum delay before restarting the intervals after an error.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Run the intervals, logging and restarting on error with jittered backoff.
 pub async fn run(config: Arc<Settings>, databases: Databases, client: Client) {
-	let mut state = State { github: github::IntervalState::default() };
+	let breaker = CircuitBreaker::new(
+		BREAKER_THRESHOLD,
+		Backoff::new(Duration::from_secs(10), Duration::from_secs(10 * 60)),
+	);
+	let limiter = Arc::new(FetchLimiter::from_settings(&config.fetch));
+	let mut state = State {
+		rss: rss::IntervalState::default(),
+		github: github::IntervalState::default(),
+		breaker,
+		limiter,
+		sends: SendGuard::new(Duration::from_secs(config.intervals.send_reprobe)),
+	};
+
+	let mut backoff = Backoff::new(RESTART_DELAY, MAX_RESTART_DELAY);
+	loop {
+		let started = Instant::now();
+		match intervals(&config, &databases, &client, &mut state).await {
+			Ok(()) => return,
+			Err(err) => tracing::error!("Error in intervals: {err}"),
+		}
 
-	while let Err(err) = intervals(&config, &databases, &client, &mut state).await {
-		tracing::error!("Error in intervals: {err}");
+		let delay = restart_delay(&mut backoff, started.elapsed());
+		tracing::info!("Restarting intervals in {delay:?}..");
+		tokio::time::sleep(delay).await;
 	}
 }
 
+/// Get the jittered delay before restarting the intervals, which ran for the
+/// given time before failing. The backoff is reset when they ran longer than
+/// the maximum delay, so that only repeated quick failures back off.
+fn restart_delay(backoff: &mut Backoff, ran_for: Duration) -> Duration {
+	if ran_for > backoff.maximum() {
+		backoff.reset();
+	}
+	backoff.next_jittered_delay()
+}
+
 /// State across interval executions, e.g. for caching.
 struct State {
+	/// RSS interval state.
+	rss: rss::IntervalState,
 	/// Github interval state.
 	github: github::IntervalState,
+	/// Circuit breaker pausing the intervals while the homeserver is failing.
+	breaker: CircuitBreaker,
+	/// Limiter for the RSS and Github fetches.
+	limiter: Arc<FetchLimiter>,
+	/// Guard pausing messages to rooms the bot is not allowed to send in.
+	sends: SendGuard,
 }
 
-/// Run the actual intervals, returning on error.
+impl State {
+	/// Record the result of an interval in the circuit breaker, passing it on.
+	fn record(&self, result: Result<()>) -> Result<()> {
+		match &result {
+			Ok(()) => self.breaker.record_success(),
+			Err(err) if is_matrix_error(err) => self.breaker.record_failure(),
+			Err(_) => {}
+		}
+		result
+	}
+}
+
+/// Whether the error is a failed Matrix request.
+fn is_matrix_error(err: &Report) -> bool {
+	err.downcast_ref::<matrix_sdk::Error>().is_some()
+		|| err.downcast_ref::<matrix_sdk::HttpError>().is_some()
+}
+
+/// Run the actual intervals in order of their due time, returning on error.
 async fn intervals(
 	config: &Settings,
 	databases: &Databases,
 	client: &Client,
 	state: &mut State,
 ) -> Result<()> {
-	let mut rss_interval = interval(Duration::from_secs(config.intervals.rss));
-	rss_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-	let mut github_interval = interval(Duration::from_secs(config.intervals.github));
-	github_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+	let start = Instant::now();
+	let mut scheduler = Scheduler::default();
+	scheduler.add(Task::Rss, Duration::from_secs(config.intervals.rss), start);
+	scheduler.add(Task::Github, Duration::from_secs(config.intervals.github), start);
 
-	loop {
-		tokio::select! {
-			_ = rss_interval.tick() => rss::interval(databases, client).await?,
-			_ = github_interval.tick() => github::interval(databases, client, &mut state.github).await?,
-		};
+	while let Some(scheduled) = scheduler.pop() {
+		tokio::time::sleep_until(scheduled.due.into()).await;
+		if state.breaker.allow() {
+			let result = match scheduled.task {
+				Task::Rss => {
+					rss::interval(
+						config,
+						databases,
+						client,
+						&mut state.rss,
+						&state.limiter,
+						&state.sends,
+					)
+					.await
+				}
+				Task::Github => {
+					github::interval(
+						config,
+						databases,
+						client,
+						&mut state.github,
+						&state.limiter,
+						&state.sends,
+					)
+					.await
+				}
+			};
+			state.record(result)?;
+		}
+		scheduler.reschedule(scheduled, Instant::now());
 	}
+	Ok(())
 }
+
+#[cfg(test)]
+mod tests;
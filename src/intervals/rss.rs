@@ -1,97 +1,815 @@
 //! RSS interval.
 
-use bonsaidb::core::schema::SerializedCollection;
-use color_eyre::Result;
+use std::{
+	cmp::Reverse,
+	collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+	hash::{Hash, Hasher},
+	ptr,
+	sync::Arc,
+	time::Instant,
+};
+
+use bonsaidb::core::{document::CollectionDocument, schema::SerializedCollection};
+use color_eyre::{Report, Result};
 use feed_rs::model::{Entry, Feed};
-use matrix_sdk::{room::Joined, ruma::events::room::message::RoomMessageEventContent, Client};
-use time::OffsetDateTime;
+use matrix_sdk::{ruma::OwnedRoomId, Client};
+use time::{Duration, OffsetDateTime, UtcOffset};
+use url::Url;
+
+use crate::{
+	clients::{
+		self,
+		github::RetryPolicy,
+		rss::{CacheValidators, ParseOptions},
+		GuardedClient,
+	},
+	database::{
+		Databases, DigestEntry, FeedAuth, InitialEntries, LinkRewrite, MessageSource,
+		OutsideActiveHours, RenderFormat, RoomPreferences, RssDigest, RssSubscription, SentMessage,
+	},
+	fetch_limiter::FetchLimiter,
+	intervals::timing::SubscriptionTimer,
+	matrix,
+	send_guard::SendGuard,
+	settings::{IntervalSettings, MessageKind, Settings},
+	sink::NotificationSink,
+	timestamps::TimestampFormatter,
+};
+
+/// Maximum number of entries listed in one digest message.
+const MAX_DIGEST_ENTRIES: usize = 50;
+
+/// Maximum number of characters of an entry's full content in a message.
+const MAX_CONTENT_LENGTH: usize = 4000;
+
+/// Maximum number of seen entry IDs remembered per subscription, unless the
+/// feed itself has more entries.
+const MAX_SEEN_ENTRY_IDS: usize = 500;
+
+/// State for the RSS interval.
+#[derive(Debug, Default)]
+pub struct IntervalState {
+	/// Last time each feed was checked per room and URL.
+	last_checked: HashMap<(OwnedRoomId, Url), Instant>,
+}
+
+impl IntervalState {
+	/// Whether the subscription is due to be checked at the given time, i.e.
+	/// its interval passed since its last check. Failing feeds back off once
+	/// they reached the failure threshold.
+	pub fn is_due(
+		&self,
+		subscription: &RssSubscription,
+		now: Instant,
+		global_interval: u64,
+		failure_threshold: u32,
+	) -> bool {
+		let interval = std::time::Duration::from_secs(
+			subscription.poll_interval(global_interval, failure_threshold),
+		);
+		self.last_checked
+			.get(&(subscription.room.clone(), subscription.url.clone()))
+			.map_or(true, |last_checked| now.saturating_duration_since(*last_checked) >= interval)
+	}
 
-use crate::database::{Databases, RssSubscription};
+	/// Remember that the subscription was checked at the given time.
+	pub fn checked(&mut self, subscription: &RssSubscription, at: Instant) {
+		self.last_checked.insert((subscription.room.clone(), subscription.url.clone()), at);
+	}
+}
 
-/// Interval function to be called every time the interval fires.
-pub async fn interval(db: &Databases, client: &Client) -> Result<()> {
+/// Interval function to be called every time the interval fires. Only feeds
+/// whose own interval passed are checked. Failing feeds do not stop the
+/// others, but a failed Matrix request is returned after all feeds ran.
+pub async fn interval(
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	state: &mut IntervalState,
+	limiter: &Arc<FetchLimiter>,
+	sends: &SendGuard,
+) -> Result<()> {
 	tracing::debug!("Running RSS interval..");
-	let http_client = reqwest::Client::new();
+	let http_client = GuardedClient::new(&config.user_agent, &config.fetch)?;
 
-	let rss_subs = RssSubscription::all_async(&db.state).await?;
-	for mut subscription in rss_subs {
-		if let Some(room) = client.get_joined_room(&subscription.contents.room) {
-			let feed_bytes =
-				http_client.get(subscription.contents.url.clone()).send().await?.bytes().await?;
-			let feed = feed_rs::parser::parse(feed_bytes.as_ref())?;
+	let started = Instant::now();
+	let mut rss_subs = Vec::new();
+	for subscription in RssSubscription::all_async(&db.state).await? {
+		if client.get_joined_room(&subscription.contents.room).is_none() {
+			subscription.delete_async(&db.state).await?;
+		} else if state.is_due(
+			&subscription.contents,
+			started,
+			config.intervals.rss,
+			config.rss.failure_threshold,
+		) {
+			state.checked(&subscription.contents, started);
+			rss_subs.push(subscription);
+		}
+	}
+	let key = config.store.secret_key();
+	let urls = rss_subs
+		.iter()
+		.map(|subscription| {
+			(
+				subscription.contents.url.clone(),
+				ParseOptions::from(&subscription.contents),
+				subscription.contents.auth(&key),
+				CacheValidators::from(&subscription.contents),
+			)
+		})
+		.collect();
+	let feeds = fetch_feeds(urls, &http_client, limiter, config.rss.retry_policy()).await;
 
-			send_feed_messages(&room, &feed, &subscription.contents.latest_update).await?;
+	let slow = std::time::Duration::from_secs(config.intervals.slow_subscription);
+	let mut errors = FeedErrors::default();
+	for (subscription, (fetched, fetch_time)) in rss_subs.into_iter().zip(feeds) {
+		let url = subscription.contents.url.clone();
+		let mut timer = SubscriptionTimer::start(format!("RSS feed {url}"), slow);
+		timer.add(fetch_time);
+		if let Err(err) = check_feed(config, db, client, subscription, fetched, sends).await {
+			errors.record(&url, err);
+		}
+	}
 
-			subscription.contents.latest_update = get_latest_entry(&feed)?;
+	send_due_digests(config, db, client, sends).await?;
+	errors.into_result()
+}
+
+/// Errors of the feeds checked in one interval. Errors of single feeds are
+/// logged so that the other feeds are still checked, but the first failed
+/// Matrix request is passed on, so that the circuit breaker notices a failing
+/// homeserver.
+#[derive(Debug, Default)]
+pub struct FeedErrors {
+	/// First failed Matrix request.
+	matrix: Option<Report>,
+}
+
+impl FeedErrors {
+	/// Record the error of checking the feed.
+	pub fn record(&mut self, url: &Url, err: Report) {
+		tracing::error!("Failed checking feed {url}: {err:?}");
+		if self.matrix.is_none() && super::is_matrix_error(&err) {
+			self.matrix = Some(err);
+		}
+	}
+
+	/// Return the first failed Matrix request as error after all feeds were
+	/// checked.
+	pub fn into_result(self) -> Result<()> {
+		self.matrix.map_or(Ok(()), Err)
+	}
+}
+
+/// Post the new entries of the fetched feed into the subscription's room.
+/// Failed fetches are counted, notifying the room once the feed failed too
+/// often in a row.
+async fn check_feed(
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	mut subscription: CollectionDocument<RssSubscription>,
+	fetched: Result<Option<(Feed, CacheValidators)>>,
+	sends: &SendGuard,
+) -> Result<()> {
+	let room = match client.get_joined_room(&subscription.contents.room) {
+		Some(room) => room,
+		None => {
+			subscription.delete_async(&db.state).await?;
+			return Ok(());
+		}
+	};
+	if !sends.allow(room.room_id()) {
+		return Ok(());
+	}
+	let feed = match fetched {
+		Ok(Some((feed, validators))) => {
+			subscription.contents.consecutive_failures = 0;
+			subscription.contents.etag = validators.etag;
+			subscription.contents.last_modified = validators.last_modified;
+			feed
+		}
+		Ok(None) => {
+			tracing::trace!("Feed {} was not modified", subscription.contents.url);
+			subscription.contents.consecutive_failures = 0;
+			subscription.contents.last_polled = Some(OffsetDateTime::now_utc());
+			subscription.update_async(&db.state).await?;
+			return Ok(());
+		}
+		Err(err) => {
+			tracing::error!("Failed fetching feed {}: {err}", subscription.contents.url);
+			subscription.contents.consecutive_failures =
+				subscription.contents.consecutive_failures.saturating_add(1);
 			subscription.update_async(&db.state).await?;
+			if subscription.contents.consecutive_failures == config.rss.failure_threshold
+				&& !subscription.contents.muted
+			{
+				let (html, body) =
+					render_failing(&subscription.contents, &err, &config.prefixes.rss);
+				let kind = MessageKind::default_for(room.is_direct());
+				let sink: &dyn NotificationSink = client;
+				let result = sink.send(room.room_id(), body, html, kind).await.map(|_| ());
+				sends.handle_result(config, client, room.room_id(), result).await?;
+			}
+			return Ok(());
+		}
+	};
+
+	let timestamps =
+		RoomPreferences::timestamps_for(room.room_id(), &config.timestamps, &db.state).await?;
+	let now = OffsetDateTime::now_utc();
+	let entries = select_entries(
+		&feed,
+		&mut subscription.contents,
+		now,
+		Duration::seconds(i64::try_from(config.clock_skew_tolerance)?),
+		timestamps.utc_offset(),
+	)?;
+	let (entries, skipped) = catch_up(entries, &mut subscription.contents, now, &config.intervals)?;
+	let url = &subscription.contents.url;
+	if subscription.contents.muted {
+		tracing::trace!("Not posting entries of muted feed {url}");
+	} else if let Some(mut digest) = RssDigest::for_room(room.room_id(), &db.state).await? {
+		collect_digest(&mut digest.contents, &feed, &entries, skipped, &subscription.contents);
+		digest.update_async(&db.state).await?;
+	} else {
+		let kind = MessageKind::default_for(room.is_direct());
+		let result = async {
+			if skipped > 0 {
+				let (html, body) =
+					render_skipped(skipped, &subscription.contents.url, &config.prefixes.rss);
+				let sink: &dyn NotificationSink = client;
+				sink.send(room.room_id(), body, html, kind).await?;
+			}
+			send_feed_messages(
+				client,
+				kind,
+				&feed_name(&feed, &subscription.contents),
+				&entries,
+				&subscription.contents,
+				config,
+				db,
+			)
+			.await
+		}
+		.await;
+		if !sends.handle_result(config, client, room.room_id(), result).await? {
+			return Ok(());
+		}
+	}
+
+	subscription.update_async(&db.state).await?;
+	Ok(())
+}
+
+/// Render the notice that the feed failed too often in a row and is checked
+/// less often until it recovers, starting with the prefix.
+fn render_failing(subscription: &RssSubscription, err: &Report, prefix: &str) -> (String, String) {
+	let url = &subscription.url;
+	let failures = subscription.consecutive_failures;
+	let html = format!(
+		"Fetching <a href=\"{url}\">{url}</a> failed {failures} times in a row, checking it less \
+		 often until it recovers: {}",
+		matrix::escape_html(&err.to_string())
+	);
+	let body = format!(
+		"Fetching {url} failed {failures} times in a row, checking it less often until it \
+		 recovers: {err}"
+	);
+	(matrix::prefixed(prefix, &html), matrix::prefixed(prefix, &body))
+}
+
+/// Fetch the feeds concurrently as far as the limiter allows, with their
+/// credentials if any, unless they were not modified according to their
+/// validators. Transiently failing fetches are retried. Returns the results
+/// along with the time spent fetching in the order of the URLs.
+async fn fetch_feeds(
+	urls: Vec<(Url, ParseOptions, Option<FeedAuth>, CacheValidators)>,
+	http_client: &GuardedClient,
+	limiter: &Arc<FetchLimiter>,
+	retry: RetryPolicy,
+) -> Vec<(Result<Option<(Feed, CacheValidators)>>, std::time::Duration)> {
+	let handles = urls
+		.into_iter()
+		.map(|(url, options, auth, validators)| {
+			let http_client = http_client.clone();
+			let limiter = limiter.clone();
+			tokio::spawn(async move {
+				let _permit = limiter.acquire().await?;
+				let started = Instant::now();
+				let feed = clients::rss::fetch_feed_with_retries(
+					&http_client,
+					url,
+					options,
+					auth.as_ref(),
+					&validators,
+					retry,
+				)
+				.await;
+				Ok::<_, Report>((feed, started.elapsed()))
+			})
+		})
+		.collect::<Vec<_>>();
+
+	let mut feeds = Vec::with_capacity(handles.len());
+	for handle in handles {
+		match handle.await.map_err(Into::into).and_then(|result| result) {
+			Ok(fetched) => feeds.push(fetched),
+			Err(err) => feeds.push((Err(err), std::time::Duration::ZERO)),
+		}
+	}
+	feeds
+}
+
+/// Send the digests that are due and have pending or skipped entries.
+async fn send_due_digests(
+	config: &Settings,
+	db: &Databases,
+	client: &Client,
+	sends: &SendGuard,
+) -> Result<()> {
+	let now = OffsetDateTime::now_utc();
+	for mut digest in RssDigest::all_async(&db.state).await? {
+		let empty = digest.contents.pending.is_empty() && digest.contents.skipped.is_empty();
+		if empty || !digest.contents.is_due(now) {
+			continue;
+		}
+
+		if let Some(room) = client.get_joined_room(&digest.contents.room) {
+			if !sends.allow(room.room_id()) {
+				continue;
+			}
+			let (html, body) = render_digest(
+				&digest.contents.pending,
+				&digest.contents.skipped,
+				&config.prefixes.rss,
+			);
+			let kind = MessageKind::default_for(room.is_direct());
+			let sink: &dyn NotificationSink = client;
+			let result = sink.send(room.room_id(), body, html, kind).await.map(|_| ());
+			if !sends.handle_result(config, client, room.room_id(), result).await? {
+				continue;
+			}
+
+			digest.contents.pending.clear();
+			digest.contents.skipped.clear();
+			digest.contents.last_sent = now;
+			digest.update_async(&db.state).await?;
 		} else {
-			subscription.delete_async(&db.state).await?;
+			digest.delete_async(&db.state).await?;
 		}
 	}
 	Ok(())
 }
 
-/// Send out messages for new feed entries into the room.
-async fn send_feed_messages(
-	room: &Joined,
+/// Add the entries of the subscription's feed and the number of its entries
+/// skipped while catching up to the digest.
+fn collect_digest(
+	digest: &mut RssDigest,
 	feed: &Feed,
-	latest_update: &OffsetDateTime,
-) -> Result<()> {
-	let new_entries = feed.entries.iter().filter(|entry| {
-		entry
-			.published
+	entries: &[&Entry],
+	skipped: usize,
+	subscription: &RssSubscription,
+) {
+	let feed_name = feed_name(feed, subscription);
+	digest.pending.extend(
+		entries.iter().map(|entry| digest_entry(&feed_name, entry, &subscription.link_rewrites)),
+	);
+	if skipped > 0 {
+		*digest.skipped.entry(subscription.url.to_string()).or_default() += skipped;
+	}
+}
+
+/// Create the digest entry for a feed entry, rewriting its link.
+fn digest_entry(feed: &str, entry: &Entry, rewrites: &[LinkRewrite]) -> DigestEntry {
+	DigestEntry {
+		feed: feed.to_owned(),
+		title: entry
+			.title
 			.as_ref()
-			.or(entry.updated.as_ref())
-			.map_or(false, |dtm| dtm.timestamp() > latest_update.unix_timestamp())
+			.map_or_else(|| String::from("Untitled"), |title| title.content.clone()),
+		link: entry.links.first().map(|link| LinkRewrite::apply_all(rewrites, &link.href)),
+	}
+}
+
+/// Render the digest entries grouped by feed as HTML and raw message, starting
+/// with the prefix. At most [`MAX_DIGEST_ENTRIES`] entries are listed, followed
+/// by the number of entries skipped while catching up, by feed URL.
+fn render_digest(
+	entries: &[DigestEntry],
+	skipped: &BTreeMap<String, usize>,
+	prefix: &str,
+) -> (String, String) {
+	let mut feeds: Vec<(&str, Vec<&DigestEntry>)> = Vec::new();
+	for entry in entries.iter().take(MAX_DIGEST_ENTRIES) {
+		match feeds.iter_mut().find(|(feed, _)| *feed == entry.feed) {
+			Some((_, feed_entries)) => feed_entries.push(entry),
+			None => feeds.push((&entry.feed, vec![entry])),
+		}
+	}
+
+	let mut message = String::from("<b>RSS digest</b><br>\n");
+	let mut body = String::from("RSS digest\n");
+	for (feed, feed_entries) in feeds {
+		message.push_str(&format!("<br>\n<b>{}</b><ul>\n", matrix::escape_html(feed)));
+		body.push_str(&format!("\n{feed}\n"));
+		for entry in feed_entries {
+			let title = matrix::escape_html(&entry.title);
+			match &entry.link {
+				Some(link) => {
+					message.push_str(&format!(
+						"<li><a href=\"{}\">{title}</a></li>\n",
+						matrix::escape_html(link)
+					));
+					body.push_str(&format!("- {}: {link}\n", entry.title));
+				}
+				None => {
+					message.push_str(&format!("<li>{title}</li>\n"));
+					body.push_str(&format!("- {}\n", entry.title));
+				}
+			}
+		}
+		message.push_str("</ul>\n");
+	}
+
+	let omitted = entries.len().saturating_sub(MAX_DIGEST_ENTRIES);
+	if omitted > 0 {
+		message.push_str(&format!("<i>and {omitted} more entries</i>"));
+		body.push_str(&format!("\nand {omitted} more entries"));
+	}
+	for (url, skipped) in skipped {
+		let text = skipped_note(*skipped, url);
+		message.push_str(&format!("<br>\n<i>{}</i>", matrix::escape_html(&text)));
+		body.push_str(&format!("\n{text}"));
+	}
+
+	(matrix::prefixed(prefix, &message), matrix::prefixed(prefix, &body))
+}
+
+/// Select the entries of the feed to post for the subscription at `now` and
+/// advance its latest update. Entries younger than the subscription's minimum
+/// entry age are deferred to a later poll, so that rapid edits settle first.
+/// Outside of the active hours (in the timezone of the offset), entries are
+/// held back or dropped as configured.
+fn select_entries<'a>(
+	feed: &'a Feed,
+	subscription: &mut RssSubscription,
+	now: OffsetDateTime,
+	tolerance: Duration,
+	offset: UtcOffset,
+) -> Result<Vec<&'a Entry>> {
+	let active = subscription.active_hours.map_or(true, |hours| hours.contains(now, offset));
+	if !active && subscription.outside_active_hours == OutsideActiveHours::Hold {
+		return Ok(Vec::new());
+	}
+
+	let min_age = subscription.min_entry_age;
+	let cutoff = now - Duration::seconds(i64::try_from(min_age)?);
+	let settled = |entry: &Entry| {
+		min_age == 0 || entry_time(entry).map_or(false, |time| time <= cutoff.unix_timestamp())
+	};
+
+	let mut entries = new_entries(
+		feed,
+		&subscription.latest_update,
+		subscription.initial_entries.take(),
+		&subscription.seen_entry_ids,
+		tolerance,
+	);
+	let seen = feed.entries.iter().filter(|entry| settled(entry)).collect::<Vec<_>>();
+	remember_entry_ids(subscription, &seen);
+	entries.retain(|entry| {
+		settled(entry)
+			&& matches_categories(entry, &subscription.categories)
+			&& matches_keywords(
+				entry,
+				&subscription.include_keywords,
+				&subscription.exclude_keywords,
+			)
 	});
 
-	for entry in new_entries {
-		let (html, body) = render_entry(entry);
-		let message = if room.is_direct() {
-			RoomMessageEventContent::text_html(body, html)
-		} else {
-			RoomMessageEventContent::notice_html(body, html)
-		};
-		room.send(message, None).await?;
+	if let Some(latest_update) = get_latest_entry(feed, settled)? {
+		subscription.latest_update = latest_update;
+	}
+	if !active {
+		entries.clear();
+	}
+	entries.retain(|entry| {
+		let hash = entry_hash(entry);
+		let repeated = subscription.last_posted_hash == Some(hash);
+		subscription.last_posted_hash = Some(hash);
+		if repeated {
+			tracing::debug!("Skipping repeated entry of feed {}", subscription.url);
+		}
+		!repeated
+	});
+	Ok(entries)
+}
+
+/// Bound the entries of a subscription that was not polled successfully for
+/// longer than configured, e.g. after downtime or failing fetches, to the
+/// newest ones. Records the successful poll at `now`. Returns the entries to
+/// post and the number of skipped older entries.
+fn catch_up<'a>(
+	mut entries: Vec<&'a Entry>,
+	subscription: &mut RssSubscription,
+	now: OffsetDateTime,
+	intervals: &IntervalSettings,
+) -> Result<(Vec<&'a Entry>, usize)> {
+	let threshold = Duration::seconds(i64::try_from(intervals.catch_up_after)?);
+	let dormant =
+		subscription.last_polled.map_or(false, |last_polled| now - last_polled > threshold);
+	subscription.last_polled = Some(now);
+	if !dormant || entries.len() <= intervals.catch_up_entries {
+		return Ok((entries, 0));
+	}
+
+	tracing::debug!("Catching up on feed {}", subscription.url);
+	let mut newest = entries.clone();
+	newest.sort_by_key(|entry| Reverse(entry_time(entry)));
+	newest.truncate(intervals.catch_up_entries);
+	let total = entries.len();
+	entries.retain(|entry| newest.iter().any(|kept| ptr::eq(*kept, *entry)));
+	Ok((entries, total - entries.len()))
+}
+
+/// Render the note about entries skipped while catching up on the feed as
+/// HTML and raw message, starting with the prefix.
+fn render_skipped(skipped: usize, url: &Url, prefix: &str) -> (String, String) {
+	let text = skipped_note(skipped, url.as_str());
+	(
+		matrix::prefixed(prefix, &format!("<i>{}</i>", matrix::escape_html(&text))),
+		matrix::prefixed(prefix, &text),
+	)
+}
+
+/// Note about entries skipped while catching up on the feed at the URL.
+fn skipped_note(skipped: usize, url: &str) -> String {
+	format!("(skipped {skipped} older entries of {url})")
+}
+
+/// Hash of the title and link of an entry, to recognize an entry that is
+/// repeated by the feed under a new ID or time.
+fn entry_hash(entry: &Entry) -> u64 {
+	let mut hasher = DefaultHasher::new();
+	entry.title.as_ref().map(|title| &title.content).hash(&mut hasher);
+	entry.links.first().map(|link| &link.href).hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Select the entries to post: entries with an ID that was not seen yet or,
+/// without ID, entries newer than the latest update. On the first poll and
+/// before any IDs were seen, entries newer than the latest update and the
+/// newest already existing entries as configured are selected. On the first
+/// poll, the latest update is the subscription time of the bot's clock, so
+/// entries published up to `tolerance` later count as already existing.
+fn new_entries<'a>(
+	feed: &'a Feed,
+	latest_update: &OffsetDateTime,
+	initial: Option<InitialEntries>,
+	seen_ids: &[String],
+	tolerance: Duration,
+) -> Vec<&'a Entry> {
+	let track_ids = initial.is_none() && !seen_ids.is_empty();
+	let latest_update = match initial {
+		Some(_) => *latest_update + tolerance,
+		None => *latest_update,
+	};
+	let initial_count = match initial {
+		Some(InitialEntries::Latest) => 1,
+		Some(InitialEntries::Backfill(count)) => count,
+		Some(InitialEntries::None) | None => 0,
+	};
+
+	let mut existing = feed
+		.entries
+		.iter()
+		.filter_map(|entry| entry_time(entry).map(|time| (time, entry)))
+		.filter(|(time, _)| *time <= latest_update.unix_timestamp())
+		.collect::<Vec<_>>();
+	existing.sort_by_key(|(time, _)| Reverse(*time));
+	let backfill =
+		existing.into_iter().take(initial_count).map(|(_, entry)| entry).collect::<Vec<_>>();
+
+	feed.entries
+		.iter()
+		.filter(|entry| match entry_id(entry) {
+			Some(id) if track_ids => !seen_ids.iter().any(|seen| seen == id),
+			_ => {
+				entry_time(entry).map_or(false, |time| time > latest_update.unix_timestamp())
+					|| backfill.iter().any(|backfilled| ptr::eq(*backfilled, *entry))
+			}
+		})
+		.collect()
+}
+
+/// Get the ID of an entry, if it is reliable. The feed parser assigns random
+/// IDs to entries without ID and link, so entries without link have none.
+fn entry_id(entry: &Entry) -> Option<&str> {
+	(!entry.id.is_empty() && !entry.links.is_empty()).then_some(entry.id.as_str())
+}
+
+/// Remember the IDs of the entries as seen by the subscription. Only the
+/// latest [`MAX_SEEN_ENTRY_IDS`] IDs are kept, but at least the given ones.
+fn remember_entry_ids(subscription: &mut RssSubscription, entries: &[&Entry]) {
+	let seen_ids = &mut subscription.seen_entry_ids;
+	for id in entries.iter().filter_map(|entry| entry_id(entry)) {
+		seen_ids.retain(|seen| seen != id);
+		seen_ids.push(id.to_owned());
+	}
+	let excess = seen_ids.len().saturating_sub(MAX_SEEN_ENTRY_IDS.max(entries.len()));
+	seen_ids.drain(..excess);
+}
+
+/// Whether the entry is in one of the categories (case-insensitive). Matches
+/// every entry if no categories are given.
+fn matches_categories(entry: &Entry, categories: &[String]) -> bool {
+	if categories.is_empty() {
+		return true;
+	}
+
+	let categories = categories.iter().map(|category| category.to_lowercase()).collect::<Vec<_>>();
+	entry.categories.iter().any(|category| {
+		categories.contains(&category.term.to_lowercase())
+			|| category
+				.label
+				.as_ref()
+				.map_or(false, |label| categories.contains(&label.to_lowercase()))
+	})
+}
+
+/// Whether the entry's title or summary contains one of the included keywords
+/// and none of the excluded keywords (case-insensitive). Matches every entry
+/// without excluded keywords if no keywords are included.
+fn matches_keywords(entry: &Entry, include: &[String], exclude: &[String]) -> bool {
+	if include.is_empty() && exclude.is_empty() {
+		return true;
+	}
+
+	let title = entry.title.as_ref().map_or("", |title| title.content.as_str());
+	let summary = entry.summary.as_ref().map(|summary| matrix::html_to_plain(&summary.content));
+	let text = format!("{title}\n{}", summary.unwrap_or_default()).to_lowercase();
+	let contains = |keyword: &String| text.contains(&keyword.to_lowercase());
+	(include.is_empty() || include.iter().any(contains)) && !exclude.iter().any(contains)
+}
+
+/// Get the publishing or update time of an entry as UNIX timestamp.
+fn entry_time(entry: &Entry) -> Option<i64> {
+	entry.published.as_ref().or(entry.updated.as_ref()).map(|dtm| dtm.timestamp())
+}
+
+/// Name of the feed shown in its messages: the subscription's label, the
+/// feed's title or its URL.
+fn feed_name(feed: &Feed, subscription: &RssSubscription) -> String {
+	match (&subscription.label, &feed.title) {
+		(Some(label), _) => label.clone(),
+		(None, Some(title)) => title.content.clone(),
+		(None, None) => subscription.url.to_string(),
+	}
+}
+
+/// Send out messages for the feed entries of the subscription into its room,
+/// naming the feed as source.
+async fn send_feed_messages(
+	sink: &dyn NotificationSink,
+	kind: MessageKind,
+	source: &str,
+	entries: &[&Entry],
+	subscription: &RssSubscription,
+	config: &Settings,
+	db: &Databases,
+) -> Result<()> {
+	let room = &subscription.room;
+	let preferences = RoomPreferences::for_room(room, &db.state).await?.map(|doc| doc.contents);
+	let settings = preferences
+		.as_ref()
+		.map_or_else(|| config.timestamps.clone(), |prefs| prefs.timestamps(&config.timestamps));
+	let timestamps = TimestampFormatter::new(&settings, OffsetDateTime::now_utc());
+	let format = RenderFormat::resolve(
+		subscription.render_override(),
+		preferences.and_then(|prefs| prefs.rss_format),
+		config.rss.format,
+	);
+	for entry in entries {
+		let (html, body) = render_entry(
+			entry,
+			Some(source),
+			&config.prefixes.rss,
+			&timestamps,
+			&subscription.link_rewrites,
+			format,
+		);
+		if let Some(event_id) = sink.send(room, body, html, kind).await? {
+			SentMessage::record(
+				room,
+				event_id,
+				MessageSource::Rss(subscription.url.clone()),
+				&db.state,
+			)
+			.await?;
+		}
 	}
 	Ok(())
 }
 
-/// Render an entry as HTML and raw message.
-fn render_entry(entry: &Entry) -> (String, String) {
+/// Render an entry as HTML and raw message, starting with the prefix and the
+/// name of the feed it is from, if given. The entry's links are rewritten by
+/// the rules. The compact format only shows the title and links, the full
+/// format shows the entry's full content as length-capped plain text instead
+/// of its summary.
+fn render_entry(
+	entry: &Entry,
+	source: Option<&str>,
+	prefix: &str,
+	timestamps: &TimestampFormatter,
+	rewrites: &[LinkRewrite],
+	format: RenderFormat,
+) -> (String, String) {
 	let mut message = String::new();
 	let mut body = String::new();
 
-	if let Some(title) = &entry.title {
-		message.push_str(&format!("<b>{}</b><br>\n", title.content));
-		body.push_str(&format!("{}\n", title.content));
+	if let Some(source) = source {
+		message.push_str(&format!("<i>{}</i>: ", matrix::escape_html(source)));
+		body.push_str(&format!("{source}: "));
+	}
+	match &entry.title {
+		Some(title) => {
+			message.push_str(&format!("<b>{}</b><br>\n", matrix::escape_html(&title.content)));
+			body.push_str(&format!("{}\n", title.content));
+		}
+		None if source.is_some() => {
+			message.push_str("<br>\n");
+			body.push('\n');
+		}
+		None => {}
 	}
 
-	if let Some(summary) = &entry.summary {
-		message.push_str(&format!("{}<br>\n", summary.content));
-		body.push_str(&format!("{}\n", summary.content));
+	if format != RenderFormat::Compact {
+		render_details(entry, timestamps, format, &mut message, &mut body);
 	}
 
 	for link in &entry.links {
+		let href = LinkRewrite::apply_all(rewrites, &link.href);
 		message.push_str(&format!(
 			"<a href=\"{}\">{}</a><br>\n",
-			link.href,
-			link.title.as_ref().unwrap_or(&link.href)
+			matrix::escape_html(&href),
+			matrix::escape_html(link.title.as_ref().unwrap_or(&href))
 		));
-		body.push_str(&format!("{}\n", link.href));
+		match &link.title {
+			Some(title) => body.push_str(&format!("{title}: {href}\n")),
+			None => body.push_str(&format!("{href}\n")),
+		}
 	}
 
-	(message, body)
+	(matrix::prefixed(prefix, &message), matrix::prefixed(prefix, &body))
 }
 
-/// Extract latest entry time from feed.
-fn get_latest_entry(feed: &Feed) -> Result<OffsetDateTime> {
-	let latest_time = feed
-		.entries
-		.iter()
-		.filter_map(|entry| entry.published.as_ref().or(entry.updated.as_ref()))
-		.fold(0, |maximum, dtm| dtm.timestamp().max(maximum));
+/// Render the time and the sanitized summary or, in the full format, the
+/// content of an entry into the HTML and raw message.
+fn render_details(
+	entry: &Entry,
+	timestamps: &TimestampFormatter,
+	format: RenderFormat,
+	message: &mut String,
+	body: &mut String,
+) {
+	if let Some(time) =
+		entry_time(entry).and_then(|ts| OffsetDateTime::from_unix_timestamp(ts).ok())
+	{
+		let time = timestamps.format(time);
+		message.push_str(&format!("<i>{time}</i><br>\n"));
+		body.push_str(&format!("{time}\n"));
+	}
+
+	let content = entry
+		.content
+		.as_ref()
+		.and_then(|content| content.body.as_deref())
+		.filter(|content| format == RenderFormat::Full && !content.trim().is_empty());
+	if let Some(content) = content {
+		let text = matrix::truncate(matrix::html_to_plain(content).trim(), MAX_CONTENT_LENGTH);
+		message.push_str(&format!("{}<br>\n", matrix::escape_html(&text).replace('\n', "<br>\n")));
+		body.push_str(&format!("{text}\n"));
+	} else if let Some(summary) = &entry.summary {
+		let summary = matrix::sanitize_html(&summary.content);
+		message.push_str(&format!("{summary}<br>\n"));
+		body.push_str(&format!("{}\n", matrix::html_to_plain(&summary)));
+	}
+}
+
+/// Extract latest entry time from feed, only considering settled entries.
+/// Returns `None` if there is no settled entry with a time.
+fn get_latest_entry(
+	feed: &Feed,
+	settled: impl Fn(&Entry) -> bool,
+) -> Result<Option<OffsetDateTime>> {
+	let latest_time =
+		feed.entries.iter().filter(|entry| settled(entry)).filter_map(entry_time).max();
 
-	let latest_time = OffsetDateTime::from_unix_timestamp(latest_time)?;
+	let latest_time = latest_time.map(OffsetDateTime::from_unix_timestamp).transpose()?;
 	Ok(latest_time)
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,865 @@
+//! Tests for the RSS interval.
+
+use color_eyre::{eyre::eyre, Result};
+use matrix_sdk::ruma::OwnedRoomId;
+
+use super::*;
+use crate::{
+	clients::rss::parse_feed,
+	database::ActiveHours,
+	settings::{FetchSettings, TimestampSettings},
+	test_utils::{test_databases, CaptureSink, MockResponse, MockServer},
+};
+
+/// Feed with three entries from the past, newest first.
+const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>Test feed</title>
+	<link>https://example.com/</link>
+	<description>Test feed</description>
+	<item>
+		<title>Newest</title>
+		<link>https://example.com/3</link>
+		<pubDate>Wed, 05 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+	<item>
+		<title>Middle</title>
+		<link>https://example.com/2</link>
+		<pubDate>Tue, 04 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+	<item>
+		<title>Oldest</title>
+		<link>https://example.com/1</link>
+		<pubDate>Mon, 03 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+</channel>
+</rss>"#;
+
+/// Titles of the selected entries.
+fn titles(entries: &[&Entry]) -> Vec<String> {
+	entries
+		.iter()
+		.filter_map(|entry| entry.title.as_ref().map(|title| title.content.clone()))
+		.collect()
+}
+
+#[test]
+fn initial_entries_none() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let now = OffsetDateTime::now_utc();
+	assert!(new_entries(&feed, &now, None, &[], Duration::ZERO).is_empty());
+	assert!(new_entries(&feed, &now, Some(InitialEntries::None), &[], Duration::ZERO).is_empty());
+	Ok(())
+}
+
+#[test]
+fn initial_entries_latest() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let now = OffsetDateTime::now_utc();
+	let entries = new_entries(&feed, &now, Some(InitialEntries::Latest), &[], Duration::ZERO);
+	assert_eq!(titles(&entries), vec!["Newest"]);
+	Ok(())
+}
+
+#[test]
+fn initial_entries_backfill() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let now = OffsetDateTime::now_utc();
+	let entries = new_entries(&feed, &now, Some(InitialEntries::Backfill(2)), &[], Duration::ZERO);
+	assert_eq!(titles(&entries), vec!["Newest", "Middle"]);
+
+	let entries = new_entries(&feed, &now, Some(InitialEntries::Backfill(10)), &[], Duration::ZERO);
+	assert_eq!(entries.len(), 3);
+	Ok(())
+}
+
+#[test]
+fn new_entries_after_latest_update() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let latest_update = OffsetDateTime::from_unix_timestamp(1_664_877_600)?; // 2022-10-04 10:00
+	let entries =
+		new_entries(&feed, &latest_update, Some(InitialEntries::Latest), &[], Duration::ZERO);
+	assert_eq!(titles(&entries), vec!["Newest", "Middle"]);
+	Ok(())
+}
+
+#[test]
+fn initial_entries_with_clock_skew() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	// The bot's clock is behind, so the newest entry seems to be newer than the
+	// subscription.
+	let subscribed = OffsetDateTime::from_unix_timestamp(1_664_964_000 - 3)?; // 2022-10-05 09:59:57
+	let entries = new_entries(&feed, &subscribed, Some(InitialEntries::None), &[], Duration::ZERO);
+	assert_eq!(titles(&entries), vec!["Newest"]);
+
+	let entries =
+		new_entries(&feed, &subscribed, Some(InitialEntries::None), &[], Duration::seconds(3));
+	assert!(entries.is_empty());
+	let entries =
+		new_entries(&feed, &subscribed, Some(InitialEntries::None), &[], Duration::seconds(2));
+	assert_eq!(titles(&entries), vec!["Newest"]);
+
+	// After the first poll, the latest update is taken from the feed itself.
+	let entries = new_entries(&feed, &subscribed, None, &[], Duration::seconds(3));
+	assert_eq!(titles(&entries), vec!["Newest"]);
+	Ok(())
+}
+
+#[test]
+fn fresh_entries_are_deferred() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let newest = OffsetDateTime::from_unix_timestamp(1_664_964_000)?; // 2022-10-05 10:00
+	let middle = OffsetDateTime::from_unix_timestamp(1_664_877_600)?; // 2022-10-04 10:00
+	let mut subscription =
+		RssSubscription::new("!room:example.com".try_into()?, "https://example.com/feed".parse()?);
+	subscription.initial_entries = None;
+	subscription.latest_update = middle;
+	subscription.min_entry_age = 600;
+
+	let now = newest + Duration::minutes(1);
+	let entries = select_entries(&feed, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert!(entries.is_empty());
+	assert_eq!(subscription.latest_update, middle);
+
+	let now = newest + Duration::minutes(10);
+	let entries = select_entries(&feed, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert_eq!(titles(&entries), vec!["Newest"]);
+	assert_eq!(subscription.latest_update, newest);
+
+	let now = newest + Duration::minutes(20);
+	assert!(
+		select_entries(&feed, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?.is_empty()
+	);
+	Ok(())
+}
+
+#[test]
+fn repeated_entry_is_suppressed() -> Result<()> {
+	let feed = |title: &str, date: &str| {
+		parse_feed(
+			format!(
+				r#"<rss version="2.0"><channel><title>Test feed</title>
+				<item><title>{title}</title><link>https://example.com/1</link>
+				<pubDate>{date}</pubDate></item></channel></rss>"#
+			)
+			.as_bytes(),
+		)
+	};
+	let mut subscription =
+		RssSubscription::new("!room:example.com".try_into()?, "https://example.com/feed".parse()?);
+	subscription.initial_entries = None;
+	subscription.latest_update = OffsetDateTime::from_unix_timestamp(1_664_791_200)?; // 2022-10-03
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+
+	let first = feed("Entry", "Tue, 04 Oct 2022 10:00:00 GMT")?;
+	let entries = select_entries(&first, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert_eq!(titles(&entries), vec!["Entry"]);
+
+	let repeated = feed("Entry", "Wed, 05 Oct 2022 10:00:00 GMT")?;
+	let entries =
+		select_entries(&repeated, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert!(entries.is_empty());
+
+	let changed = feed("Changed entry", "Wed, 05 Oct 2022 11:00:00 GMT")?;
+	let entries = select_entries(&changed, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert_eq!(titles(&entries), vec!["Changed entry"]);
+	Ok(())
+}
+
+#[test]
+fn overlapping_polls_post_entries_once() -> Result<()> {
+	let feed = |items: &[(&str, &str)]| {
+		let items = items
+			.iter()
+			.map(|(id, date)| {
+				format!(
+					"<item><title>Entry {id}</title><guid>{id}</guid>\
+					 <link>https://example.com/{id}</link><pubDate>{date}</pubDate></item>"
+				)
+			})
+			.collect::<String>();
+		parse_feed(
+			format!(
+				r#"<rss version="2.0"><channel><title>Test feed</title>{items}</channel></rss>"#
+			)
+			.as_bytes(),
+		)
+	};
+	let mut subscription =
+		RssSubscription::new("!room:example.com".try_into()?, "https://example.com/feed".parse()?);
+	subscription.initial_entries = None;
+	subscription.latest_update = OffsetDateTime::from_unix_timestamp(1_664_791_200)?; // 2022-10-03
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+
+	let first =
+		feed(&[("2", "Tue, 04 Oct 2022 10:00:00 GMT"), ("1", "Tue, 04 Oct 2022 10:00:00 GMT")])?;
+	let entries = select_entries(&first, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert_eq!(titles(&entries), vec!["Entry 2", "Entry 1"]);
+
+	// Entry 2 is republished with a newer time and entry 3 has the same time as
+	// the latest update.
+	let second = feed(&[
+		("3", "Tue, 04 Oct 2022 10:00:00 GMT"),
+		("2", "Wed, 05 Oct 2022 10:00:00 GMT"),
+		("1", "Tue, 04 Oct 2022 10:00:00 GMT"),
+	])?;
+	let entries = select_entries(&second, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+	assert_eq!(titles(&entries), vec!["Entry 3"]);
+	assert!(
+		select_entries(&second, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?.is_empty()
+	);
+	Ok(())
+}
+
+#[test]
+fn seen_entry_ids_are_bounded() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let mut subscription =
+		RssSubscription::new("!room:example.com".try_into()?, "https://example.com/feed".parse()?);
+	subscription.seen_entry_ids = (0..MAX_SEEN_ENTRY_IDS).map(|i| i.to_string()).collect();
+
+	let entries = feed.entries.iter().collect::<Vec<_>>();
+	remember_entry_ids(&mut subscription, &entries);
+	remember_entry_ids(&mut subscription, &entries);
+	assert_eq!(subscription.seen_entry_ids.len(), MAX_SEEN_ENTRY_IDS);
+	assert_eq!(subscription.seen_entry_ids[0], "3");
+	assert!(entries.iter().all(|entry| subscription.seen_entry_ids.contains(&entry.id)));
+	Ok(())
+}
+
+#[test]
+fn active_hours_wrap_around_midnight() -> Result<()> {
+	let day: ActiveHours = "09:00-17:00".parse().map_err(|err: String| eyre!(err))?;
+	let night: ActiveHours = "22:00-06:00".parse().map_err(|err: String| eyre!(err))?;
+	let at = |hour: u8, minute: u8| -> Result<OffsetDateTime> {
+		Ok(OffsetDateTime::UNIX_EPOCH.replace_time(time::Time::from_hms(hour, minute, 0)?))
+	};
+
+	assert!(day.contains(at(9, 0)?, UtcOffset::UTC));
+	assert!(day.contains(at(16, 59)?, UtcOffset::UTC));
+	assert!(!day.contains(at(17, 0)?, UtcOffset::UTC));
+	assert!(!day.contains(at(8, 59)?, UtcOffset::UTC));
+	assert!(night.contains(at(23, 0)?, UtcOffset::UTC));
+	assert!(night.contains(at(5, 59)?, UtcOffset::UTC));
+	assert!(!night.contains(at(6, 0)?, UtcOffset::UTC));
+	// 07:30 UTC is 09:30 in UTC+2.
+	assert!(day.contains(at(7, 30)?, UtcOffset::from_hms(2, 0, 0)?));
+
+	assert!("25:00-26:00".parse::<ActiveHours>().is_err());
+	assert!("09:00".parse::<ActiveHours>().is_err());
+	Ok(())
+}
+
+#[test]
+fn entries_outside_active_hours() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let newest = OffsetDateTime::from_unix_timestamp(1_664_964_000)?; // 2022-10-05 10:00
+	let middle = OffsetDateTime::from_unix_timestamp(1_664_877_600)?; // 2022-10-04 10:00
+	let mut subscription =
+		RssSubscription::new("!room:example.com".try_into()?, "https://example.com/feed".parse()?);
+	subscription.initial_entries = None;
+	subscription.latest_update = middle;
+	subscription.active_hours = Some(ActiveHours { start: 12 * 60, end: 18 * 60 });
+
+	// Held back until the active hours begin.
+	let mut held = subscription.clone();
+	let before = newest + Duration::hours(1); // 11:00
+	let entries = select_entries(&feed, &mut held, before, Duration::ZERO, UtcOffset::UTC)?;
+	assert!(entries.is_empty());
+	assert_eq!(held.latest_update, middle);
+	let during = newest + Duration::hours(2); // 12:00
+	let entries = select_entries(&feed, &mut held, during, Duration::ZERO, UtcOffset::UTC)?;
+	assert_eq!(titles(&entries), vec!["Newest"]);
+
+	// Dropped, but the latest update still advances.
+	let mut dropped = subscription;
+	dropped.outside_active_hours = OutsideActiveHours::Drop;
+	let entries = select_entries(&feed, &mut dropped, before, Duration::ZERO, UtcOffset::UTC)?;
+	assert!(entries.is_empty());
+	assert_eq!(dropped.latest_update, newest);
+	let entries = select_entries(&feed, &mut dropped, during, Duration::ZERO, UtcOffset::UTC)?;
+	assert!(entries.is_empty());
+	Ok(())
+}
+
+#[test]
+fn filter_by_categories() -> Result<()> {
+	let feed = parse_feed(
+		br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>News</title>
+	<link>https://example.com/</link>
+	<description>News</description>
+	<item>
+		<title>Politics and Tech</title>
+		<category>Politics</category>
+		<category>Tech</category>
+	</item>
+	<item>
+		<title>Sports</title>
+		<category>Sports</category>
+	</item>
+	<item>
+		<title>Uncategorized</title>
+	</item>
+</channel>
+</rss>"#,
+	)?;
+
+	let filtered = |categories: &[&str]| {
+		let categories = categories.iter().map(ToString::to_string).collect::<Vec<_>>();
+		feed.entries
+			.iter()
+			.filter(|entry| matches_categories(entry, &categories))
+			.filter_map(|entry| entry.title.as_ref().map(|title| title.content.clone()))
+			.collect::<Vec<_>>()
+	};
+
+	assert_eq!(filtered(&[]).len(), 3);
+	assert_eq!(filtered(&["tech"]), vec!["Politics and Tech"]);
+	assert_eq!(filtered(&["SPORTS", "politics"]), vec!["Politics and Tech", "Sports"]);
+	assert!(filtered(&["weather"]).is_empty());
+	Ok(())
+}
+
+#[test]
+fn filter_by_keywords() -> Result<()> {
+	let feed = parse_feed(
+		br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>Releases</title>
+	<link>https://example.com/</link>
+	<description>Releases</description>
+	<item>
+		<title>Version 1.2.1</title>
+		<description>Fixes a &lt;b&gt;Security&lt;/b&gt; issue.</description>
+		<pubDate>Wed, 05 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+	<item>
+		<title>Nightly security build</title>
+		<pubDate>Tue, 04 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+	<item>
+		<title>Version 1.2.0</title>
+		<description>New features.</description>
+		<pubDate>Mon, 03 Oct 2022 10:00:00 GMT</pubDate>
+	</item>
+</channel>
+</rss>"#,
+	)?;
+	let filtered = |include: &[&str], exclude: &[&str]| -> Result<Vec<String>> {
+		let mut subscription = RssSubscription::new(
+			"!room:example.com".try_into()?,
+			"https://example.com/feed".parse()?,
+		);
+		subscription.initial_entries = Some(InitialEntries::Backfill(10));
+		subscription.include_keywords = include.iter().map(ToString::to_string).collect();
+		subscription.exclude_keywords = exclude.iter().map(ToString::to_string).collect();
+		let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+		let entries =
+			select_entries(&feed, &mut subscription, now, Duration::ZERO, UtcOffset::UTC)?;
+		Ok(titles(&entries))
+	};
+
+	assert_eq!(filtered(&[], &[])?.len(), 3);
+	assert_eq!(filtered(&["SECURITY"], &[])?, vec!["Version 1.2.1", "Nightly security build"]);
+	assert_eq!(filtered(&["security"], &["nightly"])?, vec!["Version 1.2.1"]);
+	assert_eq!(filtered(&[], &["nightly"])?, vec!["Version 1.2.1", "Version 1.2.0"]);
+	assert!(filtered(&["weather"], &[])?.is_empty());
+	Ok(())
+}
+
+/// Timestamp formatter relative to shortly after the test entries.
+fn timestamps() -> TimestampFormatter {
+	let now =
+		OffsetDateTime::from_unix_timestamp(1_664_964_180).unwrap_or(OffsetDateTime::UNIX_EPOCH);
+	TimestampFormatter::new(&TimestampSettings::default(), now)
+}
+
+#[test]
+fn body_contains_essential_info() -> Result<()> {
+	let feed = parse_feed(
+		br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>News</title>
+	<link>https://example.com/</link>
+	<description>News</description>
+	<item>
+		<title>Big news</title>
+		<description>&lt;p&gt;Something &lt;b&gt;important&lt;/b&gt; happened.&lt;/p&gt;</description>
+		<link>https://example.com/big-news</link>
+	</item>
+</channel>
+</rss>"#,
+	)?;
+	let (html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &[], RenderFormat::Normal);
+
+	assert!(html.contains("<b>important</b>"));
+	assert!(body.contains("Big news"));
+	assert!(body.contains("Something important happened."));
+	assert!(body.contains("https://example.com/big-news"));
+	assert!(!body.contains('<'));
+	Ok(())
+}
+
+#[test]
+fn compact_format_omits_details() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let (_html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &[], RenderFormat::Compact);
+	assert_eq!(body, "Newest\nhttps://example.com/3\n");
+	Ok(())
+}
+
+/// Send the newest entry of the test feed for the subscription and get the
+/// bodies of the sent messages.
+async fn sent_bodies(
+	subscription: &RssSubscription,
+	config: &Settings,
+	db: &Databases,
+) -> Result<Vec<String>> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let entries = feed.entries.iter().take(1).collect::<Vec<_>>();
+	let sink = CaptureSink::default();
+	send_feed_messages(&sink, MessageKind::Notice, "Test feed", &entries, subscription, config, db)
+		.await?;
+	Ok(sink.sent().into_iter().map(|captured| captured.body).collect())
+}
+
+#[tokio::test]
+async fn render_format_precedence() -> Result<()> {
+	use RenderFormat::{Compact, Full, Normal};
+	assert_eq!(RenderFormat::resolve(Some(Full), Some(Compact), Normal), Full);
+	assert_eq!(RenderFormat::resolve(None, Some(Compact), Normal), Compact);
+	assert_eq!(RenderFormat::resolve(None, None, Full), Full);
+
+	let mut config = Settings::read_from("config.sample.yaml")?;
+	config.rss.format = Normal;
+	let db = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let mut subscription = RssSubscription::new(room.clone(), "https://example.com/feed".parse()?);
+	let compact = vec![String::from("Test feed: Newest\nhttps://example.com/3\n")];
+	assert_ne!(sent_bodies(&subscription, &config, &db).await?, compact);
+
+	let mut preferences = RoomPreferences::new(room);
+	preferences.rss_format = Some(Compact);
+	preferences.save(&db.state).await?;
+	assert_eq!(sent_bodies(&subscription, &config, &db).await?, compact);
+
+	subscription.render = Some(Normal);
+	assert_ne!(sent_bodies(&subscription, &config, &db).await?, compact);
+	Ok(())
+}
+
+#[test]
+fn summary_html_is_sanitized() -> Result<()> {
+	let feed = parse_feed(
+		br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>News</title>
+	<link>https://example.com/</link>
+	<description>News</description>
+	<item>
+		<title>Big news</title>
+		<description><![CDATA[<p>Read <script>steal()</script><b>this</p><img src="x" onerror="steal()"><i>now]]></description>
+		<link>https://example.com/big-news</link>
+	</item>
+</channel>
+</rss>"#,
+	)?;
+	let (html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &[], RenderFormat::Normal);
+
+	assert!(html.contains("<p>Read <b>this</b></p><i>now</i><br>\n"));
+	assert!(!html.contains("script") && !html.contains("steal") && !html.contains("<img"));
+	assert!(body.contains("Read this\nnow\n"));
+	assert!(!body.contains("steal"));
+	Ok(())
+}
+
+#[test]
+fn titles_and_links_are_escaped() -> Result<()> {
+	let feed = parse_feed(
+		br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>News</title>
+	<link>https://example.com/</link>
+	<description>News</description>
+	<item>
+		<title>Tom &amp; Jerry &lt;script&gt;steal()&lt;/script&gt;</title>
+		<link>https://example.com/?a=1&amp;b="&gt;&lt;img src=x&gt;</link>
+	</item>
+</channel>
+</rss>"#,
+	)?;
+	let (html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &[], RenderFormat::Normal);
+
+	let href = "https://example.com/?a=1&amp;b=&quot;&gt;&lt;img src=x&gt;";
+	assert!(html.contains("<b>Tom &amp; Jerry &lt;script&gt;steal()&lt;/script&gt;</b>"));
+	assert!(html.contains(&format!("<a href=\"{href}\">{href}</a>")));
+	assert!(!html.contains("<script") && !html.contains("<img"));
+	assert!(body.contains("Tom & Jerry <script>steal()</script>\n"));
+	Ok(())
+}
+
+#[test]
+fn full_content_is_rendered() -> Result<()> {
+	let feed = parse_feed(
+		br#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:content="http://purl.org/rss/1.0/modules/content/">
+<channel>
+	<title>News</title>
+	<link>https://example.com/</link>
+	<description>News</description>
+	<item>
+		<title>Big news</title>
+		<description>Short summary.</description>
+		<content:encoded><![CDATA[<p>The <script>full</script> text.</p>A &amp; B.]]></content:encoded>
+		<link>https://example.com/big-news</link>
+	</item>
+</channel>
+</rss>"#,
+	)?;
+
+	let (html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &[], RenderFormat::Full);
+	assert!(!body.contains("Short summary."));
+	assert!(body.contains("The full text.\nA & B."));
+	assert!(html.contains("The full text.<br>\nA &amp; B."));
+	assert!(!html.contains("<script>"));
+
+	let (_html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &[], RenderFormat::Normal);
+	assert!(body.contains("Short summary."));
+	assert!(!body.contains("full text"));
+
+	let summary_only = parse_feed(FEED.as_bytes())?;
+	let (_html, body) =
+		render_entry(&summary_only.entries[0], None, "", &timestamps(), &[], RenderFormat::Full);
+	assert!(body.starts_with("Newest\n"));
+	Ok(())
+}
+
+#[test]
+fn long_content_is_capped() {
+	let long = "a".repeat(MAX_CONTENT_LENGTH * 2);
+	let entry = Entry {
+		content: Some(feed_rs::model::Content { body: Some(long), ..Default::default() }),
+		..Default::default()
+	};
+	let (_html, body) = render_entry(&entry, None, "", &timestamps(), &[], RenderFormat::Full);
+	assert!(body.contains(&format!("{}…", "a".repeat(MAX_CONTENT_LENGTH))));
+	assert!(body.len() < MAX_CONTENT_LENGTH + 100);
+}
+
+#[test]
+fn configured_prefix_appears() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let (html, body) =
+		render_entry(&feed.entries[0], None, "\u{1f4f0}", &timestamps(), &[], RenderFormat::Normal);
+
+	assert!(html.starts_with("\u{1f4f0} <b>Newest</b>"));
+	assert!(body.starts_with("\u{1f4f0} Newest"));
+	assert!(body.contains("3m ago (2022-10-05 10:00 UTC+00)"));
+	Ok(())
+}
+
+#[test]
+fn links_are_rewritten() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let rewrites = vec![
+		LinkRewrite {
+			pattern: r"^https://example.com/(\d+)$".to_owned(),
+			replacement: "https://mirror.example.org/post/$1".to_owned(),
+		},
+		LinkRewrite {
+			pattern: "^https://mirror".to_owned(),
+			replacement: "https://www.mirror".to_owned(),
+		},
+	];
+	let (html, body) =
+		render_entry(&feed.entries[0], None, "", &timestamps(), &rewrites, RenderFormat::Normal);
+
+	assert!(html.contains("<a href=\"https://www.mirror.example.org/post/3\">"));
+	assert!(body.contains("https://www.mirror.example.org/post/3"));
+	assert!(!body.contains("https://example.com/3"));
+
+	let digest = digest_entry("Test feed", &feed.entries[0], &rewrites);
+	assert_eq!(digest.link.as_deref(), Some("https://www.mirror.example.org/post/3"));
+	Ok(())
+}
+
+#[test]
+fn entries_are_prefixed_with_the_feed_name() -> Result<()> {
+	let feed = parse_feed(FEED.as_bytes())?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let mut subscription = RssSubscription::new(room, "https://example.com/feed".parse()?);
+	assert_eq!(feed_name(&feed, &subscription), "Test feed");
+
+	subscription.label = Some(String::from("News & Co"));
+	let name = feed_name(&feed, &subscription);
+	assert_eq!(name, "News & Co");
+	let (html, body) =
+		render_entry(&feed.entries[0], Some(&name), "", &timestamps(), &[], RenderFormat::Compact);
+	assert!(html.starts_with("<i>News &amp; Co</i>: <b>Newest</b><br>\n"));
+	assert_eq!(body, "News & Co: Newest\nhttps://example.com/3\n");
+	Ok(())
+}
+
+#[tokio::test]
+async fn feed_messages_are_sent_to_the_sink() -> Result<()> {
+	let config = Settings::read_from("config.sample.yaml")?;
+	let db = test_databases().await?;
+	let feed = parse_feed(FEED.as_bytes())?;
+	let entries = feed.entries.iter().take(2).collect::<Vec<_>>();
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let subscription = RssSubscription::new(room.clone(), "https://example.com/feed".parse()?);
+
+	let sink = CaptureSink::default();
+	send_feed_messages(
+		&sink,
+		MessageKind::Notice,
+		"Test feed",
+		&entries,
+		&subscription,
+		&config,
+		&db,
+	)
+	.await?;
+
+	let sent = sink.sent();
+	assert_eq!(sent.len(), 2);
+	assert!(sent.iter().all(|captured| captured.body.starts_with("Test feed: ")));
+	assert!(sent.iter().all(|captured| captured.room == room));
+	assert!(sent.iter().all(|captured| captured.kind == MessageKind::Notice));
+	assert!(sent[0].body.contains("Newest") && sent[0].body.contains("https://example.com/3"));
+	assert!(sent[1].html.contains("<a href=\"https://example.com/2\">"));
+	Ok(())
+}
+
+#[tokio::test]
+async fn dormant_subscription_catches_up_bounded() -> Result<()> {
+	let mut config = Settings::read_from("config.sample.yaml")?;
+	config.intervals.catch_up_after = 60 * 60;
+	config.intervals.catch_up_entries = 2;
+	let db = test_databases().await?;
+	let feed = parse_feed(FEED.as_bytes())?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let mut subscription = RssSubscription::new(room.clone(), "https://example.com/feed".parse()?);
+	subscription.initial_entries = None;
+	subscription.latest_update = OffsetDateTime::from_unix_timestamp(1_664_700_000)?; // 2022-10-02
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+
+	subscription.last_polled = Some(now - Duration::minutes(10));
+	let entries = new_entries(&feed, &subscription.latest_update, None, &[], Duration::ZERO);
+	let (entries, skipped) = catch_up(entries, &mut subscription, now, &config.intervals)?;
+	assert_eq!((entries.len(), skipped), (3, 0));
+
+	subscription.last_polled = Some(now - Duration::days(3));
+	let (entries, skipped) = catch_up(entries, &mut subscription, now, &config.intervals)?;
+	assert_eq!(titles(&entries), vec!["Newest", "Middle"]);
+	assert_eq!(skipped, 1);
+	assert_eq!(subscription.last_polled, Some(now));
+
+	let sink = CaptureSink::default();
+	let (html, body) = render_skipped(skipped, &subscription.url, "");
+	sink.send(&room, body, html, MessageKind::Notice).await?;
+	send_feed_messages(&sink, MessageKind::Notice, "", &entries, &subscription, &config, &db)
+		.await?;
+	let sent = sink.sent();
+	assert_eq!(sent.len(), 3);
+	assert_eq!(sent[0].body, "(skipped 1 older entries of https://example.com/feed)");
+	assert!(sent[1].body.contains("Newest") && sent[2].body.contains("Middle"));
+	Ok(())
+}
+
+#[test]
+fn rewrite_rules_are_parsed() {
+	assert_eq!(
+		"^http://=>https://".parse::<LinkRewrite>(),
+		Ok(LinkRewrite { pattern: "^http://".to_owned(), replacement: "https://".to_owned() })
+	);
+	assert!("[unclosed=>x".parse::<LinkRewrite>().is_err());
+	assert!("no separator".parse::<LinkRewrite>().is_err());
+}
+
+#[test]
+fn digest_groups_feeds_into_one_message() {
+	let entry = |feed: &str, title: &str| DigestEntry {
+		feed: feed.to_owned(),
+		title: title.to_owned(),
+		link: Some(format!("https://example.com/{title}")),
+	};
+	let entries = [entry("News", "first"), entry("Blog", "post"), entry("News", "second")];
+
+	let (html, body) = render_digest(&entries, &BTreeMap::new(), "");
+	assert_eq!(
+		body,
+		"RSS digest\n\nNews\n- first: https://example.com/first\n- second: \
+		 https://example.com/second\n\nBlog\n- post: https://example.com/post\n"
+	);
+	assert_eq!(html.matches("<ul>").count(), 2);
+	assert!(html.find("first").unwrap_or(usize::MAX) < html.find("second").unwrap_or(0));
+	assert!(html.find("second").unwrap_or(usize::MAX) < html.find("post").unwrap_or(0));
+}
+
+#[test]
+fn digest_entries_are_escaped() {
+	let entries = [DigestEntry {
+		feed: String::from("News & <i>Views</i>"),
+		title: String::from("<script>alert(1)</script>"),
+		link: Some(String::from("https://example.com/\"><img src=x>")),
+	}];
+
+	let (html, body) = render_digest(&entries, &BTreeMap::new(), "");
+	assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+	assert!(html.contains("href=\"https://example.com/&quot;&gt;&lt;img src=x&gt;\""));
+	assert!(html.contains("<b>News &amp; &lt;i&gt;Views&lt;/i&gt;</b>"));
+	assert!(!html.contains("<script>") && !html.contains("<img") && !html.contains("<i>"));
+	assert!(body.contains("- <script>alert(1)</script>: https://example.com/\"><img src=x>"));
+}
+
+#[test]
+fn digest_is_capped() {
+	let entries = (0..MAX_DIGEST_ENTRIES + 5)
+		.map(|i| DigestEntry { feed: "News".to_owned(), title: format!("entry {i}"), link: None })
+		.collect::<Vec<_>>();
+
+	let (_html, body) = render_digest(&entries, &BTreeMap::new(), "");
+	assert!(body.contains(&format!("entry {}", MAX_DIGEST_ENTRIES - 1)));
+	assert!(!body.contains(&format!("entry {MAX_DIGEST_ENTRIES}")));
+	assert!(body.ends_with("and 5 more entries"));
+}
+
+#[test]
+fn digest_notes_skipped_entries() {
+	let entries =
+		[DigestEntry { feed: String::from("News"), title: String::from("latest"), link: None }];
+	let url = "https://example.com/feed?a&b";
+	let skipped = BTreeMap::from([(url.to_owned(), 7)]);
+
+	let (html, body) = render_digest(&entries, &skipped, "");
+	assert!(body.ends_with(&format!("- latest\n\n(skipped 7 older entries of {url})")));
+	assert!(html.ends_with("<i>(skipped 7 older entries of https://example.com/feed?a&amp;b)</i>"));
+}
+
+#[test]
+fn feeds_are_checked_at_their_own_interval() -> Result<()> {
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let news = RssSubscription::new(room.clone(), Url::parse("https://example.com/news")?);
+	let mut releases = RssSubscription::new(room, Url::parse("https://example.com/releases")?);
+	releases.interval = Some(3600);
+	let mut state = IntervalState::default();
+	let start = Instant::now();
+	assert!(state.is_due(&news, start, 600, 3));
+	assert!(state.is_due(&releases, start, 600, 3));
+	state.checked(&news, start);
+	state.checked(&releases, start);
+
+	let tick = start + std::time::Duration::from_secs(600);
+	assert!(state.is_due(&news, tick, 600, 3));
+	assert!(!state.is_due(&releases, tick, 600, 3));
+	let hour = start + std::time::Duration::from_secs(3600);
+	assert!(state.is_due(&releases, hour, 600, 3));
+	// The global interval is the shortest possible interval.
+	releases.interval = Some(60);
+	assert!(!state.is_due(&releases, start + std::time::Duration::from_secs(300), 600, 3));
+	Ok(())
+}
+
+#[test]
+fn failing_feeds_back_off() -> Result<()> {
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let mut subscription = RssSubscription::new(room, Url::parse("https://example.com/feed")?);
+	let mut state = IntervalState::default();
+	let start = Instant::now();
+	state.checked(&subscription, start);
+	let tick = start + std::time::Duration::from_secs(600);
+
+	subscription.consecutive_failures = 2;
+	assert_eq!(subscription.poll_interval(600, 3), 600);
+	assert!(state.is_due(&subscription, tick, 600, 3));
+	subscription.consecutive_failures = 3;
+	assert_eq!(subscription.poll_interval(600, 3), 1200);
+	assert!(!state.is_due(&subscription, tick, 600, 3));
+	subscription.consecutive_failures = 5;
+	assert_eq!(subscription.poll_interval(600, 3), 4800);
+	subscription.consecutive_failures = 100;
+	assert_eq!(subscription.poll_interval(600, 3), 24 * 60 * 60);
+
+	let (html, body) = render_failing(&subscription, &eyre!("HTTP status 500"), "");
+	assert!(html.contains("<a href=\"https://example.com/feed\">"));
+	assert_eq!(
+		body,
+		"Fetching https://example.com/feed failed 100 times in a row, checking it less often \
+		 until it recovers: HTTP status 500"
+	);
+	Ok(())
+}
+
+#[tokio::test]
+async fn unmodified_feeds_are_not_fetched_again() -> Result<()> {
+	let last_modified = "Wed, 05 Oct 2022 10:00:00 GMT";
+	let server = MockServer::start(move |request| {
+		if request.header("If-None-Match") == Some("\"v1\"") {
+			MockResponse::new(304, "")
+		} else {
+			MockResponse::new(200, FEED)
+				.header("ETag", "\"v1\"")
+				.header("Last-Modified", last_modified)
+		}
+	})
+	.await?;
+	let fetch = FetchSettings { allow_private_hosts: true, ..FetchSettings::default() };
+	let limiter = Arc::new(FetchLimiter::from_settings(&fetch));
+	let http_client = GuardedClient::new("test-agent", &fetch)?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let mut subscription = RssSubscription::new(room, server.url().clone());
+
+	let urls = vec![(
+		subscription.url.clone(),
+		ParseOptions::default(),
+		None,
+		CacheValidators::from(&subscription),
+	)];
+	let (result, _) = fetch_feeds(urls, &http_client, &limiter, RetryPolicy::default())
+		.await
+		.pop()
+		.ok_or_else(|| eyre!("Feed was not fetched"))?;
+	let (feed, validators) = result?.ok_or_else(|| eyre!("Feed was reported as not modified"))?;
+	assert_eq!(feed.entries.len(), 3);
+	assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+	assert_eq!(validators.last_modified.as_deref(), Some(last_modified));
+	subscription.etag = validators.etag;
+	subscription.last_modified = validators.last_modified;
+
+	// Nothing is parsed, so there are no entries to send.
+	let urls = vec![(
+		subscription.url.clone(),
+		ParseOptions::default(),
+		None,
+		CacheValidators::from(&subscription),
+	)];
+	let (result, _) = fetch_feeds(urls, &http_client, &limiter, RetryPolicy::default())
+		.await
+		.pop()
+		.ok_or_else(|| eyre!("Feed was not fetched"))?;
+	assert!(result?.is_none());
+	let requests = server.requests();
+	assert_eq!(requests.len(), 2);
+	assert_eq!(requests[0].header("If-None-Match"), None);
+	assert_eq!(requests[1].header("If-Modified-Since"), Some(last_modified));
+
+	// Deferred entries require checking the unchanged feed again.
+	subscription.min_entry_age = 60;
+	assert_eq!(CacheValidators::from(&subscription), CacheValidators::default());
+	Ok(())
+}
@@ -0,0 +1,82 @@
+//! Scheduling of the periodic tasks in order of their due time, so that a slow
+//! task cannot starve the others.
+
+use std::{
+	cmp::Reverse,
+	collections::BinaryHeap,
+	time::{Duration, Instant},
+};
+
+/// Periodic task run by the intervals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Task {
+	/// Fetch the RSS feeds.
+	Rss,
+	/// Fetch the Github notifications.
+	Github,
+}
+
+/// A task scheduled to run at a point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Scheduled {
+	/// Point in time the task is due.
+	pub due: Instant,
+	/// Order in which the task was scheduled, so that tasks due at the same
+	/// time run in turn.
+	sequence: u64,
+	/// The task to run.
+	pub task: Task,
+	/// Time between runs of the task.
+	period: Duration,
+}
+
+/// Queue of periodic tasks, which are run in order of their due time. Missed
+/// runs of a slow task are skipped, while other tasks that became due in the
+/// meantime run before it again.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+	/// Tasks by earliest due time first.
+	queue: BinaryHeap<Reverse<Scheduled>>,
+	/// Sequence number of the next scheduled task.
+	sequence: u64,
+}
+
+impl Scheduler {
+	/// Add a task running every period, first at the given time.
+	pub fn add(&mut self, task: Task, period: Duration, first: Instant) {
+		self.push(task, period, first);
+	}
+
+	/// Take the task that is due next. It has to be handed back via
+	/// [`Scheduler::reschedule`] after running it.
+	pub fn pop(&mut self) -> Option<Scheduled> {
+		self.queue.pop().map(|Reverse(scheduled)| scheduled)
+	}
+
+	/// Schedule the next run of the task that finished at the given time. Runs
+	/// missed while it was running are skipped.
+	pub fn reschedule(&mut self, scheduled: Scheduled, now: Instant) {
+		let due = next_due(scheduled.due, scheduled.period, now);
+		self.push(scheduled.task, scheduled.period, due);
+	}
+
+	/// Add the task to the queue.
+	fn push(&mut self, task: Task, period: Duration, due: Instant) {
+		self.queue.push(Reverse(Scheduled { due, sequence: self.sequence, task, period }));
+		self.sequence += 1;
+	}
+}
+
+/// Next due time of a task after the given due time, skipping the runs that
+/// were missed by `now`.
+fn next_due(due: Instant, period: Duration, now: Instant) -> Instant {
+	let next = due + period;
+	if next > now || period.is_zero() {
+		return next;
+	}
+	let missed = (now - next).as_nanos() / period.as_nanos() + 1;
+	next + period * u32::try_from(missed).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests;
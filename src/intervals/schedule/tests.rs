@@ -0,0 +1,74 @@
+//! Tests for the task scheduler.
+
+use color_eyre::{eyre::eyre, Result};
+
+use super::*;
+
+#[test]
+fn missed_runs_are_skipped() {
+	let start = Instant::now();
+	let period = Duration::from_secs(10);
+
+	assert_eq!(next_due(start, period, start), start + period);
+	assert_eq!(
+		next_due(start, period, start + Duration::from_secs(25)),
+		start + Duration::from_secs(30)
+	);
+	assert_eq!(
+		next_due(start, period, start + Duration::from_secs(30)),
+		start + Duration::from_secs(40)
+	);
+}
+
+#[test]
+fn slow_task_does_not_starve_others() -> Result<()> {
+	let start = Instant::now();
+	let period = Duration::from_secs(10);
+	let mut scheduler = Scheduler::default();
+	scheduler.add(Task::Rss, period, start);
+	scheduler.add(Task::Github, period, start);
+
+	// Every RSS run takes longer than the period.
+	let mut now = start;
+	let mut order = Vec::new();
+	for _ in 0..6 {
+		let scheduled = scheduler.pop().ok_or_else(|| eyre!("Scheduler is empty"))?;
+		now = now.max(scheduled.due);
+		if scheduled.task == Task::Rss {
+			now += Duration::from_secs(25);
+		}
+		order.push((scheduled.task, scheduled.due - start));
+		scheduler.reschedule(scheduled, now);
+	}
+
+	assert_eq!(
+		order,
+		[
+			(Task::Rss, Duration::ZERO),
+			(Task::Github, Duration::ZERO),
+			(Task::Rss, Duration::from_secs(30)),
+			(Task::Github, Duration::from_secs(30)),
+			(Task::Rss, Duration::from_secs(60)),
+			(Task::Github, Duration::from_secs(60)),
+		]
+	);
+	Ok(())
+}
+
+#[test]
+fn tasks_run_in_due_order() -> Result<()> {
+	let start = Instant::now();
+	let mut scheduler = Scheduler::default();
+	scheduler.add(Task::Rss, Duration::from_secs(30), start);
+	scheduler.add(Task::Github, Duration::from_secs(10), start);
+
+	let mut order = Vec::new();
+	for _ in 0..5 {
+		let scheduled = scheduler.pop().ok_or_else(|| eyre!("Scheduler is empty"))?;
+		order.push(scheduled.task);
+		let due = scheduled.due;
+		scheduler.reschedule(scheduled, due);
+	}
+	assert_eq!(order, [Task::Rss, Task::Github, Task::Github, Task::Github, Task::Rss]);
+	Ok(())
+}
@@ -0,0 +1,51 @@
+//! Tests for the interval runner.
+
+use super::*;
+
+#[test]
+fn quick_failures_increase_restart_delay() {
+	let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+	let delays =
+		(0..4).map(|_| restart_delay(&mut backoff, Duration::from_millis(10))).collect::<Vec<_>>();
+	for (attempt, delay) in delays.iter().enumerate() {
+		let full = Duration::from_secs(1 << attempt);
+		assert!(*delay >= full / 2 && *delay <= full, "attempt {attempt}: {delay:?}");
+	}
+	assert!(delays[3] > delays[0]);
+
+	let delay = restart_delay(&mut backoff, Duration::from_secs(120));
+	assert!(delay <= Duration::from_secs(1), "backoff was not reset: {delay:?}");
+}
+
+#[test]
+fn failing_feed_sends_open_the_breaker() -> Result<()> {
+	let config = Settings::read_from("config.sample.yaml")?;
+	let state = State {
+		rss: rss::IntervalState::default(),
+		github: github::IntervalState::default(),
+		breaker: CircuitBreaker::new(
+			BREAKER_THRESHOLD,
+			Backoff::new(Duration::from_secs(60), Duration::from_secs(60)),
+		),
+		limiter: Arc::new(FetchLimiter::from_settings(&config.fetch)),
+		sends: SendGuard::new(Duration::from_secs(60)),
+	};
+	let urls: Vec<url::Url> =
+		vec!["https://example.com/a".parse()?, "https://example.com/b".parse()?];
+
+	for _ in 0..BREAKER_THRESHOLD {
+		assert!(state.breaker.allow());
+		let mut errors = rss::FeedErrors::default();
+		errors.record(&urls[0], color_eyre::eyre::eyre!("Feed could not be parsed"));
+		for url in &urls {
+			errors.record(url, Report::new(matrix_sdk::Error::AuthenticationRequired));
+		}
+		assert!(state.record(errors.into_result()).is_err());
+	}
+	assert!(!state.breaker.allow());
+
+	let mut errors = rss::FeedErrors::default();
+	errors.record(&urls[0], color_eyre::eyre::eyre!("Feed could not be parsed"));
+	assert!(errors.into_result().is_ok());
+	Ok(())
+}
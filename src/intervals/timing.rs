@@ -0,0 +1,65 @@
+//! Timing of the processing of single subscriptions, to find slow feeds and
+//! tokens.
+
+use std::time::{Duration, Instant};
+
+/// Measures the processing time of a subscription. The duration is logged when
+/// the timer is dropped, with a warning if it exceeds the threshold.
+#[derive(Debug)]
+pub struct SubscriptionTimer {
+	/// Description of the subscription, e.g. the feed URL.
+	subscription: String,
+	/// Point in time the timer was started.
+	started: Instant,
+	/// Time spent on the subscription before the timer was started, e.g. for
+	/// concurrent fetches.
+	spent: Duration,
+	/// Processing time above which a warning is logged.
+	threshold: Duration,
+}
+
+impl SubscriptionTimer {
+	/// Start timing the processing of the subscription.
+	pub fn start(subscription: impl Into<String>, threshold: Duration) -> Self {
+		Self {
+			subscription: subscription.into(),
+			started: Instant::now(),
+			spent: Duration::ZERO,
+			threshold,
+		}
+	}
+
+	/// Add time spent on the subscription outside of the timer.
+	pub fn add(&mut self, spent: Duration) {
+		self.spent += spent;
+	}
+
+	/// Total processing time so far.
+	pub fn elapsed(&self) -> Duration {
+		self.spent + self.started.elapsed()
+	}
+
+	/// Whether the processing time exceeds the threshold.
+	pub fn is_slow(&self) -> bool {
+		self.elapsed() > self.threshold
+	}
+}
+
+impl Drop for SubscriptionTimer {
+	fn drop(&mut self) {
+		let elapsed = self.elapsed();
+		if elapsed > self.threshold {
+			tracing::warn!(
+				"Processing {} took {}ms, which exceeds {}ms",
+				self.subscription,
+				elapsed.as_millis(),
+				self.threshold.as_millis()
+			);
+		} else {
+			tracing::debug!("Processing {} took {}ms", self.subscription, elapsed.as_millis());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests;
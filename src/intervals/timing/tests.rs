@@ -0,0 +1,18 @@
+//! Tests for the subscription timer.
+
+use std::time::Duration;
+
+use super::*;
+
+#[tokio::test]
+async fn timer_covers_processing() {
+	let mut timer = SubscriptionTimer::start("feed", Duration::from_millis(500));
+	timer.add(Duration::from_millis(100));
+	tokio::time::sleep(Duration::from_millis(50)).await;
+
+	assert!(timer.elapsed() >= Duration::from_millis(150));
+	assert!(!timer.is_slow());
+
+	timer.add(Duration::from_millis(400));
+	assert!(timer.is_slow());
+}
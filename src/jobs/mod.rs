@@ -3,8 +3,98 @@
 
 pub mod remind;
 
-use bonsaimq::job_registry;
+use std::{
+	any::type_name,
+	fmt::{self, Display},
+	future::Future,
+	sync::Arc,
+	time::Duration,
+};
+
+use bonsaimq::{job_registry, CurrentJob, JobRunner};
+use color_eyre::Result;
+use matrix_sdk::Client;
+use tokio::time::Instant;
+
+use crate::{backoff::Backoff, database::Databases, settings::Settings};
 
 job_registry!(JobRegistry, {
 	Remind: "remind" => remind::job_remind,
 });
+
+/// Run the job runner, restarting it with backoff when it stops.
+pub async fn run(config: Arc<Settings>, databases: Databases, client: Client) {
+	let start = || {
+		let mut handle = JobRunner::new(databases.jobs.clone())
+			.set_context(config.clone())
+			.set_context(databases.clone())
+			.set_context(client.clone())
+			.run::<JobRegistry>();
+		async move {
+			(&mut *handle).await??;
+			Ok(())
+		}
+	};
+	let backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(5 * 60));
+	run_restarting(start, backoff).await;
+}
+
+/// Error for context that is expected in the job runner, but missing. Jobs
+/// should be retried instead of cancelled in this case, as the context might
+/// only be missing temporarily.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingContext {
+	/// Name of the missing context type.
+	pub type_name: &'static str,
+}
+
+impl Display for MissingContext {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Expected `{}` in the job context, but it is missing", self.type_name)
+	}
+}
+
+impl std::error::Error for MissingContext {}
+
+/// Get the context of the specified type from the job. Logs an error if it is
+/// missing.
+pub fn context<C: Clone + Send + Sync + 'static>(job: &CurrentJob) -> Result<C, MissingContext> {
+	require_context(job.context())
+}
+
+/// Require the retrieved context of the specified type to be present. Logs an
+/// error if it is missing.
+fn require_context<C>(context: Option<C>) -> Result<C, MissingContext> {
+	context.ok_or_else(|| {
+		let error = MissingContext { type_name: type_name::<C>() };
+		tracing::error!("{error}");
+		error
+	})
+}
+
+/// Run the task created by `start` forever, restarting it with backoff
+/// whenever it stops. The backoff is reset when the task ran longer than the
+/// maximum backoff delay.
+async fn run_restarting<F, Fut>(mut start: F, mut backoff: Backoff)
+where
+	F: FnMut() -> Fut,
+	Fut: Future<Output = Result<()>>,
+{
+	loop {
+		let started = Instant::now();
+		match start().await {
+			Ok(()) => tracing::error!("Job runner stopped unexpectedly"),
+			Err(err) => tracing::error!("Job runner failed: {err}"),
+		}
+
+		if started.elapsed() > backoff.maximum() {
+			backoff.reset();
+		}
+		let delay = backoff.next_delay();
+		tracing::info!("Restarting job runner in {delay:?}..");
+		tokio::time::sleep(delay).await;
+	}
+}
+
+#[cfg(test)]
+mod tests;
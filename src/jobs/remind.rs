@@ -1,12 +1,24 @@
 //! Remind job.
 
+use std::sync::Arc;
+
+use bonsaidb::core::schema::SerializedCollection;
 use bonsaimq::CurrentJob;
 use color_eyre::{eyre::eyre, Result};
 use matrix_sdk::{
-	ruma::{events::room::message::RoomMessageEventContent, OwnedRoomId, OwnedUserId},
+	ruma::{OwnedRoomId, OwnedUserId},
 	Client,
 };
 use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+	database::{Databases, Reminder},
+	jobs::{context, MissingContext},
+	matrix,
+	settings::{MessageKind, Settings},
+	sink::NotificationSink,
+};
 
 /// The job's input.
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,43 +29,129 @@ pub struct RemindInput {
 	pub room_id: OwnedRoomId,
 	/// Reminder message,
 	pub message: String,
+	/// ID of the persisted reminder record.
+	#[serde(default)]
+	pub reminder: Option<u64>,
+	/// Generation of the reminder record the job was scheduled for.
+	#[serde(default)]
+	pub generation: u64,
+	/// Point in time the reminder is scheduled to fire.
+	#[serde(default)]
+	pub fire_at: Option<OffsetDateTime>,
 }
 
-/// Job to remind people of something, outer job error handler.
+/// Reminders delivered later than this after their fire time get a note
+/// about the delay.
+const DELAY_TOLERANCE: Duration = Duration::minutes(1);
+
+/// Job ID of the job for the reminder record in the given generation.
+pub fn job_id(reminder: u64, generation: u64) -> u128 {
+	(u128::from(reminder) << 64) | u128::from(generation)
+}
+
+/// Job to remind people of something, outer job error handler. Missing job
+/// context leaves the job to be retried, other errors cancel it.
 #[tracing::instrument(level = "debug", skip_all)]
 pub async fn job_remind(mut job: CurrentJob) -> Result<()> {
 	if let Err(err) = remind(&mut job).await {
+		if err.downcast_ref::<MissingContext>().is_some() {
+			tracing::error!("Retrying reminder job later: {err}");
+			return Err(err);
+		}
 		tracing::error!("Cancelling reminder job: {err}");
+		remove_record(&job).await?;
 		job.complete().await?;
 		return Err(err);
 	}
 	Ok(())
 }
 
+/// Remove the persisted reminder record of the job, if it exists.
+async fn remove_record(job: &CurrentJob) -> Result<()> {
+	let input: RemindInput = job.payload_json().ok_or_else(|| eyre!("Expected job input"))??;
+	if let Some(reminder_id) = input.reminder {
+		let db: Databases = context(job)?;
+		if let Some(reminder) = Reminder::get_async(reminder_id, &db.state).await? {
+			reminder.delete_async(&db.state).await?;
+		}
+	}
+	Ok(())
+}
+
 /// Remind someone of something, inner job.
 async fn remind(job: &mut CurrentJob) -> Result<()> {
-	let client: Client = job.context().ok_or_else(|| eyre!("Expected matrix client in context"))?;
+	let client: Client = context(job)?;
+	let db: Databases = context(job)?;
 	let input: RemindInput = job.payload_json().ok_or_else(|| eyre!("Expected job input"))??;
 
 	tracing::trace!("Sending reminder..",);
 
-	let room =
-		client.get_joined_room(&input.room_id).ok_or_else(|| eyre!("Room not in joined rooms"))?;
+	// The room of the record is up to date after room upgrades.
+	let record = match input.reminder {
+		Some(reminder_id) => Reminder::get_async(reminder_id, &db.state).await?,
+		None => None,
+	};
+	if is_outdated(&input, record.as_ref().map(|record| &record.contents)) {
+		tracing::trace!("Reminder was edited or removed, skipping outdated job.");
+		job.complete().await?;
+		return Ok(());
+	}
+	let room_id = record.map_or_else(|| input.room_id.clone(), |record| record.contents.room);
+	let room = client.get_joined_room(&room_id).ok_or_else(|| eyre!("Room not in joined rooms"))?;
 	let who_name = room
 		.get_member_no_sync(&input.who)
 		.await?
 		.and_then(|who| who.display_name().map(ToOwned::to_owned))
 		.unwrap_or_else(|| input.who.localpart().to_owned());
 
-	let message = RoomMessageEventContent::text_html(
-		format!("@{}: {}", who_name, input.message),
-		format!(
-			"<a href=\"https://matrix.to/#/{}\">@{}</a>: {}",
-			input.who, who_name, input.message
-		),
-	);
-	room.send(message, None).await?;
+	let config: Arc<Settings> = context(job)?;
+	let (html, body) =
+		reminder_message(&input, &who_name, &config.prefixes.reminders, OffsetDateTime::now_utc());
+	let sink: &dyn NotificationSink = &client;
+	sink.send(&room_id, body, html, MessageKind::Text).await?;
 
+	remove_record(job).await?;
 	job.complete().await?;
 	Ok(())
 }
+
+/// Whether the job is outdated, because its reminder record was edited since
+/// or is gone, e.g. because an earlier job fired it already. Jobs without a
+/// record are never outdated.
+fn is_outdated(input: &RemindInput, record: Option<&Reminder>) -> bool {
+	match (input.reminder, record) {
+		(None, _) => false,
+		(Some(_), None) => true,
+		(Some(_), Some(record)) => record.generation != input.generation,
+	}
+}
+
+/// Create the reminder message for the input as HTML and plain body,
+/// mentioning the person by name. Notes the delay if it is delivered late at
+/// `now`, e.g. after downtime.
+fn reminder_message(
+	input: &RemindInput,
+	who_name: &str,
+	prefix: &str,
+	now: OffsetDateTime,
+) -> (String, String) {
+	let mut text = input.message.clone();
+	if input.fire_at.map_or(false, |fire_at| now - fire_at > DELAY_TOLERANCE) {
+		text.push_str(" (this reminder was delayed due to downtime)");
+	}
+
+	let body = matrix::prefixed(prefix, &format!("@{who_name}: {text}"));
+	let html = matrix::prefixed(
+		prefix,
+		&format!(
+			"<a href=\"https://matrix.to/#/{}\">@{}</a>: {}",
+			input.who,
+			matrix::escape_html(who_name),
+			matrix::markdown_to_html(&text)
+		),
+	);
+	(html, body)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,80 @@
+//! Tests for the remind job.
+
+use color_eyre::Result;
+
+use super::*;
+
+/// Create a job input for a reminder firing at the given time.
+fn input(fire_at: Option<OffsetDateTime>) -> Result<RemindInput> {
+	Ok(RemindInput {
+		who: "@user:example.com".try_into()?,
+		room_id: "!room:example.com".try_into()?,
+		message: "Tea is ready".to_owned(),
+		reminder: Some(1),
+		generation: 0,
+		fire_at,
+	})
+}
+
+#[test]
+fn punctual_reminder_without_note() -> Result<()> {
+	let now = OffsetDateTime::now_utc();
+	let (_, body) = reminder_message(&input(Some(now - Duration::seconds(5)))?, "User", "", now);
+	assert_eq!(body, "@User: Tea is ready");
+
+	let (_, body) = reminder_message(&input(None)?, "User", "", now);
+	assert_eq!(body, "@User: Tea is ready");
+	Ok(())
+}
+
+#[test]
+fn missed_reminder_notes_delay() -> Result<()> {
+	let now = OffsetDateTime::now_utc();
+	let (_, body) = reminder_message(&input(Some(now - Duration::hours(2)))?, "User", "", now);
+	assert_eq!(body, "@User: Tea is ready (this reminder was delayed due to downtime)");
+	Ok(())
+}
+
+#[test]
+fn markdown_reminder_is_rendered_escaped() -> Result<()> {
+	let now = OffsetDateTime::now_utc();
+	let mut input = input(None)?;
+	input.message = String::from("**Tea** is <ready> & hot");
+
+	let (html, body) = reminder_message(&input, "User <3", "", now);
+	assert_eq!(body, "@User <3: **Tea** is <ready> & hot");
+	assert_eq!(
+		html,
+		"<a href=\"https://matrix.to/#/@user:example.com\">@User &lt;3</a>: <strong>Tea</strong> \
+		 is &lt;ready&gt; &amp; hot"
+	);
+	Ok(())
+}
+
+#[test]
+fn edited_and_fired_reminders_outdate_jobs() -> Result<()> {
+	let first_job = input(None)?;
+	let mut record = Reminder {
+		room: first_job.room_id.clone(),
+		who: first_job.who.clone(),
+		message: first_job.message.clone(),
+		fire_at: OffsetDateTime::now_utc(),
+		generation: 0,
+	};
+	assert!(!is_outdated(&first_job, Some(&record)));
+
+	record.generation += 1;
+	let mut edited_job = input(None)?;
+	edited_job.generation = record.generation;
+	assert!(is_outdated(&first_job, Some(&record)));
+	assert!(!is_outdated(&edited_job, Some(&record)));
+
+	// The edited job fired and removed the record.
+	assert!(is_outdated(&edited_job, None));
+	assert!(is_outdated(&first_job, None));
+
+	let mut unrecorded_job = input(None)?;
+	unrecorded_job.reminder = None;
+	assert!(!is_outdated(&unrecorded_job, None));
+	Ok(())
+}
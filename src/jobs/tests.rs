@@ -0,0 +1,49 @@
+//! Tests for the job runner setup.
+
+use std::sync::{
+	atomic::{AtomicUsize, Ordering},
+	Arc,
+};
+
+use color_eyre::{eyre::eyre, Result};
+
+use super::*;
+
+#[tokio::test]
+async fn job_runner_is_restarted_after_failures() {
+	let starts = Arc::new(AtomicUsize::new(0));
+	let start = || {
+		let starts = starts.clone();
+		async move {
+			if starts.fetch_add(1, Ordering::SeqCst) < 3 {
+				Err(eyre!("database error"))
+			} else {
+				std::future::pending::<Result<()>>().await
+			}
+		}
+	};
+	let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(10));
+
+	let result =
+		tokio::time::timeout(Duration::from_millis(500), run_restarting(start, backoff)).await;
+	assert!(result.is_err(), "runner loop must not return");
+	assert_eq!(starts.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn present_context_is_returned() {
+	assert_eq!(require_context(Some(5_u32)), Ok(5));
+}
+
+#[test]
+fn missing_context_is_reported() -> Result<()> {
+	let error = require_context::<Arc<Settings>>(None)
+		.err()
+		.ok_or_else(|| eyre!("Missing context must be an error"))?;
+	assert!(error.type_name.contains("Settings"));
+	assert!(error.to_string().contains("missing"));
+
+	let report = color_eyre::Report::new(error);
+	assert!(report.downcast_ref::<MissingContext>().is_some());
+	Ok(())
+}
@@ -1,33 +1,61 @@
 //! Crate library.
 
+mod backoff;
+mod bootstrap;
+mod circuit_breaker;
 mod clients;
 mod commands;
 mod database;
 mod events;
+mod fetch_limiter;
 mod intervals;
 mod jobs;
+mod maintenance;
 mod matrix;
+mod secrets;
+mod send_guard;
 pub mod settings;
+mod sink;
+#[cfg(test)]
+mod test_utils;
+mod timestamps;
+mod validation;
 
-use std::{sync::Arc, time::Duration};
+use std::{
+	sync::{Arc, Mutex, PoisonError},
+	time::{Duration, Instant},
+};
 
-use bonsaimq::JobRunner;
 use color_eyre::Result;
 use matrix_sdk::{
 	config::{RequestConfig, SyncSettings},
-	Client,
+	ruma::{
+		api::client::{filter::FilterDefinition, sync::sync_events::v3::Filter},
+		events::room::message::RoomMessageEventContent,
+		OwnedRoomId, UInt,
+	},
+	Client, LoopCtrl,
 };
 use tokio::sync::watch;
 
 use crate::{
-	database::{open_databases, Databases},
-	jobs::JobRegistry,
+	database::{open_databases, Databases, GithubSubscription},
 	matrix::ClientExt,
 	settings::Settings,
+	timestamps::precise_duration,
 };
 
+/// How the bot's matrix session was established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LoginKind {
+	/// The previous session was restored from the state store.
+	Restored,
+	/// A new session was created by logging in with the credentials.
+	Fresh,
+}
+
 /// Log into matrix account.
-async fn login(config: &Settings) -> Result<Client> {
+async fn login(config: &Settings) -> Result<(Client, LoginKind)> {
 	tracing::debug!("Opening state store..");
 	let client = Client::builder()
 		.request_config(
@@ -39,33 +67,66 @@ async fn login(config: &Settings) -> Result<Client> {
 		.await?;
 
 	tracing::debug!("Attempting to restore login..");
-	if !client.restore_session().await? {
+	let login_kind = if client.restore_session().await? {
+		LoginKind::Restored
+	} else {
 		tracing::debug!("No session data, attempting login instead..");
 		client
 			.login_username(&config.login.user, &config.login.password)
 			.initial_device_display_name("Matrix-Bot")
 			.send()
 			.await?;
-	}
+		LoginKind::Fresh
+	};
 
-	tracing::info!("Logged in as {:?}", client.user_id());
+	tracing::info!("Logged in as {:?} ({login_kind:?})", client.user_id());
 	client.save_session().await?;
-	Ok(client)
+	Ok((client, login_kind))
+}
+
+/// Notice to the admin room about the login, if configured. Only fresh logins
+/// are reported, as they may indicate that the previous session was lost.
+fn login_notice(
+	config: &Settings,
+	login_kind: LoginKind,
+) -> Option<(&OwnedRoomId, RoomMessageEventContent)> {
+	let admin_room = config.notifications.admin_room.as_ref()?;
+	if !config.notifications.fresh_login || login_kind != LoginKind::Fresh {
+		return None;
+	}
+
+	let notice = RoomMessageEventContent::notice_plain(
+		"Logged in with a fresh session. The previous session may have been lost.",
+	);
+	Some((admin_room, notice))
+}
+
+/// Send the login notice to the admin room, if there is one.
+async fn send_login_notice(config: &Settings, client: &Client, login_kind: LoginKind) {
+	if let Some((room_id, notice)) = login_notice(config, login_kind) {
+		if let Some(room) = client.get_joined_room(room_id) {
+			if let Err(err) = room.send(notice, None).await {
+				tracing::error!("Failed sending login notice: {err}");
+			}
+		} else {
+			tracing::warn!("Admin room {room_id} is not joined, cannot send login notice.");
+		}
+	}
 }
 
 /// Join rooms that we are invited to if the user is allowed to invite us.
-async fn process_invites(config: &Settings, client: &Client) -> Result<()> {
+async fn process_invites(config: &Settings, databases: &Databases, client: &Client) -> Result<()> {
 	tracing::debug!("Checking room invites..");
 	for room in client.invited_rooms() {
 		let room_name = room.name().unwrap_or_else(|| room.room_id().to_string());
 		if let Some(inviter) = room.invite_details().await?.inviter {
 			let inviter = inviter.user_id().to_owned();
-			if config.access.admins.contains(&inviter) {
+			if config.access.is_admin(&inviter) {
 				tracing::info!("Joining room {room_name}");
-				room.accept_invitation().await?;
+				events::accept_invite(config, databases, client, &room, &inviter).await?;
 			} else {
 				tracing::info!("Rejecting invitation to {room_name} from {inviter}");
-				room.reject_invitation().await?;
+				events::reject_invite(config, client, &room).await?;
 			}
 		}
 	}
@@ -74,30 +135,169 @@ async fn process_invites(config: &Settings, client: &Client) -> Result<()> {
 
 /// Run the matrix setup and sync event loop.
 #[tracing::instrument(level = "debug", skip_all, err)]
-async fn matrix_run(config: Arc<Settings>, databases: Databases, client: Client) -> Result<()> {
+async fn matrix_run(
+	config: Arc<Settings>,
+	databases: Databases,
+	client: Client,
+	login_kind: LoginKind,
+) -> Result<()> {
 	tracing::debug!("Initial sync..");
 	client.sync_once(SyncSettings::default()).await?;
+	send_login_notice(&config, &client, login_kind).await;
+	tokio::spawn(validation::run_on_startup(config.clone(), databases.clone(), client.clone()));
 
 	client.leave_empty_rooms().await?;
-	process_invites(&config, &client).await?;
+	process_invites(&config, &databases, &client).await?;
 
-	client.add_event_handler_context(config);
-	client.add_event_handler_context(databases);
+	client.add_event_handler_context(config.clone());
+	client.add_event_handler_context(databases.clone());
 	client.add_event_handler(events::on_invite_event);
 	client.add_event_handler(events::on_room_membership_event);
 	client.add_event_handler(events::on_room_message);
+	client.add_event_handler(events::on_room_tombstone);
+	client.add_event_handler(events::on_reaction);
 
 	tracing::info!("Running continuous sync..");
-	let sync_settings = client
-		.sync_token()
-		.await
+	let db_token = database::get_sync_token(&databases.state).await?;
+	let sync_settings = select_sync_token(client.sync_token().await, db_token)
 		.map(|sync_token| SyncSettings::default().token(sync_token))
 		.unwrap_or_default();
-	client.sync(sync_settings).await?;
+	let gaps = SyncGapDetector::new(Duration::from_secs(config.sync_gap), Instant::now());
+	client
+		.sync_with_callback(sync_settings, |response| {
+			let gap = gaps.record(Instant::now());
+			let config = config.clone();
+			let databases = databases.clone();
+			let client = client.clone();
+			async move {
+				if let Err(err) =
+					database::set_sync_token(&databases.state, &response.next_batch).await
+				{
+					tracing::error!("Failed persisting sync token: {err}");
+				}
+				if let Some(gap) = gap {
+					if let Err(err) = reconcile_after_gap(&config, &databases, &client, gap).await {
+						tracing::error!("Failed reconciling state after sync gap: {err}");
+					}
+				}
+				LoopCtrl::Continue
+			}
+		})
+		.await?;
+
+	Ok(())
+}
+
+/// Detects gaps in the continuous sync, e.g. after a long disconnect, by the
+/// time between successful syncs.
+#[derive(Debug)]
+struct SyncGapDetector {
+	/// Time between syncs that counts as gap.
+	threshold: Duration,
+	/// Point in time of the last successful sync.
+	last_sync: Mutex<Instant>,
+}
+
+impl SyncGapDetector {
+	/// Create a new detector, starting at the given time.
+	fn new(threshold: Duration, now: Instant) -> Self {
+		Self { threshold, last_sync: Mutex::new(now) }
+	}
+
+	/// Record a successful sync at the given time. Returns the time since the
+	/// previous sync if it exceeds the threshold.
+	fn record(&self, now: Instant) -> Option<Duration> {
+		let mut last_sync = self.last_sync.lock().unwrap_or_else(PoisonError::into_inner);
+		let gap = now.saturating_duration_since(*last_sync);
+		*last_sync = now;
+		(gap > self.threshold).then_some(gap)
+	}
+}
 
+/// Re-run the room housekeeping after a gap in the sync, so that invites that
+/// arrived in the meantime are handled promptly. Notifies the admin room about
+/// the gap if configured.
+async fn reconcile_after_gap(
+	config: &Settings,
+	databases: &Databases,
+	client: &Client,
+	gap: Duration,
+) -> Result<()> {
+	tracing::info!("Sync resumed after a gap of {gap:?}, reconciling state..");
+	client.leave_empty_rooms().await?;
+	process_invites(config, databases, client).await?;
+
+	if let Some((room_id, notice)) = gap_notice(config, gap) {
+		match client.get_joined_room(room_id) {
+			Some(room) => {
+				room.send(notice, None).await?;
+			}
+			None => tracing::warn!("Admin room {room_id} is not joined, cannot send gap notice."),
+		}
+	}
+	Ok(())
+}
+
+/// Notice to the admin room about a gap in the sync, if configured.
+fn gap_notice(config: &Settings, gap: Duration) -> Option<(&OwnedRoomId, RoomMessageEventContent)> {
+	let admin_room = config.notifications.admin_room.as_ref()?;
+	if !config.notifications.sync_gap {
+		return None;
+	}
+
+	let gap = time::Duration::try_from(gap).unwrap_or(time::Duration::MAX);
+	let notice = RoomMessageEventContent::notice_plain(format!(
+		"Sync resumed after a gap of {}. Invites and empty rooms were processed again.",
+		precise_duration(gap)
+	));
+	Some((admin_room, notice))
+}
+
+/// Force a full re-sync of the room state and re-run the room housekeeping,
+/// to recover from drifted local state. The sync does not include timeline
+/// events, so that the continuous sync can keep running without messages
+/// (and commands) being handled twice.
+async fn resync(config: &Settings, databases: &Databases, client: &Client) -> Result<()> {
+	tracing::info!("Running full re-sync..");
+	let mut sync_settings =
+		SyncSettings::default().full_state(true).filter(Filter::FilterDefinition(resync_filter()));
+	if let Some(token) = client.sync_token().await {
+		sync_settings = sync_settings.token(token);
+	}
+	client.sync_once(sync_settings).await?;
+
+	client.leave_empty_rooms().await?;
+	process_invites(config, databases, client).await?;
 	Ok(())
 }
 
+/// Sync filter for the full re-sync, which excludes all timeline events.
+fn resync_filter() -> FilterDefinition<'static> {
+	let mut filter = FilterDefinition::default();
+	filter.room.timeline.limit = Some(UInt::MIN);
+	filter
+}
+
+/// Select the sync token to continue syncing from. Prefers the token of the
+/// matrix state store, but falls back to the token persisted in the bot's
+/// database.
+fn select_sync_token(store_token: Option<String>, db_token: Option<String>) -> Option<String> {
+	match (store_token, db_token) {
+		(Some(token), _) => {
+			tracing::debug!("Using sync token from the state store.");
+			Some(token)
+		}
+		(None, Some(token)) => {
+			tracing::info!("No sync token in the state store, using sync token from the database.");
+			Some(token)
+		}
+		(None, None) => {
+			tracing::debug!("No sync token available.");
+			None
+		}
+	}
+}
+
 /// Run the bot.
 pub async fn run(config: Arc<Settings>) -> Result<()> {
 	let (shutdown_sender, mut shutdown) = watch::channel(false);
@@ -106,14 +306,18 @@ pub async fn run(config: Arc<Settings>) -> Result<()> {
 	})?;
 
 	let databases = open_databases(&config).await?;
-	let client = login(&config).await?;
-
-	let sync_handle = tokio::spawn(matrix_run(config.clone(), databases.clone(), client.clone()));
-	let _job_runner_handle = JobRunner::new(databases.jobs.clone())
-		.set_context(config.clone())
-		.set_context(databases.clone())
-		.set_context(client.clone())
-		.run::<JobRegistry>();
+	if let Some(backup) = &config.store.restore_from {
+		maintenance::restore(&databases, backup).await?;
+	}
+	let key = config.store.secret_key();
+	GithubSubscription::seal_all_legacy_tokens(&key, &databases.state).await?;
+	bootstrap::seed_subscriptions(&config.bootstrap, &key, &databases).await?;
+	let (client, login_kind) = login(&config).await?;
+
+	let sync_handle =
+		tokio::spawn(matrix_run(config.clone(), databases.clone(), client.clone(), login_kind));
+	let jobs_handle = tokio::spawn(jobs::run(config.clone(), databases.clone(), client.clone()));
+	let maintenance_handle = tokio::spawn(maintenance::run(config.clone(), databases.clone()));
 	let intervals_handle = tokio::spawn(intervals::run(config, databases, client.clone()));
 
 	let termination_waiter = tokio::spawn(async move {
@@ -125,9 +329,14 @@ pub async fn run(config: Arc<Settings>) -> Result<()> {
 		res = termination_waiter => { res?; },
 		res = sync_handle => res??,
 		res = intervals_handle => res?,
+		res = jobs_handle => res?,
+		res = maintenance_handle => res?,
 	};
 
 	tracing::info!("Stopping the client..");
 	client.save_session().await?;
 	Ok(())
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,208 @@
+//! Database maintenance, i.e. periodic compaction, and backups.
+
+use std::{
+	fs::File,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	sync::Arc,
+	time::Duration,
+};
+
+use bonsaidb::core::connection::AsyncConnection;
+use color_eyre::Result;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use time::OffsetDateTime;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+use crate::{
+	database::{AuditEntry, Databases},
+	settings::Settings,
+};
+
+/// Duration between automatic maintenance runs.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Number of days audit log entries are kept.
+const AUDIT_RETENTION_DAYS: i64 = 90;
+
+/// Result of a database compaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+	/// Size of the databases on disk before compaction in bytes.
+	pub size_before: u64,
+	/// Size of the databases on disk after compaction in bytes.
+	pub size_after: u64,
+}
+
+impl CompactionReport {
+	/// Number of bytes reclaimed by the compaction.
+	pub fn reclaimed(&self) -> u64 {
+		self.size_before.saturating_sub(self.size_after)
+	}
+}
+
+/// Run the maintenance daily, logging errors.
+pub async fn run(config: Arc<Settings>, databases: Databases) {
+	let mut interval = interval_at(Instant::now() + MAINTENANCE_INTERVAL, MAINTENANCE_INTERVAL);
+	interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+	loop {
+		interval.tick().await;
+		if let Err(err) = prune_audit_log(&databases).await {
+			tracing::error!("Error pruning the audit log: {err}");
+		}
+		if let Err(err) = compact(&config, &databases).await {
+			tracing::error!("Error compacting the databases: {err}");
+		}
+	}
+}
+
+/// Delete audit log entries older than the retention period.
+async fn prune_audit_log(databases: &Databases) -> Result<()> {
+	let cutoff = OffsetDateTime::now_utc() - time::Duration::days(AUDIT_RETENTION_DAYS);
+	let pruned = AuditEntry::prune_before(cutoff, &databases.state).await?;
+	tracing::debug!("Pruned {pruned} audit log entries.");
+	Ok(())
+}
+
+/// Compact the databases of the bot, logging the reclaimed space.
+pub async fn compact(config: &Settings, databases: &Databases) -> Result<CompactionReport> {
+	let paths = [config.store.database.clone(), config.store.job_runner_db.clone()];
+	compact_databases(databases, &paths).await
+}
+
+/// Compact the databases, measuring the size of the database paths.
+async fn compact_databases(databases: &Databases, paths: &[PathBuf]) -> Result<CompactionReport> {
+	tracing::debug!("Compacting databases..");
+	let size_before = total_size(paths).await?;
+	databases.state.compact().await?;
+	databases.jobs.compact().await?;
+	let size_after = total_size(paths).await?;
+
+	let report = CompactionReport { size_before, size_after };
+	tracing::info!(
+		"Compacted databases, reclaimed {} bytes ({size_before} -> {size_after} bytes)",
+		report.reclaimed()
+	);
+	Ok(report)
+}
+
+/// Back up both databases into a new timestamped, gzip-compressed tar archive
+/// in the backup directory. Returns the path of the archive.
+pub async fn backup(databases: &Databases, backup_dir: &Path) -> Result<PathBuf> {
+	let name = format!("backup-{}", OffsetDateTime::now_utc().unix_timestamp());
+	let target = backup_dir.join(format!("{name}.tar.gz"));
+	let staging = backup_dir.join(format!(".{name}"));
+	tracing::info!("Backing up databases to {}..", target.display());
+	tokio::fs::create_dir_all(staging.join("bot")).await?;
+	tokio::fs::create_dir_all(staging.join("jobs")).await?;
+
+	let result: Result<()> = async {
+		databases.state.storage().backup(staging.join("bot")).await?;
+		databases.jobs.storage().backup(staging.join("jobs")).await?;
+		let (source, archive) = (staging.clone(), target.clone());
+		tokio::task::spawn_blocking(move || write_archive(&source, &archive)).await?
+	}
+	.await;
+	tokio::fs::remove_dir_all(&staging).await?;
+	result?;
+	Ok(target)
+}
+
+/// Restore both databases from a backup archive created by [`backup`], or from
+/// a backup directory of older versions. A marker file next to the backup
+/// records the restore, so that it only happens once, even if the backup stays
+/// configured. Returns whether the backup was restored.
+pub async fn restore(databases: &Databases, backup: &Path) -> Result<bool> {
+	let marker = restore_marker(backup);
+	match tokio::fs::metadata(&marker).await {
+		Ok(_) => {
+			tracing::warn!(
+				"Backup {} was restored already, remove the `restore_from` setting.",
+				backup.display()
+			);
+			return Ok(false);
+		}
+		Err(err) if err.kind() == ErrorKind::NotFound => {}
+		Err(err) => return Err(err.into()),
+	}
+
+	tracing::info!("Restoring databases from {}..", backup.display());
+	if tokio::fs::metadata(backup).await?.is_dir() {
+		restore_directory(databases, backup).await?;
+	} else {
+		let staging = backup.with_extension("restoring");
+		let (archive, target) = (backup.to_owned(), staging.clone());
+		tokio::task::spawn_blocking(move || unpack_archive(&archive, &target)).await??;
+		let result = restore_directory(databases, &staging).await;
+		tokio::fs::remove_dir_all(&staging).await?;
+		result?;
+	}
+
+	let restored_at = OffsetDateTime::now_utc();
+	tokio::fs::write(&marker, format!("Restored at {restored_at}\n")).await?;
+	Ok(true)
+}
+
+/// Restore both databases from the unpacked backup directory.
+async fn restore_directory(databases: &Databases, backup: &Path) -> Result<()> {
+	databases.state.storage().restore(backup.join("bot")).await?;
+	databases.jobs.storage().restore(backup.join("jobs")).await?;
+	Ok(())
+}
+
+/// Path of the marker file recording that the backup was restored.
+fn restore_marker(backup: &Path) -> PathBuf {
+	let mut marker = backup.as_os_str().to_owned();
+	marker.push(".restored");
+	PathBuf::from(marker)
+}
+
+/// Write the directory's contents into a gzip-compressed tar archive. The
+/// archive is only moved to the target path once it is complete.
+fn write_archive(source: &Path, target: &Path) -> Result<()> {
+	let partial = target.with_extension("partial");
+	let encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+	let mut archive = tar::Builder::new(encoder);
+	archive.append_dir_all(".", source)?;
+	archive.into_inner()?.finish()?.sync_all()?;
+	std::fs::rename(&partial, target)?;
+	Ok(())
+}
+
+/// Unpack the gzip-compressed tar archive into the target directory.
+fn unpack_archive(archive: &Path, target: &Path) -> Result<()> {
+	tar::Archive::new(GzDecoder::new(File::open(archive)?)).unpack(target)?;
+	Ok(())
+}
+
+/// Total size of the files in the paths in bytes.
+async fn total_size(paths: &[PathBuf]) -> Result<u64> {
+	let paths = paths.to_vec();
+	let size = tokio::task::spawn_blocking(move || {
+		paths.iter().map(|path| dir_size(path)).sum::<std::io::Result<u64>>()
+	})
+	.await??;
+	Ok(size)
+}
+
+/// Size of all files in the directory (recursively) in bytes. Non-existing
+/// paths have a size of zero.
+fn dir_size(path: &Path) -> std::io::Result<u64> {
+	let metadata = match std::fs::metadata(path) {
+		Ok(metadata) => metadata,
+		Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+		Err(err) => return Err(err),
+	};
+	if !metadata.is_dir() {
+		return Ok(metadata.len());
+	}
+
+	let mut size = 0;
+	for entry in std::fs::read_dir(path)? {
+		size += dir_size(&entry?.path())?;
+	}
+	Ok(size)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,70 @@
+//! Tests for the database maintenance.
+
+use bonsaidb::core::schema::SerializedCollection;
+use color_eyre::Result;
+use matrix_sdk::ruma::OwnedRoomId;
+
+use super::*;
+use crate::{database::RssSubscription, test_utils::test_databases};
+
+#[tokio::test]
+async fn compaction_of_populated_store() -> Result<()> {
+	let databases = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	for i in 0..20 {
+		let url = format!("https://example.com/feed/{i}").parse()?;
+		let subscription =
+			RssSubscription::new(room.clone(), url).push_into_async(&databases.state).await?;
+		if i % 2 == 0 {
+			subscription.delete_async(&databases.state).await?;
+		}
+	}
+
+	let missing = PathBuf::from("does/not/exist");
+	let report = compact_databases(&databases, &[missing]).await?;
+	assert_eq!(report, CompactionReport { size_before: 0, size_after: 0 });
+
+	let remaining = RssSubscription::for_room(&room, &databases.state).await?;
+	assert_eq!(remaining.len(), 10);
+	Ok(())
+}
+
+#[test]
+fn size_of_directories() -> Result<()> {
+	assert_eq!(dir_size(Path::new("does/not/exist"))?, 0);
+	let file_size = std::fs::metadata("Cargo.toml")?.len();
+	assert_eq!(dir_size(Path::new("Cargo.toml"))?, file_size);
+	assert!(dir_size(Path::new("src"))? > file_size);
+	Ok(())
+}
+
+#[tokio::test]
+async fn backup_is_written_and_restored_once() -> Result<()> {
+	let databases = test_databases().await?;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	RssSubscription::new(room.clone(), "https://example.com/feed".parse()?)
+		.push_into_async(&databases.state)
+		.await?;
+
+	let backup_dir = std::env::temp_dir().join(format!(
+		"matrix-bot-backup-test-{}",
+		OffsetDateTime::now_utc().unix_timestamp_nanos()
+	));
+	let path = backup(&databases, &backup_dir).await?;
+	let entries = std::fs::read_dir(&backup_dir)?.count();
+	let size = dir_size(&path)?;
+
+	let restored = test_databases().await?;
+	let first = restore(&restored, &path).await?;
+	let second = restore(&restored, &path).await?;
+	let subscriptions = RssSubscription::for_room(&room, &restored.state).await?;
+	std::fs::remove_dir_all(&backup_dir)?;
+
+	assert!(path.starts_with(&backup_dir));
+	assert!(path.to_string_lossy().ends_with(".tar.gz"));
+	assert_eq!(entries, 1);
+	assert!(size > 0);
+	assert!(first && !second);
+	assert_eq!(subscriptions.len(), 1);
+	Ok(())
+}
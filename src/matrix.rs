@@ -4,6 +4,8 @@ use color_eyre::{eyre::eyre, Result as EyreResult};
 use matrix_sdk::{
 	async_trait, ruma::events::room::message::RoomMessageEventContent, Client, Result, Session,
 };
+use pulldown_cmark::{html, Event, Parser};
+use url::Url;
 
 /// Session store key for access token.
 const SESSION_ACCESS_TOKEN: &str = "SESSION_ACCESS_TOKEN";
@@ -14,6 +16,41 @@ const SESSION_USER_ID: &str = "SESSION_USER_ID";
 /// Session store key for device ID.
 const SESSION_DEVICE_ID: &str = "SESSION_DEVICE_ID";
 
+/// HTML tags kept when sanitizing HTML, a subset of the tags Matrix clients
+/// support.
+const ALLOWED_TAGS: &[&str] = &[
+	"a",
+	"b",
+	"strong",
+	"i",
+	"em",
+	"u",
+	"s",
+	"del",
+	"p",
+	"br",
+	"hr",
+	"ul",
+	"ol",
+	"li",
+	"blockquote",
+	"code",
+	"pre",
+	"sub",
+	"sup",
+	"h1",
+	"h2",
+	"h3",
+	"h4",
+	"h5",
+	"h6",
+];
+/// HTML tags removed along with their content when sanitizing HTML.
+const DROPPED_TAGS: &[&str] =
+	&["script", "style", "iframe", "object", "embed", "noscript", "template", "svg", "math"];
+/// Allowed HTML tags without closing tag.
+const VOID_TAGS: &[&str] = &["br", "hr"];
+
 /// Extended matrix client functionality.
 #[async_trait]
 pub trait ClientExt {
@@ -110,10 +147,254 @@ impl ClientExt for Client {
 /// Create a matrix message, but generate escaped HTML for plain text as well as
 /// the body.
 pub fn plain_message(body: String) -> RoomMessageEventContent {
-	let html = body
-		.replace('<', "&lt;")
-		.replace('>', "&gt;")
-		.replace('&', "&amp;")
-		.replace('\n', "<br>\n");
+	let html = escape_html(&body).replace('\n', "<br>\n");
 	RoomMessageEventContent::text_html(body, html)
 }
+
+/// Escape the special HTML characters of the text.
+pub fn escape_html(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Render user-provided Markdown as HTML. Raw HTML in the text is escaped
+/// instead of being passed through. A single paragraph is returned without the
+/// paragraph tags, so that it can be used inline.
+pub fn markdown_to_html(markdown: &str) -> String {
+	render_markdown(markdown, false)
+}
+
+/// Render user-provided Markdown as HTML like [`markdown_to_html`], but keep
+/// every line break like Github does in comments.
+pub fn markdown_to_html_with_breaks(markdown: &str) -> String {
+	render_markdown(markdown, true)
+}
+
+/// Render Markdown as HTML, escaping raw HTML and optionally turning soft line
+/// breaks into hard ones. A single paragraph is returned without the paragraph
+/// tags.
+fn render_markdown(markdown: &str, keep_breaks: bool) -> String {
+	let events = Parser::new(markdown).map(|event| match event {
+		Event::Html(html) => Event::Text(html),
+		Event::SoftBreak if keep_breaks => Event::HardBreak,
+		event => event,
+	});
+	let mut rendered = String::new();
+	html::push_html(&mut rendered, events);
+
+	let rendered = rendered.trim_end();
+	match rendered.strip_prefix("<p>").and_then(|inner| inner.strip_suffix("</p>")) {
+		Some(inner) if !inner.contains("<p>") => inner.to_owned(),
+		_ => rendered.to_owned(),
+	}
+}
+
+/// Prepend the prefix to the text, separated by a space. Returns the text
+/// unchanged if the prefix is empty.
+pub fn prefixed(prefix: &str, text: &str) -> String {
+	if prefix.is_empty() {
+		text.to_owned()
+	} else {
+		format!("{prefix} {text}")
+	}
+}
+
+/// Cut the text off after the maximum number of characters, ending it with
+/// "…". Returns the text unchanged if it is short enough.
+pub fn truncate(text: &str, max_chars: usize) -> String {
+	match text.char_indices().nth(max_chars) {
+		Some((end, _)) => format!("{}…", text[..end].trim_end()),
+		None => text.to_owned(),
+	}
+}
+
+/// Strip the reply fallback, i.e. the quoted lines of the replied-to message
+/// at the start, from the plain body of a reply.
+pub fn strip_reply_fallback(body: &str) -> &str {
+	let mut rest = body;
+	while rest.starts_with('>') {
+		rest = rest.split_once('\n').map_or("", |(_, rest)| rest);
+	}
+	rest.strip_prefix('\n').unwrap_or(rest)
+}
+
+/// Convert HTML to readable plain text by stripping all tags and decoding
+/// common entities. Line breaking elements are turned into new lines.
+pub fn html_to_plain(html: &str) -> String {
+	let mut text = String::with_capacity(html.len());
+	let mut rest = html;
+	while let Some(start) = rest.find('<') {
+		text.push_str(&rest[..start]);
+		let end = match rest[start..].find('>') {
+			Some(end) => end,
+			None => {
+				rest = &rest[start..];
+				break;
+			}
+		};
+
+		let tag = rest[start + 1..start + end]
+			.trim_start_matches('/')
+			.split(|c: char| c.is_whitespace() || c == '/')
+			.next()
+			.unwrap_or_default()
+			.to_lowercase();
+		if matches!(
+			tag.as_str(),
+			"br" | "p"
+				| "div" | "li"
+				| "tr" | "blockquote"
+				| "h1" | "h2"
+				| "h3" | "h4"
+				| "h5" | "h6"
+		) && !text.ends_with('\n')
+		{
+			text.push('\n');
+		}
+		rest = &rest[start + end + 1..];
+	}
+	text.push_str(rest);
+
+	text.replace("&nbsp;", " ")
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&apos;", "'")
+		.replace("&amp;", "&")
+		.trim()
+		.to_owned()
+}
+
+/// Sanitize untrusted HTML, e.g. of feeds, for messages. Only the allowed tags
+/// are kept, without attributes except for http(s) and mailto link targets.
+/// Scripts, styles and the like are removed along with their content, other
+/// tags are stripped keeping their text. Unclosed tags are closed and stray
+/// angle brackets are escaped.
+pub fn sanitize_html(html: &str) -> String {
+	let mut sanitized = String::with_capacity(html.len());
+	let mut open = Vec::new();
+	let mut rest = html;
+	while let Some(start) = rest.find('<') {
+		sanitized.push_str(&rest[..start].replace('>', "&gt;"));
+		rest = &rest[start..];
+		if let Some(comment) = rest.strip_prefix("<!--") {
+			rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+			continue;
+		}
+		if !rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/' || c == '!') {
+			sanitized.push_str("&lt;");
+			rest = &rest[1..];
+			continue;
+		}
+		let end = match tag_end(rest) {
+			Some(end) => end,
+			None => break,
+		};
+		let tag = &rest[1..end];
+		rest = &rest[end + 1..];
+
+		let closing = tag.starts_with('/');
+		let mut parts =
+			tag.trim_start_matches('/').splitn(2, |c: char| c.is_whitespace() || c == '/');
+		let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+		let attributes = parts.next().unwrap_or_default();
+		if DROPPED_TAGS.contains(&name.as_str()) {
+			if !closing {
+				rest = after_closing_tag(rest, &name);
+			}
+		} else if !ALLOWED_TAGS.contains(&name.as_str()) {
+			continue;
+		} else if VOID_TAGS.contains(&name.as_str()) {
+			if !closing {
+				sanitized.push_str(&format!("<{name}>"));
+			}
+		} else if closing {
+			if let Some(position) = open.iter().rposition(|tag| *tag == name) {
+				for tag in open.drain(position..).rev() {
+					sanitized.push_str(&format!("</{tag}>"));
+				}
+			}
+		} else {
+			match link_target(attributes).filter(|_| name == "a") {
+				Some(href) => sanitized.push_str(&format!("<a href=\"{}\">", escape_html(&href))),
+				None => sanitized.push_str(&format!("<{name}>")),
+			}
+			open.push(name);
+		}
+	}
+	sanitized.push_str(&rest.replace('<', "&lt;").replace('>', "&gt;"));
+
+	for tag in open.into_iter().rev() {
+		sanitized.push_str(&format!("</{tag}>"));
+	}
+	sanitized
+}
+
+/// Get the position of the `>` ending the tag at the start of the HTML,
+/// ignoring quoted attribute values.
+fn tag_end(html: &str) -> Option<usize> {
+	let mut quote = None;
+	for (index, c) in html.char_indices() {
+		match (quote, c) {
+			(None, '"' | '\'') => quote = Some(c),
+			(Some(open), c) if c == open => quote = None,
+			(None, '>') => return Some(index),
+			_ => {}
+		}
+	}
+	None
+}
+
+/// Get the HTML after the closing tag with the name, or nothing if it is not
+/// closed.
+fn after_closing_tag<'a>(html: &'a str, name: &str) -> &'a str {
+	let closing = format!("</{name}");
+	html.to_ascii_lowercase()
+		.find(&closing)
+		.and_then(|start| html[start..].find('>').map(|end| &html[start + end + 1..]))
+		.unwrap_or_default()
+}
+
+/// Get the `href` attribute of a link's attributes, if it is an http(s) or
+/// mailto URL.
+fn link_target(attributes: &str) -> Option<String> {
+	let mut rest = attributes;
+	loop {
+		rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+		if rest.is_empty() {
+			return None;
+		}
+		let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+		let name = &rest[..name_end];
+		rest = rest[name_end..].trim_start();
+		let value = match rest.strip_prefix('=').map(str::trim_start) {
+			Some(value) => {
+				let (value, remaining) = match value.chars().next() {
+					Some(quote @ ('"' | '\'')) => {
+						let inner = &value[1..];
+						let end = inner.find(quote).unwrap_or(inner.len());
+						(&inner[..end], inner.get(end + 1..).unwrap_or_default())
+					}
+					_ => {
+						let end = value.find(char::is_whitespace).unwrap_or(value.len());
+						(&value[..end], &value[end..])
+					}
+				};
+				rest = remaining;
+				value
+			}
+			None => "",
+		};
+
+		if name.eq_ignore_ascii_case("href") {
+			let href = value.replace("&amp;", "&");
+			return Url::parse(&href)
+				.ok()
+				.filter(|url| matches!(url.scheme(), "http" | "https" | "mailto"))
+				.map(|_| href);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests;
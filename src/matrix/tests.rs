@@ -0,0 +1,80 @@
+//! Tests for the matrix helper functions.
+
+use matrix_sdk::ruma::events::room::message::MessageType;
+
+use super::*;
+
+#[test]
+fn html_to_plain_strips_tags() {
+	assert_eq!(html_to_plain("<p>Hello <b>World</b></p>"), "Hello World");
+	assert_eq!(html_to_plain("Line<br>Next<br/>Last"), "Line\nNext\nLast");
+	assert_eq!(
+		html_to_plain("a &lt;tag&gt; &amp;amp; &quot;quote&quot;"),
+		"a <tag> &amp; \"quote\""
+	);
+	assert_eq!(html_to_plain("unclosed <b"), "unclosed <b");
+	assert_eq!(html_to_plain("plain text"), "plain text");
+}
+
+#[test]
+fn sanitized_html_keeps_allowed_tags_only() {
+	assert_eq!(
+		sanitize_html("<p onclick=\"evil()\">Hi <b>there</b><script>alert(1)</script></p>"),
+		"<p>Hi <b>there</b></p>"
+	);
+	assert_eq!(
+		sanitize_html("<div><img src=\"huge.png\"><i>styled</i><style>p {}</style></div>"),
+		"<i>styled</i>"
+	);
+	assert_eq!(sanitize_html("<b>unclosed <i>tags"), "<b>unclosed <i>tags</i></b>");
+	assert_eq!(sanitize_html("a < b and <b"), "a &lt; b and &lt;b");
+	assert_eq!(sanitize_html("<SCRIPT>never closed"), "");
+	assert_eq!(sanitize_html("<!-- comment -->text</b>"), "text");
+}
+
+#[test]
+fn sanitized_links_keep_safe_targets() {
+	assert_eq!(
+		sanitize_html("<a href='https://example.com/?a=1&amp;b=2' target=_blank>link</a>"),
+		"<a href=\"https://example.com/?a=1&amp;b=2\">link</a>"
+	);
+	assert_eq!(sanitize_html("<a title=\"a > b\" href=javascript:alert(1)>x</a>"), "<a>x</a>");
+	assert_eq!(sanitize_html("<a>x</a>"), "<a>x</a>");
+}
+
+#[test]
+fn reply_fallback_is_stripped() {
+	assert_eq!(
+		strip_reply_fallback("> <@user:example.com> Original\n> second line\n\nReply"),
+		"Reply"
+	);
+	assert_eq!(strip_reply_fallback("No reply"), "No reply");
+	assert_eq!(strip_reply_fallback("> only quote"), "");
+}
+
+#[test]
+fn prefix_is_prepended() {
+	assert_eq!(prefixed("", "text"), "text");
+	assert_eq!(prefixed("\u{23f0}", "text"), "\u{23f0} text");
+}
+
+#[test]
+fn plain_message_is_escaped_once() {
+	let message = plain_message(String::from("a < b & c\nnext"));
+	assert_eq!(message.body(), "a < b & c\nnext");
+	let html = match message.msgtype {
+		MessageType::Text(text) => text.formatted.map(|formatted| formatted.body),
+		_ => None,
+	};
+	assert_eq!(html.as_deref(), Some("a &lt; b &amp; c<br>\nnext"));
+}
+
+#[test]
+fn markdown_is_rendered_safely() {
+	assert_eq!(
+		markdown_to_html("**bold** and `code`"),
+		"<strong>bold</strong> and <code>code</code>"
+	);
+	assert_eq!(markdown_to_html("<b>raw</b> & more"), "&lt;b&gt;raw&lt;/b&gt; &amp; more");
+	assert_eq!(markdown_to_html("one\n\ntwo"), "<p>one</p>\n<p>two</p>");
+}
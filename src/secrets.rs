@@ -0,0 +1,84 @@
+//! Encryption of secrets stored in the database, e.g. feed credentials and
+//! Github tokens, so that they are not readable from the database files or
+//! backups without the configuration.
+
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+use chacha20poly1305::{
+	aead::{Aead, NewAead},
+	ChaCha20Poly1305, Key, Nonce,
+};
+use color_eyre::{
+	eyre::{bail, eyre},
+	Result,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Length of the random nonce in bytes.
+const NONCE_LENGTH: usize = 12;
+/// Context the key is derived in, separating it from other uses of the
+/// passphrase.
+const KEY_CONTEXT: &[u8] = b"matrix-bot database secrets\0";
+
+/// Key to encrypt secrets with, derived from a configured passphrase.
+#[derive(Clone)]
+pub struct SecretKey(Key);
+
+impl SecretKey {
+	/// Derive the key from the passphrase.
+	pub fn derive(passphrase: &str) -> Self {
+		let mut hasher = Sha256::new();
+		hasher.update(KEY_CONTEXT);
+		hasher.update(passphrase.as_bytes());
+		Self(hasher.finalize())
+	}
+}
+
+impl Debug for SecretKey {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.write_str("SecretKey(<redacted>)")
+	}
+}
+
+/// Secret encrypted with a [`SecretKey`]. Stored as base64 of a random nonce
+/// followed by the ciphertext.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+	/// Encrypt the plaintext with the key.
+	pub fn seal(plaintext: &str, key: &SecretKey) -> Result<Self> {
+		let nonce = rand::random::<[u8; NONCE_LENGTH]>();
+		let ciphertext = ChaCha20Poly1305::new(&key.0)
+			.encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+			.map_err(|_| eyre!("Encrypting the secret failed"))?;
+		let mut sealed = nonce.to_vec();
+		sealed.extend_from_slice(&ciphertext);
+		Ok(Self(base64::encode(sealed)))
+	}
+
+	/// Decrypt the secret with the key. Fails if the secret was encrypted with
+	/// another key or was tampered with.
+	pub fn open(&self, key: &SecretKey) -> Result<String> {
+		let sealed = base64::decode(&self.0)?;
+		if sealed.len() < NONCE_LENGTH {
+			bail!("Encrypted secret is truncated");
+		}
+		let (nonce, ciphertext) = sealed.split_at(NONCE_LENGTH);
+		let plaintext = ChaCha20Poly1305::new(&key.0)
+			.decrypt(Nonce::from_slice(nonce), ciphertext)
+			.map_err(|_| eyre!("Decrypting the secret failed, was the passphrase changed?"))?;
+		Ok(String::from_utf8(plaintext)?)
+	}
+}
+
+impl Debug for Secret {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.write_str("Secret(<encrypted>)")
+	}
+}
+
+#[cfg(test)]
+mod tests;
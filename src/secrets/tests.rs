@@ -0,0 +1,29 @@
+//! Tests for the secret encryption.
+
+use color_eyre::Result;
+
+use super::*;
+
+#[test]
+fn sealed_secrets_open_with_the_same_key_only() -> Result<()> {
+	let key = SecretKey::derive("password");
+	let secret = Secret::seal("hunter2", &key)?;
+	assert_eq!(secret.open(&key)?, "hunter2");
+	assert!(secret.open(&SecretKey::derive("other")).is_err());
+
+	// Every encryption uses a fresh nonce.
+	assert_ne!(Secret::seal("hunter2", &key)?, secret);
+	Ok(())
+}
+
+#[test]
+fn secrets_are_not_stored_or_shown_in_plaintext() -> Result<()> {
+	let key = SecretKey::derive("password");
+	let secret = Secret::seal("hunter2", &key)?;
+	assert!(!serde_json::to_string(&secret)?.contains("hunter2"));
+	assert!(!format!("{secret:?}").contains("hunter2"));
+
+	let tampered = Secret(String::from("AAAA"));
+	assert!(tampered.open(&key).is_err());
+	Ok(())
+}
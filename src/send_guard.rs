@@ -0,0 +1,113 @@
+//! Guard pausing automated messages to rooms the bot is not allowed to send
+//! messages in.
+
+use std::{
+	collections::HashMap,
+	sync::{Mutex, PoisonError},
+	time::{Duration, Instant},
+};
+
+use color_eyre::{Report, Result};
+use matrix_sdk::{
+	ruma::{
+		api::client::error::ErrorKind, events::room::message::RoomMessageEventContent, OwnedRoomId,
+		RoomId,
+	},
+	Client,
+};
+
+use crate::settings::Settings;
+
+/// Tracks rooms in which sending was forbidden, e.g. due to a too low power
+/// level. Automated messages to these rooms are paused and re-probed after a
+/// delay.
+#[derive(Debug)]
+pub struct SendGuard {
+	/// Delay after which a paused room is probed again.
+	reprobe: Duration,
+	/// Paused rooms with the point in time of the next probe.
+	paused: Mutex<HashMap<OwnedRoomId, Instant>>,
+}
+
+impl SendGuard {
+	/// Create a new guard, probing paused rooms again after the delay.
+	pub fn new(reprobe: Duration) -> Self {
+		Self { reprobe, paused: Mutex::new(HashMap::new()) }
+	}
+
+	/// Whether automated messages to the room are allowed, i.e. the room is
+	/// not paused or is due to be probed again.
+	pub fn allow(&self, room: &RoomId) -> bool {
+		let paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+		paused.get(room).map_or(true, |probe_at| *probe_at <= Instant::now())
+	}
+
+	/// Record a successful send to the room, resuming it if it was paused.
+	pub fn record_success(&self, room: &RoomId) {
+		let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+		if paused.remove(room).is_some() {
+			tracing::info!("Sending messages to room {room} works again.");
+		}
+	}
+
+	/// Record a forbidden send to the room, pausing it until the next probe.
+	/// Returns whether the room was newly paused.
+	pub fn record_forbidden(&self, room: &RoomId) -> bool {
+		let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+		paused.insert(room.to_owned(), Instant::now() + self.reprobe).is_none()
+	}
+
+	/// Handle the result of sending automated messages to the room. Forbidden
+	/// sends pause the room and notify the admin room once, other errors are
+	/// passed on. Returns whether the messages were sent.
+	pub async fn handle_result(
+		&self,
+		config: &Settings,
+		client: &Client,
+		room: &RoomId,
+		result: Result<()>,
+	) -> Result<bool> {
+		match result {
+			Ok(()) => {
+				self.record_success(room);
+				Ok(true)
+			}
+			Err(err) if is_forbidden(&err) => {
+				if self.record_forbidden(room) {
+					tracing::warn!(
+						"Not allowed to send messages in {room}, pausing messages there."
+					);
+					notify_admin(config, client, room).await;
+				}
+				Ok(false)
+			}
+			Err(err) => Err(err),
+		}
+	}
+}
+
+/// Whether the error is a Matrix request rejected as forbidden.
+fn is_forbidden(err: &Report) -> bool {
+	err.downcast_ref::<matrix_sdk::Error>()
+		.and_then(matrix_sdk::Error::client_api_error_kind)
+		.map_or(false, |kind| matches!(kind, ErrorKind::Forbidden))
+}
+
+/// Notify the admin room, if there is one, that the bot cannot send messages
+/// into the room.
+async fn notify_admin(config: &Settings, client: &Client, room: &RoomId) {
+	if let Some(admin_room) =
+		config.notifications.admin_room.as_ref().and_then(|room| client.get_joined_room(room))
+	{
+		let notice = RoomMessageEventContent::notice_plain(format!(
+			"I am not allowed to send messages in {room}, so its subscriptions are paused. Please \
+			 check my power level there."
+		));
+		if let Err(err) = admin_room.send(notice, None).await {
+			tracing::error!("Failed sending forbidden room notice: {err}");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,38 @@
+//! Tests for the send guard.
+
+use color_eyre::eyre::eyre;
+use matrix_sdk::ruma::OwnedRoomId;
+
+use super::*;
+
+#[test]
+fn forbidden_send_pauses_room() -> Result<()> {
+	let guard = SendGuard::new(Duration::from_secs(60));
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	let other: OwnedRoomId = "!other:example.com".try_into()?;
+	assert!(guard.allow(&room));
+
+	assert!(guard.record_forbidden(&room));
+	assert!(!guard.allow(&room));
+	assert!(guard.allow(&other));
+	assert!(!guard.record_forbidden(&room), "only the first pause must be reported");
+
+	guard.record_success(&room);
+	assert!(guard.allow(&room));
+	Ok(())
+}
+
+#[test]
+fn paused_room_is_probed_again() -> Result<()> {
+	let guard = SendGuard::new(Duration::ZERO);
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+	guard.record_forbidden(&room);
+	assert!(guard.allow(&room), "probe must be allowed after the delay");
+	assert!(!guard.record_forbidden(&room));
+	Ok(())
+}
+
+#[test]
+fn other_errors_are_not_forbidden() {
+	assert!(!is_forbidden(&eyre!("something else")));
+}
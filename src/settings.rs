@@ -1,11 +1,25 @@
 //! Configuration module
 
-use std::{path::PathBuf, str::FromStr};
+use std::{
+	collections::HashMap,
+	fmt::{Debug, Formatter, Result as FmtResult},
+	path::PathBuf,
+	str::FromStr,
+	time::Duration,
+};
 
 use config::{ConfigError, Environment, File};
-use matrix_sdk::ruma::OwnedUserId;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, UserId};
 use serde::{de::Error, Deserialize, Deserializer};
+use time::UtcOffset;
 use tracing::Level;
+use url::Url;
+
+use crate::{
+	clients::github::{NotificationReason, RetryPolicy},
+	database::RenderFormat,
+	secrets::SecretKey,
+};
 
 /// This app's configuration
 #[derive(Debug, Clone, Deserialize)]
@@ -15,6 +29,20 @@ pub struct Settings {
 	pub log_level: Level,
 	/// Matrix request timeout (in seconds).
 	pub request_timeout: u64,
+	/// Whether to validate all subscriptions on startup.
+	#[serde(default)]
+	pub validate_on_startup: bool,
+	/// User agent for outbound HTTP requests (RSS feeds, Github API).
+	#[serde(default = "default_user_agent")]
+	pub user_agent: String,
+	/// Tolerated clock difference (in seconds) between the bot and remote
+	/// servers when comparing their timestamps.
+	#[serde(default = "default_clock_skew_tolerance")]
+	pub clock_skew_tolerance: u64,
+	/// Time (in seconds) without a successful sync after which the room state
+	/// is reconciled once the sync resumes.
+	#[serde(default = "default_sync_gap")]
+	pub sync_gap: u64,
 	/// Matrix login information.
 	pub login: LoginSettings,
 	/// Persons who have access to the bot.
@@ -23,6 +51,36 @@ pub struct Settings {
 	pub store: StoreSettings,
 	/// Intervalled execution configuration.
 	pub intervals: IntervalSettings,
+	/// Limits for outbound feed and API fetches.
+	#[serde(default)]
+	pub fetch: FetchSettings,
+	/// Reminder configuration.
+	#[serde(default)]
+	pub reminders: ReminderSettings,
+	/// Command handling configuration.
+	#[serde(default)]
+	pub commands: CommandSettings,
+	/// Operator notification configuration.
+	#[serde(default)]
+	pub notifications: NotificationSettings,
+	/// Room handling configuration.
+	#[serde(default)]
+	pub rooms: RoomSettings,
+	/// Message prefixes per feature.
+	#[serde(default)]
+	pub prefixes: PrefixSettings,
+	/// Github notification configuration.
+	#[serde(default)]
+	pub github: GithubSettings,
+	/// RSS feed configuration.
+	#[serde(default)]
+	pub rss: RssSettings,
+	/// Timestamp display configuration.
+	#[serde(default)]
+	pub timestamps: TimestampSettings,
+	/// Subscriptions to create on startup.
+	#[serde(default)]
+	pub bootstrap: BootstrapSettings,
 }
 
 impl Settings {
@@ -40,13 +98,14 @@ impl Settings {
 			.add_source(File::with_name(cfg_path).required(false))
 			.add_source(Environment::with_prefix("APP").separator("__"))
 			.build()?
-			.try_deserialize()?;
+			.try_deserialize::<Self>()?;
+		config.timestamps.validate()?;
 		Ok(config)
 	}
 }
 
 /// Login settings
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct LoginSettings {
 	/// Homeserver.
 	pub home_server: String,
@@ -63,10 +122,38 @@ pub struct AccessSettings {
 	pub admins: Vec<OwnedUserId>,
 	/// Moderators (execute commands only)
 	pub mods: Vec<OwnedUserId>,
+	/// Message to send into the room before rejecting an invite from a
+	/// non-admin. Invites are rejected silently if not set.
+	#[serde(default)]
+	pub invite_rejection_message: Option<String>,
+}
+
+impl AccessSettings {
+	/// Whether the user is an admin.
+	#[must_use]
+	pub fn is_admin(&self, user: &UserId) -> bool {
+		self.admins.iter().any(|admin| **admin == *user)
+	}
+
+	/// Whether the user is a moderator. Admins count as moderators as well.
+	#[must_use]
+	pub fn is_mod(&self, user: &UserId) -> bool {
+		self.is_admin(user) || self.mods.iter().any(|moderator| **moderator == *user)
+	}
+}
+
+impl Debug for LoginSettings {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("LoginSettings")
+			.field("home_server", &self.home_server)
+			.field("user", &self.user)
+			.field("password", &Redacted)
+			.finish()
+	}
 }
 
 /// Store paths.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct StoreSettings {
 	/// Location of matrix state-store.
 	pub state_store: PathBuf,
@@ -76,6 +163,36 @@ pub struct StoreSettings {
 	pub job_runner_db: PathBuf,
 	/// Location of the bot's database.
 	pub database: PathBuf,
+	/// Directory to write database backups to via `!admin backup`. Backups
+	/// are disabled if unset.
+	#[serde(default)]
+	pub backup_dir: Option<PathBuf>,
+	/// Backup archive to restore into the databases on startup. It is only
+	/// restored once, recorded by a marker file next to it.
+	#[serde(default)]
+	pub restore_from: Option<PathBuf>,
+}
+
+impl StoreSettings {
+	/// Key to encrypt secrets in the bot's database with, derived from the
+	/// passphrase.
+	#[must_use]
+	pub fn secret_key(&self) -> SecretKey {
+		SecretKey::derive(&self.passphrase)
+	}
+}
+
+impl Debug for StoreSettings {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("StoreSettings")
+			.field("state_store", &self.state_store)
+			.field("passphrase", &Redacted)
+			.field("job_runner_db", &self.job_runner_db)
+			.field("database", &self.database)
+			.field("backup_dir", &self.backup_dir)
+			.field("restore_from", &self.restore_from)
+			.finish()
+	}
 }
 
 /// Intervals settings.
@@ -84,8 +201,482 @@ pub struct IntervalSettings {
 	/// Number of seconds of the interval duration for RSS feed checks.
 	pub rss: u64,
 	/// Number of seconds of the interval duration for Github notification
-	/// checks. Intervals may be skipped when rate-limiting is reached.
+	/// checks. Github announces a minimum poll interval via the
+	/// `X-Poll-Interval` header, usually 60 seconds. Polls of a token are
+	/// skipped until it has passed, so shorter intervals do not poll more
+	/// often.
+	#[serde(default = "default_github_interval")]
 	pub github: u64,
+	/// Number of seconds after which rooms the bot was not allowed to send
+	/// messages in are probed again.
+	#[serde(default = "default_send_reprobe")]
+	pub send_reprobe: u64,
+	/// Number of seconds processing a single subscription may take before a
+	/// warning is logged.
+	#[serde(default = "default_slow_subscription")]
+	pub slow_subscription: u64,
+	/// Number of seconds without a successful poll after which an RSS feed
+	/// catches up, i.e. only its newest entries are posted.
+	#[serde(default = "default_catch_up_after")]
+	pub catch_up_after: u64,
+	/// Maximum number of entries posted when an RSS feed catches up.
+	#[serde(default = "default_catch_up_entries")]
+	pub catch_up_entries: usize,
+}
+
+/// Limits for outbound feed and API fetches.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FetchSettings {
+	/// Maximum number of fetches running at the same time.
+	#[serde(default = "default_max_concurrent_fetches")]
+	pub max_concurrent_fetches: usize,
+	/// Maximum number of fetches started per second. Zero means no limit.
+	#[serde(default = "default_max_requests_per_second")]
+	pub max_requests_per_second: u32,
+	/// Whether feeds may be fetched from hosts resolving to private, loopback
+	/// or link-local addresses.
+	#[serde(default)]
+	pub allow_private_hosts: bool,
+	/// Only fetch feeds from these hosts and their subdomains. Any host is
+	/// allowed if empty.
+	#[serde(default)]
+	pub allowed_hosts: Vec<String>,
+	/// Never fetch feeds from these hosts and their subdomains.
+	#[serde(default)]
+	pub denied_hosts: Vec<String>,
+	/// Timeout of a whole fetch request (in seconds).
+	#[serde(default = "default_fetch_timeout")]
+	pub timeout: u64,
+	/// Timeout of connecting to the fetched host (in seconds).
+	#[serde(default = "default_fetch_connect_timeout")]
+	pub connect_timeout: u64,
+}
+
+impl Default for FetchSettings {
+	fn default() -> Self {
+		Self {
+			max_concurrent_fetches: default_max_concurrent_fetches(),
+			max_requests_per_second: default_max_requests_per_second(),
+			allow_private_hosts: false,
+			allowed_hosts: Vec::new(),
+			denied_hosts: Vec::new(),
+			timeout: default_fetch_timeout(),
+			connect_timeout: default_fetch_connect_timeout(),
+		}
+	}
+}
+
+/// Command handling settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CommandSettings {
+	/// Number of seconds before the bot joined a room in which commands are
+	/// still processed after joining.
+	#[serde(default)]
+	pub pre_join_grace: u64,
+	/// Minimum power level in the room required to make the bot leave via
+	/// `!leave`, in addition to being admin or mod. No requirement if unset.
+	#[serde(default)]
+	pub leave_min_power_level: Option<i64>,
+	/// Other bot accounts of the same deployment. Their messages are ignored
+	/// like the bot's own messages, unless `process_sibling_messages` is set.
+	#[serde(default)]
+	pub sibling_accounts: Vec<OwnedUserId>,
+	/// Whether to process messages of the sibling accounts.
+	#[serde(default)]
+	pub process_sibling_messages: bool,
+}
+
+/// Subscriptions declared in the configuration, which are created on startup
+/// if they do not exist yet.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BootstrapSettings {
+	/// RSS subscriptions to create.
+	#[serde(default)]
+	pub rss: Vec<RssBootstrap>,
+	/// Github notification subscriptions to create.
+	#[serde(default)]
+	pub github: Vec<GithubBootstrap>,
+}
+
+/// RSS subscription to create on startup.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RssBootstrap {
+	/// Room to post the feed entries into.
+	pub room: OwnedRoomId,
+	/// Full feed URL.
+	pub url: Url,
+}
+
+/// Github notification subscription to create on startup.
+#[derive(Clone, Deserialize)]
+pub struct GithubBootstrap {
+	/// Room to post the notifications into.
+	pub room: OwnedRoomId,
+	/// Github login username.
+	pub user: String,
+	/// Github access token.
+	pub token: String,
+}
+
+/// Timestamp display settings.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimestampSettings {
+	/// Offset of the timezone to show times in, in minutes from UTC.
+	#[serde(default)]
+	pub utc_offset_minutes: i32,
+	/// Format of absolute times, as `time` crate format description.
+	#[serde(default = "default_timestamp_format")]
+	pub format: String,
+}
+
+impl TimestampSettings {
+	/// Offset of the configured timezone. Falls back to UTC on an invalid
+	/// offset.
+	#[must_use]
+	pub fn utc_offset(&self) -> UtcOffset {
+		UtcOffset::from_whole_seconds(self.utc_offset_minutes.saturating_mul(60))
+			.unwrap_or(UtcOffset::UTC)
+	}
+
+	/// Make sure the format of absolute times is a valid format description.
+	pub fn validate(&self) -> Result<(), ConfigError> {
+		time::format_description::parse(&self.format).map_err(|err| {
+			ConfigError::Message(format!("Invalid timestamp format `{}`: {err}", self.format))
+		})?;
+		Ok(())
+	}
+}
+
+impl Default for TimestampSettings {
+	fn default() -> Self {
+		Self { utc_offset_minutes: 0, format: default_timestamp_format() }
+	}
+}
+
+/// Github notification settings.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GithubSettings {
+	/// Message type per notification reason, overriding the default of text
+	/// messages in direct chats and notices otherwise.
+	#[serde(default)]
+	pub message_types: HashMap<NotificationReason, MessageKind>,
+	/// Client ID of a registered Github OAuth app with device flow enabled.
+	/// Enables `!github login` if set.
+	#[serde(default)]
+	pub oauth_client_id: Option<String>,
+	/// Maximum number of notification pages fetched per poll. Older
+	/// notifications beyond these pages are skipped.
+	#[serde(default = "default_max_notification_pages")]
+	pub max_notification_pages: usize,
+	/// Number of retries of Github requests failing due to network errors or
+	/// unavailable servers.
+	#[serde(default = "default_request_retries")]
+	pub request_retries: u32,
+	/// Delay (in seconds) before the first retry of a Github request, doubling
+	/// with every further retry.
+	#[serde(default = "default_retry_delay")]
+	pub retry_delay: u64,
+	/// Maximum number of characters of a comment shown in a notification.
+	/// Longer comments are collapsed, linking to the full comment.
+	#[serde(default = "default_max_comment_length")]
+	pub max_comment_length: usize,
+}
+
+impl GithubSettings {
+	/// Retry policy of the Github clients.
+	#[must_use]
+	pub fn retry_policy(&self) -> RetryPolicy {
+		RetryPolicy {
+			retries: self.request_retries,
+			base_delay: Duration::from_secs(self.retry_delay),
+		}
+	}
+}
+
+impl Default for GithubSettings {
+	fn default() -> Self {
+		Self {
+			message_types: HashMap::new(),
+			oauth_client_id: None,
+			max_notification_pages: default_max_notification_pages(),
+			request_retries: default_request_retries(),
+			retry_delay: default_retry_delay(),
+			max_comment_length: default_max_comment_length(),
+		}
+	}
+}
+
+/// RSS feed settings.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RssSettings {
+	/// Format entries are rendered in, unless overridden by the room or feed.
+	#[serde(default)]
+	pub format: RenderFormat,
+	/// Number of retries of feed fetches failing due to network errors or
+	/// server errors.
+	#[serde(default = "default_fetch_retries")]
+	pub fetch_retries: u32,
+	/// Delay (in seconds) before the first retry of a feed fetch, doubling
+	/// with every further retry.
+	#[serde(default = "default_retry_delay")]
+	pub retry_delay: u64,
+	/// Number of failed fetches in a row after which a feed is checked less
+	/// often and its room is notified.
+	#[serde(default = "default_failure_threshold")]
+	pub failure_threshold: u32,
+}
+
+impl RssSettings {
+	/// Retry policy of feed fetches.
+	#[must_use]
+	pub fn retry_policy(&self) -> RetryPolicy {
+		RetryPolicy {
+			retries: self.fetch_retries,
+			base_delay: Duration::from_secs(self.retry_delay),
+		}
+	}
+}
+
+impl Default for RssSettings {
+	fn default() -> Self {
+		Self {
+			format: RenderFormat::default(),
+			fetch_retries: default_fetch_retries(),
+			retry_delay: default_retry_delay(),
+			failure_threshold: default_failure_threshold(),
+		}
+	}
+}
+
+/// Matrix message type to send messages as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+	/// Normal text message (`m.text`), usually notifying users.
+	Text,
+	/// Notice (`m.notice`), usually not notifying users.
+	Notice,
+}
+
+impl MessageKind {
+	/// Default message type of automated messages: text messages in direct
+	/// chats and notices otherwise.
+	#[must_use]
+	pub fn default_for(is_direct: bool) -> Self {
+		if is_direct {
+			Self::Text
+		} else {
+			Self::Notice
+		}
+	}
+}
+
+/// Message prefix settings, e.g. emojis to distinguish message sources.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrefixSettings {
+	/// Prefix of RSS feed entry messages.
+	#[serde(default)]
+	pub rss: String,
+	/// Prefix of Github notification messages.
+	#[serde(default)]
+	pub github: String,
+	/// Prefix of reminder messages.
+	#[serde(default)]
+	pub reminders: String,
+}
+
+/// Room handling settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoomSettings {
+	/// Whether to leave rooms after they were upgraded and the bot followed
+	/// into the replacement room.
+	#[serde(default)]
+	pub leave_upgraded: bool,
+	/// Whether to post a summary of the existing subscriptions when the bot is
+	/// invited into a room it already has subscriptions in.
+	#[serde(default)]
+	pub rejoin_summary: bool,
+	/// Command availability per room. All commands are enabled in rooms that
+	/// are not listed.
+	#[serde(default)]
+	pub commands: HashMap<OwnedRoomId, RoomCommands>,
+	/// Maximum number of RSS subscriptions per room. Unlimited if unset.
+	/// Admins are exempt.
+	#[serde(default)]
+	pub max_rss_per_room: Option<usize>,
+	/// Maximum number of Github subscriptions per room. Unlimited if unset.
+	/// Admins are exempt.
+	#[serde(default)]
+	pub max_github_per_room: Option<usize>,
+}
+
+/// Command availability in a room, by command name (e.g. "leave").
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RoomCommands {
+	/// Only these commands are enabled in the room, if given.
+	#[serde(default)]
+	pub enabled: Option<Vec<String>>,
+	/// Commands disabled in the room.
+	#[serde(default)]
+	pub disabled: Vec<String>,
+}
+
+/// Operator notification settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NotificationSettings {
+	/// Room to send operator notifications to.
+	#[serde(default)]
+	pub admin_room: Option<OwnedRoomId>,
+	/// Whether to notify the admin room when the bot logged in with a fresh
+	/// session instead of restoring its previous one.
+	#[serde(default)]
+	pub fresh_login: bool,
+	/// Whether to notify the admin room when the sync resumed after a gap.
+	#[serde(default)]
+	pub sync_gap: bool,
+}
+
+/// Reminder settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ReminderSettings {
+	/// How to confirm a scheduled reminder.
+	#[serde(default)]
+	pub confirmation: ReminderConfirmation,
+}
+
+/// How to confirm a scheduled reminder.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReminderConfirmation {
+	/// Reply with a message.
+	#[default]
+	Message,
+	/// React to the command message.
+	Reaction,
+	/// Do not confirm at all.
+	None,
+}
+
+/// Placeholder for secrets in debug output.
+struct Redacted;
+
+impl Debug for GithubBootstrap {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.debug_struct("GithubBootstrap")
+			.field("room", &self.room)
+			.field("user", &self.user)
+			.field("token", &Redacted)
+			.finish()
+	}
+}
+
+impl Debug for Redacted {
+	fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+		f.write_str("<redacted>")
+	}
+}
+
+/// Default user agent for outbound HTTP requests, including a contact URL.
+fn default_user_agent() -> String {
+	concat!(
+		env!("CARGO_PKG_NAME"),
+		"/",
+		env!("CARGO_PKG_VERSION"),
+		" (+https://github.com/FlixCoder/matrix-bot)"
+	)
+	.to_owned()
+}
+
+/// Default tolerated clock difference to remote servers, in seconds.
+fn default_clock_skew_tolerance() -> u64 {
+	5
+}
+
+/// Default time without a successful sync that counts as gap, in seconds.
+fn default_sync_gap() -> u64 {
+	5 * 60
+}
+
+/// Default interval of Github notification checks, in seconds.
+fn default_github_interval() -> u64 {
+	5 * 60
+}
+
+/// Default maximum number of Github notification pages fetched per poll.
+fn default_max_notification_pages() -> usize {
+	10
+}
+
+/// Default number of retries of transiently failing Github requests.
+fn default_request_retries() -> u32 {
+	3
+}
+
+/// Default delay before the first retry of a Github request or feed fetch, in
+/// seconds.
+fn default_retry_delay() -> u64 {
+	1
+}
+
+/// Default maximum number of characters of a comment in a Github
+/// notification.
+fn default_max_comment_length() -> usize {
+	500
+}
+
+/// Default number of retries of failing feed fetches.
+fn default_fetch_retries() -> u32 {
+	2
+}
+
+/// Default number of failed fetches in a row before backing off a feed.
+fn default_failure_threshold() -> u32 {
+	3
+}
+
+/// Default delay to probe rooms again after a forbidden send, in seconds.
+fn default_send_reprobe() -> u64 {
+	60 * 60
+}
+
+/// Default processing time of a subscription before warning, in seconds.
+fn default_slow_subscription() -> u64 {
+	30
+}
+
+/// Default time without a successful poll before catching up, in seconds.
+fn default_catch_up_after() -> u64 {
+	24 * 60 * 60
+}
+
+/// Default maximum number of entries posted when catching up.
+fn default_catch_up_entries() -> usize {
+	5
+}
+
+/// Default format of absolute timestamps.
+fn default_timestamp_format() -> String {
+	String::from("[year]-[month]-[day] [hour]:[minute] UTC[offset_hour sign:mandatory]")
+}
+
+/// Default maximum number of concurrent fetches.
+fn default_max_concurrent_fetches() -> usize {
+	4
+}
+
+/// Default maximum number of fetches started per second.
+fn default_max_requests_per_second() -> u32 {
+	10
+}
+
+/// Default timeout of a fetch request in seconds.
+fn default_fetch_timeout() -> u64 {
+	30
+}
+
+/// Default timeout of connecting for a fetch in seconds.
+fn default_fetch_connect_timeout() -> u64 {
+	10
 }
 
 /// Deserializes `String` into `tracing::Level`
@@ -99,3 +690,6 @@ where
 
 	Ok(level)
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,50 @@
+//! Tests for the settings.
+
+use color_eyre::Result;
+
+use super::*;
+
+#[test]
+fn access_roles() -> Result<()> {
+	let admin: OwnedUserId = "@admin:example.com".try_into()?;
+	let moderator: OwnedUserId = "@mod:example.com".try_into()?;
+	let other: OwnedUserId = "@other:example.com".try_into()?;
+	let access = AccessSettings {
+		admins: vec![admin.clone()],
+		mods: vec![moderator.clone()],
+		invite_rejection_message: None,
+	};
+
+	assert!(access.is_admin(&admin));
+	assert!(access.is_mod(&admin));
+	assert!(!access.is_admin(&moderator));
+	assert!(access.is_mod(&moderator));
+	assert!(!access.is_admin(&other));
+	assert!(!access.is_mod(&other));
+	Ok(())
+}
+
+#[test]
+fn timestamp_format_validation() {
+	let valid = TimestampSettings {
+		utc_offset_minutes: 0,
+		format: "[day].[month].[year] [hour]:[minute]".to_owned(),
+	};
+	assert!(valid.validate().is_ok());
+	assert!(TimestampSettings::default().validate().is_ok());
+
+	let invalid = TimestampSettings { utc_offset_minutes: 0, format: "[day].[mnth]".to_owned() };
+	assert!(invalid.validate().is_err());
+}
+
+#[test]
+fn github_interval_defaults() -> Result<()> {
+	let intervals: IntervalSettings = serde_json::from_value(serde_json::json!({ "rss": 600 }))?;
+	assert_eq!(intervals.github, 300);
+	assert_eq!(intervals.rss, 600);
+
+	let intervals: IntervalSettings =
+		serde_json::from_value(serde_json::json!({ "rss": 600, "github": 120 }))?;
+	assert_eq!(intervals.github, 120);
+	Ok(())
+}
@@ -0,0 +1,44 @@
+//! Destinations of the bot's notifications, so that feed entries, Github
+//! notifications and reminders are rendered once and can be sent anywhere.
+
+use color_eyre::{eyre::eyre, Result};
+use matrix_sdk::{
+	async_trait,
+	ruma::{events::room::message::RoomMessageEventContent, OwnedEventId, RoomId},
+	Client,
+};
+
+use crate::settings::MessageKind;
+
+/// Destination of notifications, e.g. the Matrix homeserver.
+#[async_trait]
+pub trait NotificationSink: Send + Sync {
+	/// Send a notification with plain body and HTML into the room. Returns
+	/// the ID of the sent event, if the sink has events.
+	async fn send(
+		&self,
+		room: &RoomId,
+		body: String,
+		html: String,
+		kind: MessageKind,
+	) -> Result<Option<OwnedEventId>>;
+}
+
+#[async_trait]
+impl NotificationSink for Client {
+	async fn send(
+		&self,
+		room: &RoomId,
+		body: String,
+		html: String,
+		kind: MessageKind,
+	) -> Result<Option<OwnedEventId>> {
+		let room = self.get_joined_room(room).ok_or_else(|| eyre!("Room {room} is not joined"))?;
+		let message = match kind {
+			MessageKind::Text => RoomMessageEventContent::text_html(body, html),
+			MessageKind::Notice => RoomMessageEventContent::notice_html(body, html),
+		};
+		let response = room.send(message, None).await?;
+		Ok(Some(response.event_id))
+	}
+}
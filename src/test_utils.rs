@@ -0,0 +1,264 @@
+//! Shared helpers for tests.
+
+use std::{
+	collections::HashMap,
+	sync::{Arc, Mutex, PoisonError},
+};
+
+use bonsaidb::local::{
+	config::{Builder, StorageConfiguration},
+	AsyncDatabase,
+};
+use bonsaimq::MessageQueueSchema;
+use color_eyre::Result;
+use matrix_sdk::{
+	async_trait,
+	ruma::{events::room::message::OriginalRoomMessageEvent, OwnedEventId, OwnedRoomId, RoomId},
+};
+use serde_json::json;
+use tokio::{
+	io::{AsyncReadExt, AsyncWriteExt},
+	net::{TcpListener, TcpStream},
+	task::JoinHandle,
+};
+use url::Url;
+
+use crate::{
+	clients::GuardedClient,
+	database::{BotSchema, Databases},
+	settings::{FetchSettings, MessageKind},
+	sink::NotificationSink,
+};
+
+/// Open in-memory databases for testing.
+pub async fn test_databases() -> Result<Databases> {
+	let state =
+		AsyncDatabase::open::<BotSchema>(StorageConfiguration::default().memory_only()).await?;
+	let jobs =
+		AsyncDatabase::open::<MessageQueueSchema>(StorageConfiguration::default().memory_only())
+			.await?;
+	Ok(Databases { state, jobs })
+}
+
+/// HTTP client allowed to send requests to local mock servers.
+pub fn local_http_client() -> Result<GuardedClient> {
+	let fetch = FetchSettings { allow_private_hosts: true, ..FetchSettings::default() };
+	Ok(GuardedClient::new("test-agent", &fetch)?)
+}
+
+/// Create a text message event from the sender with the body.
+pub fn text_message_event(sender: &str, body: &str) -> Result<OriginalRoomMessageEvent> {
+	let event = serde_json::from_value(json!({
+		"type": "m.room.message",
+		"event_id": "$event:example.com",
+		"sender": sender,
+		"origin_server_ts": 1_665_000_000_000_u64,
+		"room_id": "!room:example.com",
+		"content": {
+			"msgtype": "m.text",
+			"body": body,
+		},
+	}))?;
+	Ok(event)
+}
+
+/// A notification captured by the [`CaptureSink`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captured {
+	/// Room the notification was sent to.
+	pub room: OwnedRoomId,
+	/// Plain body.
+	pub body: String,
+	/// HTML.
+	pub html: String,
+	/// Message type.
+	pub kind: MessageKind,
+}
+
+/// Notification sink capturing the sent notifications instead of sending them.
+#[derive(Debug, Default)]
+pub struct CaptureSink {
+	/// Notifications sent so far.
+	sent: Mutex<Vec<Captured>>,
+}
+
+impl CaptureSink {
+	/// All notifications sent so far.
+	pub fn sent(&self) -> Vec<Captured> {
+		self.sent.lock().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+}
+
+#[async_trait]
+impl NotificationSink for CaptureSink {
+	async fn send(
+		&self,
+		room: &RoomId,
+		body: String,
+		html: String,
+		kind: MessageKind,
+	) -> Result<Option<OwnedEventId>> {
+		let captured = Captured { room: room.to_owned(), body, html, kind };
+		self.sent.lock().unwrap_or_else(PoisonError::into_inner).push(captured);
+		Ok(None)
+	}
+}
+
+/// Handler function type of the [`MockServer`].
+type Handler = Arc<dyn Fn(&MockRequest) -> MockResponse + Send + Sync>;
+
+/// A request received by the [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+	/// HTTP method.
+	pub method: String,
+	/// Request path including the query.
+	pub path: String,
+	/// Request headers with lowercase names.
+	pub headers: HashMap<String, String>,
+	/// Request body.
+	pub body: Vec<u8>,
+}
+
+impl MockRequest {
+	/// Get a header value by (case-insensitive) name.
+	pub fn header(&self, name: &str) -> Option<&str> {
+		self.headers.get(&name.to_lowercase()).map(String::as_str)
+	}
+}
+
+/// A response to be sent by the [`MockServer`].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+	/// HTTP status code.
+	pub status: u16,
+	/// Additional response headers.
+	pub headers: Vec<(String, String)>,
+	/// Response body.
+	pub body: Vec<u8>,
+}
+
+impl MockResponse {
+	/// Create a new response with the status code and body.
+	pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+		Self { status, headers: Vec::new(), body: body.into() }
+	}
+
+	/// Add a header to the response.
+	pub fn header(mut self, name: &str, value: &str) -> Self {
+		self.headers.push((name.to_owned(), value.to_owned()));
+		self
+	}
+}
+
+/// Minimal HTTP/1.1 server on localhost for testing HTTP clients. Every
+/// connection is answered with one response and closed afterwards.
+#[derive(Debug)]
+pub struct MockServer {
+	/// Base URL of the server.
+	url: Url,
+	/// Requests received so far.
+	requests: Arc<Mutex<Vec<MockRequest>>>,
+	/// Server task handle.
+	handle: JoinHandle<()>,
+}
+
+impl MockServer {
+	/// Start a new server, answering requests using the handler.
+	pub async fn start<F>(handler: F) -> std::io::Result<Self>
+	where
+		F: Fn(&MockRequest) -> MockResponse + Send + Sync + 'static,
+	{
+		let listener = TcpListener::bind("127.0.0.1:0").await?;
+		let url = Url::parse(&format!("http://{}/", listener.local_addr()?))
+			.map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
+		let requests = Arc::new(Mutex::new(Vec::new()));
+		let handler: Handler = Arc::new(handler);
+
+		let received = requests.clone();
+		let handle = tokio::spawn(async move {
+			while let Ok((stream, _)) = listener.accept().await {
+				let handler = handler.clone();
+				let received = received.clone();
+				tokio::spawn(async move {
+					if let Err(err) = handle_connection(stream, &handler, &received).await {
+						tracing::error!("Mock server connection error: {err}");
+					}
+				});
+			}
+		});
+
+		Ok(Self { url, requests, handle })
+	}
+
+	/// Base URL of the server, ending in a slash.
+	pub fn url(&self) -> &Url {
+		&self.url
+	}
+
+	/// All requests received so far.
+	pub fn requests(&self) -> Vec<MockRequest> {
+		self.requests.lock().unwrap_or_else(PoisonError::into_inner).clone()
+	}
+}
+
+impl Drop for MockServer {
+	fn drop(&mut self) {
+		self.handle.abort();
+	}
+}
+
+/// Read one request from the connection, answer it and record it.
+async fn handle_connection(
+	mut stream: TcpStream,
+	handler: &Handler,
+	received: &Mutex<Vec<MockRequest>>,
+) -> std::io::Result<()> {
+	let mut buffer = Vec::new();
+	let mut chunk = [0_u8; 4096];
+	let header_end = loop {
+		let read = stream.read(&mut chunk).await?;
+		if read == 0 {
+			return Ok(());
+		}
+		buffer.extend_from_slice(&chunk[..read]);
+		if let Some(pos) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+			break pos + 4;
+		}
+	};
+
+	let head = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+	let mut lines = head.split("\r\n");
+	let mut request_line = lines.next().unwrap_or_default().split(' ');
+	let method = request_line.next().unwrap_or_default().to_owned();
+	let path = request_line.next().unwrap_or_default().to_owned();
+	let headers = lines
+		.filter_map(|line| line.split_once(':'))
+		.map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_owned()))
+		.collect::<HashMap<_, _>>();
+
+	let content_length =
+		headers.get("content-length").and_then(|len| len.parse::<usize>().ok()).unwrap_or(0);
+	let mut body = buffer[header_end..].to_vec();
+	while body.len() < content_length {
+		let read = stream.read(&mut chunk).await?;
+		if read == 0 {
+			break;
+		}
+		body.extend_from_slice(&chunk[..read]);
+	}
+
+	let request = MockRequest { method, path, headers, body };
+	let response = handler(&request);
+	received.lock().unwrap_or_else(PoisonError::into_inner).push(request);
+
+	let mut raw = format!("HTTP/1.1 {} Mock\r\n", response.status);
+	for (name, value) in &response.headers {
+		raw.push_str(&format!("{name}: {value}\r\n"));
+	}
+	raw.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+	stream.write_all(raw.as_bytes()).await?;
+	stream.write_all(&response.body).await?;
+	stream.shutdown().await?;
+	Ok(())
+}
@@ -0,0 +1,72 @@
+//! Tests for the bot's setup and run logic.
+
+use super::*;
+
+#[test]
+fn sync_token_prefers_store() {
+	let token = select_sync_token(Some("store".to_owned()), Some("db".to_owned()));
+	assert_eq!(token.as_deref(), Some("store"));
+}
+
+#[test]
+fn sync_token_falls_back_to_database() {
+	let token = select_sync_token(None, Some("db".to_owned()));
+	assert_eq!(token.as_deref(), Some("db"));
+
+	assert_eq!(select_sync_token(None, None), None);
+}
+
+#[test]
+fn resync_excludes_timeline() {
+	let filter = resync_filter();
+	assert_eq!(filter.room.timeline.limit, Some(UInt::MIN));
+	assert_eq!(filter.room.state.limit, None);
+}
+
+#[test]
+fn login_notice_only_on_fresh_login() -> color_eyre::Result<()> {
+	let mut config = Settings::read_from("config.sample.yaml")?;
+	config.notifications.admin_room = Some("!admin:example.com".try_into()?);
+	config.notifications.fresh_login = true;
+
+	let (room_id, notice) = login_notice(&config, LoginKind::Fresh)
+		.ok_or_else(|| color_eyre::eyre::eyre!("No notice"))?;
+	assert_eq!(room_id.as_str(), "!admin:example.com");
+	assert!(notice.body().contains("fresh session"));
+	assert!(login_notice(&config, LoginKind::Restored).is_none());
+
+	config.notifications.fresh_login = false;
+	assert!(login_notice(&config, LoginKind::Fresh).is_none());
+	Ok(())
+}
+
+#[test]
+fn sync_gap_is_detected() {
+	let start = Instant::now();
+	let gaps = SyncGapDetector::new(Duration::from_secs(300), start);
+
+	assert_eq!(gaps.record(start + Duration::from_secs(30)), None);
+	assert_eq!(gaps.record(start + Duration::from_secs(330)), None);
+	assert_eq!(
+		gaps.record(start + Duration::from_secs(5730)),
+		Some(Duration::from_secs(5400)),
+		"a simulated gap triggers the reconciliation"
+	);
+	assert_eq!(gaps.record(start + Duration::from_secs(5760)), None);
+}
+
+#[test]
+fn gap_notice_only_if_configured() -> color_eyre::Result<()> {
+	let mut config = Settings::read_from("config.sample.yaml")?;
+	config.notifications.admin_room = Some("!admin:example.com".try_into()?);
+	config.notifications.sync_gap = true;
+
+	let (room_id, notice) = gap_notice(&config, Duration::from_secs(5400))
+		.ok_or_else(|| color_eyre::eyre::eyre!("No notice"))?;
+	assert_eq!(room_id.as_str(), "!admin:example.com");
+	assert!(notice.body().contains("gap of 1h30m"));
+
+	config.notifications.sync_gap = false;
+	assert!(gap_notice(&config, Duration::from_secs(5400)).is_none());
+	Ok(())
+}
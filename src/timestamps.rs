@@ -0,0 +1,121 @@
+//! Human-friendly formatting of timestamps in messages.
+
+use time::{
+	format_description::{self, well_known::Rfc3339},
+	Duration, OffsetDateTime, UtcOffset,
+};
+
+use crate::settings::TimestampSettings;
+
+/// Formats of absolute times per language tag.
+const LOCALE_FORMATS: &[(&str, &str)] = &[
+	("de", "[day].[month].[year] [hour]:[minute]"),
+	("en", "[month]/[day]/[year] [hour repr:12 padding:none]:[minute] [period]"),
+	("en-GB", "[day]/[month]/[year] [hour]:[minute]"),
+	("es", "[day]/[month]/[year] [hour]:[minute]"),
+	("fr", "[day]/[month]/[year] [hour]:[minute]"),
+	("it", "[day]/[month]/[year] [hour]:[minute]"),
+	("ja", "[year]/[month]/[day] [hour]:[minute]"),
+	("nl", "[day]-[month]-[year] [hour]:[minute]"),
+];
+
+/// Format of absolute times in the language, e.g. `de` or `en-US`. Tags
+/// without their own format use the format of their primary language. Returns
+/// `None` for unsupported languages.
+pub fn locale_format(locale: &str) -> Option<&'static str> {
+	let language = locale.split('-').next().unwrap_or(locale);
+	[locale, language].into_iter().find_map(|tag| {
+		LOCALE_FORMATS
+			.iter()
+			.find(|(known, _)| known.eq_ignore_ascii_case(tag))
+			.map(|(_, format)| *format)
+	})
+}
+
+/// Formatter of timestamps relative to a fixed current time.
+#[derive(Debug, Clone)]
+pub struct TimestampFormatter {
+	/// Current time to format relative to.
+	now: OffsetDateTime,
+	/// Offset of the timezone to show absolute times in.
+	offset: UtcOffset,
+	/// Format description of absolute times.
+	format: String,
+}
+
+impl TimestampFormatter {
+	/// Create a new formatter from the settings, relative to the given current
+	/// time.
+	pub fn new(settings: &TimestampSettings, now: OffsetDateTime) -> Self {
+		Self { now, offset: settings.utc_offset(), format: settings.format.clone() }
+	}
+
+	/// Format the timestamp as relative time followed by the absolute time,
+	/// e.g. "3m ago (2022-10-05 10:00)".
+	pub fn format(&self, at: OffsetDateTime) -> String {
+		format!("{} ({})", relative_time(self.now - at), self.absolute(at))
+	}
+
+	/// Format a future timestamp as absolute time followed by the precise time
+	/// until then, e.g. "2022-10-05 10:00 (in 3h12m)".
+	pub fn scheduled(&self, at: OffsetDateTime) -> String {
+		let until = at - self.now;
+		let relative = if until < Duration::MINUTE {
+			String::from("now")
+		} else {
+			format!("in {}", precise_duration(until))
+		};
+		format!("{} ({relative})", self.absolute(at))
+	}
+
+	/// Format the timestamp as absolute time in the configured timezone and
+	/// format. Falls back to RFC 3339 on an invalid format.
+	pub fn absolute(&self, at: OffsetDateTime) -> String {
+		let at = at.to_offset(self.offset);
+		format_description::parse(&self.format)
+			.ok()
+			.and_then(|format| at.format(&format).ok())
+			.or_else(|| at.format(&Rfc3339).ok())
+			.unwrap_or_else(|| at.to_string())
+	}
+}
+
+/// Format the time passed since a point in time, e.g. "3m ago". Negative
+/// durations are formatted as future times, e.g. "in 3m".
+pub fn relative_time(elapsed: Duration) -> String {
+	let amount = elapsed.abs();
+	let amount = if amount < Duration::MINUTE {
+		return String::from("just now");
+	} else if amount < Duration::HOUR {
+		format!("{}m", amount.whole_minutes())
+	} else if amount < Duration::DAY {
+		format!("{}h", amount.whole_hours())
+	} else {
+		format!("{}d", amount.whole_days())
+	};
+
+	if elapsed.is_negative() {
+		format!("in {amount}")
+	} else {
+		format!("{amount} ago")
+	}
+}
+
+/// Format a duration with its two largest units, e.g. "3h12m" or "2d5h".
+/// Seconds are omitted.
+pub fn precise_duration(duration: Duration) -> String {
+	let duration = duration.abs();
+	let days = duration.whole_days();
+	let hours = duration.whole_hours() % 24;
+	let minutes = duration.whole_minutes() % 60;
+	if days > 0 {
+		format!("{days}d{hours}h")
+	} else if hours > 0 {
+		format!("{hours}h{minutes}m")
+	} else {
+		format!("{minutes}m")
+	}
+}
+
+#[cfg(test)]
+mod tests;
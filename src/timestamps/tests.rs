@@ -0,0 +1,64 @@
+//! Tests for the timestamp formatting.
+
+use color_eyre::Result;
+
+use super::*;
+
+#[test]
+fn relative_formatting() {
+	assert_eq!(relative_time(Duration::seconds(30)), "just now");
+	assert_eq!(relative_time(Duration::minutes(3)), "3m ago");
+	assert_eq!(relative_time(Duration::minutes(59)), "59m ago");
+	assert_eq!(relative_time(Duration::minutes(90)), "1h ago");
+	assert_eq!(relative_time(Duration::hours(23)), "23h ago");
+	assert_eq!(relative_time(Duration::hours(50)), "2d ago");
+	assert_eq!(relative_time(Duration::minutes(-5)), "in 5m");
+}
+
+#[test]
+fn precise_formatting() {
+	assert_eq!(precise_duration(Duration::seconds(30)), "0m");
+	assert_eq!(precise_duration(Duration::minutes(45)), "45m");
+	assert_eq!(precise_duration(Duration::minutes(192)), "3h12m");
+	assert_eq!(precise_duration(Duration::hours(53)), "2d5h");
+	assert_eq!(precise_duration(Duration::minutes(-90)), "1h30m");
+}
+
+#[test]
+fn absolute_time_in_timezone() -> Result<()> {
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+	let settings = TimestampSettings {
+		utc_offset_minutes: 120,
+		format: "[year]-[month]-[day] [hour]:[minute]".to_owned(),
+	};
+	let formatter = TimestampFormatter::new(&settings, now);
+
+	assert_eq!(formatter.format(now - Duration::minutes(3)), "3m ago (2022-10-05 21:57)");
+	Ok(())
+}
+
+#[test]
+fn absolute_time_in_configured_formats() -> Result<()> {
+	let now = OffsetDateTime::from_unix_timestamp(1_665_000_000)?;
+	let iso = TimestampSettings {
+		utc_offset_minutes: 0,
+		format: "[year]-[month]-[day] [hour]:[minute]".to_owned(),
+	};
+	let european = TimestampSettings {
+		utc_offset_minutes: 0,
+		format: "[day].[month].[year] [hour]:[minute]".to_owned(),
+	};
+
+	assert_eq!(TimestampFormatter::new(&iso, now).absolute(now), "2022-10-05 20:00");
+	assert_eq!(TimestampFormatter::new(&european, now).absolute(now), "05.10.2022 20:00");
+	Ok(())
+}
+
+#[test]
+fn locales_fall_back_to_their_language() {
+	assert_eq!(locale_format("de"), Some("[day].[month].[year] [hour]:[minute]"));
+	assert_eq!(locale_format("DE-ch"), locale_format("de"));
+	assert_eq!(locale_format("en-US"), locale_format("en"));
+	assert_ne!(locale_format("en-GB"), locale_format("en"));
+	assert_eq!(locale_format("tlh"), None);
+}
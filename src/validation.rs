@@ -0,0 +1,103 @@
+//! Validation of all stored subscriptions, e.g. on startup.
+
+use std::sync::Arc;
+
+use bonsaidb::core::schema::SerializedCollection;
+use color_eyre::Result;
+use matrix_sdk::{ruma::events::room::message::RoomMessageEventContent, Client};
+
+use crate::{
+	clients::{
+		github::Github,
+		rss::{validate_feeds, ParseOptions},
+		GuardedClient,
+	},
+	database::{Databases, GithubSubscription, RssSubscription},
+	settings::Settings,
+};
+
+/// Validate all subscriptions on startup if configured, logging the broken
+/// ones and posting a summary to the admin room if there is one.
+pub async fn run_on_startup(config: Arc<Settings>, databases: Databases, client: Client) {
+	if !config.validate_on_startup {
+		return;
+	}
+
+	tracing::info!("Validating all subscriptions..");
+	let problems = match validate_subscriptions(&config, &databases).await {
+		Ok(problems) => problems,
+		Err(err) => {
+			tracing::error!("Failed validating subscriptions: {err}");
+			return;
+		}
+	};
+	for problem in &problems {
+		tracing::warn!("Broken subscription: {problem}");
+	}
+
+	if let Some(room) =
+		config.notifications.admin_room.as_ref().and_then(|room| client.get_joined_room(room))
+	{
+		let notice = RoomMessageEventContent::notice_markdown(format_summary(&problems));
+		if let Err(err) = room.send(notice, None).await {
+			tracing::error!("Failed sending subscription validation summary: {err}");
+		}
+	}
+}
+
+/// Validate all stored RSS feeds and Github tokens without posting anything.
+/// Returns descriptions of the broken subscriptions.
+async fn validate_subscriptions(config: &Settings, databases: &Databases) -> Result<Vec<String>> {
+	let mut problems = Vec::new();
+
+	let rss_subscriptions = RssSubscription::all_async(&databases.state).await?;
+	let key = config.store.secret_key();
+	let feeds = rss_subscriptions
+		.iter()
+		.map(|doc| {
+			(doc.contents.url.clone(), ParseOptions::from(&doc.contents), doc.contents.auth(&key))
+		})
+		.collect();
+	let results = validate_feeds(feeds, &config.user_agent, &config.fetch).await;
+	for (subscription, (url, result)) in rss_subscriptions.iter().zip(results) {
+		if let Err(err) = result {
+			problems.push(format!("RSS feed {url} in {}: {err}", subscription.contents.room));
+		}
+	}
+
+	let http_client = GuardedClient::new(&config.user_agent, &config.fetch)?;
+	for subscription in GithubSubscription::all_async(&databases.state).await? {
+		let subscription = subscription.contents;
+		for (index, token) in subscription.tokens(&key).into_iter().enumerate() {
+			let client = Github::new(
+				subscription.user.clone(),
+				token,
+				&http_client,
+				config.github.retry_policy(),
+			)?
+			.with_api_url(subscription.api_url.as_ref());
+			if let Err(err) = client.test_token().await {
+				problems.push(format!(
+					"Github token #{index} of {} in {}: {err}",
+					subscription.user, subscription.room
+				));
+			}
+		}
+	}
+
+	Ok(problems)
+}
+
+/// Format the validation problems as markdown summary.
+fn format_summary(problems: &[String]) -> String {
+	if problems.is_empty() {
+		return String::from("Startup validation: all subscriptions are OK.");
+	}
+
+	let mut lines = vec![format!("Startup validation: {} broken subscriptions:", problems.len())];
+	lines.extend(problems.iter().map(|problem| format!("- {problem}")));
+	lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests;
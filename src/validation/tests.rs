@@ -0,0 +1,45 @@
+//! Tests for the subscription validation.
+
+use color_eyre::Result;
+use matrix_sdk::ruma::OwnedRoomId;
+
+use super::*;
+use crate::test_utils::{test_databases, MockResponse, MockServer};
+
+/// A minimal valid RSS feed.
+const VALID_FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+<channel>
+	<title>Test feed</title>
+	<link>https://example.com/</link>
+	<description>Test feed</description>
+</channel>
+</rss>"#;
+
+#[tokio::test]
+async fn broken_subscriptions_are_reported() -> Result<()> {
+	let server = MockServer::start(|request| match request.path.as_str() {
+		"/valid" => MockResponse::new(200, VALID_FEED),
+		_ => MockResponse::new(404, "not found"),
+	})
+	.await?;
+	let databases = test_databases().await?;
+	let mut config = Settings::read_from("config.sample.yaml")?;
+	config.fetch.allow_private_hosts = true;
+	let room: OwnedRoomId = "!room:example.com".try_into()?;
+
+	let valid = server.url().join("valid")?;
+	let missing = server.url().join("missing")?;
+	RssSubscription::new(room.clone(), valid.clone()).insert(&databases.state).await?;
+	RssSubscription::new(room.clone(), missing.clone()).insert(&databases.state).await?;
+
+	let problems = validate_subscriptions(&config, &databases).await?;
+	assert_eq!(problems.len(), 1);
+	assert!(problems[0].starts_with(&format!("RSS feed {missing} in !room:example.com")));
+
+	let summary = format_summary(&problems);
+	assert!(summary.starts_with("Startup validation: 1 broken subscriptions:"));
+	assert!(summary.contains(missing.as_str()));
+	assert!(!summary.contains(valid.as_str()));
+	Ok(())
+}